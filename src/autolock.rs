@@ -0,0 +1,80 @@
+// src/autolock.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Third-party imports
+
+// Local imports
+
+use keyring::KeyringHandle;
+use protocol::v1::prompt::PromptsHandle;
+use settings::{LockSection, SettingsHandle};
+
+
+// ===========================================================================
+// Auto lock
+// ===========================================================================
+
+
+// How often the keyring is checked against the lock settings
+const CHECK_INTERVAL_MS: u64 = 500;
+
+
+// Lock the keyring if it has expired at the given time, and wake the
+// conversations waiting on prompts so they find it locked. Returns true if
+// the keyring was locked by this call.
+pub fn check(
+    keyring: &KeyringHandle, prompts: &PromptsHandle, lock: &LockSection,
+    now: Instant
+) -> bool
+{
+    let locked = {
+        let mut keyring = keyring.write().expect("failed to write keyring");
+        keyring.expired(now, lock) && keyring.lock()
+    };
+    if locked {
+        prompts.keyring_locked();
+    }
+    locked
+}
+
+
+// Start a thread that re-locks the keyring once it has been idle or unlocked
+// for too long. Nothing is started if no timeout is configured.
+pub fn spawn(
+    settings: SettingsHandle, keyring: KeyringHandle, prompts: PromptsHandle
+) -> Option<JoinHandle<()>>
+{
+    {
+        let config = settings.read().expect("failed to read server settings");
+        let lock = config.lock();
+        if lock.idle_timeout.is_none() && lock.max_unlocked.is_none() {
+            return None;
+        }
+    }
+
+    let handle = thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(CHECK_INTERVAL_MS));
+        let config = settings.read().expect("failed to read server settings");
+        if check(&keyring, &prompts, config.lock(), Instant::now()) {
+            eprintln!("sasd: keyring locked after timeout");
+        }
+    });
+    Some(handle)
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -10,6 +10,8 @@
 
 // Stdlib imports
 
+use std::io;
+
 // Third-party imports
 
 use config::ConfigError;
@@ -32,6 +34,7 @@ error_chain! {
 
     foreign_links {
         Config(ConfigError);
+        Io(io::Error);
     }
 
     errors {
@@ -41,6 +44,58 @@ error_chain! {
             description("settings validation failure")
             display("Settings validation error: {}", msg)
         }
+        ListenerError(msg: String) {
+            description("listener setup failure")
+            display("Listener error: {}", msg)
+        }
+        PeerNotAllowed(uid: u32, gid: u32) {
+            description("peer not allowed to connect")
+            display("Peer not allowed to connect: uid {}, gid {}", uid, gid)
+        }
+        KeyringLoadError(msg: String) {
+            description("keyring load failure")
+            display("Unable to load keyring: {}", msg)
+        }
+        KeyringSaveError(msg: String) {
+            description("keyring save failure")
+            display("Unable to save keyring: {}", msg)
+        }
+        KeyringLocked {
+            description("keyring is locked")
+            display("Keyring is locked")
+        }
+        InvalidKey(msg: String) {
+            description("invalid key")
+            display("Invalid key: {}", msg)
+        }
+        PolicyViolation(msg: String) {
+            description("password policy violation")
+            display("Password policy violation: {}", msg)
+        }
+        InvalidQuery(msg: String) {
+            description("invalid key query")
+            display("Invalid key query: {}", msg)
+        }
+        ProtocolPhase(msg: String) {
+            description("unexpected protocol message")
+            display("Protocol phase error: {}", msg)
+        }
+        ProtocolNeedKey(msg: String) {
+            description("no key for protocol")
+            display("Protocol needs a key: {}", msg)
+        }
+        ProtocolNeedConfirmation(msg: String) {
+            description("key use not confirmed")
+            display("Protocol needs confirmation: {}", msg)
+        }
+        ProtocolFailed(msg: String) {
+            description("protocol failure")
+            display("Protocol failed: {}", msg)
+        }
+        SshAgentError(msg: String) {
+            description("ssh-agent request failure")
+            display("ssh-agent error: {}", msg)
+        }
         InvalidStateValue(expected: String, value: String) {
             description("invalid StateValue")
             display("Invalid StateValue: expected {}, got {} instead",
@@ -0,0 +1,543 @@
+// src/keyfile.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::str;
+
+// Third-party imports
+
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit};
+use chacha20poly1305::aead::{Aead, Payload};
+use getrandom::getrandom;
+use rmp::Marker;
+use rmp::encode::{self, ValueWriteError};
+use scrypt::{Params, scrypt};
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Key, is_secret_attr};
+use secret::SecretBuf;
+
+
+// ===========================================================================
+// File format
+// ===========================================================================
+
+
+// A keyring file is laid out as:
+//
+// magic (8) | version (1) | log_n (1) | r (4) | p (4) | salt (16) |
+// nonce (12) | ciphertext
+//
+// Integers are big endian. The file key is derived from the master
+// passphrase with scrypt, and the keys are sealed as a msgpack list of maps
+// with ChaCha20-Poly1305 using the whole header as associated data, so any
+// change to the header is caught when the file is opened.
+const MAGIC: &'static [u8; 8] = b"SASDKEYR";
+
+const VERSION: u8 = 1;
+
+const SALT_LEN: usize = 16;
+
+const NONCE_LEN: usize = 12;
+
+const KEY_LEN: usize = 32;
+
+const HEADER_LEN: usize = 8 + 1 + 1 + 4 + 4 + SALT_LEN + NONCE_LEN;
+
+// Refuse work factors that would take unreasonably long or use too much
+// memory, eg from a tampered header. scrypt needs 128 * r * 2^log_n bytes
+// and does p times that much work.
+const MAX_LOG_N: u8 = 20;
+
+const MAX_R: u32 = 32;
+
+const MAX_P: u32 = 16;
+
+const MAX_MEMORY: u64 = 1 << 30;
+
+
+// ===========================================================================
+// KdfParams
+// ===========================================================================
+
+
+// scrypt cost parameters, stored in the file header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KdfParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+
+impl Default for KdfParams {
+    fn default() -> Self
+    {
+        KdfParams {
+            log_n: 15,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+
+impl KdfParams {
+    fn check(&self) -> SasdResult<()>
+    {
+        let memory = 128 * self.r as u64 * (1u64 << self.log_n.min(63));
+        let errmsg = if self.log_n > MAX_LOG_N {
+            format!("scrypt log_n too large: {}", self.log_n)
+        } else if self.r > MAX_R {
+            format!("scrypt r too large: {}", self.r)
+        } else if self.p > MAX_P {
+            format!("scrypt p too large: {}", self.p)
+        } else if memory > MAX_MEMORY {
+            format!("scrypt needs too much memory: {} bytes", memory)
+        } else {
+            return Ok(());
+        };
+        bail!(SasdErrorKind::KeyringLoadError(errmsg))
+    }
+
+    fn derive_key(&self, passphrase: &[u8], salt: &[u8])
+        -> SasdResult<SecretBuf>
+    {
+        self.check()?;
+        let params = Params::new(self.log_n, self.r, self.p, KEY_LEN)
+            .map_err(|e| {
+                let errmsg = format!("invalid scrypt parameters: {}", e);
+                SasdErrorKind::KeyringLoadError(errmsg)
+            })?;
+
+        let mut key = SecretBuf::from(vec![0u8; KEY_LEN]);
+        scrypt(passphrase, salt, &params, key.as_mut_bytes()).map_err(|e| {
+            SasdErrorKind::KeyringLoadError(format!("scrypt failed: {}", e))
+        })?;
+        Ok(key)
+    }
+}
+
+
+// ===========================================================================
+// KeyringFile
+// ===========================================================================
+
+
+// An open keyring file. The derived file key is kept so that saving does not
+// need the passphrase again; a fresh nonce is used for every save.
+pub struct KeyringFile {
+    path: PathBuf,
+    params: KdfParams,
+    salt: [u8; SALT_LEN],
+    key: SecretBuf,
+}
+
+
+impl fmt::Debug for KeyringFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        f.debug_struct("KeyringFile")
+            .field("path", &self.path)
+            .field("params", &self.params)
+            .finish()
+    }
+}
+
+
+impl KeyringFile {
+    // Start a new keyring file. Nothing is written until the first save.
+    pub fn create(path: &Path, passphrase: &[u8], params: KdfParams)
+        -> SasdResult<Self>
+    {
+        let mut salt = [0u8; SALT_LEN];
+        random_bytes(&mut salt, SasdErrorKind::KeyringSaveError)?;
+        let key = params.derive_key(passphrase, &salt)?;
+        let ret = KeyringFile {
+            path: path.to_owned(),
+            params: params,
+            salt: salt,
+            key: key,
+        };
+        Ok(ret)
+    }
+
+    // Open and decrypt an existing keyring file
+    pub fn open(path: &Path, passphrase: &[u8])
+        -> SasdResult<(Self, Vec<Key>)>
+    {
+        let load_err = |msg: String| {
+            SasdErrorKind::KeyringLoadError(
+                format!("{}: {}", path.display(), msg),
+            )
+        };
+
+        let mut data = Vec::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .map_err(|e| load_err(e.to_string()))?;
+
+        if data.len() < HEADER_LEN || data[..8] != MAGIC[..] {
+            bail!(load_err("not a keyring file".to_owned()))
+        }
+        if data[8] != VERSION {
+            let errmsg = format!("unsupported keyring version {}", data[8]);
+            bail!(load_err(errmsg))
+        }
+
+        let params = KdfParams {
+            log_n: data[9],
+            r: read_u32(&data[10..14]),
+            p: read_u32(&data[14..18]),
+        };
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&data[18..18 + SALT_LEN]);
+        let nonce = &data[18 + SALT_LEN..HEADER_LEN];
+
+        let file = KeyringFile {
+            path: path.to_owned(),
+            params: params,
+            salt: salt,
+            key: params.derive_key(passphrase, &salt)?,
+        };
+
+        let payload = Payload {
+            msg: &data[HEADER_LEN..],
+            aad: &data[..HEADER_LEN],
+        };
+        let plaintext = file.cipher().decrypt(nonce.into(), payload).map_err(
+            |_| {
+                load_err("wrong passphrase or damaged keyring file".to_owned())
+            },
+        )?;
+        let plaintext = SecretBuf::from(plaintext);
+
+        let keys = decode_keys(plaintext.as_bytes()).map_err(load_err)?;
+        Ok((file, keys))
+    }
+
+    pub fn path(&self) -> &Path
+    {
+        &self.path
+    }
+
+    // Encrypt the keys and atomically replace the keyring file
+    pub fn save(&self, keys: &[Key]) -> SasdResult<()>
+    {
+        let mut nonce = [0u8; NONCE_LEN];
+        random_bytes(&mut nonce, SasdErrorKind::KeyringSaveError)?;
+        let header = self.header(&nonce);
+
+        let plaintext = encode_keys(keys);
+        let payload = Payload {
+            msg: plaintext.as_bytes(),
+            aad: &header[..],
+        };
+        let ciphertext = self.cipher()
+            .encrypt((&nonce[..]).into(), payload)
+            .map_err(|_| {
+                SasdErrorKind::KeyringSaveError(
+                    "unable to encrypt keyring".to_owned(),
+                )
+            })?;
+
+        let mut data = header;
+        data.extend_from_slice(&ciphertext);
+        write_atomic(&self.path, &data).map_err(|e| {
+            let errmsg = format!("{}: {}", self.path.display(), e);
+            SasdErrorKind::KeyringSaveError(errmsg).into()
+        })
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305
+    {
+        ChaCha20Poly1305::new(self.key.as_bytes().into())
+    }
+
+    fn header(&self, nonce: &[u8]) -> Vec<u8>
+    {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(&MAGIC[..]);
+        header.push(VERSION);
+        header.push(self.params.log_n);
+        header.extend_from_slice(&write_u32(self.params.r));
+        header.extend_from_slice(&write_u32(self.params.p));
+        header.extend_from_slice(&self.salt);
+        header.extend_from_slice(nonce);
+        header
+    }
+}
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+fn random_bytes<F>(buf: &mut [u8], errkind: F) -> SasdResult<()>
+where
+    F: Fn(String) -> SasdErrorKind,
+{
+    getrandom(buf).map_err(|e| {
+        errkind(format!("unable to get random bytes: {}", e)).into()
+    })
+}
+
+
+fn read_u32(buf: &[u8]) -> u32
+{
+    buf.iter().fold(0, |acc, &b| (acc << 8) | b as u32)
+}
+
+
+fn write_u32(n: u32) -> [u8; 4]
+{
+    [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+
+// Counts the bytes written to it, so that a buffer of the right size can
+// be made before anything secret is written
+struct Counter(usize);
+
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+
+fn write_keys<W: Write>(wr: &mut W, keys: &[Key])
+    -> Result<(), ValueWriteError>
+{
+    encode::write_array_len(wr, keys.len() as u32)?;
+    for k in keys {
+        let len = k.public().len() + k.secret().len();
+        encode::write_map_len(wr, len as u32)?;
+        for (a, v) in k.public() {
+            encode::write_str(wr, a)?;
+            encode::write_str(wr, v)?;
+        }
+        // A secret that is not UTF-8 is kept as bytes
+        for (a, v) in k.secret() {
+            encode::write_str(wr, a)?;
+            match v.as_str() {
+                Some(s) => encode::write_str(wr, s)?,
+                None => encode::write_bin(wr, v.as_bytes())?,
+            }
+        }
+    }
+    Ok(())
+}
+
+
+// The keys are written straight into a secret buffer of the exact size
+// needed, so no copy of a secret is left behind in a plain buffer
+fn encode_keys(keys: &[Key]) -> SecretBuf
+{
+    let mut counter = Counter(0);
+    write_keys(&mut counter, keys).expect("failed to encode keyring");
+
+    let mut buf = SecretBuf::from(vec![0u8; counter.0]);
+    write_keys(&mut buf.as_mut_bytes(), keys)
+        .expect("failed to encode keyring");
+    buf
+}
+
+
+// Reads msgpack from a buffer w/o copying strings and bytes out of it
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]>
+    {
+        if len > self.data.len() {
+            return None;
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(taken)
+    }
+
+    fn len(&mut self, size: usize) -> Option<usize>
+    {
+        self.take(size).map(|b| read_u32(b) as usize)
+    }
+
+    fn array_len(&mut self) -> Option<usize>
+    {
+        match Marker::from_u8(self.take(1)?[0]) {
+            Marker::FixArray(n) => Some(n as usize),
+            Marker::Array16 => self.len(2),
+            Marker::Array32 => self.len(4),
+            _ => None,
+        }
+    }
+
+    fn map_len(&mut self) -> Option<usize>
+    {
+        match Marker::from_u8(self.take(1)?[0]) {
+            Marker::FixMap(n) => Some(n as usize),
+            Marker::Map16 => self.len(2),
+            Marker::Map32 => self.len(4),
+            _ => None,
+        }
+    }
+
+    // The bytes of a string, or of binary data if binary is true
+    fn str_or_bin(&mut self, binary: bool) -> Option<&'a [u8]>
+    {
+        let len = match Marker::from_u8(self.take(1)?[0]) {
+            Marker::FixStr(n) => n as usize,
+            Marker::Str8 => self.len(1)?,
+            Marker::Str16 => self.len(2)?,
+            Marker::Str32 => self.len(4)?,
+            Marker::Bin8 if binary => self.len(1)?,
+            Marker::Bin16 if binary => self.len(2)?,
+            Marker::Bin32 if binary => self.len(4)?,
+            _ => return None,
+        };
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Option<&'a str>
+    {
+        str::from_utf8(self.str_or_bin(false)?).ok()
+    }
+}
+
+
+// Secret values are copied straight from the decrypted data into secret
+// buffers
+fn decode_keys(data: &[u8]) -> Result<Vec<Key>, String>
+{
+    let errmsg = || "invalid keyring contents".to_owned();
+
+    let mut rd = Reader { data: data };
+    let count = rd.array_len().ok_or_else(&errmsg)?;
+
+    let mut keys = Vec::new();
+    for _ in 0..count {
+        let len = rd.map_len().ok_or_else(&errmsg)?;
+        let mut attrs = Attrs::new();
+        let mut secret = Vec::new();
+        for _ in 0..len {
+            let a = rd.str().ok_or_else(&errmsg)?;
+            if is_secret_attr(a) {
+                let v = rd.str_or_bin(true).ok_or_else(&errmsg)?;
+                secret.push((a, SecretBuf::from(v)));
+            } else {
+                let v = rd.str().ok_or_else(&errmsg)?;
+                attrs.insert(a.to_owned(), v.to_owned());
+            }
+        }
+        let mut key = Key::new(attrs);
+        for (a, v) in secret {
+            key.set_secret(a, v);
+        }
+        keys.push(key);
+    }
+    if !rd.data.is_empty() {
+        return Err(errmsg());
+    }
+    Ok(keys)
+}
+
+
+// Write to a temporary file next to the target, flush it to disk, then
+// rename it over the target so that a crash never leaves a partial keyring
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()>
+{
+    let mut tmp_name = path.file_name()
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "not a file path")
+        })?
+        .to_owned();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+
+    let result = open_private(&tmp)
+        .and_then(|mut f| {
+            f.write_all(data)?;
+            f.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, path));
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    sync_dir(path)
+}
+
+
+#[cfg(unix)]
+fn open_private(path: &Path) -> io::Result<File>
+{
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+
+#[cfg(windows)]
+fn open_private(path: &Path) -> io::Result<File>
+{
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+}
+
+
+// Make the rename itself durable
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()>
+{
+    match path.parent() {
+        Some(dir) if dir != Path::new("") => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+
+#[cfg(windows)]
+fn sync_dir(_path: &Path) -> io::Result<()>
+{
+    Ok(())
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
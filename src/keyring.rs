@@ -0,0 +1,443 @@
+// src/keyring.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+// Third-party imports
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyfile::{KdfParams, KeyringFile};
+use query::Query;
+use secret::SecretBuf;
+use settings::LockSection;
+
+
+// ===========================================================================
+// Keyring Helpers
+// ===========================================================================
+
+
+// attr=value pairs describing a single key
+pub type Attrs = BTreeMap<String, String>;
+
+
+// Secret attributes of a key, wiped from memory when dropped
+pub type SecretAttrs = BTreeMap<String, SecretBuf>;
+
+
+// Attributes whose name starts with this are secret
+pub const SECRET_PREFIX: char = '!';
+
+
+// Shown in place of a secret attribute's value
+pub const SECRET_PLACEHOLDER: &'static str = "?";
+
+
+pub fn is_secret_attr(attr: &str) -> bool
+{
+    attr.starts_with(SECRET_PREFIX)
+}
+
+
+pub type KeyringHandle = Arc<RwLock<Keyring>>;
+
+
+pub fn new_keyring_handle(keyring: Keyring) -> KeyringHandle
+{
+    Arc::new(RwLock::new(keyring))
+}
+
+
+// ===========================================================================
+// Key
+// ===========================================================================
+
+
+// A single key. Secret attributes (eg !password) are kept apart from the
+// public ones so that they are only ever handed to protocol modules and never
+// listed or logged.
+#[derive(Clone, PartialEq)]
+pub struct Key {
+    public: Attrs,
+    secret: SecretAttrs,
+}
+
+
+impl Key {
+    pub fn new(attrs: Attrs) -> Self
+    {
+        let mut public = Attrs::new();
+        let mut secret = SecretAttrs::new();
+        for (k, v) in attrs {
+            if is_secret_attr(&k) {
+                secret.insert(k, SecretBuf::from(v));
+            } else {
+                public.insert(k, v);
+            }
+        }
+        Key {
+            public: public,
+            secret: secret,
+        }
+    }
+
+    // Same as new, plus a secret attribute whose value never has to be
+    // copied out of a SecretBuf
+    pub fn with_secret(attrs: Attrs, attr: &str, value: SecretBuf) -> Self
+    {
+        let mut key = Key::new(attrs);
+        key.secret.insert(attr.to_owned(), value);
+        key
+    }
+
+    pub fn public(&self) -> &Attrs
+    {
+        &self.public
+    }
+
+    pub fn secret(&self) -> &SecretAttrs
+    {
+        &self.secret
+    }
+
+    pub fn set_public(&mut self, attr: &str, value: String)
+    {
+        assert!(!is_secret_attr(attr), "not a public attr: {}", attr);
+        self.public.insert(attr.to_owned(), value);
+    }
+
+    pub fn set_secret(&mut self, attr: &str, value: SecretBuf)
+    {
+        assert!(is_secret_attr(attr), "not a secret attr: {}", attr);
+        self.secret.insert(attr.to_owned(), value);
+    }
+
+    // Look up an attribute, public or secret
+    pub fn get(&self, attr: &str) -> Option<&str>
+    {
+        if is_secret_attr(attr) {
+            self.secret.get(attr).and_then(|v| v.as_str())
+        } else {
+            self.public.get(attr).map(|v| v.as_str())
+        }
+    }
+
+    // Public attributes along with a placeholder for every secret attribute
+    pub fn masked(&self) -> Attrs
+    {
+        let mut attrs = self.public.clone();
+        for k in self.secret.keys() {
+            attrs.insert(k.clone(), SECRET_PLACEHOLDER.to_owned());
+        }
+        attrs
+    }
+
+    // A key matches another if its public attributes are exactly the
+    // other's, and it has the same value for every secret attribute the
+    // other has
+    pub fn matches(&self, other: &Key) -> bool
+    {
+        self.public == other.public &&
+            other.secret.iter().all(|(k, v)| match self.secret.get(k) {
+                Some(s) => s.ct_eq(v.as_bytes()),
+                None => false,
+            })
+    }
+}
+
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        f.debug_struct("Key")
+            .field("attrs", &self.masked())
+            .finish()
+    }
+}
+
+
+// ===========================================================================
+// Keyring
+// ===========================================================================
+
+
+// Keys shared by every client session. A keyring opened from a file is
+// written back to it whenever it is saved.
+//
+// While locked, the keyring holds no keys and no file key; they are only
+// brought back by unlocking with the master passphrase.
+#[derive(Debug, Default)]
+pub struct Keyring {
+    keys: Vec<Key>,
+    file: Option<KeyringFile>,
+    path: Option<PathBuf>,
+    locked: bool,
+
+    // When the keyring was last unlocked and last used by a client, for
+    // locking it again automatically
+    unlocked_at: Option<Instant>,
+    last_used: Option<Instant>,
+}
+
+
+impl Keyring {
+    pub fn new() -> Self
+    {
+        Keyring {
+            keys: Vec::new(),
+            file: None,
+            path: None,
+            locked: false,
+            unlocked_at: Some(Instant::now()),
+            last_used: Some(Instant::now()),
+        }
+    }
+
+    // A locked keyring for the keyring file at path. The file is only read
+    // once the keyring is unlocked.
+    pub fn locked(path: &Path) -> Self
+    {
+        Keyring {
+            keys: Vec::new(),
+            file: None,
+            path: Some(path.to_owned()),
+            locked: true,
+            unlocked_at: None,
+            last_used: None,
+        }
+    }
+
+    // Load the keyring file at path, creating an empty one if it does not
+    // exist yet
+    pub fn open(path: &Path, passphrase: &[u8]) -> SasdResult<Self>
+    {
+        Keyring::open_with_params(path, passphrase, KdfParams::default())
+    }
+
+    // Same as open, using the given key derivation parameters if a new file
+    // is created
+    pub fn open_with_params(path: &Path, passphrase: &[u8],
+                            params: KdfParams)
+        -> SasdResult<Self>
+    {
+        if path.exists() {
+            let (file, keys) = KeyringFile::open(path, passphrase)?;
+            return Ok(Keyring {
+                keys: keys,
+                file: Some(file),
+                path: Some(path.to_owned()),
+                locked: false,
+                unlocked_at: Some(Instant::now()),
+                last_used: Some(Instant::now()),
+            });
+        }
+
+        let keyring = Keyring {
+            keys: Vec::new(),
+            file: Some(KeyringFile::create(path, passphrase, params)?),
+            path: Some(path.to_owned()),
+            locked: false,
+            unlocked_at: Some(Instant::now()),
+            last_used: Some(Instant::now()),
+        };
+        keyring.save()?;
+        Ok(keyring)
+    }
+
+    pub fn is_locked(&self) -> bool
+    {
+        self.locked
+    }
+
+    // Drop every key along with the file key. Since the keyring is shared,
+    // this locks it for every session at once. A keyring without a file
+    // could never be unlocked again, so it is left as is and false is
+    // returned.
+    pub fn lock(&mut self) -> bool
+    {
+        if self.path.is_none() {
+            return false;
+        }
+        self.keys.clear();
+        self.file = None;
+        self.locked = true;
+        self.unlocked_at = None;
+        self.last_used = None;
+        true
+    }
+
+    // Reload the keys from the keyring file. If the file does not exist, an
+    // empty one is only created when create is true.
+    pub fn unlock(&mut self, passphrase: &[u8], create: bool)
+        -> SasdResult<()>
+    {
+        if !self.locked {
+            return Ok(());
+        }
+        let keyring = match self.path {
+            Some(ref p) if create || p.exists() => {
+                Keyring::open(p, passphrase)?
+            }
+            _ => {
+                let errmsg = "keyring file does not exist".to_owned();
+                bail!(SasdErrorKind::KeyringLoadError(errmsg))
+            }
+        };
+        *self = keyring;
+        Ok(())
+    }
+
+    // Record that a client used the keyring
+    pub fn touch(&mut self)
+    {
+        if !self.locked {
+            self.last_used = Some(Instant::now());
+        }
+    }
+
+    // Whether an unlocked keyring has been idle or unlocked for longer than
+    // the lock settings allow at the given time. A keyring without a file
+    // never expires since it can't be locked.
+    pub fn expired(&self, now: Instant, lock: &LockSection) -> bool
+    {
+        let past = |since: Option<Instant>, timeout| match (since, timeout) {
+            (Some(s), Some(t)) => now.duration_since(s) >= t,
+            _ => false,
+        };
+        !self.locked && self.path.is_some() &&
+            (past(self.last_used, lock.idle_timeout) ||
+                 past(self.unlocked_at, lock.max_unlocked))
+    }
+
+    // Write the keys to the keyring file, if there is one
+    pub fn save(&self) -> SasdResult<()>
+    {
+        match self.file {
+            Some(ref f) => f.save(&self.keys),
+            None => Ok(()),
+        }
+    }
+
+    // Save the keyring after a change to its keys. If saving fails the keys
+    // are put back to the given copy of them from before the change.
+    pub fn save_or_restore(&mut self, old: Vec<Key>) -> SasdResult<()>
+    {
+        if let Err(e) = self.save() {
+            self.keys = old;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn keys(&self) -> &[Key]
+    {
+        &self.keys[..]
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.keys.is_empty()
+    }
+
+    // Returns false if a key with the same public attributes is already in
+    // the keyring
+    pub fn add(&mut self, key: Key) -> bool
+    {
+        if self.keys.iter().any(|k| k.public() == key.public()) {
+            return false;
+        }
+        self.keys.push(key);
+        true
+    }
+
+    // Returns false if no key matched the given key
+    pub fn remove(&mut self, key: &Key) -> bool
+    {
+        let before = self.keys.len();
+        self.keys.retain(|k| !k.matches(key));
+        self.keys.len() != before
+    }
+
+    // Set a public attribute of the key w/ exactly the given public
+    // attributes and save the keyring. If saving fails the key is left as it
+    // was. Returns false if there is no such key.
+    pub fn set_attr(&mut self, public: &Attrs, attr: &str, value: &str)
+        -> SasdResult<bool>
+    {
+        assert!(!is_secret_attr(attr), "only public attrs can be set");
+        let index = match self.keys.iter().position(|k| k.public() == public) {
+            Some(i) => i,
+            None => return Ok(false),
+        };
+
+        let old = self.keys[index]
+            .public
+            .insert(attr.to_owned(), value.to_owned());
+        if let Err(e) = self.save() {
+            let public = &mut self.keys[index].public;
+            match old {
+                Some(v) => public.insert(attr.to_owned(), v),
+                None => public.remove(attr),
+            };
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    // Replace the key w/ exactly the given public attributes and save the
+    // keyring. If saving fails the old key is put back. Returns false if
+    // there is no such key.
+    pub fn update(&mut self, public: &Attrs, key: Key) -> SasdResult<bool>
+    {
+        let index = match self.keys.iter().position(|k| k.public() == public) {
+            Some(i) => i,
+            None => return Ok(false),
+        };
+
+        let old = mem::replace(&mut self.keys[index], key);
+        if let Err(e) = self.save() {
+            self.keys[index] = old;
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    pub fn find(&self, query: &Query) -> Vec<&Key>
+    {
+        self.keys.iter().filter(|k| query.matches(k)).collect()
+    }
+
+    // Returns the number of keys removed
+    pub fn remove_matching(&mut self, query: &Query) -> usize
+    {
+        let before = self.keys.len();
+        self.keys.retain(|k| !query.matches(k));
+        before - self.keys.len()
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
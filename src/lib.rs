@@ -0,0 +1,95 @@
+// src/lib.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Features
+// ===========================================================================
+
+// #![feature(use_extern_macros)]
+
+// ===========================================================================
+// Externs
+// ===========================================================================
+
+// Third-party externs
+
+extern crate chacha20poly1305;
+extern crate config;
+extern crate data_encoding;
+extern crate des;
+extern crate ed25519_dalek;
+
+#[macro_use]
+extern crate error_chain;
+
+#[cfg(windows)]
+extern crate hex;
+
+extern crate getrandom;
+extern crate hmac;
+
+#[cfg(unix)]
+extern crate libc;
+
+#[cfg(test)]
+#[macro_use]
+extern crate matches;
+
+extern crate md4;
+extern crate md5;
+extern crate p256;
+extern crate pbkdf2;
+
+#[cfg(test)]
+#[macro_use]
+extern crate quickcheck;
+
+#[cfg(windows)]
+extern crate rand;
+extern crate rmp;
+extern crate rmpv;
+extern crate scrypt;
+// extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate sha1;
+extern crate sha2;
+extern crate siminau_rpc;
+
+#[macro_use]
+extern crate siminau_rpc_derive;
+
+#[cfg(test)]
+extern crate tempdir;
+
+#[cfg(windows)]
+extern crate winapi;
+
+
+// ===========================================================================
+// Modules
+// ===========================================================================
+
+
+pub mod autolock;
+pub mod error;
+pub mod keyfile;
+pub mod keyring;
+pub mod rpc;
+pub mod secret;
+pub mod os;
+pub mod protocol;
+pub mod query;
+pub mod server;
+pub mod settings;
+pub mod state;
+
+#[cfg(test)]
+mod test;
+
+
+// ===========================================================================
+//
+// ===========================================================================
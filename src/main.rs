@@ -3,12 +3,6 @@
 //
 // This file is released under the MIT License.
 
-// ===========================================================================
-// Features
-// ===========================================================================
-
-// #![feature(use_extern_macros)]
-
 // ===========================================================================
 // Externs
 // ===========================================================================
@@ -18,36 +12,9 @@
 extern crate appdirs;
 extern crate config;
 
-#[macro_use]
-extern crate error_chain;
-
-#[cfg(windows)]
-extern crate hex;
-
-#[cfg(test)]
-#[macro_use]
-extern crate matches;
-
-#[cfg(test)]
-#[macro_use]
-extern crate quickcheck;
-
-#[cfg(windows)]
-extern crate rand;
-extern crate rmpv;
-// extern crate serde;
-#[macro_use]
-extern crate serde_derive;
-extern crate siminau_rpc;
+// Local externs
 
-#[macro_use]
-extern crate siminau_rpc_derive;
-
-#[cfg(test)]
-extern crate tempdir;
-
-#[cfg(windows)]
-extern crate winapi;
+extern crate sasd;
 
 
 // ===========================================================================
@@ -57,33 +24,122 @@ extern crate winapi;
 
 // Stdlib imports
 
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+
 // Third-party imports
 
+use config::{Config, File};
+
 // Local imports
 
+use sasd::error::{SasdErrorKind, SasdResult};
+use sasd::keyring::{Keyring, new_keyring_handle};
+use sasd::protocol::v1::auth::{ProtocolRegistry,
+                               new_protocol_registry_handle};
+use sasd::secret::SecretBuf;
+use sasd::server;
+use sasd::settings::{Settings, SettingsBuilder, SettingsConfig,
+                     new_settings_handle};
+
+
 // ===========================================================================
-// Modules
+// Main
 // ===========================================================================
 
 
-pub mod error;
-pub mod rpc;
-pub mod os;
-pub mod protocol;
-pub mod settings;
-pub mod state;
+// The config file is given as the first argument, falling back to sasd.toml
+// in the user's config directory
+fn config_path() -> SasdResult<PathBuf>
+{
+    if let Some(arg) = env::args_os().nth(1) {
+        return Ok(PathBuf::from(arg));
+    }
+
+    let mut path = appdirs::user_config_dir(Some("sasd"), Some("Siminau"), false)
+        .map_err(|_| {
+            SasdErrorKind::SettingsError(
+                "unable to determine user config directory".to_owned(),
+            )
+        })?;
+    path.push("sasd.toml");
+    Ok(path)
+}
 
-#[cfg(test)]
-mod test;
 
-// ===========================================================================
-// Main
-// ===========================================================================
+// The keyring file set in the settings, falling back to a file in the user's
+// data directory
+fn keyring_path(settings: &Settings) -> SasdResult<PathBuf>
+{
+    if let Some(ref path) = settings.keyring().path {
+        return Ok(path.clone());
+    }
+
+    let dir = appdirs::user_data_dir(Some("sasd"), Some("Siminau"), false)
+        .map_err(|_| {
+            SasdErrorKind::SettingsError(
+                "unable to determine user data directory".to_owned(),
+            )
+        })?;
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join("keyring"))
+}
+
+
+// The master passphrase is read as a single line from stdin
+fn read_passphrase() -> SasdResult<SecretBuf>
+{
+    eprint!("Keyring passphrase: ");
+    io::stderr().flush()?;
+
+    // Reserve room up front so that the passphrase isn't copied around as
+    // the string grows
+    let mut passphrase = String::with_capacity(1024);
+    io::stdin().read_line(&mut passphrase)?;
+    while passphrase.ends_with('\n') || passphrase.ends_with('\r') {
+        passphrase.pop();
+    }
+    Ok(SecretBuf::from(passphrase))
+}
+
+
+fn run() -> SasdResult<()>
+{
+    let path = config_path()?;
+    let mut config = Config::new();
+    config.merge(File::from(path.as_path()))?;
+
+    let settings_config: SettingsConfig = config.try_into()?;
+    let settings = SettingsBuilder::from_config(settings_config)?;
+
+    let path = keyring_path(&settings)?;
+    let keyring = if settings.keyring().start_locked {
+        Keyring::locked(&path)
+    } else {
+        let passphrase = read_passphrase()?;
+        Keyring::open(&path, passphrase.as_bytes())?
+    };
+    let protocols = ProtocolRegistry::builtin();
+    server::serve(
+        new_settings_handle(settings),
+        new_keyring_handle(keyring),
+        new_protocol_registry_handle(protocols),
+    )
+}
 
 
 fn main()
 {
-    println!("Hello, world!");
+    if let Err(e) = run() {
+        eprintln!("sasd: {}", e);
+        for cause in e.iter().skip(1) {
+            eprintln!("caused by: {}", cause);
+        }
+        process::exit(1);
+    }
 }
 
 
@@ -0,0 +1,484 @@
+// src/os/unix/agent.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::io::{self, Read, Write};
+
+// Third-party imports
+
+use data_encoding::BASE64;
+use ed25519_dalek::{Signer, SigningKey};
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Key, KeyringHandle};
+use protocol::v1::prompt::PromptsHandle;
+use protocol::v1::sign::ed25519_key;
+use query::Query;
+use secret::SecretBuf;
+use settings::SettingsHandle;
+
+
+// ===========================================================================
+// Wire format
+// ===========================================================================
+
+
+// Message numbers from the ssh-agent protocol (draft-miller-ssh-agent)
+pub const SSH_AGENT_FAILURE: u8 = 5;
+pub const SSH_AGENT_SUCCESS: u8 = 6;
+pub const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+pub const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+pub const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+pub const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+pub const SSH_AGENTC_ADD_IDENTITY: u8 = 17;
+pub const SSH_AGENTC_REMOVE_IDENTITY: u8 = 18;
+pub const SSH_AGENTC_REMOVE_ALL_IDENTITIES: u8 = 19;
+pub const SSH_AGENTC_ADD_ID_CONSTRAINED: u8 = 25;
+
+// Key constraints sent w/ SSH_AGENTC_ADD_ID_CONSTRAINED
+pub const SSH_AGENT_CONSTRAIN_LIFETIME: u8 = 1;
+pub const SSH_AGENT_CONSTRAIN_CONFIRM: u8 = 2;
+
+pub const ED25519: &'static str = "ssh-ed25519";
+
+// Messages are much smaller than this; anything longer is refused rather
+// than read into memory
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+
+fn agent_error(msg: &str) -> SasdErrorKind
+{
+    SasdErrorKind::SshAgentError(msg.to_owned())
+}
+
+
+// Messages are a uint32 length followed by that many bytes, the first of
+// which is the message number. None is returned once the client hangs up.
+// Requests can carry private keys so they are read into a SecretBuf.
+pub fn read_agent_message<R: Read>(rd: &mut R)
+    -> SasdResult<Option<SecretBuf>>
+{
+    let mut len = [0u8; 4];
+    match rd.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 || len > MAX_MESSAGE_LEN {
+        bail!(agent_error("invalid message length"))
+    }
+    let mut msg = SecretBuf::from(vec![0u8; len]);
+    rd.read_exact(msg.as_mut_bytes())?;
+    Ok(Some(msg))
+}
+
+
+pub fn write_agent_message<W: Write>(wr: &mut W, msg: &[u8]) -> SasdResult<()>
+{
+    let mut buf = Vec::with_capacity(msg.len() + 4);
+    put_u32(&mut buf, msg.len() as u32);
+    buf.extend_from_slice(msg);
+    wr.write_all(&buf)?;
+    wr.flush()?;
+    Ok(())
+}
+
+
+pub fn put_u32(buf: &mut Vec<u8>, n: u32)
+{
+    buf.extend_from_slice(&n.to_be_bytes());
+}
+
+
+// Strings are a uint32 length followed by that many bytes
+pub fn put_string(buf: &mut Vec<u8>, s: &[u8])
+{
+    put_u32(buf, s.len() as u32);
+    buf.extend_from_slice(s);
+}
+
+
+// Reads the fields of a message in order
+pub struct AgentReader<'a> {
+    buf: &'a [u8],
+}
+
+
+impl<'a> AgentReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self
+    {
+        AgentReader { buf: buf }
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.buf.is_empty()
+    }
+
+    pub fn byte(&mut self) -> SasdResult<u8>
+    {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> SasdResult<u32>
+    {
+        let mut n = [0u8; 4];
+        n.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(n))
+    }
+
+    pub fn string(&mut self) -> SasdResult<&'a [u8]>
+    {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn take(&mut self, len: usize) -> SasdResult<&'a [u8]>
+    {
+        if len > self.buf.len() {
+            bail!(agent_error("message is too short"))
+        }
+        let (field, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(field)
+    }
+}
+
+
+// ===========================================================================
+// Keys
+// ===========================================================================
+
+
+// The blob ssh uses to name an ed25519 public key
+pub fn ed25519_blob(public: &[u8]) -> Vec<u8>
+{
+    let mut blob = Vec::new();
+    put_string(&mut blob, ED25519.as_bytes());
+    put_string(&mut blob, public);
+    blob
+}
+
+
+// An ssh key in the keyring has proto=ssh, alg=ed25519, the public key in
+// its pubkey attr and the 32 byte private seed in its !secret attr, both
+// base64. Returns the key's public key blob if it is such a key.
+fn key_blob(key: &Key) -> Option<Vec<u8>>
+{
+    let is_ssh = key.get("proto") == Some("ssh") &&
+        key.get("alg") == Some("ed25519") &&
+        key.secret().contains_key("!secret");
+    if !is_ssh {
+        return None;
+    }
+    let pubkey = key.get("pubkey")
+        .and_then(|p| BASE64.decode(p.as_bytes()).ok());
+    match pubkey {
+        Some(ref p) if p.len() == 32 => Some(ed25519_blob(p)),
+        _ => None,
+    }
+}
+
+
+// Public attrs of the ssh keys w/ the given public key blob
+fn matching_keys(keys: &[Key], blob: &[u8]) -> Vec<Attrs>
+{
+    keys.iter()
+        .filter(|k| key_blob(k).as_ref().map(|b| &b[..]) == Some(blob))
+        .map(|k| k.public().clone())
+        .collect()
+}
+
+
+fn signing_key(key: &Key) -> SasdResult<SigningKey>
+{
+    match key.secret().get("!secret") {
+        Some(secret) => ed25519_key(secret),
+        None => bail!(agent_error("key has no secret")),
+    }
+}
+
+
+// ===========================================================================
+// Agent
+// ===========================================================================
+
+
+// Answers ssh-agent requests from a single client w/ the ssh keys in the
+// keyring. Keys added or removed by ssh-add are added to or removed from the
+// keyring, and signing w/ a confirm=yes key waits for a confirmer like a
+// protocol conversation does.
+pub struct Agent {
+    settings: SettingsHandle,
+    keyring: KeyringHandle,
+    prompts: PromptsHandle,
+
+    // Identity of the client, for confirmers
+    peer: Attrs,
+}
+
+
+impl Agent {
+    pub fn new(
+        settings: SettingsHandle, keyring: KeyringHandle,
+        prompts: PromptsHandle, peer: Attrs
+    ) -> Self
+    {
+        Agent {
+            settings: settings,
+            keyring: keyring,
+            prompts: prompts,
+            peer: peer,
+        }
+    }
+
+    // Answer a single request. Anything that can't be done is answered w/
+    // SSH_AGENT_FAILURE, as the protocol has no way to say why.
+    pub fn handle(&self, msg: &[u8]) -> Vec<u8>
+    {
+        match self.dispatch(msg) {
+            Ok(reply) => reply,
+            Err(_) => vec![SSH_AGENT_FAILURE],
+        }
+    }
+
+    fn dispatch(&self, msg: &[u8]) -> SasdResult<Vec<u8>>
+    {
+        let mut rd = AgentReader::new(msg);
+        let code = rd.byte()?;
+
+        // A locked keyring has no identities to list
+        {
+            let mut keyring = self.keyring.write().expect(
+                "failed to write keyring",
+            );
+            if keyring.is_locked() {
+                if code == SSH_AGENTC_REQUEST_IDENTITIES {
+                    return Ok(identities_answer(&[]));
+                }
+                bail!(agent_error("keyring is locked"))
+            }
+            keyring.touch();
+        }
+
+        match code {
+            SSH_AGENTC_REQUEST_IDENTITIES => Ok(self.identities()),
+            SSH_AGENTC_SIGN_REQUEST => self.sign(rd),
+            SSH_AGENTC_ADD_IDENTITY => self.add(rd, false),
+            SSH_AGENTC_ADD_ID_CONSTRAINED => self.add(rd, true),
+            SSH_AGENTC_REMOVE_IDENTITY => self.remove(rd),
+            SSH_AGENTC_REMOVE_ALL_IDENTITIES => self.remove_all(),
+            _ => bail!(agent_error("unsupported request")),
+        }
+    }
+
+    fn identities(&self) -> Vec<u8>
+    {
+        let keyring = self.keyring.read().expect("failed to read keyring");
+        let identities: Vec<(Vec<u8>, String)> = keyring
+            .keys()
+            .iter()
+            .filter_map(|k| {
+                let comment = k.get("comment").unwrap_or("").to_owned();
+                key_blob(k).map(|blob| (blob, comment))
+            })
+            .collect();
+        identities_answer(&identities)
+    }
+
+    fn sign(&self, mut rd: AgentReader) -> SasdResult<Vec<u8>>
+    {
+        let blob = rd.string()?;
+        let data = rd.string()?;
+
+        // The flags only pick the hash for RSA signatures
+        rd.u32()?;
+
+        let (public, signing) = {
+            let keyring = self.keyring.read().expect("failed to read keyring");
+            let key = keyring
+                .keys()
+                .iter()
+                .find(|k| key_blob(k).as_ref().map(|b| &b[..]) == Some(blob));
+            match key {
+                Some(k) => (k.public().clone(), signing_key(k)?),
+                None => bail!(agent_error("no such key")),
+            }
+        };
+        if ed25519_blob(signing.verifying_key().as_bytes()) != blob {
+            bail!(agent_error("key's secret does not match its pubkey"))
+        }
+
+        // The keyring is not held while waiting on a confirmer
+        let confirm = public.get("confirm").map(|c| c == "yes");
+        if confirm == Some(true) && !self.confirm(&public) {
+            bail!(agent_error("use of the key was not confirmed"))
+        }
+
+        let mut signature = Vec::new();
+        put_string(&mut signature, ED25519.as_bytes());
+        put_string(&mut signature, &signing.sign(data).to_bytes());
+        let mut reply = vec![SSH_AGENT_SIGN_RESPONSE];
+        put_string(&mut reply, &signature);
+        Ok(reply)
+    }
+
+    fn confirm(&self, key: &Attrs) -> bool
+    {
+        let (timeout, remember) = {
+            let config = self.settings.read().expect(
+                "failed to read server settings",
+            );
+            let protocol = config.protocol();
+            (protocol.confirm_timeout, protocol.confirm_remember)
+        };
+        self.prompts
+            .confirm(key, &self.peer, timeout, remember, None)
+    }
+
+    // Only ed25519 keys can be added. The confirm constraint marks the key
+    // confirm=yes; a lifetime can't be honoured so such keys are refused.
+    fn add(&self, mut rd: AgentReader, constrained: bool)
+        -> SasdResult<Vec<u8>>
+    {
+        if rd.string()? != ED25519.as_bytes() {
+            bail!(agent_error("unsupported key type"))
+        }
+        let public = rd.string()?;
+
+        // The private key is the seed followed by the public key
+        let private = SecretBuf::from(rd.string()?);
+        let comment = rd.string()?;
+        if public.len() != 32 || private.len() != 64 ||
+            &private.as_bytes()[32..] != public
+        {
+            bail!(agent_error("invalid ed25519 key"))
+        }
+
+        let mut attrs = Attrs::new();
+        attrs.insert("proto".to_owned(), "ssh".to_owned());
+        attrs.insert("alg".to_owned(), "ed25519".to_owned());
+        attrs.insert("pubkey".to_owned(), BASE64.encode(public));
+        if !comment.is_empty() {
+            let comment = String::from_utf8_lossy(comment).into_owned();
+            attrs.insert("comment".to_owned(), comment);
+        }
+        while constrained && !rd.is_empty() {
+            match rd.byte()? {
+                SSH_AGENT_CONSTRAIN_CONFIRM => {
+                    attrs.insert("confirm".to_owned(), "yes".to_owned());
+                }
+                _ => bail!(agent_error("unsupported key constraint")),
+            }
+        }
+        if !rd.is_empty() {
+            bail!(agent_error("unexpected data after the key"))
+        }
+        let mut seed = SecretBuf::from(vec![0u8; BASE64.encode_len(32)]);
+        BASE64.encode_mut(&private.as_bytes()[..32], seed.as_mut_bytes());
+        let key = Key::with_secret(attrs, "!secret", seed);
+        if signing_key(&key)?.verifying_key().as_bytes() != public {
+            bail!(agent_error("invalid ed25519 key"))
+        }
+
+        // Adding a key again replaces it, eg to change its comment
+        let mut keyring = self.keyring.write().expect(
+            "failed to write keyring",
+        );
+        let old = keyring.keys().to_vec();
+        for stale in matching_keys(&old, &ed25519_blob(public)) {
+            keyring.remove(&Key::new(stale));
+        }
+        keyring.add(key);
+        keyring.save_or_restore(old)?;
+        Ok(vec![SSH_AGENT_SUCCESS])
+    }
+
+    fn remove(&self, mut rd: AgentReader) -> SasdResult<Vec<u8>>
+    {
+        let blob = rd.string()?;
+        let mut keyring = self.keyring.write().expect(
+            "failed to write keyring",
+        );
+        let old = keyring.keys().to_vec();
+        let matching = matching_keys(&old, blob);
+        if matching.is_empty() {
+            bail!(agent_error("no such key"))
+        }
+        for public in matching {
+            keyring.remove(&Key::new(public));
+        }
+        keyring.save_or_restore(old)?;
+        Ok(vec![SSH_AGENT_SUCCESS])
+    }
+
+    // Same as DeleteKey w/ the query proto=ssh, so only allowed if the
+    // settings say so
+    fn remove_all(&self) -> SasdResult<Vec<u8>>
+    {
+        let allowed = {
+            let config = self.settings.read().expect(
+                "failed to read server settings",
+            );
+            config.unix().ssh_agent_remove_all
+        };
+        if !allowed {
+            bail!(agent_error("removing all identities is not allowed"))
+        }
+        let query = Query::parse("proto=ssh")?;
+        let mut keyring = self.keyring.write().expect(
+            "failed to write keyring",
+        );
+        let old = keyring.keys().to_vec();
+        if keyring.remove_matching(&query) > 0 {
+            keyring.save_or_restore(old)?;
+        }
+        Ok(vec![SSH_AGENT_SUCCESS])
+    }
+}
+
+
+fn identities_answer(identities: &[(Vec<u8>, String)]) -> Vec<u8>
+{
+    let mut reply = vec![SSH_AGENT_IDENTITIES_ANSWER];
+    put_u32(&mut reply, identities.len() as u32);
+    for &(ref blob, ref comment) in identities {
+        put_string(&mut reply, blob);
+        put_string(&mut reply, comment.as_bytes());
+    }
+    reply
+}
+
+
+// Answer requests from a single client until it hangs up
+pub fn serve_agent<S>(stream: &mut S, agent: &Agent) -> SasdResult<()>
+where
+    S: Read + Write,
+{
+    loop {
+        let msg = match read_agent_message(stream)? {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+        write_agent_message(stream, &agent.handle(msg.as_bytes()))?;
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
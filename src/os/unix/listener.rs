@@ -0,0 +1,187 @@
+// src/os/unix/listener.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::fs::{self, Permissions};
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+// Third-party imports
+
+use libc;
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use settings::PeersSection;
+
+
+// ===========================================================================
+// Socket directory
+// ===========================================================================
+
+
+// Name of the current user's socket inside the socket directory
+pub fn socket_path(socket_dir: &Path) -> PathBuf
+{
+    let uid = unsafe { libc::getuid() };
+    socket_dir.join(format!("sasd-{}.sock", uid))
+}
+
+
+// Name of the current user's ssh-agent socket inside the socket directory,
+// for SSH_AUTH_SOCK
+pub fn agent_socket_path(socket_dir: &Path) -> PathBuf
+{
+    let uid = unsafe { libc::getuid() };
+    socket_dir.join(format!("sasd-agent-{}.sock", uid))
+}
+
+
+// The socket directory must belong to the user running the daemon and must
+// not be writable by anyone else, otherwise another user could replace the
+// socket with their own
+pub fn check_socket_dir(socket_dir: &Path) -> SasdResult<()>
+{
+    let meta = fs::metadata(socket_dir)?;
+    let uid = unsafe { libc::getuid() };
+
+    let errmsg = if !meta.is_dir() {
+        Some(format!("not a directory: {}", socket_dir.display()))
+    } else if meta.uid() != uid {
+        Some(format!(
+            "socket directory {} is owned by uid {}, expected {}",
+            socket_dir.display(),
+            meta.uid(),
+            uid
+        ))
+    } else if meta.mode() & 0o022 != 0 {
+        Some(format!(
+            "socket directory {} is group or world writable",
+            socket_dir.display()
+        ))
+    } else {
+        None
+    };
+
+    match errmsg {
+        Some(msg) => bail!(SasdErrorKind::ListenerError(msg)),
+        None => Ok(()),
+    }
+}
+
+
+// Remove a socket left behind by a daemon that did not shut down cleanly.
+// Nothing is removed if the path is not a socket or if something is still
+// accepting connections on it.
+pub fn remove_stale_socket(path: &Path) -> SasdResult<()>
+{
+    let meta = match fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    if !meta.file_type().is_socket() {
+        let errmsg = format!("{} exists and is not a socket", path.display());
+        bail!(SasdErrorKind::ListenerError(errmsg))
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => {
+            let errmsg =
+                format!("{} is in use by another process", path.display());
+            bail!(SasdErrorKind::ListenerError(errmsg))
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            fs::remove_file(path)?;
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+
+// ===========================================================================
+// Listener
+// ===========================================================================
+
+
+// Create the current user's socket in the socket directory. The socket is
+// only accessible by the user running the daemon and the allowed peers.
+pub fn bind(socket_dir: &Path, peers: &PeersSection)
+    -> SasdResult<UnixListener>
+{
+    bind_path(socket_dir, &socket_path(socket_dir), peers)
+}
+
+
+// Create the current user's ssh-agent socket in the socket directory, w/
+// the same checks and permissions as bind()
+pub fn bind_agent(socket_dir: &Path, peers: &PeersSection)
+    -> SasdResult<UnixListener>
+{
+    bind_path(socket_dir, &agent_socket_path(socket_dir), peers)
+}
+
+
+// Mode and group of the socket. W/o allowed peers only the owner may
+// connect. A single allowed group gets the socket's group; any other allowed
+// peers need the socket to be world accessible, check_peer() then turns
+// away everyone else.
+pub fn socket_mode(peers: &PeersSection) -> (u32, Option<u32>)
+{
+    match (peers.allow_uids.len(), peers.allow_gids.len()) {
+        (0, 0) => (0o600, None),
+        (0, 1) => (0o660, Some(peers.allow_gids[0])),
+        _ => (0o666, None),
+    }
+}
+
+
+fn bind_path(socket_dir: &Path, path: &Path, peers: &PeersSection)
+    -> SasdResult<UnixListener>
+{
+    check_socket_dir(socket_dir)?;
+    remove_stale_socket(path)?;
+
+    // Make sure the socket is never created with looser permissions, even
+    // briefly. The group is changed before the socket is opened up to it.
+    let listener = {
+        let oldmask = unsafe { libc::umask(0o177) };
+        let result = UnixListener::bind(path);
+        unsafe { libc::umask(oldmask) };
+        result?
+    };
+    let (mode, gid) = socket_mode(peers);
+    if let Some(gid) = gid {
+        let cpath = CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+            let errmsg = format!("invalid socket path: {}", path.display());
+            SasdErrorKind::ListenerError(errmsg)
+        })?;
+        let ret = unsafe { libc::chown(cpath.as_ptr(), !0, gid) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+    fs::set_permissions(path, Permissions::from_mode(mode))?;
+
+    Ok(listener)
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -20,6 +20,9 @@
 // ===========================================================================
 
 
+pub mod agent;
+pub mod listener;
+pub mod peer;
 // pub mod protocol;
 
 
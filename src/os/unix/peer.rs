@@ -0,0 +1,121 @@
+// src/os/unix/peer.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::io;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+// Third-party imports
+
+use libc;
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::Attrs;
+use settings::PeersSection;
+
+
+// ===========================================================================
+// PeerCred
+// ===========================================================================
+
+
+// Identity of the process on the other end of a client connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerCred {
+    pub uid: u32,
+    pub gid: u32,
+
+    // Not every platform reports the peer's pid
+    pub pid: Option<i32>,
+}
+
+
+impl PeerCred {
+    pub fn new(uid: u32, gid: u32, pid: Option<i32>) -> Self
+    {
+        PeerCred {
+            uid: uid,
+            gid: gid,
+            pid: pid,
+        }
+    }
+
+    // The peer's identity as attrs, for confirmers
+    pub fn attrs(&self) -> Attrs
+    {
+        let mut attrs = Attrs::new();
+        attrs.insert("uid".to_owned(), self.uid.to_string());
+        attrs.insert("gid".to_owned(), self.gid.to_string());
+        if let Some(pid) = self.pid {
+            attrs.insert("pid".to_owned(), pid.to_string());
+        }
+        attrs
+    }
+}
+
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn peer_cred(stream: &UnixStream) -> SasdResult<PeerCred>
+{
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(PeerCred::new(cred.uid, cred.gid, Some(cred.pid)))
+}
+
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn peer_cred(stream: &UnixStream) -> SasdResult<PeerCred>
+{
+    let mut uid: libc::uid_t = 0;
+    let mut gid: libc::gid_t = 0;
+    let ret = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(PeerCred::new(uid, gid, None))
+}
+
+
+// Only the user running the daemon may connect, unless the peer's user or
+// group has been explicitly allowed in the settings
+pub fn check_peer(peer: &PeerCred, peers: &PeersSection) -> SasdResult<()>
+{
+    let owner = unsafe { libc::getuid() };
+    if peer.uid == owner || peers.allow_uids.contains(&peer.uid)
+        || peers.allow_gids.contains(&peer.gid)
+    {
+        Ok(())
+    } else {
+        bail!(SasdErrorKind::PeerNotAllowed(peer.uid, peer.gid))
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -16,6 +16,8 @@ use std::fs::File;
 
 // Local imports
 
+use secret::SecretBuf;
+
 
 // ===========================================================================
 // Modules
@@ -31,13 +33,13 @@ pub mod v1;
 
 
 pub struct SessionStore {
-    pub auth_token: String,
+    pub auth_token: SecretBuf,
     pub auth_file: Option<File>,
 }
 
 
 impl SessionStore {
-    pub fn new(auth_token: String) -> Self
+    pub fn new(auth_token: SecretBuf) -> Self
     {
         Self {
             auth_token: auth_token,
@@ -51,7 +53,7 @@ impl Default for SessionStore {
     fn default() -> Self
     {
         Self {
-            auth_token: String::with_capacity(64),
+            auth_token: SecretBuf::new(),
             auth_file: None,
         }
     }
@@ -21,17 +21,21 @@ use std::path::{Path, PathBuf};
 use hex;
 use rand::{OsRng, Rng};
 use rmpv::{Utf8String, Value};
-use siminau_rpc::message::{CodeConvert, Message, MessageType, RpcMessage};
+use siminau_rpc::message::{Message, MessageType, RpcMessage};
 use siminau_rpc::message::request::RpcRequest;
 use winapi;
 
 // Local imports
 
-use error::{SasdErrorKind, SasdResult, SasdResultExt};
+use error::{SasdErrorKind, SasdResult};
 use protocol::{State, StateValue};
 use protocol::v1::{Session, SessionRequest, SessionResponse,
                    StateValue as V1StateValue};
+
+// SessionState used to live here, re-export it for existing users
+pub use protocol::v1::SessionState;
 use rpc::v1 as rpc1;
+use secret::SecretBuf;
 
 // Grab SessionStore from parent module
 
@@ -39,42 +43,12 @@ use super::SessionStore;
 use state::SessionStateHandle;
 
 
-// ===========================================================================
-// SessionState
-// ===========================================================================
-
-
-pub trait SessionState {
-    fn check_msg(&self, msg: Message) -> SasdResult<SessionRequest>
-    {
-        // Check request method value
-        let code = msg.as_vec()[2].as_u64().ok_or(
-            SasdErrorKind::InvalidMessage,
-        )?;
-
-        rpc1::SessionMethod::from_u64(code).chain_err(|| {
-            SasdErrorKind::InvalidMessage
-        })?;
-
-        let req = SessionRequest::from(msg).chain_err(
-            || SasdErrorKind::InvalidMessage,
-        )?;
-
-        self.check_msg_method(req)
-    }
-
-    fn check_msg_method(&self, req: SessionRequest)
-        -> SasdResult<SessionRequest>;
-}
-
-
 // ===========================================================================
 // Initialize session state
 // ===========================================================================
 
 
-// TODO: add private members to hold client session token and auth token
-// Tokens should use protected memory
+// The auth token is kept in the session store as a SecretBuf
 #[derive(Debug)]
 pub struct InitSession;
 
@@ -135,7 +109,6 @@ impl InitSession {
     }
 
     // TODO: use values from config object
-    // TODO: can secure memory be used here?
     fn make_auth_file(&self, filepath: &Path) -> File
     {
         // Create and return a temp file
@@ -150,7 +123,7 @@ impl InitSession {
             .expect("tmp file create failed")
     }
 
-    fn write_auth_token(&self, tok: String, f: &mut File)
+    fn write_auth_token(&self, tok: &SecretBuf, f: &mut File)
     {
         // Write auth token to the temp file
         f.write_all(tok.as_bytes()).expect(
@@ -196,8 +169,7 @@ impl InitSession {
         -> SasdResult<SessionResponse>
     {
         // Create auth token and store it in the session store
-        let auth_token = self.make_random_hexstr(32);
-        state.session_store().auth_token.push_str(&auth_token[..]);
+        let auth_token = SecretBuf::from(self.make_random_hexstr(32));
 
         // Create custom name
         let mut filepath = {
@@ -214,9 +186,10 @@ impl InitSession {
         let mut tmpfile = self.make_auth_file(filepath.as_path());
 
         // Write auth token to file
-        self.write_auth_token(auth_token, &mut tmpfile);
+        self.write_auth_token(&auth_token, &mut tmpfile);
 
-        // Store the temporary file in the session store
+        // Store the token and the temporary file in the session store
+        state.session_store().auth_token = auth_token;
         state.session_store().auth_file = Some(tmpfile);
 
         // Create SessionResponse w/ file location as arg
@@ -232,12 +205,14 @@ impl InitSession {
             return false;
         }
 
-        let auth_token = match args[0].as_str() {
-            Some(s) => String::from(s),
-            None => return false,
-        };
-
-        &auth_token == &session_store.auth_token
+        // An empty token means no token has been handed out yet
+        match args[0].as_str() {
+            Some(s) => {
+                !session_store.auth_token.is_empty() &&
+                    session_store.auth_token.ct_eq(s.as_bytes())
+            }
+            None => false,
+        }
     }
 }
 
@@ -281,7 +256,6 @@ impl State for InitSession {
 // ===========================================================================
 
 
-// TODO: Tokens should use protected memory
 #[derive(Debug)]
 pub struct AuthSession;
 
@@ -322,8 +296,8 @@ impl AuthSession {
     fn auth_attach(&self, state: &mut SessionStateHandle, req: SessionRequest)
         -> SasdResult<(Option<Session>, Option<Message>)>
     {
-        // Get expected token str slices
-        let auth_token = &state.session_store().auth_token[..];
+        // Get expected token
+        let auth_token = &state.session_store().auth_token;
 
         // Get auth token from request message
         let args = req.message_args();
@@ -331,7 +305,7 @@ impl AuthSession {
 
         // Compare token
         let errmsg = {
-            if auth_token != req_auth_token {
+            if !auth_token.ct_eq(req_auth_token.as_bytes()) {
                 Some("auth token doesn't match")
             } else {
                 None
@@ -263,6 +263,28 @@ pub trait State {
 }
 
 
+// Forward messages to whichever state is currently held
+impl State for StateValue {
+    fn change(&mut self, state: SessionStateHandle, msg: Message)
+        -> SasdResult<(Option<StateValue>, Option<Message>)>
+    {
+        match self {
+            &mut StateValue::Start(ref mut s) => s.change(state, msg),
+            &mut StateValue::V1(ref mut v) => v.change(state, msg),
+        }
+    }
+
+    fn dispatch(&mut self, state: &mut SessionStateHandle, msg: Message)
+        -> SasdResult<(Option<StateValue>, Option<Message>)>
+    {
+        match self {
+            &mut StateValue::Start(ref mut s) => s.dispatch(state, msg),
+            &mut StateValue::V1(ref mut v) => v.dispatch(state, msg),
+        }
+    }
+}
+
+
 // ===========================================================================
 // Start state
 // ===========================================================================
@@ -0,0 +1,301 @@
+// src/protocol/v1/apop.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Third-party imports
+
+use getrandom::getrandom;
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Keyring};
+use secret::SecretBuf;
+
+use super::auth::{AuthInfo, AuthProtocol, Phase, Role, find_key,
+                  key_template, to_hex};
+
+
+// ===========================================================================
+// Kind
+// ===========================================================================
+
+
+const SECRETS: &'static [&'static str] = &["!password"];
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    // RFC 1939: MD5 of the challenge followed by the password
+    Apop,
+
+    // RFC 2195: HMAC-MD5 of the challenge keyed by the password
+    Cram,
+}
+
+
+impl Kind {
+    fn name(&self) -> &'static str
+    {
+        match *self {
+            Kind::Apop => "apop",
+            Kind::Cram => "cram",
+        }
+    }
+
+    fn digest(&self, challenge: &[u8], password: &[u8]) -> SecretBuf
+    {
+        let digest = match *self {
+            Kind::Apop => {
+                let mut md5 = Md5::new();
+                md5.update(challenge);
+                md5.update(password);
+                to_hex(&md5.finalize())
+            }
+            Kind::Cram => {
+                let mut mac = Hmac::<Md5>::new_from_slice(password)
+                    .expect("HMAC accepts keys of any length");
+                mac.update(challenge);
+                to_hex(&mac.finalize().into_bytes())
+            }
+        };
+        SecretBuf::from(digest)
+    }
+}
+
+
+// ===========================================================================
+// Apop
+// ===========================================================================
+
+
+// APOP and CRAM-MD5 exchange a single challenge and response. The response
+// is the user name, a space and the hex digest. Any base64 encoding used on
+// the wire (eg by SASL) is left to the client.
+//
+// In the client role the server's challenge is written and the response
+// read back. In the server role a challenge is read, then the client's
+// response is written and checked against the key for the user it names.
+pub struct Apop {
+    kind: Kind,
+    role: Role,
+    attrs: Attrs,
+    template: Attrs,
+    key: Option<Attrs>,
+    user: String,
+    password: SecretBuf,
+    challenge: Option<Vec<u8>>,
+    response: Option<SecretBuf>,
+}
+
+
+pub fn new_apop() -> Box<AuthProtocol>
+{
+    Box::new(Apop::new(Kind::Apop))
+}
+
+
+pub fn new_cram() -> Box<AuthProtocol>
+{
+    Box::new(Apop::new(Kind::Cram))
+}
+
+
+impl Apop {
+    fn new(kind: Kind) -> Self
+    {
+        Apop {
+            kind: kind,
+            role: Role::Client,
+            attrs: Attrs::new(),
+            template: Attrs::new(),
+            key: None,
+            user: String::new(),
+            password: SecretBuf::new(),
+            challenge: None,
+            response: None,
+        }
+    }
+
+    fn phase_error(&self, msg: &str) -> SasdErrorKind
+    {
+        SasdErrorKind::ProtocolPhase(format!("{}: {}", self.kind.name(), msg))
+    }
+
+    fn failed(&self, msg: &str) -> SasdErrorKind
+    {
+        SasdErrorKind::ProtocolFailed(format!("{}: {}", self.kind.name(), msg))
+    }
+
+    // --------------------
+    // Client role
+    // --------------------
+    fn client_write(&mut self, challenge: &[u8]) -> SasdResult<Phase>
+    {
+        if self.response.is_some() {
+            bail!(self.phase_error("challenge already written"))
+        }
+        let digest = self.kind.digest(challenge, self.password.as_bytes());
+
+        let mut response = Vec::with_capacity(self.user.len() + 1 + 32);
+        response.extend_from_slice(self.user.as_bytes());
+        response.push(b' ');
+        response.extend_from_slice(digest.as_bytes());
+        self.response = Some(SecretBuf::from(response));
+        Ok(Phase::Continue)
+    }
+
+    fn client_read(&mut self) -> SasdResult<(SecretBuf, Phase)>
+    {
+        match self.response.take() {
+            Some(response) => Ok((response, Phase::Done)),
+            None => bail!(self.phase_error("no challenge written")),
+        }
+    }
+
+    // --------------------
+    // Server role
+    // --------------------
+    fn server_read(&mut self) -> SasdResult<(SecretBuf, Phase)>
+    {
+        if self.challenge.is_some() {
+            bail!(self.phase_error("challenge already read"))
+        }
+
+        let mut nonce = [0u8; 8];
+        getrandom(&mut nonce).map_err(|e| {
+            self.failed(&format!("unable to get random bytes: {}", e))
+        })?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let challenge = format!("<{}.{}@sasd>", to_hex(&nonce), now);
+        self.challenge = Some(challenge.clone().into_bytes());
+        Ok((SecretBuf::from(challenge), Phase::Continue))
+    }
+
+    fn server_write(&mut self, keyring: &Keyring, response: &[u8])
+        -> SasdResult<Phase>
+    {
+        let challenge = match self.challenge {
+            Some(ref c) => c.clone(),
+            None => bail!(self.phase_error("no challenge read")),
+        };
+
+        let (user, digest) = {
+            let response = str::from_utf8(response)
+                .map_err(|_| self.failed("response is not text"))?;
+            let mut parts = response.rsplitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some(digest), Some(user)) if !user.is_empty() => {
+                    (user.to_owned(), SecretBuf::from(digest))
+                }
+                _ => bail!(self.failed("malformed response")),
+            }
+        };
+
+        let mut attrs = self.attrs.clone();
+        attrs.insert("user".to_owned(), user.clone());
+        let expected = match find_key(keyring, &attrs, SECRETS) {
+            Some(key) => {
+                self.key = Some(key.public().clone());
+                let password = key.secret()["!password"].as_bytes();
+                self.kind.digest(&challenge, password)
+            }
+            None => bail!(self.failed("authentication failed")),
+        };
+        if !expected.ct_eq(digest.as_bytes()) {
+            bail!(self.failed("authentication failed"))
+        }
+
+        self.user = user;
+        Ok(Phase::Done)
+    }
+}
+
+
+impl AuthProtocol for Apop {
+    fn start(&mut self, keyring: &mut Keyring, attrs: &Attrs)
+        -> SasdResult<()>
+    {
+        self.role = Role::from_attrs(attrs)?;
+        self.template = key_template(attrs, SECRETS);
+        self.attrs = attrs.clone();
+        if self.role == Role::Server {
+            // The key is picked by the user named in the response
+            return Ok(());
+        }
+
+        let key = match find_key(keyring, attrs, SECRETS) {
+            Some(k) => k,
+            None => {
+                let errmsg = "no key w/ a password".to_owned();
+                bail!(SasdErrorKind::ProtocolNeedKey(errmsg))
+            }
+        };
+        self.user = match key.public().get("user") {
+            Some(user) => user.clone(),
+            None => bail!(self.failed("key has no user attribute")),
+        };
+        self.password = key.secret()["!password"].clone();
+        self.key = Some(key.public().clone());
+        Ok(())
+    }
+
+    fn write(&mut self, keyring: &mut Keyring, data: &[u8])
+        -> SasdResult<Phase>
+    {
+        match self.role {
+            Role::Client => self.client_write(data),
+            Role::Server => self.server_write(keyring, data),
+        }
+    }
+
+    fn read(&mut self, _keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>
+    {
+        match self.role {
+            Role::Client => self.client_read(),
+            Role::Server => self.server_read(),
+        }
+    }
+
+    fn needkey(&self) -> Attrs
+    {
+        self.template.clone()
+    }
+
+    fn key(&self) -> Option<&Attrs>
+    {
+        self.key.as_ref()
+    }
+
+    fn authinfo(&self) -> Option<AuthInfo>
+    {
+        let info = AuthInfo {
+            cuid: Some(self.user.clone()),
+            ..AuthInfo::default()
+        };
+        Some(info)
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
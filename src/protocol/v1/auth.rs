@@ -0,0 +1,335 @@
+// src/protocol/v1/auth.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// Third-party imports
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Key, Keyring, SECRET_PLACEHOLDER, is_secret_attr};
+use secret::SecretBuf;
+
+use super::apop;
+use super::chap;
+use super::otp;
+use super::p9any;
+use super::p9sk1;
+use super::plaintext;
+use super::scram;
+use super::sign;
+
+
+// ===========================================================================
+// Phase
+// ===========================================================================
+
+
+// Where a protocol conversation is after a step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    // More messages are expected
+    Continue,
+
+    // The protocol has finished successfully
+    Done,
+}
+
+
+impl Phase {
+    pub fn is_done(&self) -> bool
+    {
+        *self == Phase::Done
+    }
+}
+
+
+// ===========================================================================
+// AuthInfo
+// ===========================================================================
+
+
+// What a finished protocol established, returned by ProtocolAuthInfo
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuthInfo {
+    // Client user
+    pub cuid: Option<String>,
+
+    // Server user
+    pub suid: Option<String>,
+
+    // Capability or ticket to hand on to the other party
+    pub cap: Option<SecretBuf>,
+
+    // Secret shared w/ the other party, eg a session key
+    pub secret: Option<SecretBuf>,
+}
+
+
+// ===========================================================================
+// AuthProtocol
+// ===========================================================================
+
+
+// A protocol module runs one side of an authentication protocol on behalf of
+// a client. The client passes along whatever the other party sends with
+// ProtocolWrite, and sends on whatever it gets back from ProtocolRead, so
+// secrets never have to leave the daemon.
+//
+// Errors of kind ProtocolPhase reject a single message and the conversation
+// carries on; ProtocolFailed ends the conversation. ProtocolNeedKey means no
+// key matched, and the client is sent the attrs from needkey(). Any other
+// error ends the client's session.
+//
+// A call that returns ProtocolNeedKey may be made again once a prompter has
+// added a key, so it must leave the module as it was.
+pub trait AuthProtocol: Send {
+    // Called once w/ the attrs given to ProtocolStart, eg to pick a key
+    fn start(&mut self, keyring: &mut Keyring, attrs: &Attrs)
+        -> SasdResult<()>;
+
+    // Bytes from the other party
+    fn write(&mut self, keyring: &mut Keyring, data: &[u8])
+        -> SasdResult<Phase>;
+
+    // Bytes for the other party
+    fn read(&mut self, keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>;
+
+    // Called after start, write and read once the keyring has been let go,
+    // for slow work that doesn't need it, eg talking to an auth server.
+    // Anything needed from a key must have been copied out already.
+    fn run_unlocked(&mut self) -> SasdResult<()>
+    {
+        Ok(())
+    }
+
+    // Attrs a key would need for the module to go on, after it has returned
+    // a ProtocolNeedKey error
+    fn needkey(&self) -> Attrs
+    {
+        Attrs::new()
+    }
+
+    // Public attrs of the key the module is using, once it has picked one,
+    // so that keys marked confirm=yes can be confirmed before they are used
+    fn key(&self) -> Option<&Attrs>
+    {
+        None
+    }
+
+    // Called once the protocol is done
+    fn authinfo(&self) -> Option<AuthInfo>
+    {
+        None
+    }
+}
+
+
+// ===========================================================================
+// ProtocolRegistry
+// ===========================================================================
+
+
+// Creates a fresh module for every conversation
+pub type NewProtocol = fn() -> Box<AuthProtocol>;
+
+
+pub type ProtocolRegistryHandle = Arc<ProtocolRegistry>;
+
+
+pub fn new_protocol_registry_handle(registry: ProtocolRegistry)
+    -> ProtocolRegistryHandle
+{
+    Arc::new(registry)
+}
+
+
+// Protocol modules by the value of the proto attribute. The registry is
+// filled in before the server starts and can't be changed after that.
+#[derive(Default)]
+pub struct ProtocolRegistry {
+    protocols: BTreeMap<String, NewProtocol>,
+}
+
+
+impl ProtocolRegistry {
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    // A registry holding the modules that come w/ sasd
+    pub fn builtin() -> Self
+    {
+        let mut registry = Self::new();
+        registry.register("apop", apop::new_apop);
+        registry.register("chap", chap::new_chap);
+        registry.register("cram", apop::new_cram);
+        registry.register("hotp", otp::new_hotp);
+        registry.register("mschapv2", chap::new_mschapv2);
+        registry.register("p9any", p9any::new_protocol);
+        registry.register("p9sk1", p9sk1::new_protocol);
+        registry.register("pass", plaintext::new_protocol);
+        registry.register("scram-sha-256", scram::new_protocol);
+        registry.register("sign", sign::new_protocol);
+        registry.register("totp", otp::new_totp);
+        registry
+    }
+
+    // Returns false, leaving the registry as is, if a module is already
+    // registered under the same name
+    pub fn register(&mut self, proto: &str, new: NewProtocol) -> bool
+    {
+        if self.protocols.contains_key(proto) {
+            return false;
+        }
+        self.protocols.insert(proto.to_owned(), new);
+        true
+    }
+
+    pub fn names(&self) -> Vec<&str>
+    {
+        self.protocols.keys().map(|k| k.as_str()).collect()
+    }
+
+    // Create the module for a protocol, if there is one
+    pub fn find(&self, proto: &str) -> Option<Box<AuthProtocol>>
+    {
+        self.protocols.get(proto).map(|new| new())
+    }
+}
+
+
+// ===========================================================================
+// Role
+// ===========================================================================
+
+
+// Which side of the protocol the module runs for the client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+
+impl Role {
+    // The role attr given to ProtocolStart; client if there is none
+    pub fn from_attrs(attrs: &Attrs) -> SasdResult<Role>
+    {
+        match attrs.get("role").map(|r| r.as_str()) {
+            None | Some("client") => Ok(Role::Client),
+            Some("server") => Ok(Role::Server),
+            Some(role) => {
+                let errmsg = format!("unknown role: {}", role);
+                bail!(SasdErrorKind::ProtocolFailed(errmsg))
+            }
+        }
+    }
+}
+
+
+// ===========================================================================
+// Key lookup
+// ===========================================================================
+
+
+// ProtocolStart attrs that say how to run a protocol rather than which key
+// to use
+const PROTOCOL_PARAMS: &'static [&'static str] = &["role", "input"];
+
+
+fn is_key_attr(attr: &str) -> bool
+{
+    !is_secret_attr(attr) && !PROTOCOL_PARAMS.contains(&attr)
+}
+
+
+// Whether a key has all of the public attrs given to ProtocolStart as well
+// as the given secret attrs
+pub fn key_matches(key: &Key, attrs: &Attrs, secrets: &[&str]) -> bool
+{
+    let public = attrs
+        .iter()
+        .filter(|&(a, _)| is_key_attr(a))
+        .all(|(a, v)| key.public().get(a) == Some(v));
+    public && secrets.iter().all(|s| key.secret().contains_key(*s))
+}
+
+
+// The first key matching the ProtocolStart attrs and secrets
+pub fn find_key<'a>(keyring: &'a Keyring, attrs: &Attrs, secrets: &[&str])
+    -> Option<&'a Key>
+{
+    keyring
+        .keys()
+        .iter()
+        .find(|key| key_matches(key, attrs, secrets))
+}
+
+
+// The attrs a key found by find_key() would need, for ProtocolNeedKey
+pub fn key_template(attrs: &Attrs, secrets: &[&str]) -> Attrs
+{
+    let mut template: Attrs = attrs
+        .iter()
+        .filter(|&(a, _)| is_key_attr(a))
+        .map(|(a, v)| (a.clone(), v.clone()))
+        .collect();
+    for s in secrets {
+        template.insert(s.to_string(), SECRET_PLACEHOLDER.to_owned());
+    }
+    template
+}
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+// Lower case hex, as used by most challenge/response protocols
+pub fn to_hex(data: &[u8]) -> String
+{
+    const DIGITS: &'static [u8; 16] = b"0123456789abcdef";
+    let mut ret = String::with_capacity(data.len() * 2);
+    for b in data {
+        ret.push(DIGITS[(b >> 4) as usize] as char);
+        ret.push(DIGITS[(b & 0xf) as usize] as char);
+    }
+    ret
+}
+
+
+// Spread 56 key bits over the 8 bytes of a DES key; DES ignores the low
+// (parity) bit of each byte
+pub fn des_key(bits: &[u8]) -> [u8; 8]
+{
+    [
+        bits[0],
+        (bits[0] << 7) | (bits[1] >> 1),
+        (bits[1] << 6) | (bits[2] >> 2),
+        (bits[2] << 5) | (bits[3] >> 3),
+        (bits[3] << 4) | (bits[4] >> 4),
+        (bits[4] << 3) | (bits[5] >> 5),
+        (bits[5] << 2) | (bits[6] >> 6),
+        bits[6] << 1,
+    ]
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,469 @@
+// src/protocol/v1/chap.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+use des::Des;
+use des::cipher::{BlockEncrypt, KeyInit};
+use des::cipher::generic_array::GenericArray;
+use getrandom::getrandom;
+use md4::Md4;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Keyring};
+use secret::SecretBuf;
+
+use super::auth::{AuthInfo, AuthProtocol, Phase, Role, des_key, find_key,
+                  key_template, to_hex};
+
+
+// ===========================================================================
+// Key lookup
+// ===========================================================================
+
+
+const SECRETS: &'static [&'static str] = &["!password"];
+
+
+// The public attrs, user and password of the key matching the ProtocolStart
+// attrs. Only the client role is supported.
+fn client_key(name: &str, keyring: &Keyring, attrs: &Attrs)
+    -> SasdResult<(Attrs, String, SecretBuf)>
+{
+    if Role::from_attrs(attrs)? != Role::Client {
+        let errmsg = format!("{} protocol only has a client role", name);
+        bail!(SasdErrorKind::ProtocolFailed(errmsg))
+    }
+
+    let key = match find_key(keyring, attrs, SECRETS) {
+        Some(k) => k,
+        None => {
+            let errmsg = "no key w/ a password".to_owned();
+            bail!(SasdErrorKind::ProtocolNeedKey(errmsg))
+        }
+    };
+    let user = match key.public().get("user") {
+        Some(user) => user.clone(),
+        None => {
+            let errmsg = format!("{}: key has no user attribute", name);
+            bail!(SasdErrorKind::ProtocolFailed(errmsg))
+        }
+    };
+    let password = key.secret()["!password"].clone();
+    Ok((key.public().clone(), user, password))
+}
+
+
+fn phase_error(name: &str, msg: &str) -> SasdErrorKind
+{
+    SasdErrorKind::ProtocolPhase(format!("{}: {}", name, msg))
+}
+
+
+// ===========================================================================
+// CHAP
+// ===========================================================================
+
+
+// RFC 1994 CHAP w/ MD5. The client writes the identifier byte of the
+// Challenge packet followed by the challenge value, and reads back the
+// 16 byte response value.
+pub struct Chap {
+    template: Attrs,
+    key: Option<Attrs>,
+    user: String,
+    password: SecretBuf,
+    response: Option<SecretBuf>,
+}
+
+
+pub fn new_chap() -> Box<AuthProtocol>
+{
+    let chap = Chap {
+        template: Attrs::new(),
+        key: None,
+        user: String::new(),
+        password: SecretBuf::new(),
+        response: None,
+    };
+    Box::new(chap)
+}
+
+
+pub fn chap_response(id: u8, password: &[u8], challenge: &[u8]) -> SecretBuf
+{
+    let mut md5 = Md5::new();
+    md5.update([id]);
+    md5.update(password);
+    md5.update(challenge);
+    SecretBuf::from(md5.finalize().to_vec())
+}
+
+
+impl AuthProtocol for Chap {
+    fn start(&mut self, keyring: &mut Keyring, attrs: &Attrs)
+        -> SasdResult<()>
+    {
+        self.template = key_template(attrs, SECRETS);
+        let (key, user, password) = client_key("chap", keyring, attrs)?;
+        self.key = Some(key);
+        self.user = user;
+        self.password = password;
+        Ok(())
+    }
+
+    fn write(&mut self, _keyring: &mut Keyring, data: &[u8])
+        -> SasdResult<Phase>
+    {
+        if self.response.is_some() {
+            bail!(phase_error("chap", "challenge already written"))
+        }
+        if data.len() < 2 {
+            bail!(phase_error("chap", "challenge too short"))
+        }
+        let response =
+            chap_response(data[0], self.password.as_bytes(), &data[1..]);
+        self.response = Some(response);
+        Ok(Phase::Continue)
+    }
+
+    fn read(&mut self, _keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>
+    {
+        match self.response.take() {
+            Some(response) => Ok((response, Phase::Done)),
+            None => bail!(phase_error("chap", "no challenge written")),
+        }
+    }
+
+    fn needkey(&self) -> Attrs
+    {
+        self.template.clone()
+    }
+
+    fn key(&self) -> Option<&Attrs>
+    {
+        self.key.as_ref()
+    }
+
+    fn authinfo(&self) -> Option<AuthInfo>
+    {
+        let info = AuthInfo {
+            cuid: Some(self.user.clone()),
+            ..AuthInfo::default()
+        };
+        Some(info)
+    }
+}
+
+
+// ===========================================================================
+// MS-CHAPv2 primitives (RFC 2759, RFC 3079)
+// ===========================================================================
+
+
+const CHALLENGE_LEN: usize = 16;
+
+const NT_RESPONSE_LEN: usize = 24;
+
+// Length of "S=" followed by 40 hex digits
+const AUTH_RESPONSE_LEN: usize = 42;
+
+const MAGIC1: &'static [u8] = b"Magic server to client signing constant";
+
+const MAGIC2: &'static [u8] = b"Pad to make it do more than one iteration";
+
+const MASTER_MAGIC: &'static [u8] = b"This is the MPPE Master Key";
+
+
+// MD4 of the password in UTF-16LE
+pub fn nt_password_hash(password: &str) -> SecretBuf
+{
+    let mut unicode = SecretBuf::from(vec![0u8; password.len() * 4]);
+    let mut len = 0;
+    {
+        let buf = unicode.as_mut_bytes();
+        for c in password.encode_utf16() {
+            buf[len] = c as u8;
+            buf[len + 1] = (c >> 8) as u8;
+            len += 2;
+        }
+    }
+
+    let mut md4 = Md4::new();
+    md4.update(&unicode.as_bytes()[..len]);
+    SecretBuf::from(md4.finalize().to_vec())
+}
+
+
+fn password_hash_hash(password_hash: &[u8]) -> SecretBuf
+{
+    SecretBuf::from(Md4::digest(password_hash).to_vec())
+}
+
+
+fn challenge_hash(peer_challenge: &[u8], auth_challenge: &[u8], user: &str)
+    -> [u8; 8]
+{
+    let mut sha1 = Sha1::new();
+    sha1.update(peer_challenge);
+    sha1.update(auth_challenge);
+    sha1.update(user.as_bytes());
+    let mut ret = [0u8; 8];
+    ret.copy_from_slice(&sha1.finalize()[..8]);
+    ret
+}
+
+
+fn challenge_response(challenge: &[u8; 8], password_hash: &[u8])
+    -> [u8; NT_RESPONSE_LEN]
+{
+    let mut padded = SecretBuf::from(vec![0u8; 21]);
+    padded.as_mut_bytes()[..16].copy_from_slice(password_hash);
+
+    let mut ret = [0u8; NT_RESPONSE_LEN];
+    for (i, out) in ret.chunks_mut(8).enumerate() {
+        let key = des_key(&padded.as_bytes()[i * 7..i * 7 + 7]);
+        let des = Des::new_from_slice(&key).expect("DES key is 8 bytes");
+        let mut block = GenericArray::clone_from_slice(challenge);
+        des.encrypt_block(&mut block);
+        out.copy_from_slice(&block);
+    }
+    ret
+}
+
+
+pub fn generate_nt_response(
+    auth_challenge: &[u8], peer_challenge: &[u8], user: &str,
+    password_hash: &[u8]
+) -> [u8; NT_RESPONSE_LEN]
+{
+    let challenge = challenge_hash(peer_challenge, auth_challenge, user);
+    challenge_response(&challenge, password_hash)
+}
+
+
+// The "S=..." string the server sends back to prove it knows the password
+pub fn authenticator_response(
+    password_hash: &[u8], nt_response: &[u8], peer_challenge: &[u8],
+    auth_challenge: &[u8], user: &str
+) -> String
+{
+    let hash_hash = password_hash_hash(password_hash);
+
+    let mut sha1 = Sha1::new();
+    sha1.update(hash_hash.as_bytes());
+    sha1.update(nt_response);
+    sha1.update(MAGIC1);
+    let digest = sha1.finalize();
+
+    let challenge = challenge_hash(peer_challenge, auth_challenge, user);
+    let mut sha1 = Sha1::new();
+    sha1.update(digest);
+    sha1.update(challenge);
+    sha1.update(MAGIC2);
+
+    format!("S={}", to_hex(&sha1.finalize()).to_uppercase())
+}
+
+
+// The MPPE master key both sides derive from a successful exchange
+pub fn master_key(password_hash: &[u8], nt_response: &[u8]) -> SecretBuf
+{
+    let hash_hash = password_hash_hash(password_hash);
+
+    let mut sha1 = Sha1::new();
+    sha1.update(hash_hash.as_bytes());
+    sha1.update(nt_response);
+    sha1.update(MASTER_MAGIC);
+    SecretBuf::from(sha1.finalize()[..16].to_vec())
+}
+
+
+// ===========================================================================
+// MS-CHAPv2
+// ===========================================================================
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Challenge,
+    Response,
+    Verify,
+    Done,
+}
+
+
+// RFC 2759 MS-CHAPv2, client side:
+//
+// 1. write the 16 byte authenticator challenge from the Challenge packet
+// 2. read the 49 byte response value for the Response packet: peer
+//    challenge (16), reserved (8), NT-Response (24) and flags (1)
+// 3. write the message from the Success packet, which starts w/ the
+//    authenticator response ("S=" and 40 hex digits)
+//
+// Once the server has proved it knows the password, the MPPE master key is
+// returned as the secret by ProtocolAuthInfo.
+pub struct MsChapV2 {
+    template: Attrs,
+    key: Option<Attrs>,
+    user: String,
+    password_hash: SecretBuf,
+    step: Step,
+    nt_response: [u8; NT_RESPONSE_LEN],
+    peer_challenge: [u8; CHALLENGE_LEN],
+    auth_response: String,
+    master_key: Option<SecretBuf>,
+}
+
+
+pub fn new_mschapv2() -> Box<AuthProtocol>
+{
+    let mschap = MsChapV2 {
+        template: Attrs::new(),
+        key: None,
+        user: String::new(),
+        password_hash: SecretBuf::new(),
+        step: Step::Challenge,
+        nt_response: [0u8; NT_RESPONSE_LEN],
+        peer_challenge: [0u8; CHALLENGE_LEN],
+        auth_response: String::new(),
+        master_key: None,
+    };
+    Box::new(mschap)
+}
+
+
+impl MsChapV2 {
+    fn challenge(&mut self, auth_challenge: &[u8]) -> SasdResult<Phase>
+    {
+        if auth_challenge.len() != CHALLENGE_LEN {
+            let errmsg = format!("challenge must be {} bytes", CHALLENGE_LEN);
+            bail!(phase_error("mschapv2", &errmsg))
+        }
+
+        getrandom(&mut self.peer_challenge).map_err(|e| {
+            let errmsg = format!("unable to get random bytes: {}", e);
+            SasdErrorKind::ProtocolFailed(errmsg)
+        })?;
+        let hash = self.password_hash.as_bytes();
+        self.nt_response = generate_nt_response(
+            auth_challenge,
+            &self.peer_challenge,
+            &self.user,
+            hash,
+        );
+        self.auth_response = authenticator_response(
+            hash,
+            &self.nt_response,
+            &self.peer_challenge,
+            auth_challenge,
+            &self.user,
+        );
+        self.step = Step::Response;
+        Ok(Phase::Continue)
+    }
+
+    fn verify(&mut self, success: &[u8]) -> SasdResult<Phase>
+    {
+        // Anything after the authenticator response (eg " M=...") is the
+        // server's message to the user
+        let valid = success.len() >= AUTH_RESPONSE_LEN &&
+            success[..AUTH_RESPONSE_LEN]
+                .eq_ignore_ascii_case(self.auth_response.as_bytes());
+        if !valid {
+            let errmsg = "mschapv2: server failed to authenticate".to_owned();
+            bail!(SasdErrorKind::ProtocolFailed(errmsg))
+        }
+
+        let key = master_key(self.password_hash.as_bytes(), &self.nt_response);
+        self.master_key = Some(key);
+        self.step = Step::Done;
+        Ok(Phase::Done)
+    }
+}
+
+
+impl AuthProtocol for MsChapV2 {
+    fn start(&mut self, keyring: &mut Keyring, attrs: &Attrs)
+        -> SasdResult<()>
+    {
+        self.template = key_template(attrs, SECRETS);
+        let (key, user, password) = client_key("mschapv2", keyring, attrs)?;
+        let password = match password.as_str() {
+            Some(p) => nt_password_hash(p),
+            None => {
+                let errmsg = "mschapv2: password is not text".to_owned();
+                bail!(SasdErrorKind::ProtocolFailed(errmsg))
+            }
+        };
+        self.key = Some(key);
+        self.user = user;
+        self.password_hash = password;
+        Ok(())
+    }
+
+    fn write(&mut self, _keyring: &mut Keyring, data: &[u8])
+        -> SasdResult<Phase>
+    {
+        match self.step {
+            Step::Challenge => self.challenge(data),
+            Step::Verify => self.verify(data),
+            _ => bail!(phase_error("mschapv2", "expected a read")),
+        }
+    }
+
+    fn read(&mut self, _keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>
+    {
+        if self.step != Step::Response {
+            bail!(phase_error("mschapv2", "expected a write"))
+        }
+
+        let mut response = Vec::with_capacity(49);
+        response.extend_from_slice(&self.peer_challenge);
+        response.extend_from_slice(&[0u8; 8]);
+        response.extend_from_slice(&self.nt_response);
+        response.push(0);
+        self.step = Step::Verify;
+        Ok((SecretBuf::from(response), Phase::Continue))
+    }
+
+    fn needkey(&self) -> Attrs
+    {
+        self.template.clone()
+    }
+
+    fn key(&self) -> Option<&Attrs>
+    {
+        self.key.as_ref()
+    }
+
+    fn authinfo(&self) -> Option<AuthInfo>
+    {
+        let info = AuthInfo {
+            cuid: Some(self.user.clone()),
+            secret: self.master_key.clone(),
+            ..AuthInfo::default()
+        };
+        Some(info)
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
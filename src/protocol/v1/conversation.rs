@@ -0,0 +1,224 @@
+// src/protocol/v1/conversation.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::fmt;
+
+// Third-party imports
+
+use rmpv::Value;
+use siminau_rpc::message::Message;
+use siminau_rpc::message::request::RpcRequest;
+use siminau_rpc::message::response::RpcResponse;
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::Attrs;
+use protocol;
+use protocol::State;
+use rpc::v1 as rpc1;
+use state::SessionStateHandle;
+
+use super::{ProtocolResponse, Session, StateValue, check_protocol_msg,
+            is_locked, protocol_failure, protocol_locked, protocol_method,
+            run_module, touch};
+use super::auth::AuthProtocol;
+
+
+// ===========================================================================
+// Conversation
+// ===========================================================================
+
+
+// A session in the middle of running a protocol module. ProtocolWrite and
+// ProtocolRead are passed to the module, and ProtocolAuthInfo is refused
+// until the module is done; any other request ends the conversation and is
+// handled by Session.
+pub struct Conversation {
+    proto: String,
+    module: Box<AuthProtocol>,
+
+    // The confirm=yes key a confirmer has allowed the module to use
+    confirmed: Option<Attrs>,
+}
+
+
+impl fmt::Debug for Conversation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        f.debug_struct("Conversation")
+            .field("proto", &self.proto)
+            .finish()
+    }
+}
+
+
+// Implement From and Into traits
+impl From<Conversation> for protocol::StateValue {
+    fn from(c: Conversation) -> protocol::StateValue
+    {
+        protocol::StateValue::V1(StateValue::Conversation(c))
+    }
+}
+
+
+impl Conversation {
+    // The module must already have been started
+    pub fn new(proto: String, module: Box<AuthProtocol>) -> Self
+    {
+        Conversation::with_confirmed(proto, module, None)
+    }
+
+    // For a module whose key was confirmed when it was started
+    pub fn with_confirmed(
+        proto: String, module: Box<AuthProtocol>, confirmed: Option<Attrs>
+    ) -> Self
+    {
+        Conversation {
+            proto: proto,
+            module: module,
+            confirmed: confirmed,
+        }
+    }
+
+    pub fn from_value(v: protocol::StateValue) -> SasdResult<Self>
+    {
+        match v {
+            protocol::StateValue::V1(StateValue::Conversation(c)) => Ok(c),
+            _ => {
+                let expected = format!("StateValue::V1(Conversation)");
+                let value = format!("StateValue::{:?}", v);
+                Err(SasdErrorKind::InvalidStateValue(expected, value).into())
+            }
+        }
+    }
+
+    pub fn proto(&self) -> &str
+    {
+        &self.proto
+    }
+
+    // Pass a ProtocolWrite or ProtocolRead to the module, or refuse a
+    // ProtocolAuthInfo
+    fn step(&mut self, state: &mut SessionStateHandle, msg: Message)
+        -> SasdResult<(Option<protocol::StateValue>, Option<Message>)>
+    {
+        let req = check_protocol_msg(msg)?;
+        let result = match req.message_method() {
+            rpc1::ProtocolMethod::ProtocolWrite => {
+                let data = req.message_args()[0].as_slice().unwrap();
+                run_module(
+                    state,
+                    &mut *self.module,
+                    &mut self.confirmed,
+                    |module, keyring| {
+                        module
+                            .write(keyring, data)
+                            .map(|phase| (Value::from(phase.is_done()), phase))
+                    },
+                )
+            }
+            rpc1::ProtocolMethod::ProtocolRead => {
+                run_module(
+                    state,
+                    &mut *self.module,
+                    &mut self.confirmed,
+                    |module, keyring| {
+                        module.read(keyring).map(|(data, phase)| {
+                            let data = Value::Binary(data.as_bytes().to_vec());
+                            let done = Value::from(phase.is_done());
+                            (Value::Array(vec![data, done]), phase)
+                        })
+                    },
+                )
+            }
+            // There is nothing to report until the module is done
+            rpc1::ProtocolMethod::ProtocolAuthInfo => {
+                let resp = ProtocolResponse::new(
+                    req.message_id(),
+                    rpc1::ProtocolError::InvalidProtocolAuth,
+                    Value::from("protocol has not finished"),
+                );
+                return Ok((None, Some(resp.into())));
+            }
+            _ => unreachable!(),
+        };
+
+        let (resp, next) = match result {
+            Ok((value, phase)) => {
+                let resp = ProtocolResponse::new(
+                    req.message_id(),
+                    rpc1::ProtocolError::Nil,
+                    value,
+                );
+
+                // The module's results are kept for ProtocolAuthInfo
+                let next = if phase.is_done() {
+                    let authinfo = self.module.authinfo();
+                    Some(Session::with_authinfo(authinfo).into())
+                } else {
+                    None
+                };
+                (resp, next)
+            }
+            Err(e) => {
+                // The module is dropped if it failed, its key may not be
+                // used or the keyring was locked while it waited
+                let resp = protocol_failure(&req, e, &*self.module)?;
+                let next = match resp.error_code() {
+                    rpc1::ProtocolError::ProtocolError |
+                    rpc1::ProtocolError::ProtocolNeedConfirmation |
+                    rpc1::ProtocolError::Locked => {
+                        Some(Session::new().into())
+                    }
+                    _ => None,
+                };
+                (resp, next)
+            }
+        };
+        Ok((next, Some(resp.into())))
+    }
+}
+
+
+impl State for Conversation {
+    fn dispatch(&mut self, state: &mut SessionStateHandle, msg: Message)
+        -> SasdResult<(Option<protocol::StateValue>, Option<Message>)>
+    {
+        match protocol_method(&msg) {
+            Some(rpc1::ProtocolMethod::ProtocolWrite) |
+            Some(rpc1::ProtocolMethod::ProtocolRead) |
+            Some(rpc1::ProtocolMethod::ProtocolAuthInfo) => {}
+            _ => {
+                let mut session = Session::new();
+                let (next, reply) = session.dispatch(state, msg)?;
+                let next = next.unwrap_or_else(|| session.into());
+                return Ok((Some(next), reply));
+            }
+        }
+
+        // The module may hold key material, so it is dropped as soon as the
+        // keyring is found to be locked
+        if is_locked(state) {
+            let resp = protocol_locked(msg)?;
+            return Ok((Some(Session::new().into()), Some(resp.into())));
+        }
+        touch(state);
+
+        self.step(state, msg)
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,281 @@
+// src/protocol/v1/keygen.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+use data_encoding::{BASE32_NOPAD, BASE64};
+use getrandom::getrandom;
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Key, is_secret_attr};
+use secret::SecretBuf;
+use settings::PolicySection;
+
+use super::sign::ed25519_key;
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+fn invalid_key(msg: &str) -> SasdErrorKind
+{
+    SasdErrorKind::InvalidKey(msg.to_owned())
+}
+
+
+// Random bytes, wiped from memory once dropped
+pub fn random_bytes(len: usize) -> SasdResult<SecretBuf>
+{
+    let mut buf = SecretBuf::from(vec![0u8; len]);
+    getrandom(buf.as_mut_bytes()).map_err(|e| {
+        SasdErrorKind::Msg(format!("unable to get random bytes: {}", e))
+    })?;
+    Ok(buf)
+}
+
+
+// ===========================================================================
+// Charset
+// ===========================================================================
+
+
+// Character classes a generated password can be drawn from, by the names
+// used in the charset spec attr
+const CHAR_CLASSES: &'static [(&'static str, &'static str)] = &[
+    ("lower", "abcdefghijklmnopqrstuvwxyz"),
+    ("upper", "ABCDEFGHIJKLMNOPQRSTUVWXYZ"),
+    ("digits", "0123456789"),
+    ("symbols", "!#$%&()*+,-./:;<=>?@[]^_{|}~"),
+];
+
+
+const DEFAULT_CHARSET: &'static str = "lower,upper,digits";
+
+
+// The classes a password is drawn from. Every class is used at least once
+// in a password.
+struct Charset {
+    classes: Vec<&'static [u8]>,
+}
+
+
+impl Charset {
+    // A comma separated list of class names
+    fn parse(charset: &str) -> SasdResult<Self>
+    {
+        let mut classes: Vec<&'static [u8]> = Vec::new();
+        for name in charset.split(',').map(|n| n.trim()) {
+            let class = CHAR_CLASSES.iter().find(|&&(n, _)| n == name);
+            match class {
+                Some(&(_, chars)) => {
+                    if !classes.contains(&chars.as_bytes()) {
+                        classes.push(chars.as_bytes());
+                    }
+                }
+                None => {
+                    let errmsg = format!("unknown charset: {}", name);
+                    bail!(invalid_key(&errmsg))
+                }
+            }
+        }
+        Ok(Charset { classes: classes })
+    }
+
+    fn chars(&self) -> Vec<u8>
+    {
+        self.classes.iter().flat_map(|c| c.iter().cloned()).collect()
+    }
+
+    fn is_used(&self, password: &[u8]) -> bool
+    {
+        self.classes
+            .iter()
+            .all(|class| password.iter().any(|b| class.contains(b)))
+    }
+
+    // Draw each character uniformly from the whole charset, drawing the
+    // password again until it has every class in it
+    fn password(&self, length: usize) -> SasdResult<SecretBuf>
+    {
+        let chars = self.chars();
+
+        // Bytes at or over this would make some characters more likely
+        let limit = 256 - 256 % chars.len();
+        loop {
+            let mut password = SecretBuf::from(vec![0u8; length]);
+            let mut filled = 0;
+            while filled < length {
+                let random = random_bytes(length)?;
+                for &b in random.as_bytes() {
+                    if filled < length && (b as usize) < limit {
+                        let c = chars[b as usize % chars.len()];
+                        password.as_mut_bytes()[filled] = c;
+                        filled += 1;
+                    }
+                }
+            }
+            if self.is_used(password.as_bytes()) {
+                return Ok(password);
+            }
+        }
+    }
+}
+
+
+// ===========================================================================
+// KeySpec
+// ===========================================================================
+
+
+const DEFAULT_PASSWORD_LEN: usize = 24;
+const MAX_PASSWORD_LEN: usize = 1024;
+
+// RFC 4226 recommends a 160 bit seed
+const DEFAULT_SEED_LEN: usize = 20;
+const MIN_SEED_LEN: usize = 16;
+const MAX_SEED_LEN: usize = 64;
+
+
+// What GenerateKey is to generate, from the spec map sent w/ the request:
+// type is password, ed25519 or totp. A password can have a length and a
+// charset; a totp seed can have a length in bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeySpec {
+    Password { length: usize, charset: String },
+    Ed25519,
+    Totp { length: usize },
+}
+
+
+fn parse_length(spec: &Attrs, default: usize, min: usize, max: usize)
+    -> SasdResult<usize>
+{
+    match spec.get("length") {
+        None => Ok(default),
+        Some(l) => match l.parse() {
+            Ok(l) if l >= min && l <= max => Ok(l),
+            _ => {
+                let errmsg = format!("length must be from {} to {}", min, max);
+                bail!(invalid_key(&errmsg))
+            }
+        },
+    }
+}
+
+
+// Unless the spec says otherwise, a password is long enough for the policy
+// of its domain and has every class the policy asks for
+fn password_spec(spec: &Attrs, policy: Option<&PolicySection>)
+    -> SasdResult<KeySpec>
+{
+    let (min_length, classes) = match policy {
+        Some(p) => (p.min_length, p.classes.clone()),
+        None => (0, Vec::new()),
+    };
+    let default = DEFAULT_PASSWORD_LEN.max(min_length);
+    let length = parse_length(spec, default, 1, MAX_PASSWORD_LEN)?;
+    let charset = match spec.get("charset") {
+        Some(c) => c.clone(),
+        None => {
+            let mut charset = vec![DEFAULT_CHARSET.to_owned()];
+            charset.extend(classes);
+            charset.join(",")
+        }
+    };
+    if length < Charset::parse(&charset)?.classes.len() {
+        bail!(invalid_key("length is too short for the charset"))
+    }
+    Ok(KeySpec::Password {
+        length: length,
+        charset: charset,
+    })
+}
+
+
+impl KeySpec {
+    // The policy is that of the domain of the key to generate, if any
+    pub fn from_attrs(spec: &Attrs, policy: Option<&PolicySection>)
+        -> SasdResult<Self>
+    {
+        let (known, keyspec) = match spec.get("type").map(|t| t.as_str()) {
+            Some("password") => {
+                let keyspec = password_spec(spec, policy)?;
+                (&["type", "length", "charset"][..], keyspec)
+            }
+            Some("ed25519") => (&["type"][..], KeySpec::Ed25519),
+            Some("totp") => {
+                let (min, max) = (MIN_SEED_LEN, MAX_SEED_LEN);
+                let length = parse_length(spec, DEFAULT_SEED_LEN, min, max)?;
+                (&["type", "length"][..], KeySpec::Totp { length: length })
+            }
+            Some(t) => bail!(invalid_key(&format!("unknown key type: {}", t))),
+            None => bail!(invalid_key("spec has no type")),
+        };
+        let unknown = spec.keys().find(|a| !known.contains(&a.as_str()));
+        if let Some(attr) = unknown {
+            bail!(invalid_key(&format!("unknown spec attribute: {}", attr)))
+        }
+        Ok(keyspec)
+    }
+
+    // A new key w/ the given public attrs and generated secrets:
+    //
+    // * password: a random !password
+    // * ed25519: a base64 !secret seed and its base64 pubkey, as used by
+    //   proto=sign and proto=ssh keys; alg is set to ed25519
+    // * totp: a base32 !secret seed, as in otpauth URIs
+    pub fn generate(&self, attrs: &Attrs) -> SasdResult<Key>
+    {
+        if attrs.keys().any(|a| is_secret_attr(a)) {
+            bail!(invalid_key("secret attributes are generated"))
+        }
+
+        let mut attrs = attrs.clone();
+        let (attr, secret) = match *self {
+            KeySpec::Password {
+                length,
+                ref charset,
+            } => {
+                let password = Charset::parse(charset)?.password(length)?;
+                ("!password", password)
+            }
+            KeySpec::Ed25519 => {
+                match attrs.get("alg").map(|a| a.as_str()) {
+                    None | Some("ed25519") => {}
+                    Some(_) => bail!(invalid_key("alg must be ed25519")),
+                }
+                let seed = BASE64.encode(random_bytes(32)?.as_bytes());
+                let seed = SecretBuf::from(seed);
+                let public = ed25519_key(&seed)?.verifying_key();
+                let pubkey = BASE64.encode(public.as_bytes());
+                attrs.insert("alg".to_owned(), "ed25519".to_owned());
+                attrs.insert("pubkey".to_owned(), pubkey);
+                ("!secret", seed)
+            }
+            KeySpec::Totp { length } => {
+                let seed = random_bytes(length)?;
+                let seed = BASE32_NOPAD.encode(seed.as_bytes());
+                ("!secret", SecretBuf::from(seed))
+            }
+        };
+        Ok(Key::with_secret(attrs, attr, secret))
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -12,8 +12,10 @@
 
 // Third-party imports
 
-use siminau_rpc::message::Message;
-use siminau_rpc::message::request::RequestMessage;
+use rmpv::{Utf8String, Value};
+use siminau_rpc::message::{CodeConvert, Message, MessageType, RpcMessage};
+use siminau_rpc::message::notify::NotificationMessage;
+use siminau_rpc::message::request::{RequestMessage, RpcRequest};
 use siminau_rpc::message::response::ResponseMessage;
 
 // Local imports
@@ -21,20 +23,44 @@ use siminau_rpc::message::response::ResponseMessage;
 #[cfg(windows)]
 pub use os::windows::protocol::v1::{AuthSession, InitSession};
 
-use error::SasdResult;
+use error::{SasdError, SasdErrorKind, SasdResult, SasdResultExt};
+use keyring::{Attrs, Key, Keyring, is_secret_attr};
 use protocol;
 use protocol::State;
+use query::Query;
 use rpc::v1 as rpc1;
+use secret::SecretBuf;
+use settings::PolicySection;
 
 use super::SessionStateHandle;
 
+use self::auth::{AuthInfo, AuthProtocol};
+use self::keygen::KeySpec;
+use self::prompt::{PromptKind, Prompter};
+
+// Re-exports
+
+pub use self::conversation::Conversation;
+
 
 // ===========================================================================
 // Modules
 // ===========================================================================
 
 
+pub mod apop;
+pub mod auth;
+pub mod chap;
+pub mod conversation;
+pub mod keygen;
+pub mod otp;
+pub mod p9any;
+pub mod p9sk1;
 pub mod plaintext;
+pub mod policy;
+pub mod prompt;
+pub mod scram;
+pub mod sign;
 
 
 // ===========================================================================
@@ -48,9 +74,136 @@ pub type SessionRequest = RequestMessage<rpc1::SessionMethod>;
 pub type SessionResponse = ResponseMessage<rpc1::SessionError>;
 
 
+pub type ProtocolRequest = RequestMessage<rpc1::ProtocolMethod>;
+
+
+pub type ProtocolResponse = ResponseMessage<rpc1::ProtocolError>;
+
+
+pub type ProtocolInfo = NotificationMessage<rpc1::ProtocolNotice>;
+
+
 // pub type Info = NotificationMessage<rpc::Notice>;
 
 
+// ===========================================================================
+// Message helpers
+// ===========================================================================
+
+
+// Convert a map of string attr=value pairs into Attrs. Any non-string attr or
+// value, or an empty map, is an invalid message.
+pub fn attrs_from_value(value: &Value) -> SasdResult<Attrs>
+{
+    let map = value.as_map().ok_or(SasdErrorKind::InvalidMessage)?;
+    if map.is_empty() {
+        bail!(SasdErrorKind::InvalidMessage)
+    }
+
+    let mut attrs = Attrs::new();
+    for &(ref k, ref v) in map {
+        let k = k.as_str().ok_or(SasdErrorKind::InvalidMessage)?;
+        let v = v.as_str().ok_or(SasdErrorKind::InvalidMessage)?;
+        attrs.insert(k.to_owned(), v.to_owned());
+    }
+    Ok(attrs)
+}
+
+
+// Same as attrs_from_value, except that the values of secret attrs are
+// copied straight into secret buffers and never held in a plain String
+pub fn key_from_value(value: &Value) -> SasdResult<Key>
+{
+    let map = value.as_map().ok_or(SasdErrorKind::InvalidMessage)?;
+    if map.is_empty() {
+        bail!(SasdErrorKind::InvalidMessage)
+    }
+
+    let mut public = Attrs::new();
+    let mut secret = Vec::new();
+    for &(ref k, ref v) in map {
+        let k = k.as_str().ok_or(SasdErrorKind::InvalidMessage)?;
+        let v = v.as_str().ok_or(SasdErrorKind::InvalidMessage)?;
+        if is_secret_attr(k) {
+            secret.push((k, SecretBuf::from(v)));
+        } else {
+            public.insert(k.to_owned(), v.to_owned());
+        }
+    }
+    let mut key = Key::new(public);
+    for (k, v) in secret {
+        key.set_secret(k, v);
+    }
+    Ok(key)
+}
+
+
+pub fn attrs_to_value(attrs: &Attrs) -> Value
+{
+    let map = attrs
+        .iter()
+        .map(|(k, v)| {
+            (
+                Value::String(Utf8String::from(k.as_str())),
+                Value::String(Utf8String::from(v.as_str())),
+            )
+        })
+        .collect();
+    Value::Map(map)
+}
+
+
+// Convert the results of a finished protocol into a map. Only the fields
+// the module filled in are included; cap and secret are bytes.
+pub fn authinfo_to_value(info: &AuthInfo) -> Value
+{
+    let mut map = Vec::new();
+    if let Some(ref cuid) = info.cuid {
+        map.push((Value::from("cuid"), Value::from(cuid.as_str())));
+    }
+    if let Some(ref suid) = info.suid {
+        map.push((Value::from("suid"), Value::from(suid.as_str())));
+    }
+    if let Some(ref cap) = info.cap {
+        map.push((Value::from("cap"), Value::Binary(cap.as_bytes().to_vec())));
+    }
+    if let Some(ref secret) = info.secret {
+        let secret = Value::Binary(secret.as_bytes().to_vec());
+        map.push((Value::from("secret"), secret));
+    }
+    Value::Map(map)
+}
+
+
+// ===========================================================================
+// SessionState
+// ===========================================================================
+
+
+pub trait SessionState {
+    fn check_msg(&self, msg: Message) -> SasdResult<SessionRequest>
+    {
+        // Check request method value
+        let code = msg.as_vec()[2].as_u64().ok_or(
+            SasdErrorKind::InvalidMessage,
+        )?;
+
+        rpc1::SessionMethod::from_u64(code).chain_err(|| {
+            SasdErrorKind::InvalidMessage
+        })?;
+
+        let req = SessionRequest::from(msg).chain_err(
+            || SasdErrorKind::InvalidMessage,
+        )?;
+
+        self.check_msg_method(req)
+    }
+
+    fn check_msg_method(&self, req: SessionRequest)
+        -> SasdResult<SessionRequest>;
+}
+
+
 // ===========================================================================
 // StateType
 // ===========================================================================
@@ -62,6 +215,7 @@ pub enum StateValue {
     InitSession(InitSession),
     AuthSession(AuthSession),
     Session(Session),
+    Conversation(Conversation),
 }
 
 
@@ -69,6 +223,7 @@ pub enum StateValue {
 #[derive(Debug)]
 pub enum StateValue {
     Session(Session),
+    Conversation(Conversation),
 }
 
 
@@ -76,17 +231,18 @@ impl StateValue {
     // --------------------
     // is methods
     // --------------------
-    #[cfg(unix)]
     pub fn is_session(&self) -> bool
     {
-        true
+        match self {
+            &StateValue::Session(_) => true,
+            _ => false,
+        }
     }
 
-    #[cfg(windows)]
-    pub fn is_session(&self) -> bool
+    pub fn is_conversation(&self) -> bool
     {
         match self {
-            &StateValue::Session(_) => true,
+            &StateValue::Conversation(_) => true,
             _ => false,
         }
     }
@@ -112,18 +268,18 @@ impl StateValue {
     // --------------------
     // as methods
     // --------------------
-    #[cfg(unix)]
     pub fn as_session(&self) -> Option<&Session>
     {
-        let &StateValue::Session(ref s) = self;
-        Some(s)
+        match self {
+            &StateValue::Session(ref s) => Some(s),
+            _ => None,
+        }
     }
 
-    #[cfg(windows)]
-    pub fn as_session(&self) -> Option<&Session>
+    pub fn as_conversation(&self) -> Option<&Conversation>
     {
         match self {
-            &StateValue::Session(ref s) => Some(s),
+            &StateValue::Conversation(ref c) => Some(c),
             _ => None,
         }
     }
@@ -149,18 +305,18 @@ impl StateValue {
     // --------------------
     // to methods
     // --------------------
-    #[cfg(unix)]
     pub fn to_session(self) -> Option<Session>
     {
-        let StateValue::Session(s) = self;
-        Some(s)
+        match self {
+            StateValue::Session(s) => Some(s),
+            _ => None,
+        }
     }
 
-    #[cfg(windows)]
-    pub fn to_session(self) -> Option<Session>
+    pub fn to_conversation(self) -> Option<Conversation>
     {
         match self {
-            StateValue::Session(s) => Some(s),
+            StateValue::Conversation(c) => Some(c),
             _ => None,
         }
     }
@@ -185,13 +341,65 @@ impl StateValue {
 }
 
 
+// Forward messages to whichever v1 state is currently held
+#[cfg(unix)]
+impl State for StateValue {
+    fn change(&mut self, state: SessionStateHandle, msg: Message)
+        -> SasdResult<(Option<protocol::StateValue>, Option<Message>)>
+    {
+        match self {
+            &mut StateValue::Session(ref mut s) => s.change(state, msg),
+            &mut StateValue::Conversation(ref mut c) => c.change(state, msg),
+        }
+    }
+
+    fn dispatch(&mut self, state: &mut SessionStateHandle, msg: Message)
+        -> SasdResult<(Option<protocol::StateValue>, Option<Message>)>
+    {
+        match self {
+            &mut StateValue::Session(ref mut s) => s.dispatch(state, msg),
+            &mut StateValue::Conversation(ref mut c) => c.dispatch(state, msg),
+        }
+    }
+}
+
+
+#[cfg(windows)]
+impl State for StateValue {
+    fn change(&mut self, state: SessionStateHandle, msg: Message)
+        -> SasdResult<(Option<protocol::StateValue>, Option<Message>)>
+    {
+        match self {
+            &mut StateValue::InitSession(ref mut s) => s.change(state, msg),
+            &mut StateValue::AuthSession(ref mut s) => s.change(state, msg),
+            &mut StateValue::Session(ref mut s) => s.change(state, msg),
+            &mut StateValue::Conversation(ref mut c) => c.change(state, msg),
+        }
+    }
+
+    fn dispatch(&mut self, state: &mut SessionStateHandle, msg: Message)
+        -> SasdResult<(Option<protocol::StateValue>, Option<Message>)>
+    {
+        match self {
+            &mut StateValue::InitSession(ref mut s) => s.dispatch(state, msg),
+            &mut StateValue::AuthSession(ref mut s) => s.dispatch(state, msg),
+            &mut StateValue::Session(ref mut s) => s.dispatch(state, msg),
+            &mut StateValue::Conversation(ref mut c) => c.dispatch(state, msg),
+        }
+    }
+}
+
+
 // ===========================================================================
 // Session
 // ===========================================================================
 
 
 #[derive(Debug)]
-pub struct Session;
+pub struct Session {
+    // Results of the last protocol to finish, for ProtocolAuthInfo
+    authinfo: Option<AuthInfo>,
+}
 
 
 // Implement From and Into traits
@@ -203,19 +411,971 @@ impl From<Session> for protocol::StateValue {
 }
 
 
+impl SessionState for Session {
+    fn check_msg_method(&self, req: SessionRequest)
+        -> SasdResult<SessionRequest>
+    {
+        {
+            let args = req.message_args();
+            let numargs = args.len();
+            match req.message_method() {
+                rpc1::SessionMethod::KeyList => {
+                    let query = args.get(0).map(|q| q.as_str().is_some());
+                    if numargs > 1 || query == Some(false) {
+                        bail!(SasdErrorKind::InvalidMessage)
+                    }
+                }
+                rpc1::SessionMethod::CreateKey => {
+                    if numargs != 1 {
+                        bail!(SasdErrorKind::InvalidMessage)
+                    }
+                    key_from_value(&args[0])?;
+                }
+                rpc1::SessionMethod::GenerateKey => {
+                    if numargs != 2 {
+                        bail!(SasdErrorKind::InvalidMessage)
+                    }
+                    key_from_value(&args[0])?;
+                    attrs_from_value(&args[1])?;
+                }
+                rpc1::SessionMethod::UpdateKey => {
+                    if numargs != 2 {
+                        bail!(SasdErrorKind::InvalidMessage)
+                    }
+                    attrs_from_value(&args[0])?;
+                    key_from_value(&args[1])?;
+                }
+                rpc1::SessionMethod::DeleteKey => {
+                    if numargs < 1 || numargs > 2 {
+                        bail!(SasdErrorKind::InvalidMessage)
+                    }
+                    // A string is a query, which must be valid UTF-8
+                    if args[0].is_str() {
+                        if args[0].as_str().is_none() {
+                            bail!(SasdErrorKind::InvalidMessage)
+                        }
+                    } else {
+                        key_from_value(&args[0])?;
+                    }
+                    if numargs == 2 && !args[1].is_bool() {
+                        bail!(SasdErrorKind::InvalidMessage)
+                    }
+                }
+                rpc1::SessionMethod::Unlock => {
+                    // A msgpack string need not be valid UTF-8
+                    if numargs < 1 || numargs > 2 ||
+                        args[0].as_str().is_none()
+                    {
+                        bail!(SasdErrorKind::InvalidMessage)
+                    }
+                    if numargs == 2 && !args[1].is_bool() {
+                        bail!(SasdErrorKind::InvalidMessage)
+                    }
+                }
+                rpc1::SessionMethod::Lock => {
+                    if numargs != 0 {
+                        bail!(SasdErrorKind::InvalidMessage)
+                    }
+                }
+                #[cfg(windows)]
+                _ => bail!(SasdErrorKind::UnexpectedMessage),
+            }
+        }
+        Ok(req)
+    }
+}
+
+
 impl Session {
     pub fn new() -> Self
     {
-        Session
+        Session { authinfo: None }
+    }
+
+    pub fn with_authinfo(authinfo: Option<AuthInfo>) -> Self
+    {
+        Session { authinfo: authinfo }
+    }
+
+    pub fn from_value(v: protocol::StateValue) -> SasdResult<Self>
+    {
+        match v {
+            protocol::StateValue::V1(StateValue::Session(s)) => Ok(s),
+            _ => {
+                let expected = format!("StateValue::V1(Session)");
+                let value = format!("StateValue::{:?}", v);
+                Err(SasdErrorKind::InvalidStateValue(expected, value).into())
+            }
+        }
+    }
+
+    fn unlock(&self, state: &mut SessionStateHandle, req: SessionRequest)
+        -> SasdResult<SessionResponse>
+    {
+        let result = {
+            let args = req.message_args();
+            let passphrase = args[0].as_str().unwrap();
+            let create = args.get(1).and_then(|v| v.as_bool()).unwrap_or(false);
+            let mut keyring = state.keyring().write().expect(
+                "failed to write keyring",
+            );
+            keyring.unlock(passphrase.as_bytes(), create)
+        };
+
+        // Failing to unlock leaves the keyring locked, so the session can
+        // carry on w/ another try
+        let (err, result) = match result {
+            Ok(()) => (rpc1::SessionError::Nil, Value::Nil),
+            Err(e) => {
+                let err = match e.kind() {
+                    &SasdErrorKind::KeyringLoadError(_) => {
+                        rpc1::SessionError::InvalidPassphrase
+                    }
+                    _ => rpc1::SessionError::KeyringError,
+                };
+                (err, Value::from(e.to_string()))
+            }
+        };
+        Ok(SessionResponse::new(req.message_id(), err, result))
+    }
+
+    fn lock(&self, state: &mut SessionStateHandle, req: SessionRequest)
+        -> SasdResult<SessionResponse>
+    {
+        let locked = {
+            let mut keyring = state.keyring().write().expect(
+                "failed to write keyring",
+            );
+            keyring.lock()
+        };
+        let (err, result) = if locked {
+            state.prompts().keyring_locked();
+            (rpc1::SessionError::Nil, Value::Nil)
+        } else {
+            let errmsg = "keyring has no file to unlock it from";
+            (rpc1::SessionError::NotLockable, Value::from(errmsg))
+        };
+        Ok(SessionResponse::new(req.message_id(), err, result))
+    }
+
+    fn key_list(&self, state: &mut SessionStateHandle, req: SessionRequest)
+        -> SasdResult<SessionResponse>
+    {
+        let query = match req.message_args().get(0) {
+            Some(q) => {
+                match Query::parse(q.as_str().unwrap()) {
+                    Ok(query) => query,
+                    Err(e) => return Ok(invalid_query(&req, e)),
+                }
+            }
+            None => Query::default(),
+        };
+
+        let keys = {
+            let keyring = state.keyring().read().expect(
+                "failed to read keyring",
+            );
+            keyring
+                .find(&query)
+                .iter()
+                .map(|k| attrs_to_value(&k.masked()))
+                .collect()
+        };
+
+        let resp = SessionResponse::new(
+            req.message_id(),
+            rpc1::SessionError::Nil,
+            Value::Array(keys),
+        );
+        Ok(resp)
+    }
+
+    fn create_key(&self, state: &mut SessionStateHandle, req: SessionRequest)
+        -> SasdResult<SessionResponse>
+    {
+        // A key needs at least one public attribute to be told apart from
+        // other keys
+        let mut key = key_from_value(&req.message_args()[0])?;
+        if let Err(e) = sign::add_pubkey(&mut key) {
+            return Ok(invalid_key(&req, e));
+        }
+        let policy = password_policy(state, key.public());
+        if let Err(e) = policy::check_secrets(key.secret(), policy.as_ref()) {
+            return refused_key(&req, e);
+        }
+        if key.public().is_empty() {
+            bail!(SasdErrorKind::InvalidMessage)
+        }
+
+        let resp = if add_key(state, key)? {
+            SessionResponse::new(
+                req.message_id(),
+                rpc1::SessionError::Nil,
+                Value::Nil,
+            )
+        } else {
+            key_exists(&req)
+        };
+        Ok(resp)
+    }
+
+    // Same as CreateKey, except that the secrets are made up here and only
+    // the public attrs are sent back
+    fn generate_key(&self, state: &mut SessionStateHandle,
+                    req: SessionRequest)
+        -> SasdResult<SessionResponse>
+    {
+        // Any secret attrs given are only kept as placeholders, which the
+        // spec refuses
+        let attrs = key_from_value(&req.message_args()[0])?.masked();
+        let spec = attrs_from_value(&req.message_args()[1])?;
+        let policy = password_policy(state, &attrs);
+        let generated = KeySpec::from_attrs(&spec, policy.as_ref())
+            .and_then(|s| s.generate(&attrs));
+        let key = match generated {
+            Ok(key) => key,
+            Err(e) => return refused_key(&req, e),
+        };
+
+        // The spec may ask for a password the policy would refuse
+        if let Some(password) = key.secret().get("!password") {
+            if let Err(e) = policy::check_password(password, policy.as_ref()) {
+                return refused_key(&req, e);
+            }
+        }
+
+        let public = attrs_to_value(key.public());
+        let resp = if add_key(state, key)? {
+            SessionResponse::new(
+                req.message_id(),
+                rpc1::SessionError::Nil,
+                public,
+            )
+        } else {
+            key_exists(&req)
+        };
+        Ok(resp)
+    }
+
+    fn update_key(&self, state: &mut SessionStateHandle, req: SessionRequest)
+        -> SasdResult<SessionResponse>
+    {
+        let public = attrs_from_value(&req.message_args()[0])?;
+        let update = key_from_value(&req.message_args()[1])?;
+        let policy = password_policy(state, &public);
+
+        let mut keyring = state.keyring().write().expect(
+            "failed to write keyring",
+        );
+        let found = keyring.keys().iter().find(|k| k.public() == &public);
+        let mut key = match found {
+            Some(k) => k.clone(),
+            None => return Ok(key_not_found(&req)),
+        };
+        if let Err(e) = update_secrets(&mut key, update, policy.as_ref()) {
+            return refused_key(&req, e);
+        }
+        keyring.update(&public, key)?;
+
+        let resp = SessionResponse::new(
+            req.message_id(),
+            rpc1::SessionError::Nil,
+            Value::Nil,
+        );
+        Ok(resp)
+    }
+
+    fn delete_key(&self, state: &mut SessionStateHandle, req: SessionRequest)
+        -> SasdResult<SessionResponse>
+    {
+        let single = match req.message_args().get(1) {
+            Some(v) => v.as_bool().unwrap(),
+            None => false,
+        };
+
+        // A map names a single key exactly, a string is a query that may
+        // match several keys
+        let query = match req.message_args()[0].as_str() {
+            Some(q) => {
+                match Query::parse(q) {
+                    // Refuse to wipe the whole keyring by accident
+                    Ok(ref query) if query.is_empty() => {
+                        let err = SasdErrorKind::InvalidQuery(
+                            "empty query".to_owned(),
+                        );
+                        return Ok(invalid_query(&req, err.into()));
+                    }
+                    Ok(query) => Some(query),
+                    Err(e) => return Ok(invalid_query(&req, e)),
+                }
+            }
+            None => None,
+        };
+
+        let removed = {
+            let mut keyring = state.keyring().write().expect(
+                "failed to write keyring",
+            );
+            let old = keyring.keys().to_vec();
+            let removed = match query {
+                Some(ref query) => {
+                    if single && keyring.find(query).len() > 1 {
+                        let resp = SessionResponse::new(
+                            req.message_id(),
+                            rpc1::SessionError::MultipleKeys,
+                            Value::from("query matches more than one key"),
+                        );
+                        return Ok(resp);
+                    }
+                    keyring.remove_matching(query)
+                }
+                None => {
+                    let key = key_from_value(&req.message_args()[0])?;
+                    if keyring.remove(&key) { 1 } else { 0 }
+                }
+            };
+            if removed > 0 {
+                keyring.save_or_restore(old)?;
+            }
+            removed
+        };
+
+        let resp = if removed > 0 {
+            SessionResponse::new(
+                req.message_id(),
+                rpc1::SessionError::Nil,
+                Value::from(removed as u64),
+            )
+        } else {
+            key_not_found(&req)
+        };
+        Ok(resp)
+    }
+
+    // Outside of a conversation, a protocol can only be started or asked
+    // about the results of the last one
+    fn protocol_request(&self, state: &mut SessionStateHandle, msg: Message)
+        -> SasdResult<(Option<protocol::StateValue>, Option<Message>)>
+    {
+        let req = check_protocol_msg(msg)?;
+        match req.message_method() {
+            rpc1::ProtocolMethod::ProtocolStart => {
+                self.protocol_start(state, req)
+            }
+            rpc1::ProtocolMethod::ProtocolAuthInfo => {
+                Ok((None, Some(self.auth_info(&req).into())))
+            }
+            rpc1::ProtocolMethod::ProtocolNeedKey => {
+                Ok((None, Some(self.need_key(state, &req).into())))
+            }
+            rpc1::ProtocolMethod::ProtocolNeedKeyDone => {
+                Ok((None, Some(self.need_key_done(state, &req).into())))
+            }
+            rpc1::ProtocolMethod::ProtocolConfirm => {
+                Ok((None, Some(self.confirm(state, &req).into())))
+            }
+            _ => bail!(SasdErrorKind::UnexpectedMessage),
+        }
+    }
+
+    // Start a conversation w/ the protocol module named by the proto attr
+    fn protocol_start(
+        &self, state: &mut SessionStateHandle, req: ProtocolRequest
+    ) -> SasdResult<(Option<protocol::StateValue>, Option<Message>)>
+    {
+        let attrs = attrs_from_value(&req.message_args()[0])?;
+        let proto = attrs["proto"].clone();
+        let mut module = match state.protocols().find(&proto) {
+            Some(m) => m,
+            None => {
+                let resp = ProtocolResponse::new(
+                    req.message_id(),
+                    rpc1::ProtocolError::UnknownProtocol,
+                    Value::from(format!("unknown protocol: {}", proto)),
+                );
+                return Ok((None, Some(resp.into())));
+            }
+        };
+
+        let mut confirmed = None;
+        let result = run_module(
+            state,
+            &mut *module,
+            &mut confirmed,
+            |module, keyring| module.start(keyring, &attrs),
+        );
+
+        match result {
+            Ok(()) => {
+                let resp = ProtocolResponse::new(
+                    req.message_id(),
+                    rpc1::ProtocolError::Nil,
+                    Value::Nil,
+                );
+                let next =
+                    Conversation::with_confirmed(proto, module, confirmed);
+                Ok((Some(next.into()), Some(resp.into())))
+            }
+            Err(e) => {
+                let resp = protocol_failure(&req, e, &*module)?;
+                Ok((None, Some(resp.into())))
+            }
+        }
+    }
+
+    fn auth_info(&self, req: &ProtocolRequest) -> ProtocolResponse
+    {
+        let (err, result) = match self.authinfo {
+            Some(ref info) => {
+                (rpc1::ProtocolError::Nil, authinfo_to_value(info))
+            }
+            None => {
+                (
+                    rpc1::ProtocolError::InvalidProtocolAuth,
+                    Value::from("no protocol has finished"),
+                )
+            }
+        };
+        ProtocolResponse::new(req.message_id(), err, result)
+    }
+
+    // Register the session as a prompter
+    fn need_key(&self, state: &mut SessionStateHandle, req: &ProtocolRequest)
+        -> ProtocolResponse
+    {
+        let (err, result) = match state.notifier().cloned() {
+            Some(_) if state.prompter().is_some() => {
+                (rpc1::ProtocolError::Nil, Value::Nil)
+            }
+            Some(notifier) => {
+                let prompts = state.prompts().clone();
+                let kind = PromptKind::NeedKey;
+                state.set_prompter(Prompter::new(prompts, kind, notifier));
+                (rpc1::ProtocolError::Nil, Value::Nil)
+            }
+            None => {
+                (
+                    rpc1::ProtocolError::InvalidProtocolMessage,
+                    Value::from("session can't be sent notifications"),
+                )
+            }
+        };
+        ProtocolResponse::new(req.message_id(), err, result)
+    }
+
+    fn need_key_done(
+        &self, state: &mut SessionStateHandle, req: &ProtocolRequest
+    ) -> ProtocolResponse
+    {
+        let tag = req.message_args()[0].as_u64().unwrap();
+        let errmsg = if state.prompter().is_none() {
+            Some("session is not a prompter")
+        } else if !state.prompts().needkey_done(tag) {
+            Some("no conversation is waiting on the tag")
+        } else {
+            None
+        };
+
+        let (err, result) = match errmsg {
+            Some(msg) => {
+                (rpc1::ProtocolError::InvalidProtocolMessage, Value::from(msg))
+            }
+            None => (rpc1::ProtocolError::Nil, Value::Nil),
+        };
+        ProtocolResponse::new(req.message_id(), err, result)
+    }
+
+    // Register the session as a confirmer, or pass on a confirmer's answer
+    fn confirm(&self, state: &mut SessionStateHandle, req: &ProtocolRequest)
+        -> ProtocolResponse
+    {
+        let args = req.message_args();
+        let errmsg = if args.is_empty() {
+            match state.notifier().cloned() {
+                Some(_) if state.confirmer().is_some() => None,
+                Some(notifier) => {
+                    let prompts = state.prompts().clone();
+                    let kind = PromptKind::Confirm;
+                    state.set_confirmer(Prompter::new(prompts, kind, notifier));
+                    None
+                }
+                None => Some("session can't be sent notifications"),
+            }
+        } else {
+            // The answer has already been checked by check_protocol_msg
+            let answer = attrs_from_value(&args[0]).unwrap();
+            let tag = answer["tag"].parse::<u64>().unwrap();
+            let yes = answer["answer"] == "yes";
+            if state.confirmer().is_none() {
+                Some("session is not a confirmer")
+            } else if !state.prompts().confirm_done(tag, yes) {
+                Some("no conversation is waiting on the tag")
+            } else {
+                None
+            }
+        };
+
+        let (err, result) = match errmsg {
+            Some(msg) => {
+                (rpc1::ProtocolError::InvalidProtocolMessage, Value::from(msg))
+            }
+            None => (rpc1::ProtocolError::Nil, Value::Nil),
+        };
+        ProtocolResponse::new(req.message_id(), err, result)
+    }
+}
+
+
+// Run a protocol module w/ the keyring, then make sure the key it picked,
+// if any, may be used before the module does any work w/o the keyring. The
+// confirmed key is kept in confirmed so that a conversation only asks about
+// its key once.
+fn run_module<T, F>(
+    state: &mut SessionStateHandle, module: &mut AuthProtocol,
+    confirmed: &mut Option<Attrs>, run: F
+) -> SasdResult<T>
+where
+    F: FnMut(&mut AuthProtocol, &mut Keyring) -> SasdResult<T>,
+{
+    let result = run_with_keyring(state, module, run)?;
+    confirm_key(state, module, confirmed)?;
+    module.run_unlocked()?;
+    Ok(result)
+}
+
+
+// Run a protocol module w/ the keyring. If the module needs a key and there
+// is a prompter to ask for it, the module is held until a prompter answers
+// or the needkey timeout passes, then run once more. The module's
+// ProtocolNeedKey error is returned if no prompter answers in time.
+fn run_with_keyring<T, F>(
+    state: &mut SessionStateHandle, module: &mut AuthProtocol, mut run: F
+) -> SasdResult<T>
+where
+    F: FnMut(&mut AuthProtocol, &mut Keyring) -> SasdResult<T>,
+{
+    let result = {
+        let mut keyring = state.keyring().write().expect(
+            "failed to write keyring",
+        );
+        run(module, &mut keyring)
+    };
+
+    match result {
+        Err(ref e) if is_need_key(e) => {}
+        _ => return result,
+    }
+
+    // The keyring is not held while waiting so that the prompter can add
+    // the key
+    let timeout = {
+        let config = state.server_settings().read().expect(
+            "failed to read server settings",
+        );
+        config.protocol().needkey_timeout
+    };
+    let template = module.needkey();
+    let answered =
+        state.prompts().needkey(&template, timeout, state.prompter());
+    if is_locked(state) {
+        bail!(SasdErrorKind::KeyringLocked)
+    }
+    if !answered {
+        return result;
+    }
+
+    let mut keyring = state.keyring().write().expect(
+        "failed to write keyring",
+    );
+    run(module, &mut keyring)
+}
+
+
+// If the module is using a key marked confirm=yes, ask the confirmers
+// whether the client may use it. A ProtocolNeedConfirmation error is
+// returned unless one says yes before the confirm timeout passes.
+fn confirm_key(
+    state: &mut SessionStateHandle, module: &AuthProtocol,
+    confirmed: &mut Option<Attrs>
+) -> SasdResult<()>
+{
+    let key = match module.key() {
+        Some(k) if k.get("confirm").map(|v| v == "yes") == Some(true) => k,
+        _ => return Ok(()),
+    };
+    if confirmed.as_ref() == Some(key) {
+        return Ok(());
+    }
+
+    let (timeout, remember) = {
+        let config = state.server_settings().read().expect(
+            "failed to read server settings",
+        );
+        let protocol = config.protocol();
+        (protocol.confirm_timeout, protocol.confirm_remember)
+    };
+    let peer = peer_attrs(state);
+    let yes = {
+        let prompts = state.prompts();
+        prompts.confirm(key, &peer, timeout, remember, state.confirmer())
+    };
+    if is_locked(state) {
+        bail!(SasdErrorKind::KeyringLocked)
+    }
+    if !yes {
+        let errmsg = "use of the key was not confirmed".to_owned();
+        bail!(SasdErrorKind::ProtocolNeedConfirmation(errmsg))
+    }
+    *confirmed = Some(key.clone());
+    Ok(())
+}
+
+
+// Attrs identifying the client, for confirmers
+#[cfg(unix)]
+fn peer_attrs(state: &SessionStateHandle) -> Attrs
+{
+    state.peer().map(|p| p.attrs()).unwrap_or_default()
+}
+
+
+#[cfg(windows)]
+fn peer_attrs(_state: &SessionStateHandle) -> Attrs
+{
+    Attrs::new()
+}
+
+
+fn is_need_key(err: &SasdError) -> bool
+{
+    match err.kind() {
+        &SasdErrorKind::ProtocolNeedKey(_) => true,
+        _ => false,
+    }
+}
+
+
+fn is_locked(state: &mut SessionStateHandle) -> bool
+{
+    let keyring = state.keyring().read().expect("failed to read keyring");
+    keyring.is_locked()
+}
+
+
+// Any client request keeps the keyring from being locked for being idle
+fn touch(state: &mut SessionStateHandle)
+{
+    let mut keyring = state.keyring().write().expect(
+        "failed to write keyring",
+    );
+    keyring.touch();
+}
+
+
+fn protocol_method(msg: &Message) -> Option<rpc1::ProtocolMethod>
+{
+    msg.as_vec()
+        .get(2)
+        .and_then(|v| v.as_u64())
+        .and_then(|code| rpc1::ProtocolMethod::from_u64(code).ok())
+}
+
+
+// Convert a message into a ProtocolRequest, checking its arguments
+fn check_protocol_msg(msg: Message) -> SasdResult<ProtocolRequest>
+{
+    let req = ProtocolRequest::from(msg).chain_err(
+        || SasdErrorKind::InvalidMessage,
+    )?;
+
+    {
+        let args = req.message_args();
+        let numargs = args.len();
+        match req.message_method() {
+            rpc1::ProtocolMethod::ProtocolStart => {
+                if numargs != 1 {
+                    bail!(SasdErrorKind::InvalidMessage)
+                }
+                if !attrs_from_value(&args[0])?.contains_key("proto") {
+                    bail!(SasdErrorKind::InvalidMessage)
+                }
+            }
+            rpc1::ProtocolMethod::ProtocolWrite => {
+                if numargs != 1 || !args[0].is_bin() {
+                    bail!(SasdErrorKind::InvalidMessage)
+                }
+            }
+            rpc1::ProtocolMethod::ProtocolRead |
+            rpc1::ProtocolMethod::ProtocolNeedKey |
+            rpc1::ProtocolMethod::ProtocolAuthInfo => {
+                if numargs != 0 {
+                    bail!(SasdErrorKind::InvalidMessage)
+                }
+            }
+            rpc1::ProtocolMethod::ProtocolNeedKeyDone => {
+                if numargs != 1 || !args[0].is_u64() {
+                    bail!(SasdErrorKind::InvalidMessage)
+                }
+            }
+            rpc1::ProtocolMethod::ProtocolConfirm => {
+                if numargs > 1 {
+                    bail!(SasdErrorKind::InvalidMessage)
+                }
+                if numargs == 1 {
+                    let answer = attrs_from_value(&args[0])?;
+                    let tag = answer.get("tag").map(|t| t.parse::<u64>());
+                    let yes_or_no = answer
+                        .get("answer")
+                        .map(|a| a == "yes" || a == "no");
+                    match (tag, yes_or_no) {
+                        (Some(Ok(_)), Some(true)) => {}
+                        _ => bail!(SasdErrorKind::InvalidMessage),
+                    }
+                }
+            }
+        }
+    }
+    Ok(req)
+}
+
+
+fn session_locked(req: &SessionRequest) -> SessionResponse
+{
+    SessionResponse::new(
+        req.message_id(),
+        rpc1::SessionError::Locked,
+        Value::from("keyring is locked"),
+    )
+}
+
+
+fn protocol_locked(msg: Message) -> SasdResult<ProtocolResponse>
+{
+    let req = ProtocolRequest::from(msg).chain_err(
+        || SasdErrorKind::InvalidMessage,
+    )?;
+    let resp = ProtocolResponse::new(
+        req.message_id(),
+        rpc1::ProtocolError::Locked,
+        Value::from("keyring is locked"),
+    );
+    Ok(resp)
+}
+
+
+// Reply to a protocol request whose module returned an error. Errors that
+// are not about the protocol itself are passed on.
+fn protocol_failure(
+    req: &ProtocolRequest, err: SasdError, module: &AuthProtocol
+) -> SasdResult<ProtocolResponse>
+{
+    let (code, result) = match err.kind() {
+        &SasdErrorKind::ProtocolPhase(_) => {
+            (
+                rpc1::ProtocolError::InvalidProtocolMessage,
+                Value::from(err.to_string()),
+            )
+        }
+        &SasdErrorKind::ProtocolNeedKey(_) => {
+            (
+                rpc1::ProtocolError::ProtocolNeedKey,
+                attrs_to_value(&module.needkey()),
+            )
+        }
+        &SasdErrorKind::ProtocolNeedConfirmation(_) => {
+            (
+                rpc1::ProtocolError::ProtocolNeedConfirmation,
+                Value::from(err.to_string()),
+            )
+        }
+        &SasdErrorKind::ProtocolFailed(_) => {
+            (rpc1::ProtocolError::ProtocolError, Value::from(err.to_string()))
+        }
+        &SasdErrorKind::KeyringLocked => {
+            (rpc1::ProtocolError::Locked, Value::from("keyring is locked"))
+        }
+        _ => return Err(err),
+    };
+    Ok(ProtocolResponse::new(req.message_id(), code, result))
+}
+
+
+// Add a key to the keyring and save it. Returns false if the key is already
+// in the keyring. If saving fails the key is not kept.
+fn add_key(state: &mut SessionStateHandle, key: Key) -> SasdResult<bool>
+{
+    let mut keyring = state.keyring().write().expect(
+        "failed to write keyring",
+    );
+    let old = keyring.keys().to_vec();
+    let added = keyring.add(key);
+    if added {
+        keyring.save_or_restore(old)?;
     }
+    Ok(added)
+}
+
+
+// The password policy for a key, by its dom attr
+fn password_policy(state: &mut SessionStateHandle, attrs: &Attrs)
+    -> Option<PolicySection>
+{
+    let domain = match attrs.get("dom") {
+        Some(d) => d,
+        None => return None,
+    };
+    let config = state.server_settings().read().expect(
+        "failed to read server settings",
+    );
+    config.policy(domain).cloned()
+}
+
+
+// Set secret attrs of a key. A new !password must pass the policy, and the
+// pubkey of a sign or ssh key is made again from a new !secret. The update
+// can't have any public attrs.
+fn update_secrets(key: &mut Key, update: Key,
+                  policy: Option<&PolicySection>)
+    -> SasdResult<()>
+{
+    let fixed = update.public().keys().chain(update.secret().keys()).find(
+        |a| !is_secret_attr(a) || a.as_str() == policy::HISTORY_ATTR,
+    );
+    if let Some(attr) = fixed {
+        let errmsg = format!("{} can't be updated", attr);
+        bail!(SasdErrorKind::InvalidKey(errmsg))
+    }
+
+    for (attr, value) in update.secret() {
+        if attr == "!password" {
+            policy::set_password(key, value.clone(), policy)?;
+            continue;
+        }
+        if value.is_empty() {
+            bail!(SasdErrorKind::InvalidKey(format!("{} is empty", attr)))
+        }
+        if attr == "!secret" {
+            let mut public = key.public().clone();
+            public.remove("pubkey");
+            let mut trial = Key::with_secret(public, attr, value.clone());
+            sign::add_pubkey(&mut trial)?;
+            if let Some(pubkey) = trial.public().get("pubkey") {
+                key.set_public("pubkey", pubkey.clone());
+            }
+        }
+        key.set_secret(attr, value.clone());
+    }
+    Ok(())
+}
+
+
+fn key_exists(req: &SessionRequest) -> SessionResponse
+{
+    SessionResponse::new(
+        req.message_id(),
+        rpc1::SessionError::KeyExists,
+        Value::from("key already exists"),
+    )
+}
+
+
+fn key_not_found(req: &SessionRequest) -> SessionResponse
+{
+    SessionResponse::new(
+        req.message_id(),
+        rpc1::SessionError::KeyNotFound,
+        Value::from("key not found"),
+    )
+}
+
+
+// Reply to a request for a key that can't be created
+fn invalid_key(req: &SessionRequest, err: SasdError) -> SessionResponse
+{
+    SessionResponse::new(
+        req.message_id(),
+        rpc1::SessionError::InvalidKey,
+        Value::from(err.to_string()),
+    )
+}
+
+
+// Reply to a request for a key that can't be created or updated as asked,
+// either because the key is invalid or because a password breaks the policy
+fn refused_key(req: &SessionRequest, err: SasdError)
+    -> SasdResult<SessionResponse>
+{
+    let code = match *err.kind() {
+        SasdErrorKind::InvalidKey(_) => rpc1::SessionError::InvalidKey,
+        SasdErrorKind::PolicyViolation(_) => {
+            rpc1::SessionError::PolicyViolation
+        }
+        _ => return Err(err),
+    };
+    let resp = SessionResponse::new(
+        req.message_id(),
+        code,
+        Value::from(err.to_string()),
+    );
+    Ok(resp)
+}
+
+
+// Reply to a request whose query could not be parsed
+fn invalid_query(req: &SessionRequest, err: SasdError) -> SessionResponse
+{
+    SessionResponse::new(
+        req.message_id(),
+        rpc1::SessionError::InvalidQuery,
+        Value::from(err.to_string()),
+    )
 }
 
 
 impl State for Session {
-    fn dispatch(&mut self, _state: &mut SessionStateHandle, _msg: Message)
+    fn dispatch(&mut self, state: &mut SessionStateHandle, msg: Message)
         -> SasdResult<(Option<protocol::StateValue>, Option<Message>)>
     {
-        unimplemented!()
+        match msg.message_type() {
+            MessageType::Request => {
+                if protocol_method(&msg).is_some() {
+                    if is_locked(state) {
+                        self.authinfo = None;
+                        return Ok((None, Some(protocol_locked(msg)?.into())));
+                    }
+                    touch(state);
+                    return self.protocol_request(state, msg);
+                }
+                touch(state);
+
+                let req = self.check_msg(msg)?;
+                let resp = match req.message_method() {
+                    rpc1::SessionMethod::Unlock => self.unlock(state, req)?,
+                    rpc1::SessionMethod::Lock => self.lock(state, req)?,
+                    _ if is_locked(state) => session_locked(&req),
+                    rpc1::SessionMethod::KeyList => self.key_list(state, req)?,
+                    rpc1::SessionMethod::CreateKey => {
+                        self.create_key(state, req)?
+                    }
+                    rpc1::SessionMethod::GenerateKey => {
+                        self.generate_key(state, req)?
+                    }
+                    rpc1::SessionMethod::UpdateKey => {
+                        self.update_key(state, req)?
+                    }
+                    rpc1::SessionMethod::DeleteKey => {
+                        self.delete_key(state, req)?
+                    }
+                    #[cfg(windows)]
+                    _ => unreachable!(),
+                };
+                Ok((None, Some(resp.into())))
+            }
+            MessageType::Notification => {
+                bail!(SasdErrorKind::UnexpectedMessage)
+            }
+            MessageType::Response => unreachable!(),
+        }
     }
 }
 
@@ -0,0 +1,393 @@
+// src/protocol/v1/otp.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Third-party imports
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Key, Keyring};
+use secret::SecretBuf;
+
+use super::auth::{AuthInfo, AuthProtocol, Phase, Role, find_key,
+                  key_template};
+
+
+// ===========================================================================
+// Code generation (RFC 4226, RFC 6238)
+// ===========================================================================
+
+
+const SECRETS: &'static [&'static str] = &["!secret"];
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+
+impl Algorithm {
+    // The algorithm attr, as used in otpauth URIs; SHA1 if there is none
+    pub fn from_attr(attr: Option<&str>) -> Option<Algorithm>
+    {
+        match attr.map(|a| a.to_uppercase()) {
+            None => Some(Algorithm::Sha1),
+            Some(a) => match a.as_str() {
+                "SHA1" => Some(Algorithm::Sha1),
+                "SHA256" => Some(Algorithm::Sha256),
+                "SHA512" => Some(Algorithm::Sha512),
+                _ => None,
+            },
+        }
+    }
+
+    fn mac(&self, key: &[u8], data: &[u8]) -> Vec<u8>
+    {
+        const ERRMSG: &'static str = "HMAC accepts keys of any length";
+        match *self {
+            Algorithm::Sha1 => {
+                let mut mac = Hmac::<Sha1>::new_from_slice(key).expect(ERRMSG);
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Algorithm::Sha256 => {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(key).expect(ERRMSG);
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Algorithm::Sha512 => {
+                let mut mac =
+                    Hmac::<Sha512>::new_from_slice(key).expect(ERRMSG);
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+}
+
+
+// The HOTP value for a counter, zero padded to the given number of digits
+pub fn hotp(secret: &[u8], counter: u64, digits: u32, algorithm: Algorithm)
+    -> SecretBuf
+{
+    let mut counter_bytes = [0u8; 8];
+    for (i, b) in counter_bytes.iter_mut().enumerate() {
+        *b = (counter >> (56 - i * 8)) as u8;
+    }
+    let hash = SecretBuf::from(algorithm.mac(secret, &counter_bytes));
+    let hash = hash.as_bytes();
+
+    // Dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let code = (u64::from(hash[offset] & 0x7f) << 24) |
+        (u64::from(hash[offset + 1]) << 16) |
+        (u64::from(hash[offset + 2]) << 8) |
+        u64::from(hash[offset + 3]);
+    let code = code % 10u64.pow(digits);
+    SecretBuf::from(format!("{:0width$}", code, width = digits as usize))
+}
+
+
+// The TOTP counter for a unix time
+pub fn totp_counter(unix_time: u64, period: u64) -> u64
+{
+    unix_time / period
+}
+
+
+// ===========================================================================
+// Key params
+// ===========================================================================
+
+
+const DEFAULT_DIGITS: u32 = 6;
+
+const DEFAULT_PERIOD: u64 = 30;
+
+
+fn failed(msg: &str) -> SasdErrorKind
+{
+    SasdErrorKind::ProtocolFailed(msg.to_owned())
+}
+
+
+// The attrs of an otp key that say how codes are made
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Params {
+    digits: u32,
+    period: u64,
+    algorithm: Algorithm,
+}
+
+
+impl Params {
+    fn from_key(key: &Key) -> SasdResult<Params>
+    {
+        let digits = match key.get("digits") {
+            None => DEFAULT_DIGITS,
+            Some(d) => match d.parse() {
+                Ok(d) if d >= 6 && d <= 10 => d,
+                _ => bail!(failed("digits must be from 6 to 10")),
+            },
+        };
+        let period = match key.get("period") {
+            None => DEFAULT_PERIOD,
+            Some(p) => match p.parse() {
+                Ok(p) if p > 0 => p,
+                _ => bail!(failed("period must be a positive number")),
+            },
+        };
+        let algorithm = match Algorithm::from_attr(key.get("algorithm")) {
+            Some(a) => a,
+            None => bail!(failed("algorithm must be SHA1, SHA256 or SHA512")),
+        };
+        Ok(Params {
+            digits: digits,
+            period: period,
+            algorithm: algorithm,
+        })
+    }
+}
+
+
+// The !secret attr is base32, as in otpauth URIs. Case, spaces and padding
+// are ignored.
+fn decode_secret(key: &Key) -> SasdResult<SecretBuf>
+{
+    let encoded = match key.secret()["!secret"].as_str() {
+        Some(s) => {
+            let encoded: Vec<u8> = s.bytes()
+                .filter(|&b| b != b' ' && b != b'=')
+                .map(|b| b.to_ascii_uppercase())
+                .collect();
+            SecretBuf::from(encoded)
+        }
+        None => bail!(failed("secret is not base32")),
+    };
+    match BASE32_NOPAD.decode(encoded.as_bytes()) {
+        Ok(secret) => Ok(SecretBuf::from(secret)),
+        Err(_) => bail!(failed("secret is not base32")),
+    }
+}
+
+
+// ===========================================================================
+// Otp
+// ===========================================================================
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    // RFC 6238: the counter is the time in periods since the unix epoch
+    Totp,
+
+    // RFC 4226: the counter is the key's counter attr
+    Hotp,
+}
+
+
+// The totp and hotp protocols hand out one-time codes from a seed kept in
+// the key's !secret attr, for clients that need to log in somewhere w/ a
+// second factor. Only the client role is supported. The conversation is
+// done once the code from the key picked at start has been read, so that
+// the client can then ask for the key's user in ProtocolAuthInfo.
+//
+// Every hotp code uses up a counter value, so the key's counter attr is
+// incremented and the keyring saved before the code is handed out.
+pub struct Otp {
+    kind: Kind,
+    template: Attrs,
+    key: Option<Attrs>,
+    user: Option<String>,
+    done: bool,
+}
+
+
+pub fn new_totp() -> Box<AuthProtocol>
+{
+    Box::new(Otp::new(Kind::Totp))
+}
+
+
+pub fn new_hotp() -> Box<AuthProtocol>
+{
+    Box::new(Otp::new(Kind::Hotp))
+}
+
+
+impl Otp {
+    fn new(kind: Kind) -> Self
+    {
+        Otp {
+            kind: kind,
+            template: Attrs::new(),
+            key: None,
+            user: None,
+            done: false,
+        }
+    }
+
+    fn name(&self) -> &'static str
+    {
+        match self.kind {
+            Kind::Totp => "totp",
+            Kind::Hotp => "hotp",
+        }
+    }
+
+    fn totp(&self, key: &Key) -> SasdResult<SecretBuf>
+    {
+        let params = Params::from_key(key)?;
+        let secret = decode_secret(key)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| failed("system clock is before the unix epoch"))?
+            .as_secs();
+        let counter = totp_counter(now, params.period);
+        Ok(hotp(secret.as_bytes(), counter, params.digits, params.algorithm))
+    }
+
+    fn hotp(&self, keyring: &mut Keyring, key: &Key) -> SasdResult<SecretBuf>
+    {
+        let params = Params::from_key(key)?;
+        let secret = decode_secret(key)?;
+        let counter: u64 = match key.get("counter") {
+            None => 0,
+            Some(c) => c.parse()
+                .map_err(|_| failed("counter must be a number"))?,
+        };
+
+        // The code is only handed out once the next counter value is safely
+        // in the keyring file
+        let next = match counter.checked_add(1) {
+            Some(n) => n.to_string(),
+            None => bail!(failed("counter is used up")),
+        };
+        if !keyring.set_attr(key.public(), "counter", &next)? {
+            bail!(failed("key was removed"))
+        }
+        Ok(hotp(secret.as_bytes(), counter, params.digits, params.algorithm))
+    }
+}
+
+
+// Whether a key's public attrs are those of the key picked at start, other
+// than a hotp counter
+fn same_key(public: &Attrs, picked: &Attrs) -> bool
+{
+    let without_counter = |attrs: &Attrs| -> Vec<(String, String)> {
+        attrs
+            .iter()
+            .filter(|&(a, _)| a != "counter")
+            .map(|(a, v)| (a.clone(), v.clone()))
+            .collect()
+    };
+    without_counter(public) == without_counter(picked)
+}
+
+
+impl AuthProtocol for Otp {
+    fn start(&mut self, keyring: &mut Keyring, attrs: &Attrs)
+        -> SasdResult<()>
+    {
+        if Role::from_attrs(attrs)? != Role::Client {
+            let errmsg = format!("{} protocol only has a client role",
+                                 self.name());
+            bail!(SasdErrorKind::ProtocolFailed(errmsg))
+        }
+
+        self.template = key_template(attrs, SECRETS);
+        match find_key(keyring, attrs, SECRETS) {
+            Some(key) => {
+                Params::from_key(key)?;
+                self.key = Some(key.public().clone());
+                self.user = key.get("user").map(|u| u.to_owned());
+                Ok(())
+            }
+            None => {
+                let errmsg = "no key w/ a secret".to_owned();
+                bail!(SasdErrorKind::ProtocolNeedKey(errmsg))
+            }
+        }
+    }
+
+    fn write(&mut self, _keyring: &mut Keyring, _data: &[u8])
+        -> SasdResult<Phase>
+    {
+        let errmsg = format!("{} protocol takes no input", self.name());
+        bail!(SasdErrorKind::ProtocolPhase(errmsg))
+    }
+
+    fn read(&mut self, keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>
+    {
+        if self.done {
+            let errmsg = format!("{} protocol is done", self.name());
+            bail!(SasdErrorKind::ProtocolPhase(errmsg))
+        }
+
+        // The key is looked up again since another conversation may have
+        // changed its counter
+        let key = {
+            let picked = self.key.as_ref().expect("module was started");
+            let found = keyring
+                .keys()
+                .iter()
+                .find(|k| same_key(k.public(), picked));
+            match found {
+                Some(k) => k.clone(),
+                None => bail!(failed("key was removed")),
+            }
+        };
+        let code = match self.kind {
+            Kind::Totp => self.totp(&key)?,
+            Kind::Hotp => self.hotp(keyring, &key)?,
+        };
+        self.done = true;
+        Ok((code, Phase::Done))
+    }
+
+    fn needkey(&self) -> Attrs
+    {
+        self.template.clone()
+    }
+
+    fn key(&self) -> Option<&Attrs>
+    {
+        self.key.as_ref()
+    }
+
+    fn authinfo(&self) -> Option<AuthInfo>
+    {
+        let info = AuthInfo {
+            cuid: self.user.clone(),
+            ..AuthInfo::default()
+        };
+        Some(info)
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
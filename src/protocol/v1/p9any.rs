@@ -0,0 +1,335 @@
+// src/protocol/v1/p9any.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::str;
+
+// Third-party imports
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Keyring};
+use secret::SecretBuf;
+
+use super::auth::{AuthInfo, AuthProtocol, Phase, Role, key_matches,
+                  key_template};
+use super::p9sk1::P9sk1;
+
+
+// ===========================================================================
+// P9any
+// ===========================================================================
+
+
+const SECRETS: &'static [&'static str] = &["!password"];
+
+// The only protocol offered or accepted
+const PROTO: &'static str = "p9sk1";
+
+
+fn failed(msg: &str) -> SasdErrorKind
+{
+    SasdErrorKind::ProtocolFailed(format!("p9any: {}", msg))
+}
+
+
+fn phase_error(msg: &str) -> SasdErrorKind
+{
+    SasdErrorKind::ProtocolPhase(format!("p9any: {}", msg))
+}
+
+
+// Messages are NUL terminated strings
+fn message(data: &[u8]) -> SasdResult<&str>
+{
+    let data = match data.iter().position(|&b| b == 0) {
+        Some(end) => &data[..end],
+        None => data,
+    };
+    str::from_utf8(data).map_err(|_| failed("message is not text").into())
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    // Server's list of proto@authdom offers, server to client
+    Offer,
+
+    // The offer picked by the client, client to server
+    Choice,
+
+    // Server's OK, server to client (v.2 only)
+    Ok,
+
+    // The chosen protocol runs from here on
+    Chosen,
+}
+
+
+// Plan 9's p9any picks the protocol and authentication domain to use, then
+// runs that protocol. Only p9sk1 is offered or accepted.
+//
+// In the client role the server's offers are written and the choice read
+// back, then the server's OK is written. In the server role the offers
+// (one per p9sk1 key w/ an authdom) are read, the choice written and the OK
+// read. The start attrs, w/ proto=p9sk1 and the chosen authdom, pick the
+// key for p9sk1.
+pub struct P9any {
+    role: Role,
+    attrs: Attrs,
+    template: Attrs,
+    step: Step,
+    v2: bool,
+    offers: Vec<String>,
+    choice: Option<SecretBuf>,
+    chosen: Option<P9sk1>,
+}
+
+
+pub fn new_protocol() -> Box<AuthProtocol>
+{
+    Box::new(P9any::new())
+}
+
+
+impl P9any {
+    pub fn new() -> Self
+    {
+        P9any {
+            role: Role::Client,
+            attrs: Attrs::new(),
+            template: Attrs::new(),
+            step: Step::Offer,
+            v2: false,
+            offers: Vec::new(),
+            choice: None,
+            chosen: None,
+        }
+    }
+
+    // The start attrs for p9sk1 in a domain
+    fn p9sk1_attrs(&self, authdom: Option<&str>) -> Attrs
+    {
+        let mut attrs = self.attrs.clone();
+        attrs.insert("proto".to_owned(), PROTO.to_owned());
+        if let Some(dom) = authdom {
+            attrs.insert("authdom".to_owned(), dom.to_owned());
+        }
+        attrs
+    }
+
+    fn start_p9sk1(&mut self, keyring: &mut Keyring, authdom: &str)
+        -> SasdResult<()>
+    {
+        let attrs = self.p9sk1_attrs(Some(authdom));
+        let mut p9sk1 = P9sk1::new();
+        let result = p9sk1.start(keyring, &attrs);
+        self.template = p9sk1.needkey();
+        result?;
+        self.chosen = Some(p9sk1);
+        Ok(())
+    }
+
+    // --------------------
+    // Client role
+    // --------------------
+    fn client_offer(&mut self, keyring: &mut Keyring, data: &[u8])
+        -> SasdResult<Phase>
+    {
+        let offers: Vec<String> = {
+            let mut words = message(data)?.split(' ').peekable();
+            if words.peek() == Some(&"v.2") {
+                self.v2 = true;
+                words.next();
+            }
+            words.map(|w| w.to_owned()).collect()
+        };
+
+        // The first p9sk1 domain there is a key for
+        let mut domains = offers.iter().filter_map(|o| {
+            let mut parts = o.splitn(2, '@');
+            match (parts.next(), parts.next()) {
+                (Some(PROTO), Some(dom)) => Some(dom),
+                _ => None,
+            }
+        });
+        let first = match domains.next() {
+            Some(dom) => dom,
+            None => bail!(failed("server offers no p9sk1 domain")),
+        };
+        let authdom = ::std::iter::once(first)
+            .chain(domains)
+            .find(|dom| {
+                let attrs = self.p9sk1_attrs(Some(dom));
+                keyring.keys().iter().any(|k| key_matches(k, &attrs, SECRETS))
+            })
+            .unwrap_or(first)
+            .to_owned();
+
+        // Without a key for any domain, the key for the first is asked for
+        self.start_p9sk1(keyring, &authdom)?;
+        let choice = format!("{} {}\0", PROTO, authdom);
+        self.choice = Some(SecretBuf::from(choice));
+        self.step = Step::Choice;
+        Ok(Phase::Continue)
+    }
+
+    fn client_choice(&mut self) -> SasdResult<(SecretBuf, Phase)>
+    {
+        self.step = if self.v2 { Step::Ok } else { Step::Chosen };
+        Ok((self.choice.take().unwrap_or_default(), Phase::Continue))
+    }
+
+    fn client_ok(&mut self, data: &[u8]) -> SasdResult<Phase>
+    {
+        if message(data)? != "OK" {
+            bail!(failed("server did not accept the choice"))
+        }
+        self.step = Step::Chosen;
+        Ok(Phase::Continue)
+    }
+
+    // --------------------
+    // Server role
+    // --------------------
+    fn server_offer(&mut self, keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>
+    {
+        let attrs = self.p9sk1_attrs(None);
+        self.offers = keyring
+            .keys()
+            .iter()
+            .filter(|k| key_matches(k, &attrs, SECRETS))
+            .filter_map(|k| k.get("authdom"))
+            .map(|dom| format!("{}@{}", PROTO, dom))
+            .collect();
+        if self.offers.is_empty() {
+            self.template = key_template(&attrs, SECRETS);
+            self.template.insert("authdom".to_owned(), "?".to_owned());
+            let errmsg = "no p9sk1 key w/ an authdom".to_owned();
+            bail!(SasdErrorKind::ProtocolNeedKey(errmsg))
+        }
+
+        let offer = format!("v.2 {}\0", self.offers.join(" "));
+        self.step = Step::Choice;
+        Ok((SecretBuf::from(offer), Phase::Continue))
+    }
+
+    fn server_choice(&mut self, keyring: &mut Keyring, data: &[u8])
+        -> SasdResult<Phase>
+    {
+        let authdom = {
+            let mut parts = message(data)?.splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some(proto), Some(dom)) => {
+                    let offer = format!("{}@{}", proto, dom);
+                    if !self.offers.contains(&offer) {
+                        bail!(failed("client chose something not offered"))
+                    }
+                    dom.to_owned()
+                }
+                _ => bail!(failed("malformed choice")),
+            }
+        };
+        self.start_p9sk1(keyring, &authdom)?;
+        self.step = Step::Ok;
+        Ok(Phase::Continue)
+    }
+
+    fn server_ok(&mut self) -> SasdResult<(SecretBuf, Phase)>
+    {
+        self.step = Step::Chosen;
+        Ok((SecretBuf::from("OK\0"), Phase::Continue))
+    }
+}
+
+
+impl Default for P9any {
+    fn default() -> Self
+    {
+        P9any::new()
+    }
+}
+
+
+impl AuthProtocol for P9any {
+    fn start(&mut self, _keyring: &mut Keyring, attrs: &Attrs)
+        -> SasdResult<()>
+    {
+        // The key is picked once the domain is known
+        self.role = Role::from_attrs(attrs)?;
+        self.attrs = attrs.clone();
+        Ok(())
+    }
+
+    fn write(&mut self, keyring: &mut Keyring, data: &[u8])
+        -> SasdResult<Phase>
+    {
+        if let Some(ref mut chosen) = self.chosen {
+            if self.step == Step::Chosen {
+                return chosen.write(keyring, data);
+            }
+        }
+        match (self.role, self.step) {
+            (Role::Client, Step::Offer) => self.client_offer(keyring, data),
+            (Role::Client, Step::Ok) => self.client_ok(data),
+            (Role::Server, Step::Choice) => {
+                self.server_choice(keyring, data)
+            }
+            _ => bail!(phase_error("expected a read")),
+        }
+    }
+
+    fn read(&mut self, keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>
+    {
+        if let Some(ref mut chosen) = self.chosen {
+            if self.step == Step::Chosen {
+                return chosen.read(keyring);
+            }
+        }
+        match (self.role, self.step) {
+            (Role::Client, Step::Choice) => self.client_choice(),
+            (Role::Server, Step::Offer) => self.server_offer(keyring),
+            (Role::Server, Step::Ok) => self.server_ok(),
+            _ => bail!(phase_error("expected a write")),
+        }
+    }
+
+    fn run_unlocked(&mut self) -> SasdResult<()>
+    {
+        match self.chosen {
+            Some(ref mut chosen) => chosen.run_unlocked(),
+            None => Ok(()),
+        }
+    }
+
+    fn needkey(&self) -> Attrs
+    {
+        self.template.clone()
+    }
+
+    fn key(&self) -> Option<&Attrs>
+    {
+        self.chosen.as_ref().and_then(|c| c.key())
+    }
+
+    fn authinfo(&self) -> Option<AuthInfo>
+    {
+        self.chosen.as_ref().and_then(|c| c.authinfo())
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,738 @@
+// src/protocol/v1/p9sk1.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::cmp;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// Third-party imports
+
+use des::Des;
+use des::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use des::cipher::generic_array::GenericArray;
+use getrandom::getrandom;
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Key, Keyring};
+use secret::SecretBuf;
+
+use super::auth::{AuthInfo, AuthProtocol, Phase, Role, des_key, find_key,
+                  key_template};
+
+
+// ===========================================================================
+// Plan 9 auth constants (authsrv(6))
+// ===========================================================================
+
+
+pub const ANAMELEN: usize = 28;
+pub const AERRLEN: usize = 64;
+pub const DOMLEN: usize = 48;
+pub const DESKEYLEN: usize = 7;
+pub const CHALLEN: usize = 8;
+
+pub const TICKREQLEN: usize = 3 * ANAMELEN + CHALLEN + DOMLEN + 1;
+pub const TICKETLEN: usize = CHALLEN + 2 * ANAMELEN + DESKEYLEN + 1;
+pub const AUTHENTLEN: usize = CHALLEN + 4 + 1;
+
+// Message types
+pub const AUTH_TREQ: u8 = 1;
+pub const AUTH_OK: u8 = 4;
+pub const AUTH_ERR: u8 = 5;
+pub const AUTH_TS: u8 = 64;
+pub const AUTH_TC: u8 = 65;
+pub const AUTH_AS: u8 = 66;
+pub const AUTH_AC: u8 = 67;
+
+// Port of the ticket service on the auth server
+const TICKET_PORT: u16 = 567;
+
+const AUTH_TIMEOUT: u64 = 10;
+
+
+fn failed(msg: &str) -> SasdErrorKind
+{
+    SasdErrorKind::ProtocolFailed(format!("p9sk1: {}", msg))
+}
+
+
+fn phase_error(msg: &str) -> SasdErrorKind
+{
+    SasdErrorKind::ProtocolPhase(format!("p9sk1: {}", msg))
+}
+
+
+fn random_chal() -> SasdResult<[u8; CHALLEN]>
+{
+    let mut chal = [0u8; CHALLEN];
+    getrandom(&mut chal).map_err(|e| {
+        failed(&format!("unable to get random bytes: {}", e))
+    })?;
+    Ok(chal)
+}
+
+
+// ===========================================================================
+// Plan 9 DES
+// ===========================================================================
+
+
+fn des_cipher(key: &[u8]) -> Des
+{
+    Des::new_from_slice(&des_key(key)).expect("DES key is 8 bytes")
+}
+
+
+// Plan 9's encrypt(): DES over overlapping 8 byte blocks, 7 bytes apart, so
+// any buffer of at least 8 bytes can be encrypted in place. The key is a 7
+// byte DES key.
+pub fn encrypt(key: &[u8], buf: &mut [u8])
+{
+    assert!(buf.len() >= 8, "can't encrypt less than a block");
+    let des = des_cipher(key);
+    let n = (buf.len() - 1) / 7;
+    let r = (buf.len() - 1) % 7;
+    for i in 0..n {
+        let block = &mut buf[i * 7..][..8];
+        des.encrypt_block(GenericArray::from_mut_slice(block));
+    }
+    if r != 0 {
+        let start = n * 7 - 7 + r;
+        des.encrypt_block(GenericArray::from_mut_slice(&mut buf[start..]));
+    }
+}
+
+
+// Plan 9's decrypt(), undoing encrypt()
+pub fn decrypt(key: &[u8], buf: &mut [u8])
+{
+    assert!(buf.len() >= 8, "can't decrypt less than a block");
+    let des = des_cipher(key);
+    let n = (buf.len() - 1) / 7;
+    let r = (buf.len() - 1) % 7;
+    if r != 0 {
+        let start = n * 7 - 7 + r;
+        des.decrypt_block(GenericArray::from_mut_slice(&mut buf[start..]));
+    }
+    for i in (0..n).rev() {
+        let block = &mut buf[i * 7..][..8];
+        des.decrypt_block(GenericArray::from_mut_slice(block));
+    }
+}
+
+
+// Plan 9's passtokey(): the DES key for a password
+pub fn passtokey(password: &[u8]) -> SecretBuf
+{
+    let mut n = cmp::min(password.len(), ANAMELEN - 1);
+    let mut buf = SecretBuf::from(vec![b' '; ANAMELEN]);
+    {
+        let buf = buf.as_mut_bytes();
+        buf[..n].copy_from_slice(&password[..n]);
+        buf[n] = 0;
+    }
+
+    let mut key = SecretBuf::from(vec![0u8; DESKEYLEN]);
+    let mut t = 0;
+    loop {
+        {
+            let (key, buf) = (key.as_mut_bytes(), buf.as_bytes());
+            for i in 0..DESKEYLEN {
+                let (lo, hi) = (buf[t + i] >> i, buf[t + i + 1] << (7 - i));
+                key[i] = lo.wrapping_add(hi);
+            }
+        }
+        if n <= 8 {
+            return key;
+        }
+        n -= 8;
+        t += 8;
+        if n < 8 {
+            t -= 8 - n;
+            n = 8;
+        }
+        encrypt(key.as_bytes(), &mut buf.as_mut_bytes()[t..t + 8]);
+    }
+}
+
+
+// ===========================================================================
+// Messages
+// ===========================================================================
+
+
+// Fixed length, NUL padded strings
+fn put_string(buf: &mut Vec<u8>, s: &str, len: usize)
+{
+    let n = cmp::min(s.len(), len - 1);
+    buf.extend_from_slice(&s.as_bytes()[..n]);
+    buf.extend((n..len).map(|_| 0u8));
+}
+
+
+fn get_string(buf: &[u8]) -> String
+{
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).into_owned()
+}
+
+
+fn get_chal(buf: &[u8]) -> [u8; CHALLEN]
+{
+    let mut chal = [0u8; CHALLEN];
+    chal.copy_from_slice(&buf[..CHALLEN]);
+    chal
+}
+
+
+// Sent by the server for the client to pass on to the auth server
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ticketreq {
+    pub kind: u8,
+    pub authid: String,
+    pub authdom: String,
+    pub chal: [u8; CHALLEN],
+    pub hostid: String,
+    pub uid: String,
+}
+
+
+impl Ticketreq {
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut buf = Vec::with_capacity(TICKREQLEN);
+        buf.push(self.kind);
+        put_string(&mut buf, &self.authid, ANAMELEN);
+        put_string(&mut buf, &self.authdom, DOMLEN);
+        buf.extend_from_slice(&self.chal);
+        put_string(&mut buf, &self.hostid, ANAMELEN);
+        put_string(&mut buf, &self.uid, ANAMELEN);
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> SasdResult<Ticketreq>
+    {
+        if buf.len() != TICKREQLEN {
+            bail!(failed("ticket request has the wrong length"))
+        }
+        let mut at = 1;
+        let mut next = |len: usize| {
+            let field = &buf[at..at + len];
+            at += len;
+            field
+        };
+        Ok(Ticketreq {
+            kind: buf[0],
+            authid: get_string(next(ANAMELEN)),
+            authdom: get_string(next(DOMLEN)),
+            chal: get_chal(next(CHALLEN)),
+            hostid: get_string(next(ANAMELEN)),
+            uid: get_string(next(ANAMELEN)),
+        })
+    }
+}
+
+
+// Made by the auth server, once encrypted w/ the client's key and once w/
+// the server's. The key is shared by the client and server.
+#[derive(Clone, Default, PartialEq)]
+pub struct Ticket {
+    pub num: u8,
+    pub chal: [u8; CHALLEN],
+    pub cuid: String,
+    pub suid: String,
+    pub key: SecretBuf,
+}
+
+
+impl Ticket {
+    pub fn to_bytes(&self, key: &[u8]) -> SecretBuf
+    {
+        let mut buf = Vec::with_capacity(TICKETLEN);
+        buf.push(self.num);
+        buf.extend_from_slice(&self.chal);
+        put_string(&mut buf, &self.cuid, ANAMELEN);
+        put_string(&mut buf, &self.suid, ANAMELEN);
+        buf.extend_from_slice(self.key.as_bytes());
+        let mut buf = SecretBuf::from(buf);
+        encrypt(key, buf.as_mut_bytes());
+        buf
+    }
+
+    pub fn from_bytes(data: &[u8], key: &[u8]) -> SasdResult<Ticket>
+    {
+        if data.len() != TICKETLEN {
+            bail!(failed("ticket has the wrong length"))
+        }
+        let mut buf = SecretBuf::from(data);
+        decrypt(key, buf.as_mut_bytes());
+        let buf = buf.as_bytes();
+        let cuid = 1 + CHALLEN;
+        let suid = cuid + ANAMELEN;
+        let key = suid + ANAMELEN;
+        Ok(Ticket {
+            num: buf[0],
+            chal: get_chal(&buf[1..]),
+            cuid: get_string(&buf[cuid..suid]),
+            suid: get_string(&buf[suid..key]),
+            key: SecretBuf::from(&buf[key..]),
+        })
+    }
+}
+
+
+// Proves to the other party that the ticket could be decrypted
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Authenticator {
+    pub num: u8,
+    pub chal: [u8; CHALLEN],
+    pub id: u32,
+}
+
+
+impl Authenticator {
+    pub fn to_bytes(&self, key: &[u8]) -> Vec<u8>
+    {
+        let mut buf = Vec::with_capacity(AUTHENTLEN);
+        buf.push(self.num);
+        buf.extend_from_slice(&self.chal);
+        for i in 0..4 {
+            buf.push((self.id >> (i * 8)) as u8);
+        }
+        encrypt(key, &mut buf);
+        buf
+    }
+
+    pub fn from_bytes(data: &[u8], key: &[u8]) -> SasdResult<Authenticator>
+    {
+        if data.len() != AUTHENTLEN {
+            bail!(failed("authenticator has the wrong length"))
+        }
+        let mut buf = data.to_vec();
+        decrypt(key, &mut buf);
+        let id = buf[1 + CHALLEN..]
+            .iter()
+            .enumerate()
+            .fold(0, |id, (i, &b)| id | u32::from(b) << (i * 8));
+        Ok(Authenticator {
+            num: buf[0],
+            chal: get_chal(&buf[1..]),
+            id: id,
+        })
+    }
+}
+
+
+// ===========================================================================
+// Auth server
+// ===========================================================================
+
+
+// Where the ticket service for a key's domain is: the key's auth attr (host
+// or host:port), or else the authdom itself
+pub fn auth_server(key: &Key) -> Option<String>
+{
+    let host = match key.get("auth").or_else(|| key.get("authdom")) {
+        Some(h) => h,
+        None => return None,
+    };
+    if host.contains(':') {
+        Some(host.to_owned())
+    } else {
+        Some(format!("{}:{}", host, TICKET_PORT))
+    }
+}
+
+
+// Ask the auth server for tickets, returning the client's ticket and the
+// server's ticket, both still encrypted
+pub fn get_tickets(addr: &str, tr: &Ticketreq)
+    -> SasdResult<(SecretBuf, SecretBuf)>
+{
+    let io_failed = |e: ::std::io::Error| {
+        failed(&format!("auth server {}: {}", addr, e))
+    };
+    let timeout = Duration::from_secs(AUTH_TIMEOUT);
+
+    let mut stream = None;
+    for sockaddr in addr.to_socket_addrs().map_err(&io_failed)? {
+        if let Ok(s) = TcpStream::connect_timeout(&sockaddr, timeout) {
+            stream = Some(s);
+            break;
+        }
+    }
+    let mut stream = match stream {
+        Some(s) => s,
+        None => bail!(failed(&format!("unable to reach {}", addr))),
+    };
+    stream.set_read_timeout(Some(timeout)).map_err(&io_failed)?;
+    stream.set_write_timeout(Some(timeout)).map_err(&io_failed)?;
+
+    stream.write_all(&tr.to_bytes()).map_err(&io_failed)?;
+    let mut reply = [0u8; 1];
+    stream.read_exact(&mut reply).map_err(&io_failed)?;
+    match reply[0] {
+        AUTH_OK => {
+            let mut tickets = SecretBuf::from(vec![0u8; 2 * TICKETLEN]);
+            stream
+                .read_exact(tickets.as_mut_bytes())
+                .map_err(&io_failed)?;
+            let tickets = tickets.as_bytes();
+            Ok((
+                SecretBuf::from(&tickets[..TICKETLEN]),
+                SecretBuf::from(&tickets[TICKETLEN..]),
+            ))
+        }
+        AUTH_ERR => {
+            let mut errmsg = [0u8; AERRLEN];
+            stream.read_exact(&mut errmsg).map_err(&io_failed)?;
+            let errmsg = get_string(&errmsg);
+            bail!(failed(&format!("auth server: {}", errmsg)))
+        }
+        _ => bail!(failed("unexpected reply from auth server")),
+    }
+}
+
+
+// ===========================================================================
+// P9sk1
+// ===========================================================================
+
+
+const SECRETS: &'static [&'static str] = &["!password"];
+
+
+// The messages of the protocol, in order
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    // Client challenge, client to server
+    Chal,
+
+    // Ticket request, server to client
+    Ticketreq,
+
+    // Server's ticket and client's authenticator, client to server
+    Ticket,
+
+    // Server's authenticator, server to client
+    Authenticator,
+
+    Done,
+}
+
+
+// Plan 9's p9sk1. Keys hold the user's !password and the authdom of the
+// auth server that shares it.
+//
+// In the client role the client challenge is read, the server's ticket
+// request written, the ticket for the server read, and the server's
+// authenticator written. The module gets the tickets from the auth server
+// itself when the ticket request is written, w/o holding the keyring, so
+// the password never leaves the daemon. The server role is the other way
+// around and doesn't need the auth server.
+//
+// Once done, ProtocolAuthInfo has the client and server users from the
+// ticket, and the key in the ticket as the secret. In the client role the
+// cap is the server's ticket and the client's authenticator, as sent to the
+// server.
+pub struct P9sk1 {
+    role: Role,
+    template: Attrs,
+    step: Step,
+    user: String,
+    authdom: String,
+    auth: Option<String>,
+    key_attrs: Option<Attrs>,
+    key: SecretBuf,
+    cchal: [u8; CHALLEN],
+    schal: [u8; CHALLEN],
+    ticket: Option<Ticket>,
+    cap: Option<SecretBuf>,
+
+    // Ticket request to take to the auth server once the keyring is let go
+    treq: Option<Ticketreq>,
+
+    // Message waiting for the client's ProtocolRead
+    out: Option<SecretBuf>,
+}
+
+
+pub fn new_protocol() -> Box<AuthProtocol>
+{
+    Box::new(P9sk1::new())
+}
+
+
+impl P9sk1 {
+    pub fn new() -> Self
+    {
+        P9sk1 {
+            role: Role::Client,
+            template: Attrs::new(),
+            step: Step::Chal,
+            user: String::new(),
+            authdom: String::new(),
+            auth: None,
+            key_attrs: None,
+            key: SecretBuf::new(),
+            cchal: [0u8; CHALLEN],
+            schal: [0u8; CHALLEN],
+            ticket: None,
+            cap: None,
+            treq: None,
+            out: None,
+        }
+    }
+
+    // The key in the ticket, which the authenticators are encrypted w/
+    fn ticket_key(&self) -> &[u8]
+    {
+        match self.ticket {
+            Some(ref t) => t.key.as_bytes(),
+            None => unreachable!(),
+        }
+    }
+
+    // --------------------
+    // Client role
+    // --------------------
+    fn client_chal(&mut self) -> SasdResult<(SecretBuf, Phase)>
+    {
+        self.cchal = random_chal()?;
+        self.step = Step::Ticketreq;
+        Ok((SecretBuf::from(&self.cchal[..]), Phase::Continue))
+    }
+
+    fn client_ticketreq(&mut self, data: &[u8]) -> SasdResult<Phase>
+    {
+        let mut tr = Ticketreq::from_bytes(data)?;
+        if tr.kind != AUTH_TREQ {
+            bail!(failed("expected a ticket request"))
+        }
+        if tr.authdom != self.authdom {
+            bail!(failed("server asked for another authdom"))
+        }
+        if self.auth.is_none() {
+            bail!(failed("key has no auth or authdom attribute"))
+        }
+
+        tr.hostid = self.user.clone();
+        tr.uid = self.user.clone();
+        self.treq = Some(tr);
+        self.step = Step::Ticket;
+        Ok(Phase::Continue)
+    }
+
+    // Get the tickets for a ticket request from the auth server
+    fn client_tickets(&mut self, tr: Ticketreq) -> SasdResult<()>
+    {
+        let auth = self.auth.clone().unwrap_or_default();
+        let (client, server) = get_tickets(&auth, &tr)?;
+        let ticket =
+            Ticket::from_bytes(client.as_bytes(), self.key.as_bytes())?;
+        if ticket.num != AUTH_TC || ticket.chal != tr.chal {
+            bail!(failed("auth server rejected the password"))
+        }
+
+        let auth = Authenticator {
+            num: AUTH_AC,
+            chal: tr.chal,
+            id: 0,
+        };
+        let mut out = server.as_bytes().to_vec();
+        out.extend(auth.to_bytes(ticket.key.as_bytes()));
+        let out = SecretBuf::from(out);
+        self.cap = Some(out.clone());
+        self.out = Some(out);
+        self.ticket = Some(ticket);
+        Ok(())
+    }
+
+    fn client_ticket(&mut self) -> SasdResult<(SecretBuf, Phase)>
+    {
+        self.step = Step::Authenticator;
+        Ok((self.out.take().unwrap_or_default(), Phase::Continue))
+    }
+
+    fn client_authenticator(&mut self, data: &[u8]) -> SasdResult<Phase>
+    {
+        let auth = Authenticator::from_bytes(data, self.ticket_key())?;
+        if auth.num != AUTH_AS || auth.chal != self.cchal {
+            bail!(failed("server failed to authenticate"))
+        }
+        self.step = Step::Done;
+        Ok(Phase::Done)
+    }
+
+    // --------------------
+    // Server role
+    // --------------------
+    fn server_chal(&mut self, data: &[u8]) -> SasdResult<Phase>
+    {
+        if data.len() != CHALLEN {
+            bail!(failed("challenge has the wrong length"))
+        }
+        self.cchal = get_chal(data);
+        self.step = Step::Ticketreq;
+        Ok(Phase::Continue)
+    }
+
+    fn server_ticketreq(&mut self) -> SasdResult<(SecretBuf, Phase)>
+    {
+        self.schal = random_chal()?;
+        let tr = Ticketreq {
+            kind: AUTH_TREQ,
+            authid: self.user.clone(),
+            authdom: self.authdom.clone(),
+            chal: self.schal,
+            ..Ticketreq::default()
+        };
+        self.step = Step::Ticket;
+        Ok((SecretBuf::from(tr.to_bytes()), Phase::Continue))
+    }
+
+    fn server_ticket(&mut self, data: &[u8]) -> SasdResult<Phase>
+    {
+        if data.len() != TICKETLEN + AUTHENTLEN {
+            bail!(failed("ticket has the wrong length"))
+        }
+        let ticket =
+            Ticket::from_bytes(&data[..TICKETLEN], self.key.as_bytes())?;
+        if ticket.num != AUTH_TS || ticket.chal != self.schal {
+            bail!(failed("authentication failed"))
+        }
+        let auth = Authenticator::from_bytes(
+            &data[TICKETLEN..],
+            ticket.key.as_bytes(),
+        )?;
+        if auth.num != AUTH_AC || auth.chal != self.schal {
+            bail!(failed("authentication failed"))
+        }
+        self.ticket = Some(ticket);
+        self.step = Step::Authenticator;
+        Ok(Phase::Continue)
+    }
+
+    fn server_authenticator(&mut self) -> SasdResult<(SecretBuf, Phase)>
+    {
+        let auth = Authenticator {
+            num: AUTH_AS,
+            chal: self.cchal,
+            id: 0,
+        };
+        let out = auth.to_bytes(self.ticket_key());
+        self.step = Step::Done;
+        Ok((SecretBuf::from(out), Phase::Done))
+    }
+}
+
+
+impl Default for P9sk1 {
+    fn default() -> Self
+    {
+        P9sk1::new()
+    }
+}
+
+
+impl AuthProtocol for P9sk1 {
+    fn start(&mut self, keyring: &mut Keyring, attrs: &Attrs)
+        -> SasdResult<()>
+    {
+        self.role = Role::from_attrs(attrs)?;
+        self.template = key_template(attrs, SECRETS);
+        let key = match find_key(keyring, attrs, SECRETS) {
+            Some(k) => k,
+            None => {
+                let errmsg = "no key w/ a password".to_owned();
+                bail!(SasdErrorKind::ProtocolNeedKey(errmsg))
+            }
+        };
+        match (key.get("user"), key.get("authdom")) {
+            (Some(user), Some(authdom)) => {
+                self.user = user.to_owned();
+                self.authdom = authdom.to_owned();
+            }
+            _ => bail!(failed("key needs user and authdom attributes")),
+        }
+        self.auth = auth_server(key);
+        self.key_attrs = Some(key.public().clone());
+        self.key = passtokey(key.secret()["!password"].as_bytes());
+        Ok(())
+    }
+
+    fn write(&mut self, _keyring: &mut Keyring, data: &[u8])
+        -> SasdResult<Phase>
+    {
+        match (self.role, self.step) {
+            (Role::Client, Step::Ticketreq) => self.client_ticketreq(data),
+            (Role::Client, Step::Authenticator) => {
+                self.client_authenticator(data)
+            }
+            (Role::Server, Step::Chal) => self.server_chal(data),
+            (Role::Server, Step::Ticket) => self.server_ticket(data),
+            _ => bail!(phase_error("expected a read")),
+        }
+    }
+
+    fn read(&mut self, _keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>
+    {
+        match (self.role, self.step) {
+            (Role::Client, Step::Chal) => self.client_chal(),
+            (Role::Client, Step::Ticket) => self.client_ticket(),
+            (Role::Server, Step::Ticketreq) => self.server_ticketreq(),
+            (Role::Server, Step::Authenticator) => {
+                self.server_authenticator()
+            }
+            _ => bail!(phase_error("expected a write")),
+        }
+    }
+
+    fn run_unlocked(&mut self) -> SasdResult<()>
+    {
+        match self.treq.take() {
+            Some(tr) => self.client_tickets(tr),
+            None => Ok(()),
+        }
+    }
+
+    fn needkey(&self) -> Attrs
+    {
+        self.template.clone()
+    }
+
+    fn key(&self) -> Option<&Attrs>
+    {
+        self.key_attrs.as_ref()
+    }
+
+    fn authinfo(&self) -> Option<AuthInfo>
+    {
+        self.ticket.as_ref().map(|t| {
+            AuthInfo {
+                cuid: Some(t.cuid.clone()),
+                suid: Some(t.suid.clone()),
+                cap: self.cap.clone(),
+                secret: Some(SecretBuf::from(&des_key(t.key.as_bytes())[..])),
+            }
+        })
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -12,38 +12,134 @@
 
 // Third-party imports
 
-// use siminau_rpc::message::{Message, MessageType, RpcMessage};
-// use siminau_rpc::message::notify::{NotificationMessage, RpcNotice};
-// use siminau_rpc::message::request::{RequestMessage, RpcRequest};
-// use siminau_rpc::message::response::{ResponseMessage, RpcResponse};
-
 // Local imports
 
-// use rpc::v1 as rpc;
-
-
-// ===========================================================================
-// Messages
-// ===========================================================================
-
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Keyring};
+use secret::SecretBuf;
 
-// pub type PlaintextRequest = RequestMessage<rpc::RequestMethod>;
-
-
-// pub type PlaintextResponse = ResponseMessage<rpc::ResponseError>;
-
-
-// pub type PlaintextInfo = NotificationMessage<rpc::Notice>;
+use super::auth::{AuthInfo, AuthProtocol, Phase, Role, find_key,
+                  key_template};
 
 
 // ===========================================================================
-//
+// PlainText
 // ===========================================================================
 
 
-// pub trait State {
-//     fn change(self, msg: Message) -> (Box<State>, Option<PlaintextResponse>);
-// }
+const SECRETS: &'static [&'static str] = &["!password"];
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    User,
+    Password,
+    Done,
+}
+
+
+impl Default for Step {
+    fn default() -> Self
+    {
+        Step::User
+    }
+}
+
+
+// The pass protocol hands the user name and then the password of a key to
+// the client, for programs that need the password itself. Only the client
+// role is supported.
+#[derive(Default)]
+pub struct PlainText {
+    template: Attrs,
+    key: Option<Attrs>,
+    user: String,
+    password: SecretBuf,
+    step: Step,
+}
+
+
+pub fn new_protocol() -> Box<AuthProtocol>
+{
+    Box::new(PlainText::default())
+}
+
+
+impl AuthProtocol for PlainText {
+    fn start(&mut self, keyring: &mut Keyring, attrs: &Attrs)
+        -> SasdResult<()>
+    {
+        if Role::from_attrs(attrs)? != Role::Client {
+            let errmsg = "pass protocol only has a client role".to_owned();
+            bail!(SasdErrorKind::ProtocolFailed(errmsg))
+        }
+
+        self.template = key_template(attrs, SECRETS);
+        let key = match find_key(keyring, attrs, SECRETS) {
+            Some(k) => k,
+            None => {
+                let errmsg = "no key w/ a password".to_owned();
+                bail!(SasdErrorKind::ProtocolNeedKey(errmsg))
+            }
+        };
+
+        self.user = match key.public().get("user") {
+            Some(user) => user.clone(),
+            None => {
+                let errmsg = "key has no user attribute".to_owned();
+                bail!(SasdErrorKind::ProtocolFailed(errmsg))
+            }
+        };
+        self.password = key.secret()["!password"].clone();
+        self.key = Some(key.public().clone());
+        Ok(())
+    }
+
+    fn write(&mut self, _keyring: &mut Keyring, _data: &[u8])
+        -> SasdResult<Phase>
+    {
+        let errmsg = "pass protocol takes no input".to_owned();
+        bail!(SasdErrorKind::ProtocolPhase(errmsg))
+    }
+
+    fn read(&mut self, _keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>
+    {
+        match self.step {
+            Step::User => {
+                self.step = Step::Password;
+                Ok((SecretBuf::from(self.user.as_str()), Phase::Continue))
+            }
+            Step::Password => {
+                self.step = Step::Done;
+                Ok((self.password.clone(), Phase::Done))
+            }
+            Step::Done => {
+                let errmsg = "pass protocol is done".to_owned();
+                bail!(SasdErrorKind::ProtocolPhase(errmsg))
+            }
+        }
+    }
+
+    fn needkey(&self) -> Attrs
+    {
+        self.template.clone()
+    }
+
+    fn key(&self) -> Option<&Attrs>
+    {
+        self.key.as_ref()
+    }
+
+    fn authinfo(&self) -> Option<AuthInfo>
+    {
+        let info = AuthInfo {
+            cuid: Some(self.user.clone()),
+            ..AuthInfo::default()
+        };
+        Some(info)
+    }
+}
 
 
 // ===========================================================================
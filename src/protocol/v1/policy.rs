@@ -0,0 +1,209 @@
+// src/protocol/v1/policy.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+use data_encoding::BASE64;
+use sha2::{Digest, Sha256};
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Key, SecretAttrs};
+use secret::SecretBuf;
+use settings::PolicySection;
+
+use super::keygen::random_bytes;
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+fn violation(msg: &str) -> SasdErrorKind
+{
+    SasdErrorKind::PolicyViolation(msg.to_owned())
+}
+
+
+fn has_class(password: &str, class: &str) -> bool
+{
+    match class {
+        "lower" => password.chars().any(|c| c.is_lowercase()),
+        "upper" => password.chars().any(|c| c.is_uppercase()),
+        "digits" => password.chars().any(|c| c.is_numeric()),
+        _ => password.chars().any(|c| !c.is_alphanumeric()),
+    }
+}
+
+
+fn class_name(class: &str) -> &'static str
+{
+    match class {
+        "lower" => "a lowercase letter",
+        "upper" => "an uppercase letter",
+        "digits" => "a digit",
+        _ => "a symbol",
+    }
+}
+
+
+// ===========================================================================
+// Password checks
+// ===========================================================================
+
+
+// Secret attr holding hashes of a key's earlier passwords, oldest first, for
+// domains whose policy limits reuse. Clients can't set it.
+pub const HISTORY_ATTR: &'static str = "!history";
+
+
+const SALT_LEN: usize = 16;
+
+
+// Check a new password against its domain's policy. An empty password is
+// refused even if there is no policy.
+pub fn check_password(password: &SecretBuf, policy: Option<&PolicySection>)
+    -> SasdResult<()>
+{
+    if password.is_empty() {
+        bail!(violation("password is empty"))
+    }
+    let policy = match policy {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    let password = match password.as_str() {
+        Some(p) => p,
+        None => bail!(violation("password is not valid UTF-8")),
+    };
+
+    if password.chars().count() < policy.min_length {
+        let errmsg = format!(
+            "password must be at least {} characters long",
+            policy.min_length
+        );
+        bail!(violation(&errmsg))
+    }
+    let missing = policy.classes.iter().find(|c| !has_class(password, c));
+    if let Some(class) = missing {
+        let errmsg = format!("password must have {}", class_name(class));
+        bail!(violation(&errmsg))
+    }
+    Ok(())
+}
+
+
+// Check the secret attrs of a key about to be created. None may be empty,
+// and a !password must pass the policy.
+pub fn check_secrets(secret: &SecretAttrs, policy: Option<&PolicySection>)
+    -> SasdResult<()>
+{
+    for (attr, value) in secret {
+        if attr == HISTORY_ATTR {
+            let errmsg = format!("{} can't be set", attr);
+            bail!(SasdErrorKind::InvalidKey(errmsg))
+        } else if attr == "!password" {
+            check_password(value, policy)?;
+        } else if value.is_empty() {
+            let errmsg = format!("{} is empty", attr);
+            bail!(SasdErrorKind::InvalidKey(errmsg))
+        }
+    }
+    Ok(())
+}
+
+
+// Salted so that keys w/ the same password don't have the same entries
+fn hash_password(password: &SecretBuf, salt: &[u8]) -> Vec<u8>
+{
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(password.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+
+// A history entry is the base64 of a random salt followed by the hash
+fn history_entry(password: &SecretBuf) -> SasdResult<String>
+{
+    let mut entry = random_bytes(SALT_LEN)?.as_bytes().to_vec();
+    let hash = hash_password(password, &entry);
+    entry.extend(hash);
+    Ok(BASE64.encode(&entry))
+}
+
+
+fn in_history(entry: &str, password: &SecretBuf) -> bool
+{
+    match BASE64.decode(entry.as_bytes()) {
+        Ok(ref e) if e.len() > SALT_LEN => {
+            let (salt, hash) = e.split_at(SALT_LEN);
+            hash == &hash_password(password, salt)[..]
+        }
+        _ => false,
+    }
+}
+
+
+// The last count entries of a key's history
+fn last_entries(key: &Key, count: usize) -> Vec<String>
+{
+    let mut entries: Vec<String> = match key.get(HISTORY_ATTR) {
+        Some(h) => h.split_whitespace().map(|e| e.to_owned()).collect(),
+        None => Vec::new(),
+    };
+    let start = entries.len().saturating_sub(count);
+    entries.split_off(start)
+}
+
+
+// Give a key a new !password that passes its domain's policy. If the policy
+// has a reuse limit, the password must also differ from the current one
+// and those in the key's history, and the current one goes in the history.
+pub fn set_password(key: &mut Key, password: SecretBuf,
+                    policy: Option<&PolicySection>)
+    -> SasdResult<()>
+{
+    check_password(&password, policy)?;
+
+    let reuse = policy.map(|p| p.reuse).unwrap_or(0);
+    if reuse > 0 {
+        // The current password counts as one of the last passwords
+        let mut entries = last_entries(key, reuse - 1);
+        let reused = key.secret().get("!password") == Some(&password) ||
+            entries.iter().any(|e| in_history(e, &password));
+        if reused {
+            let errmsg = format!(
+                "password must differ from the last {} passwords",
+                reuse
+            );
+            bail!(violation(&errmsg))
+        }
+
+        let current = key.secret().get("!password").cloned();
+        if let (true, Some(current)) = (reuse > 1, current) {
+            entries.push(history_entry(&current)?);
+            let start = entries.len().saturating_sub(reuse - 1);
+            let history = entries[start..].join(" ");
+            key.set_secret(HISTORY_ATTR, SecretBuf::from(history));
+        }
+    }
+    key.set_secret("!password", password);
+    Ok(())
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
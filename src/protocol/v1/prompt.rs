@@ -0,0 +1,320 @@
+// src/protocol/v1/prompt.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// Third-party imports
+
+use rmpv::Value;
+use siminau_rpc::message::Message;
+
+// Local imports
+
+use keyring::Attrs;
+use rpc::v1 as rpc1;
+use server::Notifier;
+
+use super::{ProtocolInfo, attrs_to_value};
+
+
+// ===========================================================================
+// Prompts
+// ===========================================================================
+
+
+pub type PromptsHandle = Arc<Prompts>;
+
+
+pub fn new_prompts_handle() -> PromptsHandle
+{
+    Arc::new(Prompts::default())
+}
+
+
+// What a registered session is asked about
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PromptKind {
+    // Adding a missing key, answered w/ ProtocolNeedKeyDone
+    NeedKey,
+
+    // Using a key marked confirm=yes, answered w/ ProtocolConfirm
+    Confirm,
+}
+
+
+#[derive(Default)]
+struct PromptsInner {
+    next_id: u64,
+
+    // Registered prompters and confirmers by id
+    prompters: Vec<(u64, PromptKind, Notifier)>,
+
+    // Requests by tag, w/ the answer once one has been sent
+    pending: BTreeMap<u64, (PromptKind, Option<bool>)>,
+
+    // Confirm answers by key and peer, w/ when they are to be forgotten
+    remembered: BTreeMap<(Attrs, Attrs), (bool, Instant)>,
+
+    // How many times the keyring has been locked, so that requests made
+    // before it was locked are given up
+    locks: u64,
+}
+
+
+impl PromptsInner {
+    fn next_id(&mut self) -> u64
+    {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn can_ask(&self, kind: PromptKind, asker: Option<u64>) -> bool
+    {
+        self.prompters
+            .iter()
+            .any(|&(id, k, _)| k == kind && Some(id) != asker)
+    }
+}
+
+
+// Sessions that have registered as prompters or confirmers, and the
+// conversations waiting on them. Shared by every session.
+#[derive(Default)]
+pub struct Prompts {
+    inner: Mutex<PromptsInner>,
+    answered: Condvar,
+}
+
+
+impl Prompts {
+    fn lock(&self) -> MutexGuard<PromptsInner>
+    {
+        self.inner.lock().expect("failed to lock prompts")
+    }
+
+    pub fn has_prompters(&self) -> bool
+    {
+        self.lock()
+            .prompters
+            .iter()
+            .any(|&(_, kind, _)| kind == PromptKind::NeedKey)
+    }
+
+    // Ask every prompter, other than the asking session itself, for a key
+    // matching the template and wait for one of them to answer. Returns false
+    // if there is no prompter to ask or none answers before the timeout.
+    pub fn needkey(
+        &self, template: &Attrs, timeout: Duration, asker: Option<&Prompter>
+    ) -> bool
+    {
+        let notice = |tag| needkey_notice(tag, template);
+        let inner = self.lock();
+        let answer =
+            self.ask(inner, PromptKind::NeedKey, notice, timeout, asker).1;
+        answer == Some(true)
+    }
+
+    // Let the conversation waiting on a needkey request go on. Returns false
+    // if no conversation is waiting on the tag.
+    pub fn needkey_done(&self, tag: u64) -> bool
+    {
+        self.answer(PromptKind::NeedKey, tag, true)
+    }
+
+    // Ask every confirmer, other than the asking session itself, whether the
+    // peer may use the key and wait for the first answer. An answer given
+    // within the remember period for the same key and peer is reused.
+    // Returns false if the answer is no, or there is no confirmer to ask or
+    // none answers before the timeout.
+    pub fn confirm(
+        &self, key: &Attrs, peer: &Attrs, timeout: Duration,
+        remember: Option<Duration>, asker: Option<&Prompter>
+    ) -> bool
+    {
+        let now = Instant::now();
+        let id = (key.clone(), peer.clone());
+        let mut inner = self.lock();
+        inner.remembered.retain(|_, &mut (_, until)| until > now);
+        if let Some(&(answer, _)) = inner.remembered.get(&id) {
+            return answer;
+        }
+
+        let notice = |tag| confirm_notice(tag, key, peer);
+        let (mut inner, answer) =
+            self.ask(inner, PromptKind::Confirm, notice, timeout, asker);
+        match (answer, remember) {
+            (Some(answer), Some(period)) => {
+                let until = Instant::now() + period;
+                inner.remembered.insert(id, (answer, until));
+                answer
+            }
+            (answer, _) => answer == Some(true),
+        }
+    }
+
+    // Answer the conversation waiting on a confirm request. Returns false
+    // if no conversation is waiting on the tag.
+    pub fn confirm_done(&self, tag: u64, answer: bool) -> bool
+    {
+        self.answer(PromptKind::Confirm, tag, answer)
+    }
+
+    // Give up on every request being waited on, since the conversations
+    // waiting can't go on once the keyring is locked
+    pub fn keyring_locked(&self)
+    {
+        let mut inner = self.lock();
+        inner.locks += 1;
+        self.answered.notify_all();
+    }
+
+    // Send a request to the registered sessions of the given kind and wait
+    // for an answer, giving up if the keyring is locked meanwhile. Sessions
+    // that can't be written to anymore are dropped. The lock is let go
+    // while sending so that a session that is slow to read doesn't hold up
+    // every other session.
+    fn ask<'a, F>(
+        &'a self, mut inner: MutexGuard<'a, PromptsInner>, kind: PromptKind,
+        notice: F, timeout: Duration, asker: Option<&Prompter>
+    ) -> (MutexGuard<'a, PromptsInner>, Option<bool>)
+    where
+        F: Fn(u64) -> Message,
+    {
+        let deadline = Instant::now() + timeout;
+        let asker = asker.map(|p| p.id);
+        let locks = inner.locks;
+        if !inner.can_ask(kind, asker) {
+            return (inner, None);
+        }
+
+        let tag = inner.next_id();
+        inner.pending.insert(tag, (kind, None));
+        let notifiers: Vec<(u64, Notifier)> = inner
+            .prompters
+            .iter()
+            .filter(|&&(id, k, _)| k == kind && Some(id) != asker)
+            .map(|&(id, _, ref notifier)| (id, notifier.clone()))
+            .collect();
+        drop(inner);
+
+        let failed: Vec<u64> = notifiers
+            .iter()
+            .filter(|&&(_, ref notifier)| notifier.send(notice(tag)).is_err())
+            .map(|&(id, _)| id)
+            .collect();
+        inner = self.lock();
+        inner.prompters.retain(|&(id, _, _)| !failed.contains(&id));
+
+        let answer = loop {
+            if let Some(&(_, Some(answer))) = inner.pending.get(&tag) {
+                break Some(answer);
+            }
+            let now = Instant::now();
+            if !inner.can_ask(kind, asker) || inner.locks != locks ||
+                now >= deadline
+            {
+                break None;
+            }
+            inner = self.answered
+                .wait_timeout(inner, deadline - now)
+                .expect("failed to lock prompts")
+                .0;
+        };
+        inner.pending.remove(&tag);
+        (inner, answer)
+    }
+
+    fn answer(&self, kind: PromptKind, tag: u64, answer: bool) -> bool
+    {
+        let mut inner = self.lock();
+        let found = match inner.pending.get_mut(&tag) {
+            Some(&mut (k, ref mut a)) if k == kind => {
+                *a = Some(answer);
+                true
+            }
+            _ => false,
+        };
+        self.answered.notify_all();
+        found
+    }
+
+    fn unregister(&self, prompter: u64)
+    {
+        let mut inner = self.lock();
+        inner.prompters.retain(|&(id, _, _)| id != prompter);
+        self.answered.notify_all();
+    }
+}
+
+
+fn needkey_notice(tag: u64, template: &Attrs) -> Message
+{
+    let args = vec![Value::from(tag), attrs_to_value(template)];
+    ProtocolInfo::new(rpc1::ProtocolNotice::NeedKey, args).into()
+}
+
+
+fn confirm_notice(tag: u64, key: &Attrs, peer: &Attrs) -> Message
+{
+    let args = vec![
+        Value::from(tag),
+        attrs_to_value(key),
+        attrs_to_value(peer),
+    ];
+    ProtocolInfo::new(rpc1::ProtocolNotice::Confirm, args).into()
+}
+
+
+// ===========================================================================
+// Prompter
+// ===========================================================================
+
+
+// A session's registration as a prompter or confirmer, which lasts until it
+// is dropped
+pub struct Prompter {
+    prompts: PromptsHandle,
+    id: u64,
+}
+
+
+impl Prompter {
+    pub fn new(prompts: PromptsHandle, kind: PromptKind, notifier: Notifier)
+        -> Self
+    {
+        let id = {
+            let mut inner = prompts.lock();
+            let id = inner.next_id();
+            inner.prompters.push((id, kind, notifier));
+            id
+        };
+        Prompter {
+            prompts: prompts,
+            id: id,
+        }
+    }
+}
+
+
+impl Drop for Prompter {
+    fn drop(&mut self)
+    {
+        self.prompts.unregister(self.id);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
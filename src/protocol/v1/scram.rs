@@ -0,0 +1,659 @@
+// src/protocol/v1/scram.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::str;
+
+// Third-party imports
+
+use data_encoding::BASE64;
+use getrandom::getrandom;
+use hmac::{Hmac, Mac};
+use pbkdf2::pbkdf2_hmac;
+use sha2::{Digest, Sha256};
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Key, Keyring};
+use secret::SecretBuf;
+
+use super::auth::{AuthInfo, AuthProtocol, Phase, Role, find_key,
+                  key_template};
+
+
+// ===========================================================================
+// SCRAM-SHA-256 primitives (RFC 5802, RFC 7677)
+// ===========================================================================
+
+
+const DEFAULT_ITERATIONS: u32 = 4096;
+
+// A server can pick the iteration count, so how long the password takes to
+// hash is bounded. It is hashed w/o holding the keyring.
+const MAX_ITERATIONS: u32 = 1000000;
+
+const NONCE_LEN: usize = 18;
+
+const SALT_LEN: usize = 16;
+
+
+fn failed(msg: &str) -> SasdErrorKind
+{
+    SasdErrorKind::ProtocolFailed(format!("scram-sha-256: {}", msg))
+}
+
+
+fn phase_error(msg: &str) -> SasdErrorKind
+{
+    SasdErrorKind::ProtocolPhase(format!("scram-sha-256: {}", msg))
+}
+
+
+fn hmac(key: &[u8], data: &[u8]) -> SecretBuf
+{
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .expect("HMAC accepts keys of any length");
+    mac.update(data);
+    SecretBuf::from(mac.finalize().into_bytes().to_vec())
+}
+
+
+// Hi() from RFC 5802, ie the SaltedPassword
+pub fn salted_password(password: &[u8], salt: &[u8], iterations: u32)
+    -> SecretBuf
+{
+    let mut salted = SecretBuf::from(vec![0u8; 32]);
+    pbkdf2_hmac::<Sha256>(password, salt, iterations, salted.as_mut_bytes());
+    salted
+}
+
+
+fn client_key(salted: &[u8]) -> SecretBuf
+{
+    hmac(salted, b"Client Key")
+}
+
+
+fn server_key(salted: &[u8]) -> SecretBuf
+{
+    hmac(salted, b"Server Key")
+}
+
+
+fn stored_key(client_key: &[u8]) -> SecretBuf
+{
+    SecretBuf::from(Sha256::digest(client_key).to_vec())
+}
+
+
+fn xor(a: &[u8], b: &[u8]) -> SecretBuf
+{
+    SecretBuf::from(a.iter().zip(b).map(|(x, y)| x ^ y).collect::<Vec<_>>())
+}
+
+
+fn random_bytes(len: usize) -> SasdResult<Vec<u8>>
+{
+    let mut buf = vec![0u8; len];
+    getrandom(&mut buf).map_err(|e| {
+        failed(&format!("unable to get random bytes: {}", e))
+    })?;
+    Ok(buf)
+}
+
+
+fn new_nonce() -> SasdResult<String>
+{
+    Ok(BASE64.encode(&random_bytes(NONCE_LEN)?))
+}
+
+
+// User names are sent as saslname, w/ "=" and "," escaped. SASLprep is not
+// applied, so names and passwords are best kept to ASCII.
+fn to_saslname(user: &str) -> String
+{
+    user.replace('=', "=3D").replace(',', "=2C")
+}
+
+
+fn from_saslname(name: &str) -> SasdResult<String>
+{
+    let mut user = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(i) = rest.find('=') {
+        user.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with("=2C") {
+            user.push(',');
+        } else if rest.starts_with("=3D") {
+            user.push('=');
+        } else {
+            bail!(failed("malformed user name"))
+        }
+        rest = &rest[3..];
+    }
+    user.push_str(rest);
+    Ok(user)
+}
+
+
+// The value of an attribute of a message, eg r for the nonce. An m
+// attribute is a mandatory extension that isn't supported.
+fn field(msg: &str, attr: char) -> SasdResult<&str>
+{
+    let mut found = None;
+    for part in msg.split(',') {
+        let mut chars = part.chars();
+        match (chars.next(), chars.next()) {
+            (Some('m'), Some('=')) => bail!(failed("unsupported extension")),
+            (Some(a), Some('=')) if a == attr && found.is_none() => {
+                found = Some(&part[2..]);
+            }
+            _ => {}
+        }
+    }
+    match found {
+        Some(value) => Ok(value),
+        None => bail!(failed(&format!("message has no {} attribute", attr))),
+    }
+}
+
+
+fn decode_base64(value: &str, what: &str) -> SasdResult<SecretBuf>
+{
+    match BASE64.decode(value.as_bytes()) {
+        Ok(data) => Ok(SecretBuf::from(data)),
+        Err(_) => bail!(failed(&format!("{} is not base64", what))),
+    }
+}
+
+
+fn parse_iterations(value: &str) -> SasdResult<u32>
+{
+    match value.parse() {
+        Ok(i) if i > 0 && i <= MAX_ITERATIONS => Ok(i),
+        _ => bail!(failed("bad iteration count")),
+    }
+}
+
+
+// ===========================================================================
+// Credentials
+// ===========================================================================
+
+
+// What a key holds to make the SaltedPassword: either the !password itself,
+// or a !salted SaltedPassword (base64) made w/ the key's salt (base64) and
+// iterations attrs. A key w/ a !password may also have salt and iterations
+// attrs, which the server role uses instead of a random salt.
+struct Credentials {
+    password: Option<SecretBuf>,
+    salted: Option<SecretBuf>,
+    salt: Option<Vec<u8>>,
+    iterations: Option<u32>,
+}
+
+
+impl Credentials {
+    fn from_key(key: &Key) -> SasdResult<Credentials>
+    {
+        let salt = match key.get("salt") {
+            Some(s) => Some(decode_base64(s, "salt")?.as_bytes().to_vec()),
+            None => None,
+        };
+        let iterations = match key.get("iterations") {
+            Some(i) => Some(parse_iterations(i)?),
+            None => None,
+        };
+        let salted = match key.secret().get("!salted") {
+            Some(s) => {
+                let encoded = match s.as_str() {
+                    Some(e) => e,
+                    None => bail!(failed("salted password is not base64")),
+                };
+                if salt.is_none() || iterations.is_none() {
+                    let errmsg = "salted key needs salt and iterations attrs";
+                    bail!(failed(errmsg))
+                }
+                Some(decode_base64(encoded, "salted password")?)
+            }
+            None => None,
+        };
+        Ok(Credentials {
+            password: key.secret().get("!password").cloned(),
+            salted: salted,
+            salt: salt,
+            iterations: iterations,
+        })
+    }
+
+    fn salted_password(&self, salt: &[u8], iterations: u32)
+        -> SasdResult<SecretBuf>
+    {
+        if let Some(ref password) = self.password {
+            return Ok(salted_password(password.as_bytes(), salt, iterations));
+        }
+
+        let same = self.salt.as_ref().map(|s| &s[..]) == Some(salt) &&
+            self.iterations == Some(iterations);
+        match self.salted {
+            Some(ref salted) if same => Ok(salted.clone()),
+            _ => bail!(failed("salted key is for another salt")),
+        }
+    }
+
+    // The salt and iteration count the server role hands out
+    fn server_params(&self) -> SasdResult<(Vec<u8>, u32)>
+    {
+        let salt = match self.salt {
+            Some(ref s) => s.clone(),
+            None => random_bytes(SALT_LEN)?,
+        };
+        Ok((salt, self.iterations.unwrap_or(DEFAULT_ITERATIONS)))
+    }
+}
+
+
+// The public attrs and credentials of the first key w/ a !password, or
+// failing that w/ a !salted password
+fn find_credentials(keyring: &Keyring, attrs: &Attrs)
+    -> SasdResult<Option<(Attrs, Credentials)>>
+{
+    let key = find_key(keyring, attrs, &["!password"])
+        .or_else(|| find_key(keyring, attrs, &["!salted"]));
+    match key {
+        Some(key) => {
+            let public = key.public().clone();
+            Ok(Some((public, Credentials::from_key(key)?)))
+        }
+        None => Ok(None),
+    }
+}
+
+
+// ===========================================================================
+// Scram
+// ===========================================================================
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    ClientFirst,
+    ServerFirst,
+    ClientFinal,
+    ServerFinal,
+    Done,
+}
+
+
+// SCRAM-SHA-256 w/o channel binding. Each message of the exchange is read
+// from or written to the module as is; any base64 encoding used on the wire
+// (eg by SASL) is left to the client.
+//
+// In the client role client-first is read, server-first written, then
+// client-final read and server-final written; the server's signature is
+// checked before the protocol is done. The server role is the other way
+// around, picking the key by the user named in client-first.
+pub struct Scram {
+    role: Role,
+    attrs: Attrs,
+    template: Attrs,
+    key: Option<Attrs>,
+    step: Step,
+    user: String,
+    credentials: Option<Credentials>,
+
+    // Our part of the nonce, and the whole nonce once both parts are known
+    own_nonce: Option<String>,
+    nonce: String,
+
+    gs2_header: String,
+    client_first_bare: String,
+    server_first: String,
+    salted: SecretBuf,
+
+    // Salt and iteration count to make the SaltedPassword w/ once the
+    // keyring is let go
+    params: Option<(Vec<u8>, u32)>,
+
+    // The signature the server sends in server-final
+    server_signature: SecretBuf,
+}
+
+
+pub fn new_protocol() -> Box<AuthProtocol>
+{
+    Box::new(Scram::new())
+}
+
+
+impl Scram {
+    pub fn new() -> Self
+    {
+        Scram {
+            role: Role::Client,
+            attrs: Attrs::new(),
+            template: Attrs::new(),
+            key: None,
+            step: Step::ClientFirst,
+            user: String::new(),
+            credentials: None,
+            own_nonce: None,
+            nonce: String::new(),
+            gs2_header: String::new(),
+            client_first_bare: String::new(),
+            server_first: String::new(),
+            salted: SecretBuf::new(),
+            params: None,
+            server_signature: SecretBuf::new(),
+        }
+    }
+
+    // A module that uses the given nonce instead of a random one, for
+    // checking against known exchanges
+    pub fn with_nonce(nonce: &str) -> Self
+    {
+        Scram {
+            own_nonce: Some(nonce.to_owned()),
+            ..Scram::new()
+        }
+    }
+
+    fn own_nonce(&mut self) -> SasdResult<String>
+    {
+        match self.own_nonce {
+            Some(ref n) => Ok(n.clone()),
+            None => new_nonce(),
+        }
+    }
+
+    fn auth_message(&self, client_final_bare: &str) -> String
+    {
+        format!(
+            "{},{},{}",
+            self.client_first_bare,
+            self.server_first,
+            client_final_bare
+        )
+    }
+
+    fn client_final_bare(&self) -> String
+    {
+        format!(
+            "c={},r={}",
+            BASE64.encode(self.gs2_header.as_bytes()),
+            self.nonce
+        )
+    }
+
+    // --------------------
+    // Client role
+    // --------------------
+    fn client_first(&mut self) -> SasdResult<(SecretBuf, Phase)>
+    {
+        self.nonce = self.own_nonce()?;
+        self.gs2_header = "n,,".to_owned();
+        self.client_first_bare =
+            format!("n={},r={}", to_saslname(&self.user), self.nonce);
+        self.step = Step::ServerFirst;
+        let msg = format!("{}{}", self.gs2_header, self.client_first_bare);
+        Ok((SecretBuf::from(msg), Phase::Continue))
+    }
+
+    fn client_server_first(&mut self, msg: &str) -> SasdResult<Phase>
+    {
+        let nonce = field(msg, 'r')?;
+        if !nonce.starts_with(&self.nonce) || nonce.len() == self.nonce.len() {
+            bail!(failed("server nonce does not extend ours"))
+        }
+        let salt = decode_base64(field(msg, 's')?, "salt")?;
+        let iterations = parse_iterations(field(msg, 'i')?)?;
+
+        self.params = Some((salt.as_bytes().to_vec(), iterations));
+        self.nonce = nonce.to_owned();
+        self.server_first = msg.to_owned();
+        self.step = Step::ClientFinal;
+        Ok(Phase::Continue)
+    }
+
+    fn client_final(&mut self) -> SasdResult<(SecretBuf, Phase)>
+    {
+        let bare = self.client_final_bare();
+        let auth_message = self.auth_message(&bare);
+
+        let client_key = client_key(self.salted.as_bytes());
+        let stored_key = stored_key(client_key.as_bytes());
+        let signature = hmac(stored_key.as_bytes(), auth_message.as_bytes());
+        let proof = xor(client_key.as_bytes(), signature.as_bytes());
+
+        let server_key = server_key(self.salted.as_bytes());
+        self.server_signature =
+            hmac(server_key.as_bytes(), auth_message.as_bytes());
+
+        let msg = format!("{},p={}", bare, BASE64.encode(proof.as_bytes()));
+        self.step = Step::ServerFinal;
+        Ok((SecretBuf::from(msg), Phase::Continue))
+    }
+
+    fn client_server_final(&mut self, msg: &str) -> SasdResult<Phase>
+    {
+        if msg.starts_with("e=") {
+            bail!(failed(&format!("server error: {}", &msg[2..])))
+        }
+        let signature = decode_base64(field(msg, 'v')?, "server signature")?;
+        if !self.server_signature.ct_eq(signature.as_bytes()) {
+            bail!(failed("server signature does not match"))
+        }
+        self.step = Step::Done;
+        Ok(Phase::Done)
+    }
+
+    // --------------------
+    // Server role
+    // --------------------
+    fn server_client_first(&mut self, keyring: &Keyring, msg: &str)
+        -> SasdResult<Phase>
+    {
+        let mut parts = msg.splitn(3, ',');
+        let (cbind, authzid, bare) =
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(c), Some(a), Some(b)) => (c, a, b),
+                _ => bail!(failed("malformed client-first message")),
+            };
+        match cbind {
+            "n" | "y" => {}
+            _ => bail!(failed("channel binding is not supported")),
+        }
+        if !authzid.is_empty() {
+            bail!(failed("authorization identity is not supported"))
+        }
+
+        let user = from_saslname(field(bare, 'n')?)?;
+        let client_nonce = field(bare, 'r')?;
+        let mut attrs = self.attrs.clone();
+        attrs.insert("user".to_owned(), user.clone());
+        let credentials = match find_credentials(keyring, &attrs)? {
+            Some((key, c)) => {
+                self.key = Some(key);
+                c
+            }
+            None => bail!(failed("authentication failed")),
+        };
+
+        let (salt, iterations) = credentials.server_params()?;
+        self.credentials = Some(credentials);
+        self.params = Some((salt.clone(), iterations));
+        self.nonce = format!("{}{}", client_nonce, self.own_nonce()?);
+        self.gs2_header = format!("{},{},", cbind, authzid);
+        self.client_first_bare = bare.to_owned();
+        self.server_first = format!(
+            "r={},s={},i={}",
+            self.nonce,
+            BASE64.encode(&salt),
+            iterations
+        );
+        self.user = user;
+        self.step = Step::ServerFirst;
+        Ok(Phase::Continue)
+    }
+
+    fn server_first(&mut self) -> SasdResult<(SecretBuf, Phase)>
+    {
+        self.step = Step::ClientFinal;
+        Ok((SecretBuf::from(self.server_first.as_str()), Phase::Continue))
+    }
+
+    fn server_client_final(&mut self, msg: &str) -> SasdResult<Phase>
+    {
+        let bare = match msg.rfind(",p=") {
+            Some(i) => &msg[..i],
+            None => bail!(failed("client-final message has no proof")),
+        };
+        if bare != self.client_final_bare() {
+            bail!(failed("channel binding or nonce does not match"))
+        }
+        let proof = decode_base64(field(msg, 'p')?, "proof")?;
+
+        let auth_message = self.auth_message(bare);
+        let stored_key = {
+            let client_key = client_key(self.salted.as_bytes());
+            stored_key(client_key.as_bytes())
+        };
+        let signature = hmac(stored_key.as_bytes(), auth_message.as_bytes());
+        let client_key = xor(proof.as_bytes(), signature.as_bytes());
+        let valid = proof.len() == signature.len() &&
+            stored_key.ct_eq(&Sha256::digest(client_key.as_bytes()));
+        if !valid {
+            bail!(failed("authentication failed"))
+        }
+
+        let server_key = server_key(self.salted.as_bytes());
+        self.server_signature =
+            hmac(server_key.as_bytes(), auth_message.as_bytes());
+        self.step = Step::ServerFinal;
+        Ok(Phase::Continue)
+    }
+
+    fn server_final(&mut self) -> SasdResult<(SecretBuf, Phase)>
+    {
+        let signature = BASE64.encode(self.server_signature.as_bytes());
+        self.step = Step::Done;
+        Ok((SecretBuf::from(format!("v={}", signature)), Phase::Done))
+    }
+}
+
+
+impl Default for Scram {
+    fn default() -> Self
+    {
+        Scram::new()
+    }
+}
+
+
+impl AuthProtocol for Scram {
+    fn start(&mut self, keyring: &mut Keyring, attrs: &Attrs)
+        -> SasdResult<()>
+    {
+        self.role = Role::from_attrs(attrs)?;
+        self.template = key_template(attrs, &["!password"]);
+        self.attrs = attrs.clone();
+        if self.role == Role::Server {
+            // The key is picked by the user named in client-first
+            return Ok(());
+        }
+
+        match find_credentials(keyring, attrs)? {
+            Some((key, credentials)) => {
+                self.user = match key.get("user") {
+                    Some(u) if !u.is_empty() => u.clone(),
+                    _ => bail!(failed("key has no user attribute")),
+                };
+                self.key = Some(key);
+                self.credentials = Some(credentials);
+                Ok(())
+            }
+            None => {
+                let errmsg = "no key w/ a password".to_owned();
+                bail!(SasdErrorKind::ProtocolNeedKey(errmsg))
+            }
+        }
+    }
+
+    fn write(&mut self, keyring: &mut Keyring, data: &[u8])
+        -> SasdResult<Phase>
+    {
+        let msg = match str::from_utf8(data) {
+            Ok(m) => m,
+            Err(_) => bail!(failed("message is not text")),
+        };
+        match (self.role, self.step) {
+            (Role::Client, Step::ServerFirst) => self.client_server_first(msg),
+            (Role::Client, Step::ServerFinal) => self.client_server_final(msg),
+            (Role::Server, Step::ClientFirst) => {
+                self.server_client_first(keyring, msg)
+            }
+            (Role::Server, Step::ClientFinal) => self.server_client_final(msg),
+            _ => bail!(phase_error("expected a read")),
+        }
+    }
+
+    fn read(&mut self, _keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>
+    {
+        match (self.role, self.step) {
+            (Role::Client, Step::ClientFirst) => self.client_first(),
+            (Role::Client, Step::ClientFinal) => self.client_final(),
+            (Role::Server, Step::ServerFirst) => self.server_first(),
+            (Role::Server, Step::ServerFinal) => self.server_final(),
+            _ => bail!(phase_error("expected a write")),
+        }
+    }
+
+    // The password is hashed here so PBKDF2 doesn't hold up the keyring
+    fn run_unlocked(&mut self) -> SasdResult<()>
+    {
+        let (salt, iterations) = match self.params.take() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        self.salted = self.credentials
+            .as_ref()
+            .expect("module has credentials")
+            .salted_password(&salt, iterations)?;
+        Ok(())
+    }
+
+    fn needkey(&self) -> Attrs
+    {
+        self.template.clone()
+    }
+
+    fn key(&self) -> Option<&Attrs>
+    {
+        self.key.as_ref()
+    }
+
+    fn authinfo(&self) -> Option<AuthInfo>
+    {
+        let info = AuthInfo {
+            cuid: Some(self.user.clone()),
+            ..AuthInfo::default()
+        };
+        Some(info)
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,335 @@
+// src/protocol/v1/sign.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+use data_encoding::BASE64;
+use ed25519_dalek;
+use ed25519_dalek::Signer;
+use p256::ecdsa;
+use p256::ecdsa::signature::hazmat::PrehashSigner;
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Key, Keyring};
+use secret::SecretBuf;
+
+use super::auth::{AuthProtocol, Phase, Role, find_key, key_template};
+
+
+// ===========================================================================
+// Signing keys
+// ===========================================================================
+
+
+// Length of an Ed25519 seed, a P-256 private scalar and a SHA-256 digest
+const KEY_LEN: usize = 32;
+
+
+fn invalid_key(msg: &str) -> SasdErrorKind
+{
+    SasdErrorKind::InvalidKey(msg.to_owned())
+}
+
+
+// Signature algorithms, by the value of a key's alg attr
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alg {
+    Ed25519,
+    P256,
+}
+
+
+impl Alg {
+    pub fn from_attrs(attrs: &Attrs) -> SasdResult<Alg>
+    {
+        match attrs.get("alg").map(|a| a.as_str()) {
+            Some("ed25519") => Ok(Alg::Ed25519),
+            Some("p256") => Ok(Alg::P256),
+            Some(alg) => bail!(invalid_key(&format!("unknown alg: {}", alg))),
+            None => bail!(invalid_key("key has no alg attribute")),
+        }
+    }
+}
+
+
+// What the client writes to be signed, by the value of the input attr given
+// to ProtocolStart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+    // Signed as is by Ed25519, hashed w/ SHA-256 first by ECDSA
+    Message,
+
+    // A SHA-256 digest, signed directly by ECDSA. Ed25519 has no use for a
+    // digest and signs it like any other message.
+    Digest,
+}
+
+
+impl Input {
+    pub fn from_attrs(attrs: &Attrs) -> SasdResult<Input>
+    {
+        match attrs.get("input").map(|i| i.as_str()) {
+            None | Some("message") => Ok(Input::Message),
+            Some("digest") => Ok(Input::Digest),
+            Some(input) => {
+                let errmsg = format!("unknown input: {}", input);
+                bail!(SasdErrorKind::ProtocolFailed(errmsg))
+            }
+        }
+    }
+}
+
+
+// The Ed25519 key for a !secret attr holding a base64 32 byte seed
+pub fn ed25519_key(secret: &SecretBuf)
+    -> SasdResult<ed25519_dalek::SigningKey>
+{
+    let seed = decode_secret(secret)?;
+    let mut bytes = [0u8; KEY_LEN];
+    bytes.copy_from_slice(seed.as_bytes());
+    let key = ed25519_dalek::SigningKey::from_bytes(&bytes);
+    for b in bytes.iter_mut() {
+        *b = 0;
+    }
+    Ok(key)
+}
+
+
+fn decode_secret(secret: &SecretBuf) -> SasdResult<SecretBuf>
+{
+    let decoded = BASE64
+        .decode(secret.as_bytes())
+        .map(SecretBuf::from)
+        .ok();
+    match decoded {
+        Some(s) if s.len() == KEY_LEN => Ok(s),
+        _ => bail!(invalid_key("secret is not a base64 32 byte private key")),
+    }
+}
+
+
+// A private key kept in a key's !secret attr, base64 encoded
+pub enum PrivateKey {
+    Ed25519(ed25519_dalek::SigningKey),
+    P256(ecdsa::SigningKey),
+}
+
+
+impl PrivateKey {
+    pub fn from_secret(alg: Alg, secret: &SecretBuf) -> SasdResult<Self>
+    {
+        match alg {
+            Alg::Ed25519 => Ok(PrivateKey::Ed25519(ed25519_key(secret)?)),
+            Alg::P256 => {
+                let scalar = decode_secret(secret)?;
+                match ecdsa::SigningKey::from_slice(scalar.as_bytes()) {
+                    Ok(k) => Ok(PrivateKey::P256(k)),
+                    Err(_) => bail!(invalid_key("invalid P-256 private key")),
+                }
+            }
+        }
+    }
+
+    // The raw Ed25519 public key, or the uncompressed SEC1 P-256 point
+    pub fn public_key(&self) -> Vec<u8>
+    {
+        match *self {
+            PrivateKey::Ed25519(ref k) => {
+                k.verifying_key().to_bytes().to_vec()
+            }
+            PrivateKey::P256(ref k) => {
+                let point = k.verifying_key().to_encoded_point(false);
+                point.as_bytes().to_vec()
+            }
+        }
+    }
+
+    // A raw 64 byte Ed25519 signature, or a DER encoded ECDSA signature
+    pub fn sign(&self, data: &[u8], input: Input) -> SasdResult<Vec<u8>>
+    {
+        let signature = match (self, input) {
+            (&PrivateKey::Ed25519(ref k), _) => {
+                k.sign(data).to_bytes().to_vec()
+            }
+            (&PrivateKey::P256(ref k), Input::Message) => {
+                let signature: ecdsa::Signature = k.sign(data);
+                signature.to_der().as_bytes().to_vec()
+            }
+            (&PrivateKey::P256(ref k), Input::Digest) => {
+                if data.len() != KEY_LEN {
+                    let errmsg = "digest is not 32 bytes long".to_owned();
+                    bail!(SasdErrorKind::ProtocolPhase(errmsg))
+                }
+                let signature: ecdsa::Signature = match k.sign_prehash(data)
+                {
+                    Ok(s) => s,
+                    Err(_) => {
+                        let errmsg = "unable to sign digest".to_owned();
+                        bail!(SasdErrorKind::ProtocolFailed(errmsg))
+                    }
+                };
+                signature.to_der().as_bytes().to_vec()
+            }
+        };
+        Ok(signature)
+    }
+}
+
+
+// Fill in the base64 pubkey attr of a proto=sign key, or an ed25519
+// proto=ssh key, from its !secret attr, so the public key can be read back
+// w/ KeyList and the ssh agent can list the key. Other keys, and keys w/o a
+// secret, are left as is.
+pub fn add_pubkey(key: &mut Key) -> SasdResult<()>
+{
+    let has_pubkey = {
+        let public = key.public();
+        match public.get("proto").map(|p| p.as_str()) {
+            Some("sign") => true,
+            Some("ssh") => {
+                public.get("alg").map(|a| a == "ed25519") == Some(true)
+            }
+            _ => false,
+        }
+    };
+    let pubkey = match key.secret().get("!secret") {
+        Some(secret) if has_pubkey => {
+            let alg = Alg::from_attrs(key.public())?;
+            let private = PrivateKey::from_secret(alg, secret)?;
+            BASE64.encode(&private.public_key())
+        }
+        _ => return Ok(()),
+    };
+    if let Some(given) = key.public().get("pubkey") {
+        if *given != pubkey {
+            bail!(invalid_key("pubkey does not match the private key"))
+        }
+    }
+    key.set_public("pubkey", pubkey);
+    Ok(())
+}
+
+
+// ===========================================================================
+// Sign
+// ===========================================================================
+
+
+const SECRETS: &'static [&'static str] = &["!secret"];
+
+
+// The sign protocol signs whatever the client writes w/ the private key of
+// a proto=sign key, and hands back the signature to read. The private key
+// never leaves the daemon.
+#[derive(Default)]
+pub struct Sign {
+    template: Attrs,
+    key: Option<Attrs>,
+    private: Option<PrivateKey>,
+    input: Option<Input>,
+    signature: Option<SecretBuf>,
+    done: bool,
+}
+
+
+pub fn new_protocol() -> Box<AuthProtocol>
+{
+    Box::new(Sign::default())
+}
+
+
+impl AuthProtocol for Sign {
+    fn start(&mut self, keyring: &mut Keyring, attrs: &Attrs)
+        -> SasdResult<()>
+    {
+        if Role::from_attrs(attrs)? != Role::Client {
+            let errmsg = "sign protocol only has a client role".to_owned();
+            bail!(SasdErrorKind::ProtocolFailed(errmsg))
+        }
+        let input = Input::from_attrs(attrs)?;
+
+        self.template = key_template(attrs, SECRETS);
+        let key = match find_key(keyring, attrs, SECRETS) {
+            Some(k) => k,
+            None => {
+                let errmsg = "no key w/ a private key".to_owned();
+                bail!(SasdErrorKind::ProtocolNeedKey(errmsg))
+            }
+        };
+
+        let private = Alg::from_attrs(key.public()).and_then(|alg| {
+            PrivateKey::from_secret(alg, &key.secret()["!secret"])
+        });
+        let private = match private {
+            Ok(p) => p,
+            Err(e) => bail!(SasdErrorKind::ProtocolFailed(e.to_string())),
+        };
+        self.private = Some(private);
+        self.input = Some(input);
+        self.key = Some(key.public().clone());
+        Ok(())
+    }
+
+    fn write(&mut self, _keyring: &mut Keyring, data: &[u8])
+        -> SasdResult<Phase>
+    {
+        if self.signature.is_some() || self.done {
+            let errmsg = "sign protocol takes a single message".to_owned();
+            bail!(SasdErrorKind::ProtocolPhase(errmsg))
+        }
+        let signature = match (self.private.as_ref(), self.input) {
+            (Some(p), Some(input)) => p.sign(data, input)?,
+            _ => {
+                let errmsg = "sign protocol has not started".to_owned();
+                bail!(SasdErrorKind::ProtocolPhase(errmsg))
+            }
+        };
+        self.signature = Some(SecretBuf::from(signature));
+        Ok(Phase::Continue)
+    }
+
+    fn read(&mut self, _keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>
+    {
+        match self.signature.take() {
+            Some(signature) => {
+                self.done = true;
+                Ok((signature, Phase::Done))
+            }
+            None => {
+                let errmsg = if self.done {
+                    "sign protocol is done"
+                } else {
+                    "sign protocol needs a message to sign first"
+                };
+                bail!(SasdErrorKind::ProtocolPhase(errmsg.to_owned()))
+            }
+        }
+    }
+
+    fn needkey(&self) -> Attrs
+    {
+        self.template.clone()
+    }
+
+    fn key(&self) -> Option<&Attrs>
+    {
+        self.key.as_ref()
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
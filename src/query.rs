@@ -0,0 +1,280 @@
+// src/query.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Key, is_secret_attr};
+
+
+// ===========================================================================
+// Glob
+// ===========================================================================
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum GlobItem {
+    Char(char),
+    Star,
+}
+
+
+// An attribute value pattern where an unquoted * matches any run of
+// characters
+#[derive(Debug, Clone, PartialEq)]
+struct Glob {
+    items: Vec<GlobItem>,
+}
+
+
+impl Glob {
+    fn matches(&self, text: &str) -> bool
+    {
+        let text: Vec<char> = text.chars().collect();
+        let (mut p, mut t) = (0, 0);
+
+        // Position of the last star seen and the text position it was tried
+        // at, used to backtrack when a literal match fails
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while t < text.len() {
+            match self.items.get(p) {
+                Some(&GlobItem::Char(c)) if c == text[t] => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                Some(&GlobItem::Star) => {
+                    backtrack = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            match backtrack {
+                Some((star, start)) => {
+                    p = star + 1;
+                    t = start + 1;
+                    backtrack = Some((star, t));
+                }
+                None => return false,
+            }
+        }
+
+        self.items[p..].iter().all(|i| *i == GlobItem::Star)
+    }
+}
+
+
+// ===========================================================================
+// Term
+// ===========================================================================
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Test {
+    // attr?
+    Present,
+
+    // attr=value
+    Value(Glob),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+struct Term {
+    attr: String,
+    test: Test,
+    negate: bool,
+}
+
+
+impl Term {
+    fn matches(&self, key: &Key) -> bool
+    {
+        // Secret values need not be text, so their presence is checked
+        // w/o looking at them
+        let found = match self.test {
+            Test::Present if is_secret_attr(&self.attr) => {
+                key.secret().contains_key(&self.attr)
+            }
+            Test::Present => key.public().contains_key(&self.attr),
+            Test::Value(ref glob) => {
+                key.get(&self.attr).map_or(false, |v| glob.matches(v))
+            }
+        };
+        found != self.negate
+    }
+}
+
+
+// ===========================================================================
+// Query
+// ===========================================================================
+
+
+// A list of space separated terms that a key must all satisfy:
+//
+// * attr=value    attr is set to value; * in value matches any characters
+// * attr?         attr is set to anything
+// * -term         the term must not match
+//
+// Values may be wrapped in single quotes to include spaces or a literal *,
+// with '' standing for a quote character. Secret attributes may only be
+// tested for presence. An empty query matches every key.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+
+impl Query {
+    pub fn parse(query: &str) -> SasdResult<Self>
+    {
+        let terms = tokenize(query)?
+            .into_iter()
+            .map(|t| parse_term(&t))
+            .collect::<SasdResult<Vec<Term>>>()?;
+        Ok(Query { terms: terms })
+    }
+
+    pub fn matches(&self, key: &Key) -> bool
+    {
+        self.terms.iter().all(|t| t.matches(key))
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.terms.is_empty()
+    }
+}
+
+
+fn invalid_query<T>(msg: String) -> SasdResult<T>
+{
+    Err(SasdErrorKind::InvalidQuery(msg).into())
+}
+
+
+// Split a query into terms. Each character is paired with whether it was
+// quoted so that quoted =, ?, * and - lose their meaning.
+fn tokenize(query: &str) -> SasdResult<Vec<Vec<(char, bool)>>>
+{
+    let mut tokens = Vec::new();
+    let mut token = Vec::new();
+    let mut in_token = false;
+    let mut chars = query.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_token = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => {
+                            if chars.peek() == Some(&'\'') {
+                                chars.next();
+                                token.push(('\'', true));
+                            } else {
+                                break;
+                            }
+                        }
+                        Some(q) => token.push((q, true)),
+                        None => {
+                            return invalid_query(
+                                format!("unterminated quote in {:?}", query),
+                            )
+                        }
+                    }
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_token {
+                    tokens.push(token);
+                    token = Vec::new();
+                    in_token = false;
+                }
+            }
+            c => {
+                in_token = true;
+                token.push((c, false));
+            }
+        }
+    }
+    if in_token {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+
+fn parse_term(token: &[(char, bool)]) -> SasdResult<Term>
+{
+    let text: String = token.iter().map(|&(c, _)| c).collect();
+
+    let (negate, token) = match token.first() {
+        Some(&('-', false)) => (true, &token[1..]),
+        _ => (false, token),
+    };
+
+    let (attr, test) = match token.iter().position(|&t| t == ('=', false)) {
+        Some(i) => {
+            let items = token[i + 1..]
+                .iter()
+                .map(|&(c, quoted)| if c == '*' && !quoted {
+                    GlobItem::Star
+                } else {
+                    GlobItem::Char(c)
+                })
+                .collect();
+            (&token[..i], Test::Value(Glob { items: items }))
+        }
+        None => {
+            match token.split_last() {
+                Some((&('?', false), attr)) => (attr, Test::Present),
+                _ => {
+                    return invalid_query(
+                        format!("expected attr=value or attr?: {}", text),
+                    )
+                }
+            }
+        }
+    };
+
+    if attr.is_empty() {
+        return invalid_query(format!("missing attribute name: {}", text));
+    }
+    if attr.iter().any(|&(c, quoted)| !quoted && (c == '*' || c == '?')) {
+        return invalid_query(format!("invalid attribute name: {}", text));
+    }
+
+    let attr: String = attr.iter().map(|&(c, _)| c).collect();
+    if is_secret_attr(&attr) && test != Test::Present {
+        return invalid_query(
+            format!("secret attribute can only be tested for presence: {}",
+                    attr),
+        );
+    }
+
+    Ok(Term {
+        attr: attr,
+        test: test,
+        negate: negate,
+    })
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -40,32 +40,138 @@ pub enum SessionMethod {
     // 1. Auth token
     AuthAttach = 5,
 
-    // No arguments
+    // Optional single argument: query string (see query::Query). If no
+    // query is given, all keys are listed.
+    //
+    // Response will be a list of maps of attr=value pairs, one per matching
+    // key. Secret attributes are listed with a placeholder value
     KeyList = 6,
 
     // Single argument: map of attr=value pairs (both attr and value are
-    // strings)
+    // strings). Attributes starting with ! are secret and their values are
+    // never returned by KeyList. A proto=sign key w/ a !secret gets a pubkey
+    // attr holding its public key. Secret values must not be empty, and a
+    // !password must pass the password policy of the key's dom, if any
     CreateKey = 7,
 
-    // Single argument: map of attr=value pairs (both attr and value are
-    // strings)
+    // Arguments:
+    // 1. Map of the new key's public attr=value pairs (both strings)
+    // 2. Map describing the secret to generate (both strings):
+    //    * type: password, ed25519 or totp
+    //    * length: of a password (default 24, or the min_length of the
+    //      password policy of the key's dom if longer) or a totp seed in
+    //      bytes (default 20)
+    //    * charset: classes a password is drawn from, comma separated:
+    //      lower, upper, digits, symbols (default lower,upper,digits plus
+    //      the classes of the password policy of the key's dom)
+    //
+    // Creates the key w/ a secret generated by the daemon, so that it never
+    // passes through the client. An ed25519 key gets a base64 !secret and
+    // pubkey, a totp key a base32 !secret. Response will be a map of the new
+    // key's public attrs
+    GenerateKey = 34,
+
+    // Arguments:
+    // 1. Map of attr=value pairs naming a single key by its public attrs
+    // 2. Map of the secret attr=value pairs to set (both strings)
+    //
+    // A new !password must pass the password policy of the key's dom and,
+    // if the policy limits reuse, differ from the key's last passwords. A
+    // new !secret of a proto=sign key gets it a new pubkey. Response will be
+    // nil
+    UpdateKey = 36,
+
+    // Arguments:
+    // 1. Query string, or map of attr=value pairs naming a single key
+    // 2. Optional bool: if true, nothing is deleted when more than one key
+    //    matches
+    //
+    // Response will be the number of keys deleted
     DeleteKey = 8,
+
+    // Arguments:
+    // 1. Master passphrase (string)
+    // 2. Optional bool: if true and there is no keyring file yet, an empty
+    //    one is created w/ the passphrase
+    //
+    // Loads the keys from the keyring file. Unlocking an unlocked keyring
+    // does nothing
+    Unlock = 27,
+
+    // No arguments
+    //
+    // Drops all keys from memory for every session until the next Unlock.
+    // A keyring w/o a file can't be locked since it could never be unlocked
+    Lock = 28,
 }
 
 
 #[cfg(target_family = "unix")]
 #[derive(Debug, PartialEq, Clone, CodeConvert)]
 pub enum SessionMethod {
-    // No arguments
+    // Optional single argument: query string (see query::Query). If no
+    // query is given, all keys are listed.
+    //
+    // Response will be a list of maps of attr=value pairs, one per matching
+    // key. Secret attributes are listed with a placeholder value
     KeyList = 6,
 
     // Single argument: map of attr=value pairs (both attr and value are
-    // strings)
+    // strings). Attributes starting with ! are secret and their values are
+    // never returned by KeyList. A proto=sign key w/ a !secret gets a pubkey
+    // attr holding its public key. Secret values must not be empty, and a
+    // !password must pass the password policy of the key's dom, if any
     CreateKey = 7,
 
-    // Single argument: map of attr=value pairs (both attr and value are
-    // strings)
+    // Arguments:
+    // 1. Map of the new key's public attr=value pairs (both strings)
+    // 2. Map describing the secret to generate (both strings):
+    //    * type: password, ed25519 or totp
+    //    * length: of a password (default 24, or the min_length of the
+    //      password policy of the key's dom if longer) or a totp seed in
+    //      bytes (default 20)
+    //    * charset: classes a password is drawn from, comma separated:
+    //      lower, upper, digits, symbols (default lower,upper,digits plus
+    //      the classes of the password policy of the key's dom)
+    //
+    // Creates the key w/ a secret generated by the daemon, so that it never
+    // passes through the client. An ed25519 key gets a base64 !secret and
+    // pubkey, a totp key a base32 !secret. Response will be a map of the new
+    // key's public attrs
+    GenerateKey = 34,
+
+    // Arguments:
+    // 1. Map of attr=value pairs naming a single key by its public attrs
+    // 2. Map of the secret attr=value pairs to set (both strings)
+    //
+    // A new !password must pass the password policy of the key's dom and,
+    // if the policy limits reuse, differ from the key's last passwords. A
+    // new !secret of a proto=sign key gets it a new pubkey. Response will be
+    // nil
+    UpdateKey = 36,
+
+    // Arguments:
+    // 1. Query string, or map of attr=value pairs naming a single key
+    // 2. Optional bool: if true, nothing is deleted when more than one key
+    //    matches
+    //
+    // Response will be the number of keys deleted
     DeleteKey = 8,
+
+    // Arguments:
+    // 1. Master passphrase (string)
+    // 2. Optional bool: if true and there is no keyring file yet, an empty
+    //    one is created w/ the passphrase
+    //
+    // Loads the keys from the keyring file. Unlocking an unlocked keyring
+    // does nothing
+    Unlock = 27,
+
+    // No arguments
+    //
+    // Drops all keys from memory for every session until the next Unlock.
+    // A keyring w/o a file can't be locked since it could never be unlocked
+    Lock = 28,
 }
 
 
@@ -79,6 +185,33 @@ pub enum SessionError {
     KeyExists = 10,
 
     KeyNotFound = 11,
+
+    InvalidQuery = 25,
+
+    // A delete limited to a single key matched more than one key
+    MultipleKeys = 26,
+
+    // The keyring must be unlocked first
+    Locked = 29,
+
+    // Unlock failed, eg wrong passphrase
+    InvalidPassphrase = 30,
+
+    // CreateKey or UpdateKey was given a key that can't be used, eg a
+    // proto=sign key whose !secret is not a valid private key, or
+    // GenerateKey an invalid spec
+    InvalidKey = 33,
+
+    // A new !password is empty or breaks the password policy of the key's
+    // dom; the result is a string saying why
+    PolicyViolation = 35,
+
+    // Lock refused because the keyring has no file to unlock it from
+    NotLockable = 37,
+
+    // Unlock failed for a reason other than the passphrase, eg the keyring
+    // file could not be created; the result is a string saying why
+    KeyringError = 38,
 }
 
 
@@ -93,24 +226,57 @@ pub enum ProtocolMethod {
     // strings)
     // The map must include a proto attribute whose value is the name of the
     // protocol module to use
+    //
+    // Starts a conversation w/ the protocol module. Until the conversation
+    // ends, ProtocolWrite and ProtocolRead are passed to the module and
+    // ProtocolAuthInfo gets InvalidProtocolAuth; any other request ends the
+    // conversation
     ProtocolStart = 12,
 
-    // Single argument: bytes
+    // Single argument: bytes received from the other party
+    //
+    // Response will be a bool, true if the protocol has finished
     ProtocolWrite = 13,
 
-    // Single argument: bytes
+    // No arguments
+    //
+    // Response will be a list with 2 items:
+    // 1. bytes to send to the other party
+    // 2. bool, true if the protocol has finished
     ProtocolRead = 14,
 
-    // Single argument: map of attr=value pairs (attr is a string)
+    // Optional single argument: map w/ tag and answer attrs (both strings)
+    //
+    // With no arguments, registers the session as a confirmer. Until the
+    // session ends, it is sent a ProtocolNotice::Confirm notification
+    // whenever a conversation is about to use a key marked confirm=yes, and
+    // the conversation waits for a confirmer to answer. The answer is sent
+    // as {tag: <tag of the notification>, answer: yes|no}
     ProtocolConfirm = 15,
 
-    // Single argument: map of attr=value pairs (attr is a string)
+    // No arguments
+    //
+    // Registers the session as a prompter. Until the session ends, it is
+    // sent a ProtocolNotice::NeedKey notification whenever a conversation
+    // can't find a key, and the conversation waits for the prompter to add
+    // the key and send ProtocolNeedKeyDone
     ProtocolNeedKey = 16,
 
-    // Single argument: map of attr=value pairs (attr is a string)
+    // Single argument: tag (unsigned integer) of a NeedKey notification
+    //
+    // Sent by a prompter once it has added the key, so that the waiting
+    // conversation tries to find it again
     ProtocolNeedKeyDone = 17,
 
     // No arguments
+    //
+    // Response will be a map of what the last finished protocol established:
+    // 1. cuid: client user (string)
+    // 2. suid: server user (string)
+    // 3. cap: capability or ticket to hand on to the other party (bytes)
+    // 4. secret: secret shared w/ the other party, eg a session key (bytes)
+    //
+    // Only the items the protocol module filled in are included
     ProtocolAuthInfo = 18,
 }
 
@@ -119,17 +285,47 @@ pub enum ProtocolMethod {
 pub enum ProtocolError {
     Nil = 0,
 
+    // No protocol module w/ the requested name
     UnknownProtocol = 19,
 
+    // The module did not expect this message; the conversation continues.
+    // Also sent for a ProtocolNeedKeyDone or ProtocolConfirm answer that
+    // matches no waiting conversation
     InvalidProtocolMessage = 20,
 
+    // The protocol failed and the conversation has ended
     ProtocolError = 21,
 
+    // No key matched; the result is a map of the attrs a key would need
     ProtocolNeedKey = 22,
 
+    // The key needs confirming and no confirmer said yes; the conversation
+    // has ended
     ProtocolNeedConfirmation = 23,
 
+    // No protocol has finished, or the current conversation has not, so
+    // there is no auth info. A conversation in progress continues
     InvalidProtocolAuth = 24,
+
+    // Same as SessionError::Locked
+    Locked = 29,
+}
+
+
+#[derive(Debug, PartialEq, Clone, CodeConvert)]
+pub enum ProtocolNotice {
+    // Sent to prompters. Arguments:
+    // 1. tag (unsigned integer) to send back w/ ProtocolNeedKeyDone
+    // 2. map of the attrs a key would need, secret attrs w/ a placeholder
+    //    value
+    NeedKey = 31,
+
+    // Sent to confirmers. Arguments:
+    // 1. tag (unsigned integer) to send back w/ the ProtocolConfirm answer
+    // 2. map of the public attrs of the key about to be used
+    // 3. map of attrs identifying the client using the key, eg uid, gid and
+    //    pid where they are known
+    Confirm = 32,
 }
 
 
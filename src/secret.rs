@@ -0,0 +1,228 @@
+// src/secret.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::fmt;
+use std::ptr;
+use std::str;
+use std::sync::atomic::{self, Ordering};
+
+// Third-party imports
+
+#[cfg(unix)]
+use libc;
+
+// Local imports
+
+
+// ===========================================================================
+// SecretBuf
+// ===========================================================================
+
+
+// Bytes that must not outlive their use: passphrases, tokens, secret key
+// attributes and protocol intermediate values.
+//
+// The buffer is wiped when dropped, compared in constant time, never shown by
+// Debug, and locked into memory where the OS allows it so that it is not
+// written to swap. The buffer is never resized, so no stray copies are left
+// behind by reallocation.
+pub struct SecretBuf {
+    buf: Vec<u8>,
+    locked: bool,
+}
+
+
+impl SecretBuf {
+    pub fn new() -> Self
+    {
+        SecretBuf::from(Vec::new())
+    }
+
+    pub fn as_bytes(&self) -> &[u8]
+    {
+        &self.buf[..]
+    }
+
+    // The contents may be changed in place, but the buffer is never resized
+    pub fn as_mut_bytes(&mut self) -> &mut [u8]
+    {
+        &mut self.buf[..]
+    }
+
+    // Secrets are usually text; None is returned if this one isn't
+    pub fn as_str(&self) -> Option<&str>
+    {
+        str::from_utf8(&self.buf).ok()
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.buf.is_empty()
+    }
+
+    // Compare against other bytes without returning early, so the time taken
+    // does not tell how much of the secret matched. Only the length leaks.
+    pub fn ct_eq(&self, other: &[u8]) -> bool
+    {
+        if self.buf.len() != other.len() {
+            return false;
+        }
+        let diff = self.buf
+            .iter()
+            .zip(other.iter())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        unsafe { ptr::read_volatile(&diff) == 0 }
+    }
+
+    fn wipe(&mut self)
+    {
+        // Clear the whole allocation, not just the used part
+        let cap = self.buf.capacity();
+        let p = self.buf.as_mut_ptr();
+        for i in 0..cap {
+            unsafe { ptr::write_volatile(p.offset(i as isize), 0) };
+        }
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+}
+
+
+impl From<Vec<u8>> for SecretBuf {
+    fn from(buf: Vec<u8>) -> SecretBuf
+    {
+        let locked = mlock(&buf);
+        SecretBuf {
+            buf: buf,
+            locked: locked,
+        }
+    }
+}
+
+
+impl From<String> for SecretBuf {
+    fn from(s: String) -> SecretBuf
+    {
+        SecretBuf::from(s.into_bytes())
+    }
+}
+
+
+impl<'a> From<&'a [u8]> for SecretBuf {
+    fn from(s: &'a [u8]) -> SecretBuf
+    {
+        SecretBuf::from(s.to_vec())
+    }
+}
+
+
+impl<'a> From<&'a str> for SecretBuf {
+    fn from(s: &'a str) -> SecretBuf
+    {
+        SecretBuf::from(s.as_bytes())
+    }
+}
+
+
+impl Default for SecretBuf {
+    fn default() -> Self
+    {
+        SecretBuf::new()
+    }
+}
+
+
+impl Clone for SecretBuf {
+    fn clone(&self) -> Self
+    {
+        SecretBuf::from(self.as_bytes())
+    }
+}
+
+
+impl PartialEq for SecretBuf {
+    fn eq(&self, other: &SecretBuf) -> bool
+    {
+        self.ct_eq(other.as_bytes())
+    }
+}
+
+
+impl Eq for SecretBuf {}
+
+
+impl fmt::Debug for SecretBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "SecretBuf(***)")
+    }
+}
+
+
+impl Drop for SecretBuf {
+    fn drop(&mut self)
+    {
+        self.wipe();
+        if self.locked {
+            munlock(&self.buf);
+        }
+    }
+}
+
+
+// ===========================================================================
+// Memory locking
+// ===========================================================================
+
+
+// Locking may fail, eg when over RLIMIT_MEMLOCK. The secret is still usable,
+// it just may end up in swap.
+#[cfg(unix)]
+fn mlock(buf: &Vec<u8>) -> bool
+{
+    if buf.capacity() == 0 {
+        return false;
+    }
+    let ret = unsafe {
+        libc::mlock(buf.as_ptr() as *const libc::c_void, buf.capacity())
+    };
+    ret == 0
+}
+
+
+#[cfg(unix)]
+fn munlock(buf: &Vec<u8>)
+{
+    unsafe {
+        libc::munlock(buf.as_ptr() as *const libc::c_void, buf.capacity());
+    }
+}
+
+
+#[cfg(windows)]
+fn mlock(_buf: &Vec<u8>) -> bool
+{
+    false
+}
+
+
+#[cfg(windows)]
+fn munlock(_buf: &Vec<u8>) {}
+
+
+// ===========================================================================
+//
+// ===========================================================================
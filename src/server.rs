@@ -0,0 +1,372 @@
+// src/server.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::io::{self, Read, Write};
+#[cfg(windows)]
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Third-party imports
+
+use rmpv::Value;
+use rmpv::decode::read_value;
+use rmpv::encode::write_value;
+use siminau_rpc::message::{Message, RpcMessage};
+
+// Local imports
+
+use autolock;
+use error::{SasdErrorKind, SasdResult, SasdResultExt};
+use keyring::KeyringHandle;
+#[cfg(unix)]
+use os::unix::agent::{Agent, serve_agent};
+#[cfg(unix)]
+use os::unix::listener;
+#[cfg(unix)]
+use os::unix::peer::{check_peer, peer_cred};
+#[cfg(windows)]
+use protocol::SessionStore;
+use protocol::{Start, StateValue};
+use protocol::v1::auth::ProtocolRegistryHandle;
+use protocol::v1::prompt::{PromptsHandle, new_prompts_handle};
+use settings::SettingsHandle;
+use state::{SessionState, SessionStatus};
+
+
+// ===========================================================================
+// Message IO
+// ===========================================================================
+
+
+// Read a single msgpack-rpc message from the stream. None is returned if the
+// stream was closed before any part of a new message was read.
+pub fn read_message<R: Read>(rd: &mut R) -> SasdResult<Option<Message>>
+{
+    // Read the first byte separately so that a closed stream can be told
+    // apart from a message that was cut short
+    let mut first = [0u8; 1];
+    loop {
+        match rd.read(&mut first) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let mut rd = (&first[..]).chain(rd);
+    let value = read_value(&mut rd).chain_err(
+        || SasdErrorKind::InvalidMessage,
+    )?;
+
+    let msg = Message::from(value)?;
+    Ok(Some(msg))
+}
+
+
+pub fn write_message<W: Write>(wr: &mut W, msg: Message) -> SasdResult<()>
+{
+    let value = Value::Array(msg.as_vec().clone());
+    write_value(wr, &value).chain_err(|| "unable to write message")?;
+    wr.flush()?;
+    Ok(())
+}
+
+
+// Writes messages to a client from any thread, eg to send a prompter a
+// notification from another session. Once a session has a notifier, its
+// replies are sent through it as well so that messages can't be interleaved.
+#[derive(Clone)]
+pub struct Notifier {
+    writer: Arc<Mutex<Box<Write + Send>>>,
+}
+
+
+impl Notifier {
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        Notifier { writer: Arc::new(Mutex::new(Box::new(writer))) }
+    }
+
+    pub fn send(&self, msg: Message) -> SasdResult<()>
+    {
+        let mut writer = self.writer.lock().expect("failed to lock notifier");
+        write_message(&mut *writer, msg)
+    }
+}
+
+
+// ===========================================================================
+// Session
+// ===========================================================================
+
+
+#[cfg(unix)]
+pub fn new_session_state(
+    settings: SettingsHandle, keyring: KeyringHandle,
+    protocols: ProtocolRegistryHandle, prompts: PromptsHandle
+) -> SessionState
+{
+    SessionState::new(
+        settings,
+        keyring,
+        protocols,
+        prompts,
+        StateValue::Start(Start::new()),
+    )
+}
+
+
+#[cfg(windows)]
+pub fn new_session_state(
+    settings: SettingsHandle, keyring: KeyringHandle,
+    protocols: ProtocolRegistryHandle, prompts: PromptsHandle
+) -> SessionState
+{
+    SessionState::new(
+        SessionStore::default(),
+        settings,
+        keyring,
+        protocols,
+        prompts,
+        StateValue::Start(Start::new()),
+    )
+}
+
+
+// Drive a single client connection through the session state machine until
+// the client is done or disconnects
+pub fn serve_session<S>(stream: &mut S, session: &mut SessionState)
+    -> SasdResult<()>
+where
+    S: Read + Write,
+{
+    loop {
+        let msg = match read_message(stream)? {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+
+        match session.change(msg)? {
+            SessionStatus::Reply(reply) => {
+                match session.notifier() {
+                    Some(notifier) => notifier.send(reply)?,
+                    None => write_message(stream, reply)?,
+                }
+            }
+            SessionStatus::NoReply => {}
+            SessionStatus::Done => return Ok(()),
+        }
+    }
+}
+
+
+// ===========================================================================
+// Listener
+// ===========================================================================
+
+
+fn spawn_session<S>(mut stream: S, mut session: SessionState)
+where
+    S: Read + Write + Send + 'static,
+{
+    thread::spawn(move || {
+        if let Err(e) = serve_session(&mut stream, &mut session) {
+            eprintln!("sasd: session ended with error: {}", e);
+        }
+    });
+}
+
+
+// Look up who is on the other end of the connection and refuse anyone who
+// isn't allowed to use the daemon
+#[cfg(unix)]
+fn authenticate_peer(
+    stream: &UnixStream, settings: &SettingsHandle, keyring: &KeyringHandle,
+    protocols: &ProtocolRegistryHandle, prompts: &PromptsHandle
+) -> SasdResult<SessionState>
+{
+    let peer = peer_cred(stream)?;
+    {
+        let config = settings.read().expect("failed to read server settings");
+        check_peer(&peer, config.peers())?;
+    }
+
+    let mut session = new_session_state(
+        settings.clone(),
+        keyring.clone(),
+        protocols.clone(),
+        prompts.clone(),
+    );
+    session.set_peer(peer);
+    session.set_notifier(Notifier::new(stream.try_clone()?));
+    Ok(session)
+}
+
+
+// Same as authenticate_peer, for a connection to the ssh-agent socket
+#[cfg(unix)]
+fn authenticate_agent(
+    stream: &UnixStream, settings: &SettingsHandle, keyring: &KeyringHandle,
+    prompts: &PromptsHandle
+) -> SasdResult<Agent>
+{
+    let peer = peer_cred(stream)?;
+    {
+        let config = settings.read().expect("failed to read server settings");
+        check_peer(&peer, config.peers())?;
+    }
+    Ok(Agent::new(
+        settings.clone(),
+        keyring.clone(),
+        prompts.clone(),
+        peer.attrs(),
+    ))
+}
+
+
+// Accept connections on the user's ssh-agent socket, serving each one on its
+// own thread
+#[cfg(unix)]
+fn spawn_agent(
+    listener: UnixListener, settings: SettingsHandle, keyring: KeyringHandle,
+    prompts: PromptsHandle
+)
+{
+    thread::spawn(move || for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("sasd: failed to accept agent connection: {}", e);
+                continue;
+            }
+        };
+
+        match authenticate_agent(&stream, &settings, &keyring, &prompts) {
+            Ok(agent) => {
+                thread::spawn(move || {
+                    if let Err(e) = serve_agent(&mut stream, &agent) {
+                        eprintln!("sasd: agent connection ended: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("sasd: rejected agent connection: {}", e),
+        }
+    });
+}
+
+
+// Accept connections on the user's socket in the configured socket
+// directory, serving each one on its own thread. The ssh-agent socket is
+// served alongside it if enabled.
+#[cfg(unix)]
+pub fn serve(
+    settings: SettingsHandle, keyring: KeyringHandle,
+    protocols: ProtocolRegistryHandle
+) -> SasdResult<()>
+{
+    let (listener, agent_listener) = {
+        let config = settings.read().expect("failed to read server settings");
+        let unix = config.unix();
+        let listener = listener::bind(&unix.socket_dir, config.peers())?;
+        let agent_listener = if unix.ssh_agent {
+            Some(listener::bind_agent(&unix.socket_dir, config.peers())?)
+        } else {
+            None
+        };
+        (listener, agent_listener)
+    };
+    let prompts = new_prompts_handle();
+    autolock::spawn(settings.clone(), keyring.clone(), prompts.clone());
+    if let Some(agent_listener) = agent_listener {
+        spawn_agent(
+            agent_listener,
+            settings.clone(),
+            keyring.clone(),
+            prompts.clone(),
+        );
+    }
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("sasd: failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        let session = authenticate_peer(
+            &stream,
+            &settings,
+            &keyring,
+            &protocols,
+            &prompts,
+        );
+        match session {
+            Ok(session) => spawn_session(stream, session),
+            Err(e) => eprintln!("sasd: rejected connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+
+// Accept connections on the configured localhost port, serving each one on
+// its own thread
+#[cfg(windows)]
+pub fn serve(
+    settings: SettingsHandle, keyring: KeyringHandle,
+    protocols: ProtocolRegistryHandle
+) -> SasdResult<()>
+{
+    let port = {
+        let config = settings.read().expect("failed to read server settings");
+        config.port
+    };
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let prompts = new_prompts_handle();
+    autolock::spawn(settings.clone(), keyring.clone(), prompts.clone());
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(s) => {
+                let mut session = new_session_state(
+                    settings.clone(),
+                    keyring.clone(),
+                    protocols.clone(),
+                    prompts.clone(),
+                );
+                match s.try_clone() {
+                    Ok(w) => session.set_notifier(Notifier::new(w)),
+                    Err(e) => {
+                        eprintln!("sasd: failed to accept connection: {}", e);
+                        continue;
+                    }
+                }
+                spawn_session(s, session)
+            }
+            Err(e) => eprintln!("sasd: failed to accept connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -10,10 +10,11 @@
 
 // Stdlib imports
 
+use std::collections::BTreeMap;
 use std::mem;
-use std::path::PathBuf;
-use std::rc::Rc;
-use std::sync::RwLock;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 // Third-party imports
 
@@ -29,12 +30,12 @@ use error::{SasdErrorKind, SasdResult};
 // ===========================================================================
 
 
-pub type SettingsHandle = Rc<RwLock<Settings>>;
+pub type SettingsHandle = Arc<RwLock<Settings>>;
 
 
 pub fn new_settings_handle(settings: Settings) -> SettingsHandle
 {
-    Rc::new(RwLock::new(settings))
+    Arc::new(RwLock::new(settings))
 }
 
 
@@ -52,6 +53,51 @@ pub struct WindowsConfig {
 #[derive(Debug, Deserialize)]
 pub struct UnixConfig {
     socket_dir: String,
+    ssh_agent: Option<bool>,
+    ssh_agent_remove_all: Option<bool>,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct PeersConfig {
+    allow_uids: Option<Vec<u32>>,
+    allow_gids: Option<Vec<u32>>,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct KeyringConfig {
+    path: Option<String>,
+    start_locked: Option<bool>,
+}
+
+
+// Durations are given as a number of seconds or as a string with a unit
+// suffix, eg "90s", "15m", "8h" or "1d"
+#[derive(Debug, Deserialize)]
+pub struct LockConfig {
+    idle_timeout: Option<String>,
+    max_unlocked: Option<String>,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct ProtocolConfig {
+    needkey_timeout: Option<String>,
+    confirm_timeout: Option<String>,
+    confirm_remember: Option<String>,
+}
+
+
+// Policy for the !password of keys whose dom attr is the policy's domain.
+// classes names the character classes a password must have at least one
+// character of, and reuse how many of its last passwords a key may not go
+// back to.
+#[derive(Debug, Deserialize)]
+pub struct PolicyConfig {
+    min_length: Option<usize>,
+    classes: Option<Vec<String>>,
+    reuse: Option<usize>,
 }
 
 
@@ -60,6 +106,43 @@ pub struct SettingsConfig {
     port: u16,
     unix: Option<UnixConfig>,
     windows: Option<WindowsConfig>,
+    peers: Option<PeersConfig>,
+    keyring: Option<KeyringConfig>,
+    lock: Option<LockConfig>,
+    protocol: Option<ProtocolConfig>,
+    policy: Option<BTreeMap<String, PolicyConfig>>,
+}
+
+
+pub fn parse_duration(value: &str) -> SasdResult<Duration>
+{
+    let value = value.trim();
+    let (num, unit) = match value.find(|c: char| !c.is_digit(10)) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => 0,
+    };
+
+    match num.parse::<u64>() {
+        Ok(n) if multiplier > 0 => {
+            n.checked_mul(multiplier).map(Duration::from_secs).ok_or_else(
+                || {
+                    let errmsg = format!("duration too large: {}", value);
+                    SasdErrorKind::SettingsError(errmsg).into()
+                },
+            )
+        }
+        _ => {
+            let errmsg = format!("invalid duration: {:?}", value);
+            bail!(SasdErrorKind::SettingsError(errmsg))
+        }
+    }
 }
 
 
@@ -74,6 +157,8 @@ pub struct UnixBuilder {
 
     // Settings
     socket_dir: Option<PathBuf>,
+    ssh_agent: bool,
+    ssh_agent_remove_all: bool,
 }
 
 
@@ -83,6 +168,8 @@ impl UnixBuilder {
         UnixBuilder {
             _builder: builder,
             socket_dir: None,
+            ssh_agent: false,
+            ssh_agent_remove_all: false,
         }
     }
 
@@ -92,12 +179,32 @@ impl UnixBuilder {
         Ok(self)
     }
 
+    // Also serve the ssh-agent protocol on a second socket in the socket
+    // directory
+    pub fn ssh_agent(mut self, enable: bool) -> Self
+    {
+        self.ssh_agent = enable;
+        self
+    }
+
+    // Let ssh-add -D delete every proto=ssh key from the keyring. Off by
+    // default since the keys are gone for good, not just from the agent.
+    pub fn ssh_agent_remove_all(mut self, enable: bool) -> Self
+    {
+        self.ssh_agent_remove_all = enable;
+        self
+    }
+
     pub fn unix_done(self) -> SasdResult<SettingsBuilder>
     {
         let mut builder = self._builder;
         match self.socket_dir {
             Some(s) => {
-                let unix = UnixSection { socket_dir: s };
+                let unix = UnixSection {
+                    socket_dir: s,
+                    ssh_agent: self.ssh_agent,
+                    ssh_agent_remove_all: self.ssh_agent_remove_all,
+                };
                 builder.unix = Some(unix);
                 Ok(builder)
             }
@@ -160,11 +267,328 @@ impl WindowsBuilder {
 }
 
 
+#[derive(Debug)]
+pub struct PeersBuilder {
+    _builder: SettingsBuilder,
+    allow_uids: Vec<u32>,
+    allow_gids: Vec<u32>,
+}
+
+
+impl PeersBuilder {
+    fn new(builder: SettingsBuilder) -> Self
+    {
+        PeersBuilder {
+            _builder: builder,
+            allow_uids: Vec::new(),
+            allow_gids: Vec::new(),
+        }
+    }
+
+    pub fn allow_uid(mut self, uid: u32) -> Self
+    {
+        if !self.allow_uids.contains(&uid) {
+            self.allow_uids.push(uid);
+        }
+        self
+    }
+
+    pub fn allow_gid(mut self, gid: u32) -> Self
+    {
+        if !self.allow_gids.contains(&gid) {
+            self.allow_gids.push(gid);
+        }
+        self
+    }
+
+    pub fn peers_done(self) -> SasdResult<SettingsBuilder>
+    {
+        let mut builder = self._builder;
+        builder.peers = Some(PeersSection {
+            allow_uids: self.allow_uids,
+            allow_gids: self.allow_gids,
+        });
+        Ok(builder)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct KeyringBuilder {
+    _builder: SettingsBuilder,
+    path: Option<PathBuf>,
+    start_locked: bool,
+}
+
+
+impl KeyringBuilder {
+    fn new(builder: SettingsBuilder) -> Self
+    {
+        KeyringBuilder {
+            _builder: builder,
+            path: None,
+            start_locked: false,
+        }
+    }
+
+    // The keyring file itself need not exist yet, but the directory it goes
+    // in must
+    pub fn path(mut self, path: String) -> SasdResult<Self>
+    {
+        let p = PathBuf::from(path);
+        if p.is_dir() {
+            let errmsg = format!("path is a directory: {}", p.display());
+            bail!(SasdErrorKind::SettingsError(errmsg))
+        }
+        match p.parent() {
+            Some(dir) if dir != Path::new("") => {
+                self._builder.validate_path(dir.display().to_string())?;
+            }
+            _ => {}
+        }
+        self.path = Some(p);
+        Ok(self)
+    }
+
+    pub fn start_locked(mut self, locked: bool) -> Self
+    {
+        self.start_locked = locked;
+        self
+    }
+
+    pub fn keyring_done(self) -> SasdResult<SettingsBuilder>
+    {
+        let mut builder = self._builder;
+        builder.keyring = Some(KeyringSection {
+            path: self.path,
+            start_locked: self.start_locked,
+        });
+        Ok(builder)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct LockBuilder {
+    _builder: SettingsBuilder,
+    idle_timeout: Option<Duration>,
+    max_unlocked: Option<Duration>,
+}
+
+
+impl LockBuilder {
+    fn new(builder: SettingsBuilder) -> Self
+    {
+        LockBuilder {
+            _builder: builder,
+            idle_timeout: None,
+            max_unlocked: None,
+        }
+    }
+
+    // Lock the keyring after this long without any client requests
+    pub fn idle_timeout(mut self, timeout: Duration) -> SasdResult<Self>
+    {
+        let timeout = self.validate_duration("idle_timeout", timeout)?;
+        self.idle_timeout = Some(timeout);
+        Ok(self)
+    }
+
+    // Lock the keyring this long after it was unlocked, even if it is in use
+    pub fn max_unlocked(mut self, timeout: Duration) -> SasdResult<Self>
+    {
+        let timeout = self.validate_duration("max_unlocked", timeout)?;
+        self.max_unlocked = Some(timeout);
+        Ok(self)
+    }
+
+    pub fn lock_done(self) -> SasdResult<SettingsBuilder>
+    {
+        if let (Some(idle), Some(max)) = (self.idle_timeout, self.max_unlocked)
+        {
+            if idle > max {
+                bail!(SasdErrorKind::SettingsError(
+                    "lock: idle_timeout must not be longer than max_unlocked"
+                        .to_owned(),
+                ))
+            }
+        }
+
+        let mut builder = self._builder;
+        builder.lock = Some(LockSection {
+            idle_timeout: self.idle_timeout,
+            max_unlocked: self.max_unlocked,
+        });
+        Ok(builder)
+    }
+
+    fn validate_duration(&self, name: &str, timeout: Duration)
+        -> SasdResult<Duration>
+    {
+        if timeout == Duration::from_secs(0) {
+            let errmsg = format!("lock: {} must be greater than 0", name);
+            bail!(SasdErrorKind::SettingsError(errmsg))
+        }
+        Ok(timeout)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct ProtocolBuilder {
+    _builder: SettingsBuilder,
+    needkey_timeout: Duration,
+    confirm_timeout: Duration,
+    confirm_remember: Option<Duration>,
+}
+
+
+impl ProtocolBuilder {
+    fn new(builder: SettingsBuilder) -> Self
+    {
+        let defaults = ProtocolSection::default();
+        ProtocolBuilder {
+            _builder: builder,
+            needkey_timeout: defaults.needkey_timeout,
+            confirm_timeout: defaults.confirm_timeout,
+            confirm_remember: defaults.confirm_remember,
+        }
+    }
+
+    // How long a conversation waits for a prompter to add a missing key
+    pub fn needkey_timeout(mut self, timeout: Duration) -> SasdResult<Self>
+    {
+        let timeout = self.validate_duration("needkey_timeout", timeout)?;
+        self.needkey_timeout = timeout;
+        Ok(self)
+    }
+
+    // How long a conversation waits for a confirmer to answer before the
+    // use of a confirm=yes key is refused
+    pub fn confirm_timeout(mut self, timeout: Duration) -> SasdResult<Self>
+    {
+        let timeout = self.validate_duration("confirm_timeout", timeout)?;
+        self.confirm_timeout = timeout;
+        Ok(self)
+    }
+
+    // How long a confirmer's answer holds for later uses of the same key by
+    // the same peer
+    pub fn confirm_remember(mut self, period: Duration) -> SasdResult<Self>
+    {
+        let period = self.validate_duration("confirm_remember", period)?;
+        self.confirm_remember = Some(period);
+        Ok(self)
+    }
+
+    pub fn protocol_done(self) -> SasdResult<SettingsBuilder>
+    {
+        let mut builder = self._builder;
+        builder.protocol = Some(ProtocolSection {
+            needkey_timeout: self.needkey_timeout,
+            confirm_timeout: self.confirm_timeout,
+            confirm_remember: self.confirm_remember,
+        });
+        Ok(builder)
+    }
+
+    fn validate_duration(&self, name: &str, timeout: Duration)
+        -> SasdResult<Duration>
+    {
+        if timeout == Duration::from_secs(0) {
+            let errmsg = format!("protocol: {} must be greater than 0", name);
+            bail!(SasdErrorKind::SettingsError(errmsg))
+        }
+        Ok(timeout)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct PolicyBuilder {
+    _builder: SettingsBuilder,
+    domain: String,
+    min_length: usize,
+    classes: Vec<String>,
+    reuse: usize,
+}
+
+
+impl PolicyBuilder {
+    fn new(builder: SettingsBuilder, domain: String) -> Self
+    {
+        PolicyBuilder {
+            _builder: builder,
+            domain: domain,
+            min_length: 0,
+            classes: Vec::new(),
+            reuse: 0,
+        }
+    }
+
+    pub fn min_length(mut self, length: usize) -> Self
+    {
+        self.min_length = length;
+        self
+    }
+
+    // One of lower, upper, digits or symbols
+    pub fn class(mut self, class: String) -> SasdResult<Self>
+    {
+        if !PASSWORD_CLASSES.contains(&class.as_str()) {
+            let errmsg = format!(
+                "policy.{}: unknown character class: {}",
+                self.domain,
+                class
+            );
+            bail!(SasdErrorKind::SettingsError(errmsg))
+        }
+        if !self.classes.contains(&class) {
+            self.classes.push(class);
+        }
+        Ok(self)
+    }
+
+    // Refuse a new password that is the same as any of the key's last count
+    // passwords, counting the one it replaces
+    pub fn reuse(mut self, count: usize) -> Self
+    {
+        self.reuse = count;
+        self
+    }
+
+    pub fn policy_done(self) -> SasdResult<SettingsBuilder>
+    {
+        if self.domain.is_empty() {
+            bail!(SasdErrorKind::SettingsError(
+                "policy: domain must not be empty".to_owned(),
+            ))
+        }
+
+        let mut builder = self._builder;
+        builder.policies.insert(
+            self.domain,
+            PolicySection {
+                min_length: self.min_length,
+                classes: self.classes,
+                reuse: self.reuse,
+            },
+        );
+        Ok(builder)
+    }
+}
+
+
 #[derive(Debug)]
 pub struct SettingsBuilder {
     port: Option<u16>,
     unix: Option<UnixSection>,
     windows: Option<WindowsSection>,
+    peers: Option<PeersSection>,
+    keyring: Option<KeyringSection>,
+    lock: Option<LockSection>,
+    protocol: Option<ProtocolSection>,
+    policies: BTreeMap<String, PolicySection>,
 }
 
 
@@ -175,6 +599,11 @@ impl SettingsBuilder {
             port: None,
             unix: None,
             windows: None,
+            peers: None,
+            keyring: None,
+            lock: None,
+            protocol: None,
+            policies: BTreeMap::new(),
         }
     }
 
@@ -182,7 +611,15 @@ impl SettingsBuilder {
     {
         let unix_config = mem::replace(&mut config.unix, None);
         match unix_config {
-            Some(c) => self.unix().socket_dir(c.socket_dir)?.unix_done(),
+            Some(c) => {
+                self.unix()
+                    .socket_dir(c.socket_dir)?
+                    .ssh_agent(c.ssh_agent.unwrap_or(false))
+                    .ssh_agent_remove_all(
+                        c.ssh_agent_remove_all.unwrap_or(false),
+                    )
+                    .unix_done()
+            }
             None => {
                 if cfg!(unix) {
                     bail!(SasdErrorKind::SettingsError(
@@ -215,12 +652,116 @@ impl SettingsBuilder {
         }
     }
 
+    fn from_peers_config(self, config: &mut SettingsConfig)
+        -> SasdResult<Self>
+    {
+        let peers_config = mem::replace(&mut config.peers, None);
+        match peers_config {
+            Some(c) => {
+                let mut peers = self.peers();
+                for uid in c.allow_uids.unwrap_or_default() {
+                    peers = peers.allow_uid(uid);
+                }
+                for gid in c.allow_gids.unwrap_or_default() {
+                    peers = peers.allow_gid(gid);
+                }
+                peers.peers_done()
+            }
+            None => Ok(self),
+        }
+    }
+
+    fn from_keyring_config(self, config: &mut SettingsConfig)
+        -> SasdResult<Self>
+    {
+        let keyring_config = mem::replace(&mut config.keyring, None);
+        match keyring_config {
+            Some(c) => {
+                let mut keyring = self.keyring();
+                if let Some(p) = c.path {
+                    keyring = keyring.path(p)?;
+                }
+                keyring
+                    .start_locked(c.start_locked.unwrap_or(false))
+                    .keyring_done()
+            }
+            None => Ok(self),
+        }
+    }
+
+    fn from_lock_config(self, config: &mut SettingsConfig) -> SasdResult<Self>
+    {
+        let lock_config = mem::replace(&mut config.lock, None);
+        match lock_config {
+            Some(c) => {
+                let mut lock = self.lock();
+                if let Some(t) = c.idle_timeout {
+                    lock = lock.idle_timeout(parse_duration(&t)?)?;
+                }
+                if let Some(t) = c.max_unlocked {
+                    lock = lock.max_unlocked(parse_duration(&t)?)?;
+                }
+                lock.lock_done()
+            }
+            None => Ok(self),
+        }
+    }
+
+    fn from_protocol_config(self, config: &mut SettingsConfig)
+        -> SasdResult<Self>
+    {
+        let protocol_config = mem::replace(&mut config.protocol, None);
+        match protocol_config {
+            Some(c) => {
+                let mut protocol = self.protocol();
+                if let Some(t) = c.needkey_timeout {
+                    protocol = protocol.needkey_timeout(parse_duration(&t)?)?;
+                }
+                if let Some(t) = c.confirm_timeout {
+                    protocol = protocol.confirm_timeout(parse_duration(&t)?)?;
+                }
+                if let Some(t) = c.confirm_remember {
+                    let period = parse_duration(&t)?;
+                    protocol = protocol.confirm_remember(period)?;
+                }
+                protocol.protocol_done()
+            }
+            None => Ok(self),
+        }
+    }
+
+    fn from_policy_config(self, config: &mut SettingsConfig)
+        -> SasdResult<Self>
+    {
+        let policy_config = mem::replace(&mut config.policy, None);
+        let mut builder = self;
+        for (domain, c) in policy_config.unwrap_or_default() {
+            let mut policy = builder.policy(domain);
+            if let Some(l) = c.min_length {
+                policy = policy.min_length(l);
+            }
+            for class in c.classes.unwrap_or_default() {
+                policy = policy.class(class)?;
+            }
+            if let Some(r) = c.reuse {
+                policy = policy.reuse(r);
+            }
+            builder = policy.policy_done()?;
+        }
+        Ok(builder)
+    }
+
     pub fn from_config(mut config: SettingsConfig) -> SasdResult<Settings>
     {
         let builder = SettingsBuilder::new();
         let builder = builder.port(config.port)?;
         let builder = builder.from_unix_config(&mut config)?;
         let builder = builder.from_windows_config(&mut config)?;
+        let builder = builder.from_peers_config(&mut config)?;
+        let builder = builder.from_keyring_config(&mut config)?;
+        let builder = builder.from_lock_config(&mut config)?;
+        let builder = builder.from_protocol_config(&mut config)?;
+        let builder = builder.from_policy_config(&mut config)?;
         builder.build()
     }
 
@@ -234,6 +775,31 @@ impl SettingsBuilder {
         WindowsBuilder::new(self)
     }
 
+    pub fn peers(self) -> PeersBuilder
+    {
+        PeersBuilder::new(self)
+    }
+
+    pub fn keyring(self) -> KeyringBuilder
+    {
+        KeyringBuilder::new(self)
+    }
+
+    pub fn lock(self) -> LockBuilder
+    {
+        LockBuilder::new(self)
+    }
+
+    pub fn protocol(self) -> ProtocolBuilder
+    {
+        ProtocolBuilder::new(self)
+    }
+
+    pub fn policy(self, domain: String) -> PolicyBuilder
+    {
+        PolicyBuilder::new(self, domain)
+    }
+
     pub fn port(mut self, port: u16) -> SasdResult<Self>
     {
         if port < 1024 {
@@ -261,6 +827,11 @@ impl SettingsBuilder {
                     port: p,
                     unix: self.unix.unwrap(),
                     windows: self.windows,
+                    peers: self.peers.unwrap_or_default(),
+                    keyring: self.keyring.unwrap_or_default(),
+                    lock: self.lock.unwrap_or_default(),
+                    protocol: self.protocol.unwrap_or_default(),
+                    policies: self.policies,
                 }
             }
             None => {
@@ -288,6 +859,11 @@ impl SettingsBuilder {
                     port: p,
                     unix: self.unix,
                     windows: self.windows.unwrap(),
+                    peers: self.peers.unwrap_or_default(),
+                    keyring: self.keyring.unwrap_or_default(),
+                    lock: self.lock.unwrap_or_default(),
+                    protocol: self.protocol.unwrap_or_default(),
+                    policies: self.policies,
                 }
             }
             None => {
@@ -332,6 +908,78 @@ pub struct WindowsSection {
 #[derive(Debug)]
 pub struct UnixSection {
     pub socket_dir: PathBuf,
+    pub ssh_agent: bool,
+    pub ssh_agent_remove_all: bool,
+}
+
+
+// Users and groups, other than the user running the daemon, that are allowed
+// to connect
+#[derive(Debug, Default)]
+pub struct PeersSection {
+    pub allow_uids: Vec<u32>,
+    pub allow_gids: Vec<u32>,
+}
+
+
+// Where the keyring file is kept. If no path is given, the keyring file is
+// kept in the user's data directory.
+//
+// If start_locked is set, the daemon starts without asking for the master
+// passphrase and waits for a client to unlock the keyring.
+#[derive(Debug, Default)]
+pub struct KeyringSection {
+    pub path: Option<PathBuf>,
+    pub start_locked: bool,
+}
+
+
+// When the keyring is locked again after being unlocked. Either timeout may
+// be left unset.
+#[derive(Debug, Default)]
+pub struct LockSection {
+    pub idle_timeout: Option<Duration>,
+    pub max_unlocked: Option<Duration>,
+}
+
+
+// How long a conversation that needs a key waits for a prompter to add it
+// before the client is sent ProtocolNeedKey, and how long it waits for a
+// confirmer before the client is sent ProtocolNeedConfirmation. Confirmer
+// answers are only remembered if confirm_remember is set.
+#[derive(Debug)]
+pub struct ProtocolSection {
+    pub needkey_timeout: Duration,
+    pub confirm_timeout: Duration,
+    pub confirm_remember: Option<Duration>,
+}
+
+
+impl Default for ProtocolSection {
+    fn default() -> Self
+    {
+        ProtocolSection {
+            needkey_timeout: Duration::from_secs(60),
+            confirm_timeout: Duration::from_secs(60),
+            confirm_remember: None,
+        }
+    }
+}
+
+
+// Character classes a password policy can ask for
+pub const PASSWORD_CLASSES: &'static [&'static str] =
+    &["lower", "upper", "digits", "symbols"];
+
+
+// What a new !password of a key in the policy's domain must be like. It
+// must be at least min_length characters long, have a character from each
+// of classes, and differ from the key's last reuse passwords.
+#[derive(Debug, Clone, Default)]
+pub struct PolicySection {
+    pub min_length: usize,
+    pub classes: Vec<String>,
+    pub reuse: usize,
 }
 
 
@@ -341,6 +989,11 @@ pub struct Settings {
     pub port: u16,
     unix: UnixSection,
     windows: Option<WindowsSection>,
+    peers: PeersSection,
+    keyring: KeyringSection,
+    lock: LockSection,
+    protocol: ProtocolSection,
+    policies: BTreeMap<String, PolicySection>,
 }
 
 
@@ -350,6 +1003,11 @@ pub struct Settings {
     pub port: u16,
     unix: Option<UnixSection>,
     windows: WindowsSection,
+    peers: PeersSection,
+    keyring: KeyringSection,
+    lock: LockSection,
+    protocol: ProtocolSection,
+    policies: BTreeMap<String, PolicySection>,
 }
 
 
@@ -383,6 +1041,32 @@ impl Settings {
     {
         &self.windows
     }
+
+    pub fn peers(&self) -> &PeersSection
+    {
+        &self.peers
+    }
+
+    pub fn keyring(&self) -> &KeyringSection
+    {
+        &self.keyring
+    }
+
+    pub fn lock(&self) -> &LockSection
+    {
+        &self.lock
+    }
+
+    pub fn protocol(&self) -> &ProtocolSection
+    {
+        &self.protocol
+    }
+
+    // The password policy for keys whose dom attr is domain, if there is one
+    pub fn policy(&self, domain: &str) -> Option<&PolicySection>
+    {
+        self.policies.get(domain)
+    }
 }
 
 
@@ -395,7 +1079,11 @@ pub mod test {
     // Helpers
 
     pub mod helper {
-        use super::super::{Settings, UnixSection, WindowsSection};
+        use std::collections::BTreeMap;
+
+        use super::super::{KeyringSection, LockSection, PeersSection,
+                           ProtocolSection, Settings, UnixSection,
+                           WindowsSection};
 
         #[cfg(unix)]
         pub fn new_settings(port: u16, unix: UnixSection, windows: Option<WindowsSection>)
@@ -405,6 +1093,11 @@ pub mod test {
                 port: port,
                 unix: unix,
                 windows: windows,
+                peers: PeersSection::default(),
+                keyring: KeyringSection::default(),
+                lock: LockSection::default(),
+                protocol: ProtocolSection::default(),
+                policies: BTreeMap::new(),
             }
         }
 
@@ -416,6 +1109,11 @@ pub mod test {
                 port: port,
                 unix: unix,
                 windows: windows,
+                peers: PeersSection::default(),
+                keyring: KeyringSection::default(),
+                lock: LockSection::default(),
+                protocol: ProtocolSection::default(),
+                policies: BTreeMap::new(),
             }
         }
     }
@@ -15,16 +15,45 @@
 
 // Stdlib imports
 
+use std::mem;
+
 // Third-party imports
 
+use siminau_rpc::message::Message;
+
 // Local imports
 
+use error::SasdResult;
+use keyring::KeyringHandle;
+#[cfg(unix)]
+use os::unix::peer::PeerCred;
 #[cfg(windows)]
 use protocol::SessionStore;
-use protocol::StateValue;
+use protocol::{Start, State, StateValue};
+use protocol::v1::auth::ProtocolRegistryHandle;
+use protocol::v1::prompt::{Prompter, PromptsHandle};
+use server::Notifier;
 use settings::SettingsHandle;
 
 
+// ===========================================================================
+// SessionStatus
+// ===========================================================================
+
+
+// Outcome of passing a single message through the session's current state
+#[derive(Debug)]
+pub enum SessionStatus {
+    // Send the message back to the client
+    Reply(Message),
+
+    // Nothing to send back to the client
+    NoReply,
+
+    // The client has ended the session
+    Done,
+}
+
 
 // ===========================================================================
 // SessionState
@@ -35,18 +64,37 @@ pub struct SessionState {
     #[cfg(windows)]
     session_store: SessionStore,
 
+    #[cfg(unix)]
+    peer: Option<PeerCred>,
+
     server_settings: SettingsHandle,
+    keyring: KeyringHandle,
+    protocols: ProtocolRegistryHandle,
+    prompts: PromptsHandle,
+    notifier: Option<Notifier>,
+    prompter: Option<Prompter>,
+    confirmer: Option<Prompter>,
     state: StateValue,
 }
 
 
 impl SessionState {
     #[cfg(unix)]
-    pub fn new(server_settings: SettingsHandle, state: StateValue)
-        -> SessionState
+    pub fn new(
+        server_settings: SettingsHandle, keyring: KeyringHandle,
+        protocols: ProtocolRegistryHandle, prompts: PromptsHandle,
+        state: StateValue
+    ) -> SessionState
     {
         SessionState {
+            peer: None,
             server_settings: server_settings,
+            keyring: keyring,
+            protocols: protocols,
+            prompts: prompts,
+            notifier: None,
+            prompter: None,
+            confirmer: None,
             state: state,
         }
     }
@@ -54,12 +102,19 @@ impl SessionState {
     #[cfg(windows)]
     pub fn new(
         session_store: SessionStore, server_settings: SettingsHandle,
-        state: StateValue
+        keyring: KeyringHandle, protocols: ProtocolRegistryHandle,
+        prompts: PromptsHandle, state: StateValue
     ) -> SessionState
     {
         SessionState {
             session_store: session_store,
             server_settings: server_settings,
+            keyring: keyring,
+            protocols: protocols,
+            prompts: prompts,
+            notifier: None,
+            prompter: None,
+            confirmer: None,
             state: state,
         }
     }
@@ -75,10 +130,112 @@ impl SessionState {
         &mut self.server_settings
     }
 
+    pub fn keyring(&mut self) -> &mut KeyringHandle
+    {
+        &mut self.keyring
+    }
+
+    pub fn protocols(&self) -> &ProtocolRegistryHandle
+    {
+        &self.protocols
+    }
+
+    pub fn prompts(&self) -> &PromptsHandle
+    {
+        &self.prompts
+    }
+
+    // Sends messages to the client; only sessions served over a connection
+    // have one
+    pub fn notifier(&self) -> Option<&Notifier>
+    {
+        self.notifier.as_ref()
+    }
+
+    pub fn set_notifier(&mut self, notifier: Notifier)
+    {
+        self.notifier = Some(notifier);
+    }
+
+    // Set once the client has registered the session as a prompter
+    pub fn prompter(&self) -> Option<&Prompter>
+    {
+        self.prompter.as_ref()
+    }
+
+    pub fn set_prompter(&mut self, prompter: Prompter)
+    {
+        self.prompter = Some(prompter);
+    }
+
+    // Set once the client has registered the session as a confirmer
+    pub fn confirmer(&self) -> Option<&Prompter>
+    {
+        self.confirmer.as_ref()
+    }
+
+    pub fn set_confirmer(&mut self, confirmer: Prompter)
+    {
+        self.confirmer = Some(confirmer);
+    }
+
+    // Identity of the connected client, if it is known
+    #[cfg(unix)]
+    pub fn peer(&self) -> Option<&PeerCred>
+    {
+        self.peer.as_ref()
+    }
+
+    #[cfg(unix)]
+    pub fn set_peer(&mut self, peer: PeerCred)
+    {
+        self.peer = Some(peer);
+    }
+
     pub fn handle(&mut self) -> SessionStateHandle
     {
         SessionStateHandle::new(self)
     }
+
+    pub fn state(&self) -> &StateValue
+    {
+        &self.state
+    }
+
+    // Run a message through the current state and move on to whatever state
+    // it returns. If no new state is returned, the current state is kept
+    // unless there is also no reply, which means the session is done.
+    pub fn change(&mut self, msg: Message) -> SasdResult<SessionStatus>
+    {
+        // The current state is moved out so that it can be given access to
+        // the rest of the session state while it handles the message
+        let placeholder = StateValue::Start(Start::new());
+        let mut current = mem::replace(&mut self.state, placeholder);
+        let result = current.change(self.handle(), msg);
+
+        let (next, reply) = match result {
+            Ok(r) => r,
+            Err(e) => {
+                self.state = current;
+                return Err(e);
+            }
+        };
+
+        let status = match (next, reply) {
+            (None, None) => {
+                self.state = current;
+                SessionStatus::Done
+            }
+            (next, reply) => {
+                self.state = next.unwrap_or(current);
+                match reply {
+                    Some(msg) => SessionStatus::Reply(msg),
+                    None => SessionStatus::NoReply,
+                }
+            }
+        };
+        Ok(status)
+    }
 }
 
 
@@ -103,6 +260,52 @@ impl<'sessionstate> SessionStateHandle<'sessionstate> {
     {
         self.session_state.server_settings()
     }
+
+    pub fn keyring(&mut self) -> &mut KeyringHandle
+    {
+        self.session_state.keyring()
+    }
+
+    pub fn protocols(&self) -> &ProtocolRegistryHandle
+    {
+        self.session_state.protocols()
+    }
+
+    pub fn prompts(&self) -> &PromptsHandle
+    {
+        self.session_state.prompts()
+    }
+
+    pub fn notifier(&self) -> Option<&Notifier>
+    {
+        self.session_state.notifier()
+    }
+
+    pub fn prompter(&self) -> Option<&Prompter>
+    {
+        self.session_state.prompter()
+    }
+
+    pub fn set_prompter(&mut self, prompter: Prompter)
+    {
+        self.session_state.set_prompter(prompter);
+    }
+
+    pub fn confirmer(&self) -> Option<&Prompter>
+    {
+        self.session_state.confirmer()
+    }
+
+    pub fn set_confirmer(&mut self, confirmer: Prompter)
+    {
+        self.session_state.set_confirmer(confirmer);
+    }
+
+    #[cfg(unix)]
+    pub fn peer(&self) -> Option<&PeerCred>
+    {
+        self.session_state.peer()
+    }
 }
 
 
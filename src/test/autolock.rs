@@ -0,0 +1,192 @@
+// src/test/autolock.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::time::{Duration, Instant};
+
+// Third-party imports
+
+use tempdir::TempDir;
+
+// Local imports
+
+use autolock::check;
+use keyring::{Keyring, KeyringHandle, new_keyring_handle};
+use protocol::v1::prompt::new_prompts_handle;
+use settings::LockSection;
+
+use test::protocol::file_keyring;
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+fn lock_section(idle: Option<u64>, max: Option<u64>) -> LockSection
+{
+    LockSection {
+        idle_timeout: idle.map(Duration::from_secs),
+        max_unlocked: max.map(Duration::from_secs),
+    }
+}
+
+
+// Check the keyring w/o any conversation waiting on prompts
+fn check_at(keyring: &KeyringHandle, lock: &LockSection, now: Instant)
+    -> bool
+{
+    check(keyring, &new_prompts_handle(), lock, now)
+}
+
+
+fn is_locked(keyring: &KeyringHandle) -> bool
+{
+    keyring.read().unwrap().is_locked()
+}
+
+
+// ===========================================================================
+// Tests
+// ===========================================================================
+
+
+#[test]
+fn no_timeouts()
+{
+    let keyring = new_keyring_handle(Keyring::new());
+    let lock = lock_section(None, None);
+    let later = Instant::now() + Duration::from_secs(365 * 24 * 60 * 60);
+
+    assert!(!check_at(&keyring, &lock, later));
+    assert!(!is_locked(&keyring));
+}
+
+
+#[test]
+fn idle_timeout()
+{
+    // --------------------
+    // GIVEN
+    // an unlocked keyring and
+    // a 60 second idle timeout
+    // --------------------
+    let dir = TempDir::new("sasd").unwrap();
+    let keyring = new_keyring_handle(file_keyring(&dir));
+    let lock = lock_section(Some(60), None);
+    let start = Instant::now();
+
+    // --------------------
+    // WHEN
+    // the keyring is checked before and after the timeout
+    // --------------------
+    let before = check_at(&keyring, &lock, start + Duration::from_secs(30));
+    let after = check_at(&keyring, &lock, start + Duration::from_secs(61));
+
+    // --------------------
+    // THEN
+    // the keyring is only locked once the timeout has passed
+    // --------------------
+    assert!(!before);
+    assert!(after);
+    assert!(is_locked(&keyring));
+}
+
+
+#[test]
+fn use_resets_idle_timeout()
+{
+    // --------------------
+    // GIVEN
+    // an unlocked keyring that was just used and
+    // a 1 second idle timeout
+    // --------------------
+    let dir = TempDir::new("sasd").unwrap();
+    let keyring = new_keyring_handle(file_keyring(&dir));
+    let lock = lock_section(Some(1), None);
+    let start = Instant::now();
+    ::std::thread::sleep(Duration::from_millis(20));
+    keyring.write().unwrap().touch();
+
+    // --------------------
+    // WHEN
+    // the keyring is checked 1 second after it was unlocked
+    // --------------------
+    let locked = check_at(&keyring, &lock, start + Duration::from_secs(1));
+
+    // --------------------
+    // THEN
+    // the keyring is still unlocked
+    // --------------------
+    assert!(!locked);
+    assert!(!is_locked(&keyring));
+}
+
+
+#[test]
+fn max_unlocked()
+{
+    // --------------------
+    // GIVEN
+    // a keyring in constant use and
+    // a 1 second max unlocked time
+    // --------------------
+    let dir = TempDir::new("sasd").unwrap();
+    let keyring = new_keyring_handle(file_keyring(&dir));
+    let lock = lock_section(None, Some(1));
+    let start = Instant::now();
+    keyring.write().unwrap().touch();
+
+    // --------------------
+    // WHEN
+    // the keyring is checked after the max unlocked time
+    // --------------------
+    let locked = check_at(&keyring, &lock, start + Duration::from_secs(2));
+
+    // --------------------
+    // THEN
+    // the keyring is locked
+    // --------------------
+    assert!(locked);
+    assert!(is_locked(&keyring));
+}
+
+
+#[test]
+fn no_file()
+{
+    // --------------------
+    // GIVEN
+    // an unlocked keyring w/o a file and
+    // a 60 second idle timeout
+    // --------------------
+    let keyring = new_keyring_handle(Keyring::new());
+    let lock = lock_section(Some(60), None);
+    let later = Instant::now() + Duration::from_secs(61);
+
+    // --------------------
+    // WHEN
+    // the keyring is checked after the timeout
+    // --------------------
+    let locked = check_at(&keyring, &lock, later);
+
+    // --------------------
+    // THEN
+    // the keyring is left unlocked since it could never be unlocked again
+    // --------------------
+    assert!(!locked);
+    assert!(!is_locked(&keyring));
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
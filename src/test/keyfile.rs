@@ -0,0 +1,368 @@
+// src/test/keyfile.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+// Third-party imports
+
+use tempdir::TempDir;
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Key, Keyring};
+use secret::SecretBuf;
+
+use test::protocol::{alice, attrs, kdf_params};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+fn new_keyring(path: &Path) -> Keyring
+{
+    let params = kdf_params();
+    let mut keyring =
+        Keyring::open_with_params(path, b"passphrase", params).unwrap();
+    keyring.add(alice());
+    keyring.save().unwrap();
+    keyring
+}
+
+
+fn is_load_error(result: SasdResult<Keyring>) -> bool
+{
+    match result {
+        Err(e) => {
+            match e.kind() {
+                &SasdErrorKind::KeyringLoadError(_) => true,
+                _ => false,
+            }
+        }
+        Ok(_) => false,
+    }
+}
+
+
+fn read_file(path: &Path) -> Vec<u8>
+{
+    let mut data = Vec::new();
+    File::open(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+
+fn write_file(path: &Path, data: &[u8])
+{
+    File::create(path).unwrap().write_all(data).unwrap();
+}
+
+
+// ===========================================================================
+// Tests
+// ===========================================================================
+
+
+#[test]
+fn create_empty_file()
+{
+    // --------------------
+    // GIVEN
+    // a path to a keyring file that does not exist
+    // --------------------
+    let dir = TempDir::new("sasd").unwrap();
+    let path = dir.path().join("keyring");
+
+    // --------------------
+    // WHEN
+    // the keyring is opened
+    // --------------------
+    let params = kdf_params();
+    let keyring =
+        Keyring::open_with_params(&path, b"passphrase", params).unwrap();
+
+    // --------------------
+    // THEN
+    // the keyring is empty and
+    // the file is created
+    // --------------------
+    assert!(keyring.is_empty());
+    assert!(path.exists());
+}
+
+
+#[test]
+fn reopen_keeps_keys()
+{
+    // --------------------
+    // GIVEN
+    // a saved keyring holding a key w/ a secret attribute
+    // --------------------
+    let dir = TempDir::new("sasd").unwrap();
+    let path = dir.path().join("keyring");
+    new_keyring(&path);
+
+    // --------------------
+    // WHEN
+    // the keyring file is opened again w/ the same passphrase
+    // --------------------
+    let keyring = Keyring::open(&path, b"passphrase").unwrap();
+
+    // --------------------
+    // THEN
+    // the key and its secret are loaded and
+    // the secret is not stored in the clear
+    // --------------------
+    assert_eq!(keyring.keys(), &[alice()][..]);
+
+    let data = read_file(&path);
+    assert!(!data.windows(7).any(|w| w == b"hunter2"));
+}
+
+
+#[test]
+fn reopen_keeps_binary_secret()
+{
+    // --------------------
+    // GIVEN
+    // a keyring holding a key whose secret is not valid UTF-8
+    // --------------------
+    let dir = TempDir::new("sasd").unwrap();
+    let path = dir.path().join("keyring");
+    let params = kdf_params();
+    let mut keyring =
+        Keyring::open_with_params(&path, b"passphrase", params).unwrap();
+    let secret = SecretBuf::from(vec![0xff, 0x00, 0xfe]);
+    let key = Key::with_secret(alice().public().clone(), "!seed", secret);
+    keyring.add(key.clone());
+
+    // --------------------
+    // WHEN
+    // the keyring is saved and opened again
+    // --------------------
+    keyring.save().unwrap();
+    let keyring = Keyring::open(&path, b"passphrase").unwrap();
+
+    // --------------------
+    // THEN
+    // the key comes back w/ the same secret bytes
+    // --------------------
+    assert_eq!(keyring.keys(), &[key]);
+}
+
+
+#[test]
+fn reopen_keeps_many_keys()
+{
+    // --------------------
+    // GIVEN
+    // a keyring holding more than 15 keys, each w/ a secret longer than
+    // 255 bytes
+    // --------------------
+    let dir = TempDir::new("sasd").unwrap();
+    let path = dir.path().join("keyring");
+    let params = kdf_params();
+    let mut keyring =
+        Keyring::open_with_params(&path, b"passphrase", params).unwrap();
+    let password = "x".repeat(300);
+    for i in 0..20 {
+        let user = format!("user{}", i);
+        let pairs =
+            [("proto", "pass"), ("user", &user), ("!password", &password)];
+        keyring.add(Key::new(attrs(&pairs)));
+    }
+    let keys = keyring.keys().to_vec();
+
+    // --------------------
+    // WHEN
+    // the keyring is saved and opened again
+    // --------------------
+    keyring.save().unwrap();
+    let keyring = Keyring::open(&path, b"passphrase").unwrap();
+
+    // --------------------
+    // THEN
+    // every key comes back w/ its secret
+    // --------------------
+    assert_eq!(keyring.keys(), &keys[..]);
+}
+
+
+#[test]
+fn wrong_passphrase()
+{
+    // --------------------
+    // GIVEN
+    // a saved keyring
+    // --------------------
+    let dir = TempDir::new("sasd").unwrap();
+    let path = dir.path().join("keyring");
+    new_keyring(&path);
+
+    // --------------------
+    // WHEN
+    // the keyring file is opened w/ the wrong passphrase
+    // --------------------
+    let result = Keyring::open(&path, b"wrong");
+
+    // --------------------
+    // THEN
+    // a KeyringLoadError is returned
+    // --------------------
+    assert!(is_load_error(result));
+}
+
+
+#[test]
+fn tampered_file()
+{
+    // --------------------
+    // GIVEN
+    // saved keyrings w/ a modified header and a modified body
+    // --------------------
+    let dir = TempDir::new("sasd").unwrap();
+    let path = dir.path().join("keyring");
+    new_keyring(&path);
+    let data = read_file(&path);
+
+    // salt, then the last byte of the ciphertext
+    for &i in &[20, data.len() - 1] {
+        let mut bad = data.clone();
+        bad[i] ^= 1;
+        write_file(&path, &bad);
+
+        // --------------------
+        // WHEN
+        // the keyring file is opened
+        // --------------------
+        let result = Keyring::open(&path, b"passphrase");
+
+        // --------------------
+        // THEN
+        // a KeyringLoadError is returned
+        // --------------------
+        assert!(is_load_error(result));
+    }
+}
+
+
+#[test]
+fn tampered_work_factors()
+{
+    // --------------------
+    // GIVEN
+    // a saved keyring
+    // --------------------
+    let dir = TempDir::new("sasd").unwrap();
+    let path = dir.path().join("keyring");
+    new_keyring(&path);
+    let data = read_file(&path);
+
+    // log_n at byte 9, r at 10..14 and p at 14..18. The last log_n and r
+    // are fine by themselves but need 4GiB together.
+    let cases: &[(usize, &[u8])] = &[
+        (9, &[40]),
+        (10, &[0xff, 0xff, 0xff, 0xff]),
+        (14, &[0xff, 0xff, 0xff, 0xff]),
+        (9, &[20, 0, 0, 0, 32]),
+    ];
+    for &(offset, bytes) in cases {
+        let mut bad = data.clone();
+        bad[offset..offset + bytes.len()].copy_from_slice(bytes);
+        write_file(&path, &bad);
+
+        // --------------------
+        // WHEN
+        // the keyring file is opened
+        // --------------------
+        let result = Keyring::open(&path, b"passphrase");
+
+        // --------------------
+        // THEN
+        // a KeyringLoadError is returned w/o running scrypt
+        // --------------------
+        let too_large = match result {
+            Err(e) => e.to_string().contains("too"),
+            Ok(_) => false,
+        };
+        assert!(too_large, "offset {}", offset);
+    }
+}
+
+
+#[test]
+fn not_a_keyring_file()
+{
+    let dir = TempDir::new("sasd").unwrap();
+    let path = dir.path().join("keyring");
+    write_file(&path, b"port = 1234\n");
+
+    assert!(is_load_error(Keyring::open(&path, b"passphrase")));
+}
+
+
+#[test]
+fn save_replaces_file()
+{
+    // --------------------
+    // GIVEN
+    // a saved keyring holding a key
+    // --------------------
+    let dir = TempDir::new("sasd").unwrap();
+    let path = dir.path().join("keyring");
+    let mut keyring = new_keyring(&path);
+
+    // --------------------
+    // WHEN
+    // the key is removed and the keyring is saved
+    // --------------------
+    keyring.remove(&Key::new(alice().public().clone()));
+    keyring.save().unwrap();
+
+    // --------------------
+    // THEN
+    // the file holds the empty keyring and
+    // no temporary file is left behind
+    // --------------------
+    let keyring = Keyring::open(&path, b"passphrase").unwrap();
+    assert!(keyring.is_empty());
+
+    let names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    assert_eq!(names, vec!["keyring"]);
+}
+
+
+#[cfg(unix)]
+#[test]
+fn file_is_private()
+{
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new("sasd").unwrap();
+    let path = dir.path().join("keyring");
+    new_keyring(&path);
+
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
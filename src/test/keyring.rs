@@ -0,0 +1,393 @@
+// src/test/keyring.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+use tempdir::TempDir;
+
+// Local imports
+
+use error::SasdErrorKind;
+use keyring::{Key, Keyring, SECRET_PLACEHOLDER};
+
+use test::protocol::{alice, attrs, file_keyring};
+
+
+// ===========================================================================
+// Test Key
+// ===========================================================================
+
+
+mod key {
+    use super::*;
+
+    #[test]
+    fn split_secret_attrs()
+    {
+        // --------------------
+        // WHEN
+        // a Key is created w/ public and secret attributes
+        // --------------------
+        let key = alice();
+
+        // --------------------
+        // THEN
+        // attributes starting with ! are kept as secret and
+        // all attributes can be looked up
+        // --------------------
+        let public = attrs(&[("proto", "pass"), ("user", "alice")]);
+        assert_eq!(key.public(), &public);
+        assert_eq!(key.secret().len(), 1);
+        assert!(key.secret()["!password"].ct_eq(b"hunter2"));
+        assert_eq!(key.get("user"), Some("alice"));
+        assert_eq!(key.get("!password"), Some("hunter2"));
+        assert_eq!(key.get("password"), None);
+    }
+
+    #[test]
+    fn masked_hides_secrets()
+    {
+        // --------------------
+        // GIVEN
+        // a Key w/ a secret attribute
+        // --------------------
+        let key = alice();
+
+        // --------------------
+        // WHEN
+        // Key::masked() is called
+        // --------------------
+        let masked = key.masked();
+
+        // --------------------
+        // THEN
+        // the secret value is replaced by a placeholder
+        // --------------------
+        let expected = attrs(&[
+            ("proto", "pass"),
+            ("user", "alice"),
+            ("!password", SECRET_PLACEHOLDER),
+        ]);
+        assert_eq!(masked, expected);
+    }
+
+    #[test]
+    fn debug_hides_secrets()
+    {
+        // --------------------
+        // GIVEN
+        // a Key w/ a secret attribute
+        // --------------------
+        let key = alice();
+
+        // --------------------
+        // WHEN
+        // the key is formatted for debugging
+        // --------------------
+        let output = format!("{:?}", key);
+
+        // --------------------
+        // THEN
+        // the secret value does not appear
+        // --------------------
+        assert!(output.contains("alice"));
+        assert!(!output.contains("hunter2"));
+    }
+
+    #[test]
+    fn matches()
+    {
+        let key = alice();
+        let public = Key::new(attrs(&[("proto", "pass"), ("user", "alice")]));
+        let right = Key::new(attrs(
+            &[("proto", "pass"), ("user", "alice"), ("!password", "hunter2")],
+        ));
+        let wrong = Key::new(attrs(
+            &[("proto", "pass"), ("user", "alice"), ("!password", "nope")],
+        ));
+        let partial = Key::new(attrs(&[("user", "alice")]));
+
+        assert!(key.matches(&public));
+        assert!(key.matches(&right));
+        assert!(!key.matches(&wrong));
+        assert!(!key.matches(&partial));
+    }
+}
+
+
+// ===========================================================================
+// Test Keyring
+// ===========================================================================
+
+
+mod keyring {
+    use super::*;
+
+    #[test]
+    fn add_same_public_attrs()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding a key
+        // --------------------
+        let mut keyring = Keyring::new();
+        assert!(keyring.add(alice()));
+
+        // --------------------
+        // WHEN
+        // a key w/ the same public attributes but another secret is added
+        // --------------------
+        let other = Key::new(attrs(
+            &[("proto", "pass"), ("user", "alice"), ("!password", "other")],
+        ));
+        let added = keyring.add(other);
+
+        // --------------------
+        // THEN
+        // the key is not added
+        // --------------------
+        assert!(!added);
+        assert_eq!(keyring.len(), 1);
+    }
+
+    #[test]
+    fn remove_by_public_attrs()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding a key
+        // --------------------
+        let mut keyring = Keyring::new();
+        keyring.add(alice());
+
+        // --------------------
+        // WHEN
+        // the key is removed using only its public attributes
+        // --------------------
+        let public = attrs(&[("proto", "pass"), ("user", "alice")]);
+        let removed = keyring.remove(&Key::new(public));
+
+        // --------------------
+        // THEN
+        // the key is removed
+        // --------------------
+        assert!(removed);
+        assert!(keyring.is_empty());
+    }
+
+    // A keyring file holding alice's key
+    fn saved_keyring(dir: &TempDir) -> Keyring
+    {
+        let mut keyring = file_keyring(&dir);
+        keyring.add(alice());
+        keyring.save().unwrap();
+        keyring
+    }
+
+    #[test]
+    fn lock_drops_keys()
+    {
+        // --------------------
+        // GIVEN
+        // an unlocked keyring holding a key
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let mut keyring = saved_keyring(&dir);
+        assert!(!keyring.is_locked());
+
+        // --------------------
+        // WHEN
+        // the keyring is locked
+        // --------------------
+        keyring.lock();
+
+        // --------------------
+        // THEN
+        // the keyring is locked and holds no keys
+        // --------------------
+        assert!(keyring.is_locked());
+        assert!(keyring.is_empty());
+    }
+
+    #[test]
+    fn unlock_reloads_keys()
+    {
+        // --------------------
+        // GIVEN
+        // a locked keyring
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let mut keyring = saved_keyring(&dir);
+        keyring.lock();
+
+        // --------------------
+        // WHEN
+        // the keyring is unlocked w/ the master passphrase
+        // --------------------
+        keyring.unlock(b"passphrase", false).unwrap();
+
+        // --------------------
+        // THEN
+        // the key is loaded from the keyring file
+        // --------------------
+        assert!(!keyring.is_locked());
+        assert_eq!(keyring.keys(), &[alice()][..]);
+    }
+
+    #[test]
+    fn set_attr_saves()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring file holding a key
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let mut keyring = saved_keyring(&dir);
+
+        // --------------------
+        // WHEN
+        // a public attribute of the key is set
+        // --------------------
+        let public = alice().public().clone();
+        let set = keyring.set_attr(&public, "counter", "1").unwrap();
+
+        // --------------------
+        // THEN
+        // the new value is kept in the keyring file
+        // --------------------
+        assert!(set);
+        keyring.lock();
+        keyring.unlock(b"passphrase", false).unwrap();
+        assert_eq!(keyring.keys()[0].get("counter"), Some("1"));
+        assert!(!keyring.set_attr(&public, "counter", "2").unwrap());
+    }
+
+    #[test]
+    fn unlock_wrong_passphrase()
+    {
+        // --------------------
+        // GIVEN
+        // a locked keyring
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let mut keyring = saved_keyring(&dir);
+        keyring.lock();
+
+        // --------------------
+        // WHEN
+        // the keyring is unlocked w/ the wrong passphrase
+        // --------------------
+        let result = keyring.unlock(b"wrong", false);
+
+        // --------------------
+        // THEN
+        // a KeyringLoadError is returned and
+        // the keyring stays locked but can still be unlocked
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::KeyringLoadError(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+        assert!(keyring.is_locked());
+
+        keyring.unlock(b"passphrase", false).unwrap();
+        assert_eq!(keyring.len(), 1);
+    }
+
+    #[test]
+    fn lock_no_file()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring w/o a file holding a key
+        // --------------------
+        let mut keyring = Keyring::new();
+        keyring.add(alice());
+
+        // --------------------
+        // WHEN
+        // the keyring is locked
+        // --------------------
+        let locked = keyring.lock();
+
+        // --------------------
+        // THEN
+        // the keyring is left unlocked w/ its key
+        // --------------------
+        assert!(!locked);
+        assert!(!keyring.is_locked());
+        assert_eq!(keyring.len(), 1);
+    }
+
+    #[test]
+    fn unlock_missing_file()
+    {
+        // --------------------
+        // GIVEN
+        // a locked keyring whose file does not exist
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let path = dir.path().join("keyring");
+        let mut keyring = Keyring::locked(&path);
+
+        // --------------------
+        // WHEN
+        // the keyring is unlocked w/o asking for the file to be created
+        // --------------------
+        let result = keyring.unlock(b"passphrase", false);
+
+        // --------------------
+        // THEN
+        // a KeyringLoadError is returned and
+        // no file is created
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::KeyringLoadError(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+        assert!(keyring.is_locked());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unlock_create()
+    {
+        // --------------------
+        // GIVEN
+        // a locked keyring whose file does not exist
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let path = dir.path().join("keyring");
+        let mut keyring = Keyring::locked(&path);
+
+        // --------------------
+        // WHEN
+        // the keyring is unlocked asking for the file to be created
+        // --------------------
+        keyring.unlock(b"passphrase", true).unwrap();
+
+        // --------------------
+        // THEN
+        // an empty keyring file is created
+        // --------------------
+        assert!(!keyring.is_locked());
+        assert!(keyring.is_empty());
+        assert!(path.exists());
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -20,8 +20,14 @@
 // ===========================================================================
 
 
+mod autolock;
+mod keyfile;
+mod keyring;
 mod os;
 mod protocol;
+mod query;
+mod secret;
+mod server;
 mod settings;
 
 
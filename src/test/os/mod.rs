@@ -21,6 +21,9 @@
 // ===========================================================================
 
 
+#[cfg(unix)]
+mod unix;
+
 #[cfg(windows)]
 mod windows;
 
@@ -0,0 +1,662 @@
+// src/test/os/unix/agent.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::env;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+// Third-party imports
+
+use data_encoding::BASE64;
+use ed25519_dalek::{Signature, SigningKey, Verifier};
+use siminau_rpc::message::notify::RpcNotice;
+use tempdir::TempDir;
+
+// Local imports
+
+use keyring::{Attrs, Key, Keyring, KeyringHandle, new_keyring_handle};
+use os::unix::agent::{Agent, AgentReader, SSH_AGENTC_ADD_ID_CONSTRAINED,
+                      SSH_AGENTC_ADD_IDENTITY,
+                      SSH_AGENTC_REMOVE_ALL_IDENTITIES,
+                      SSH_AGENTC_REMOVE_IDENTITY,
+                      SSH_AGENTC_REQUEST_IDENTITIES, SSH_AGENTC_SIGN_REQUEST,
+                      SSH_AGENT_CONSTRAIN_CONFIRM,
+                      SSH_AGENT_CONSTRAIN_LIFETIME, SSH_AGENT_FAILURE,
+                      SSH_AGENT_IDENTITIES_ANSWER, SSH_AGENT_SIGN_RESPONSE,
+                      SSH_AGENT_SUCCESS, ed25519_blob, put_string, put_u32,
+                      read_agent_message, serve_agent, write_agent_message};
+use protocol::v1::ProtocolInfo;
+use protocol::v1::prompt::{PromptKind, Prompter, PromptsHandle,
+                           new_prompts_handle};
+use query::Query;
+use rpc::v1::ProtocolNotice;
+use server::{Notifier, read_message};
+use settings::{SettingsBuilder, SettingsHandle, new_settings_handle};
+
+use test::protocol::{attrs, file_keyring};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+fn settings(remove_all: bool) -> SettingsHandle
+{
+    let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+    let config = SettingsBuilder::new()
+        .port(1234).unwrap()
+        .unix()
+            .socket_dir(curdir).unwrap()
+            .ssh_agent_remove_all(remove_all)
+        .unix_done().unwrap()
+        .protocol()
+            .confirm_timeout(Duration::from_secs(10)).unwrap()
+        .protocol_done().unwrap()
+        .build().unwrap();
+    new_settings_handle(config)
+}
+
+
+// A local agent protocol client talking to an agent served on its own
+// thread
+struct Client {
+    stream: UnixStream,
+}
+
+
+impl Client {
+    fn new(keyring: &KeyringHandle, prompts: &PromptsHandle) -> Self
+    {
+        Client::with_settings(settings(false), keyring, prompts)
+    }
+
+    fn with_settings(settings: SettingsHandle, keyring: &KeyringHandle,
+                     prompts: &PromptsHandle)
+        -> Self
+    {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let peer = attrs(&[("uid", "1000")]);
+        let agent =
+            Agent::new(settings, keyring.clone(), prompts.clone(), peer);
+        thread::spawn(move || serve_agent(&mut server, &agent));
+        Client { stream: client }
+    }
+
+    fn request(&mut self, msg: &[u8]) -> Vec<u8>
+    {
+        write_agent_message(&mut self.stream, msg).unwrap();
+        let reply = read_agent_message(&mut self.stream).unwrap().unwrap();
+        reply.as_bytes().to_vec()
+    }
+
+    // Key blobs and comments of the listed identities
+    fn identities(&mut self) -> Vec<(Vec<u8>, String)>
+    {
+        let reply = self.request(&[SSH_AGENTC_REQUEST_IDENTITIES]);
+        let mut rd = AgentReader::new(&reply);
+        assert_eq!(rd.byte().unwrap(), SSH_AGENT_IDENTITIES_ANSWER);
+        let count = rd.u32().unwrap();
+        let identities = (0..count)
+            .map(|_| {
+                let blob = rd.string().unwrap().to_vec();
+                let comment = rd.string().unwrap();
+                (blob, String::from_utf8(comment.to_vec()).unwrap())
+            })
+            .collect();
+        assert!(rd.is_empty());
+        identities
+    }
+
+    fn add(&mut self, key: &SigningKey, comment: &str, constraints: &[u8])
+        -> Vec<u8>
+    {
+        let code = if constraints.is_empty() {
+            SSH_AGENTC_ADD_IDENTITY
+        } else {
+            SSH_AGENTC_ADD_ID_CONSTRAINED
+        };
+        let public = key.verifying_key().to_bytes();
+        let mut private = key.to_bytes().to_vec();
+        private.extend_from_slice(&public);
+
+        let mut msg = vec![code];
+        put_string(&mut msg, b"ssh-ed25519");
+        put_string(&mut msg, &public);
+        put_string(&mut msg, &private);
+        put_string(&mut msg, comment.as_bytes());
+        msg.extend_from_slice(constraints);
+        self.request(&msg)
+    }
+
+    fn sign(&mut self, key: &SigningKey, data: &[u8]) -> Vec<u8>
+    {
+        let mut msg = vec![SSH_AGENTC_SIGN_REQUEST];
+        put_string(&mut msg, &blob(key));
+        put_string(&mut msg, data);
+        put_u32(&mut msg, 0);
+        self.request(&msg)
+    }
+
+    fn remove(&mut self, key: &SigningKey) -> Vec<u8>
+    {
+        let mut msg = vec![SSH_AGENTC_REMOVE_IDENTITY];
+        put_string(&mut msg, &blob(key));
+        self.request(&msg)
+    }
+}
+
+
+fn signing_key(n: u8) -> SigningKey
+{
+    SigningKey::from_bytes(&[n; 32])
+}
+
+
+fn blob(key: &SigningKey) -> Vec<u8>
+{
+    ed25519_blob(key.verifying_key().as_bytes())
+}
+
+
+// Check a sign response holds a valid ed25519 signature of data
+fn verify(reply: &[u8], key: &SigningKey, data: &[u8]) -> bool
+{
+    let mut rd = AgentReader::new(reply);
+    assert_eq!(rd.byte().unwrap(), SSH_AGENT_SIGN_RESPONSE);
+    let mut sig = AgentReader::new(rd.string().unwrap());
+    assert_eq!(sig.string().unwrap(), b"ssh-ed25519");
+    let sig = Signature::from_slice(sig.string().unwrap()).unwrap();
+    key.verifying_key().verify(data, &sig).is_ok()
+}
+
+
+fn ssh_keys(keyring: &KeyringHandle) -> Vec<Attrs>
+{
+    let query = Query::parse("proto=ssh").unwrap();
+    let keyring = keyring.read().unwrap();
+    keyring.find(&query).iter().map(|k| k.public().clone()).collect()
+}
+
+
+// ===========================================================================
+// Test identities
+// ===========================================================================
+
+
+mod identities {
+    use super::*;
+
+    #[test]
+    fn add_and_list()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ an empty keyring
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+        let key = signing_key(1);
+
+        // --------------------
+        // WHEN
+        // an ed25519 key is added
+        // --------------------
+        let reply = client.add(&key, "alice@example.org", &[]);
+
+        // --------------------
+        // THEN
+        // the key is listed w/ its comment and
+        // stored in the keyring as a proto=ssh key
+        // --------------------
+        assert_eq!(reply, vec![SSH_AGENT_SUCCESS]);
+        let expected = vec![(blob(&key), "alice@example.org".to_owned())];
+        assert_eq!(client.identities(), expected);
+
+        let keys = ssh_keys(&keyring);
+        let pubkey = BASE64.encode(key.verifying_key().as_bytes());
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].get("alg").unwrap(), "ed25519");
+        assert_eq!(keys[0].get("pubkey").unwrap(), &pubkey);
+        assert!(keys[0].get("confirm").is_none());
+    }
+
+    #[test]
+    fn add_again_replaces()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ a key added
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+        let key = signing_key(1);
+        client.add(&key, "old", &[]);
+
+        // --------------------
+        // WHEN
+        // the same key is added w/ another comment
+        // --------------------
+        let reply = client.add(&key, "new", &[]);
+
+        // --------------------
+        // THEN
+        // only the new key is listed
+        // --------------------
+        assert_eq!(reply, vec![SSH_AGENT_SUCCESS]);
+        let expected = vec![(blob(&key), "new".to_owned())];
+        assert_eq!(client.identities(), expected);
+    }
+
+    #[test]
+    fn mismatched_key()
+    {
+        // --------------------
+        // GIVEN
+        // an agent and
+        // an add request whose public key doesn't match its private key
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+        let public = signing_key(2).verifying_key().to_bytes();
+        let mut private = signing_key(1).to_bytes().to_vec();
+        private.extend_from_slice(&public);
+        let mut msg = vec![SSH_AGENTC_ADD_IDENTITY];
+        put_string(&mut msg, b"ssh-ed25519");
+        put_string(&mut msg, &public);
+        put_string(&mut msg, &private);
+        put_string(&mut msg, b"");
+
+        // --------------------
+        // WHEN
+        // the request is sent
+        // --------------------
+        let reply = client.request(&msg);
+
+        // --------------------
+        // THEN
+        // the request fails and no key is added
+        // --------------------
+        assert_eq!(reply, vec![SSH_AGENT_FAILURE]);
+        assert!(ssh_keys(&keyring).is_empty());
+    }
+
+    #[test]
+    fn lifetime_constraint()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ an empty keyring
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+        let mut constraints = vec![SSH_AGENT_CONSTRAIN_LIFETIME];
+        put_u32(&mut constraints, 60);
+
+        // --------------------
+        // WHEN
+        // a key is added w/ a lifetime
+        // --------------------
+        let reply = client.add(&signing_key(1), "", &constraints);
+
+        // --------------------
+        // THEN
+        // the request fails
+        // --------------------
+        assert_eq!(reply, vec![SSH_AGENT_FAILURE]);
+        assert!(ssh_keys(&keyring).is_empty());
+    }
+
+    #[test]
+    fn remove()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ 2 keys added
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+        let (first, second) = (signing_key(1), signing_key(2));
+        client.add(&first, "first", &[]);
+        client.add(&second, "second", &[]);
+
+        // --------------------
+        // WHEN
+        // the first key is removed
+        // --------------------
+        let reply = client.remove(&first);
+
+        // --------------------
+        // THEN
+        // only the second key is left and
+        // removing the first key again fails
+        // --------------------
+        assert_eq!(reply, vec![SSH_AGENT_SUCCESS]);
+        let expected = vec![(blob(&second), "second".to_owned())];
+        assert_eq!(client.identities(), expected);
+        assert_eq!(client.remove(&first), vec![SSH_AGENT_FAILURE]);
+    }
+
+    #[test]
+    fn remove_all_not_allowed()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ 2 keys and
+        // settings that don't allow removing all identities
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+        client.add(&signing_key(1), "first", &[]);
+        client.add(&signing_key(2), "second", &[]);
+
+        // --------------------
+        // WHEN
+        // every identity is removed
+        // --------------------
+        let reply = client.request(&[SSH_AGENTC_REMOVE_ALL_IDENTITIES]);
+
+        // --------------------
+        // THEN
+        // the request fails and both keys are kept
+        // --------------------
+        assert_eq!(reply, vec![SSH_AGENT_FAILURE]);
+        assert_eq!(client.identities().len(), 2);
+    }
+
+    #[test]
+    fn remove_all()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ 2 ssh keys and a key for another protocol and
+        // settings that allow removing all identities
+        // --------------------
+        let mut other = Keyring::new();
+        let pass = attrs(&[("proto", "pass"), ("user", "alice")]);
+        other.add(Key::new(pass));
+        let keyring = new_keyring_handle(other);
+        let mut client = Client::with_settings(
+            settings(true),
+            &keyring,
+            &new_prompts_handle(),
+        );
+        client.add(&signing_key(1), "first", &[]);
+        client.add(&signing_key(2), "second", &[]);
+
+        // --------------------
+        // WHEN
+        // every identity is removed
+        // --------------------
+        let reply = client.request(&[SSH_AGENTC_REMOVE_ALL_IDENTITIES]);
+
+        // --------------------
+        // THEN
+        // no identity is left but the other key is kept
+        // --------------------
+        assert_eq!(reply, vec![SSH_AGENT_SUCCESS]);
+        assert!(client.identities().is_empty());
+        assert_eq!(keyring.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn add_save_failure()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ 1 key and
+        // a keyring that can't be saved
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let keyring = new_keyring_handle(file_keyring(&dir));
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+        client.add(&signing_key(1), "first", &[]);
+        dir.close().unwrap();
+
+        // --------------------
+        // WHEN
+        // the key is added again and another key is added
+        // --------------------
+        let again = client.add(&signing_key(1), "renamed", &[]);
+        let other = client.add(&signing_key(2), "second", &[]);
+
+        // --------------------
+        // THEN
+        // both requests fail and
+        // only the first key is kept w/ its comment
+        // --------------------
+        assert_eq!(again, vec![SSH_AGENT_FAILURE]);
+        assert_eq!(other, vec![SSH_AGENT_FAILURE]);
+        let expected = vec![(blob(&signing_key(1)), "first".to_owned())];
+        assert_eq!(client.identities(), expected);
+    }
+
+    #[test]
+    fn remove_save_failure()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ 1 key and
+        // a keyring that can't be saved
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let keyring = new_keyring_handle(file_keyring(&dir));
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+        let key = signing_key(1);
+        client.add(&key, "first", &[]);
+        dir.close().unwrap();
+
+        // --------------------
+        // WHEN
+        // the key is removed
+        // --------------------
+        let reply = client.remove(&key);
+
+        // --------------------
+        // THEN
+        // the request fails and the key is kept
+        // --------------------
+        assert_eq!(reply, vec![SSH_AGENT_FAILURE]);
+        assert_eq!(client.identities().len(), 1);
+    }
+
+    #[test]
+    fn locked_keyring()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ a locked keyring
+        // --------------------
+        let locked = Keyring::locked(Path::new("/nonexistent/keyring"));
+        let keyring = new_keyring_handle(locked);
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+
+        // --------------------
+        // WHEN
+        // identities are listed and a key is added
+        // --------------------
+        let identities = client.identities();
+        let reply = client.add(&signing_key(1), "", &[]);
+
+        // --------------------
+        // THEN
+        // there are no identities and the key can't be added
+        // --------------------
+        assert!(identities.is_empty());
+        assert_eq!(reply, vec![SSH_AGENT_FAILURE]);
+    }
+
+    #[test]
+    fn unsupported_request()
+    {
+        // --------------------
+        // GIVEN
+        // an agent
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+
+        // --------------------
+        // WHEN
+        // a lock request is sent
+        // --------------------
+        let mut msg = vec![22];
+        put_string(&mut msg, b"passphrase");
+        let reply = client.request(&msg);
+
+        // --------------------
+        // THEN
+        // the request fails
+        // --------------------
+        assert_eq!(reply, vec![SSH_AGENT_FAILURE]);
+    }
+}
+
+
+// ===========================================================================
+// Test sign
+// ===========================================================================
+
+
+mod sign {
+    use super::*;
+
+    #[test]
+    fn signed()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ a key added
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+        let key = signing_key(1);
+        client.add(&key, "", &[]);
+
+        // --------------------
+        // WHEN
+        // data is signed w/ the key
+        // --------------------
+        let reply = client.sign(&key, b"session id");
+
+        // --------------------
+        // THEN
+        // the signature is valid for the key
+        // --------------------
+        assert!(verify(&reply, &key, b"session id"));
+    }
+
+    #[test]
+    fn no_such_key()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ a key added
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+        client.add(&signing_key(1), "", &[]);
+
+        // --------------------
+        // WHEN
+        // data is signed w/ another key
+        // --------------------
+        let reply = client.sign(&signing_key(2), b"session id");
+
+        // --------------------
+        // THEN
+        // the request fails
+        // --------------------
+        assert_eq!(reply, vec![SSH_AGENT_FAILURE]);
+    }
+
+    #[test]
+    fn confirm_no_confirmer()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ a key added w/ the confirm constraint and
+        // no confirmer
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut client = Client::new(&keyring, &new_prompts_handle());
+        let key = signing_key(1);
+        client.add(&key, "", &[SSH_AGENT_CONSTRAIN_CONFIRM]);
+
+        // --------------------
+        // WHEN
+        // data is signed w/ the key
+        // --------------------
+        let reply = client.sign(&key, b"session id");
+
+        // --------------------
+        // THEN
+        // the key is stored w/ confirm=yes and
+        // the request fails
+        // --------------------
+        let keys = ssh_keys(&keyring);
+        assert_eq!(keys[0].get("confirm").unwrap(), "yes");
+        assert_eq!(reply, vec![SSH_AGENT_FAILURE]);
+    }
+
+    #[test]
+    fn confirmed()
+    {
+        // --------------------
+        // GIVEN
+        // an agent w/ a confirm=yes key and
+        // a confirmer that answers yes
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let prompts = new_prompts_handle();
+        let mut client = Client::new(&keyring, &prompts);
+        let key = signing_key(1);
+        client.add(&key, "", &[SSH_AGENT_CONSTRAIN_CONFIRM]);
+
+        let (mut rd, wr) = UnixStream::pair().unwrap();
+        let notifier = Notifier::new(wr);
+        let confirmer =
+            Prompter::new(prompts.clone(), PromptKind::Confirm, notifier);
+        let answerer = {
+            let prompts = prompts.clone();
+            thread::spawn(move || {
+                let msg = read_message(&mut rd).unwrap().unwrap();
+                let info = ProtocolInfo::from(msg).unwrap();
+                assert_eq!(info.message_code(), ProtocolNotice::Confirm);
+                let tag = info.message_args()[0].as_u64().unwrap();
+                assert!(prompts.confirm_done(tag, true));
+            })
+        };
+
+        // --------------------
+        // WHEN
+        // data is signed w/ the key
+        // --------------------
+        let reply = client.sign(&key, b"session id");
+
+        // --------------------
+        // THEN
+        // the confirmer was asked and
+        // the signature is valid for the key
+        // --------------------
+        answerer.join().unwrap();
+        drop(confirmer);
+        assert!(verify(&reply, &key, b"session id"));
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
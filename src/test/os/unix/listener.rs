@@ -0,0 +1,417 @@
+// src/test/os/unix/listener.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::fs::{self, File, Permissions};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::ptr;
+
+// Third-party imports
+
+use libc;
+use tempdir::TempDir;
+
+// Local imports
+
+use error::SasdErrorKind;
+use os::unix::listener::{bind, check_socket_dir, socket_path};
+use settings::PeersSection;
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+// Connect to the socket at path from a child process running as the given
+// user and group. Only root can switch users, so None is returned for
+// anyone else.
+fn connect_as(path: &Path, uid: u32, gid: u32) -> Option<bool>
+{
+    if unsafe { libc::getuid() } != 0 {
+        return None;
+    }
+
+    // Everything the child needs is prepared before forking
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (dst, src) in addr.sun_path.iter_mut()
+        .zip(path.as_os_str().as_bytes())
+    {
+        *dst = *src as libc::c_char;
+    }
+    let addrlen = mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+
+    let status = unsafe {
+        let pid = libc::fork();
+        if pid == 0 {
+            let code = if libc::setgroups(0, ptr::null()) == 0 &&
+                libc::setgid(gid) == 0 &&
+                libc::setuid(uid) == 0
+            {
+                let fd = libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0);
+                let sockaddr = &addr as *const _ as *const libc::sockaddr;
+                if fd >= 0 && libc::connect(fd, sockaddr, addrlen) == 0 {
+                    0
+                } else {
+                    1
+                }
+            } else {
+                2
+            };
+            libc::_exit(code);
+        }
+        assert!(pid > 0);
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        status
+    };
+    assert!(libc::WIFEXITED(status));
+    match libc::WEXITSTATUS(status) {
+        0 => Some(true),
+        1 => Some(false),
+        _ => panic!("failed to switch to uid {} gid {}", uid, gid),
+    }
+}
+
+
+// ===========================================================================
+// Test check_socket_dir()
+// ===========================================================================
+
+
+mod check_socket_dir {
+    use super::*;
+
+    #[test]
+    fn private_dir_ok()
+    {
+        // --------------------
+        // GIVEN
+        // a directory only writable by the current user
+        // --------------------
+        let tempdir = TempDir::new("sasd").unwrap();
+        let perms = Permissions::from_mode(0o700);
+        fs::set_permissions(tempdir.path(), perms).unwrap();
+
+        // --------------------
+        // WHEN
+        // check_socket_dir() is called with the directory
+        // --------------------
+        let result = check_socket_dir(tempdir.path());
+
+        // --------------------
+        // THEN
+        // no error is returned
+        // --------------------
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn writable_dir_error()
+    {
+        for mode in &[0o720, 0o702, 0o777] {
+            // --------------------
+            // GIVEN
+            // a directory writable by group or others
+            // --------------------
+            let tempdir = TempDir::new("sasd").unwrap();
+            let perms = Permissions::from_mode(*mode);
+            fs::set_permissions(tempdir.path(), perms).unwrap();
+
+            // --------------------
+            // WHEN
+            // check_socket_dir() is called with the directory
+            // --------------------
+            let result = check_socket_dir(tempdir.path());
+
+            // --------------------
+            // THEN
+            // a ListenerError is returned
+            // --------------------
+            let value = match result {
+                Err(e) => matches!(*e.kind(), SasdErrorKind::ListenerError(_)),
+                _ => false,
+            };
+            assert!(value);
+        }
+    }
+}
+
+
+// ===========================================================================
+// Test bind()
+// ===========================================================================
+
+
+mod bind {
+    use super::*;
+
+    #[test]
+    fn socket_user_only()
+    {
+        // --------------------
+        // GIVEN
+        // a private socket directory
+        // --------------------
+        let tempdir = TempDir::new("sasd").unwrap();
+
+        // --------------------
+        // WHEN
+        // bind() is called with the directory
+        // --------------------
+        let listener = bind(tempdir.path(), &PeersSection::default()).unwrap();
+
+        // --------------------
+        // THEN
+        // the socket is created w/ 0600 permissions and
+        // clients can connect to it
+        // --------------------
+        let path = socket_path(tempdir.path());
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(UnixStream::connect(&path).is_ok());
+
+        drop(listener);
+    }
+
+    #[test]
+    fn socket_allowed_gid()
+    {
+        // --------------------
+        // GIVEN
+        // a private socket directory and
+        // a single allowed group
+        // --------------------
+        let tempdir = TempDir::new("sasd").unwrap();
+        let gid = unsafe { libc::getgid() };
+        let peers = PeersSection {
+            allow_uids: vec![],
+            allow_gids: vec![gid],
+        };
+
+        // --------------------
+        // WHEN
+        // bind() is called with the directory and the peers
+        // --------------------
+        let listener = bind(tempdir.path(), &peers).unwrap();
+
+        // --------------------
+        // THEN
+        // the socket is created w/ 0660 permissions and
+        // the socket belongs to the allowed group
+        // --------------------
+        let meta = fs::metadata(socket_path(tempdir.path())).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o660);
+        assert_eq!(meta.gid(), gid);
+
+        drop(listener);
+    }
+
+    #[test]
+    fn socket_allowed_uid()
+    {
+        // --------------------
+        // GIVEN
+        // a private socket directory and
+        // an allowed user
+        // --------------------
+        let tempdir = TempDir::new("sasd").unwrap();
+        let peers = PeersSection {
+            allow_uids: vec![4243],
+            allow_gids: vec![],
+        };
+
+        // --------------------
+        // WHEN
+        // bind() is called with the directory and the peers
+        // --------------------
+        let listener = bind(tempdir.path(), &peers).unwrap();
+
+        // --------------------
+        // THEN
+        // the socket is created w/ 0666 permissions, leaving
+        // check_peer() to turn away everyone else
+        // --------------------
+        let path = socket_path(tempdir.path());
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o666);
+
+        drop(listener);
+    }
+
+    #[test]
+    fn allowed_peer_connects()
+    {
+        let allowed = [
+            (vec![4243], vec![]),
+            (vec![], vec![4242]),
+            (vec![4243], vec![4242]),
+        ];
+        for &(ref uids, ref gids) in &allowed {
+            // --------------------
+            // GIVEN
+            // a socket directory others can enter and
+            // another user or their group is an allowed peer
+            // --------------------
+            let tempdir = TempDir::new("sasd").unwrap();
+            let perms = Permissions::from_mode(0o711);
+            fs::set_permissions(tempdir.path(), perms).unwrap();
+            let peers = PeersSection {
+                allow_uids: uids.clone(),
+                allow_gids: gids.clone(),
+            };
+            let listener = bind(tempdir.path(), &peers).unwrap();
+
+            // --------------------
+            // WHEN
+            // the other user connects to the socket
+            // --------------------
+            let path = socket_path(tempdir.path());
+            let result = connect_as(&path, 4243, 4242);
+
+            // --------------------
+            // THEN
+            // the connection is accepted (skipped unless run as root)
+            // --------------------
+            if let Some(connected) = result {
+                assert!(connected);
+            }
+
+            drop(listener);
+        }
+    }
+
+    #[test]
+    fn other_peer_refused()
+    {
+        // --------------------
+        // GIVEN
+        // a socket directory others can enter and
+        // no allowed peers
+        // --------------------
+        let tempdir = TempDir::new("sasd").unwrap();
+        let perms = Permissions::from_mode(0o711);
+        fs::set_permissions(tempdir.path(), perms).unwrap();
+        let listener =
+            bind(tempdir.path(), &PeersSection::default()).unwrap();
+
+        // --------------------
+        // WHEN
+        // another user connects to the socket
+        // --------------------
+        let path = socket_path(tempdir.path());
+        let result = connect_as(&path, 4243, 4242);
+
+        // --------------------
+        // THEN
+        // the connection is refused (skipped unless run as root)
+        // --------------------
+        if let Some(connected) = result {
+            assert!(!connected);
+        }
+
+        drop(listener);
+    }
+
+    #[test]
+    fn remove_stale_socket()
+    {
+        // --------------------
+        // GIVEN
+        // a socket left behind by a listener that has gone away
+        // --------------------
+        let tempdir = TempDir::new("sasd").unwrap();
+        let path = socket_path(tempdir.path());
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        // --------------------
+        // WHEN
+        // bind() is called with the directory
+        // --------------------
+        let result = bind(tempdir.path(), &PeersSection::default());
+
+        // --------------------
+        // THEN
+        // a new listener is created
+        // --------------------
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn socket_in_use_error()
+    {
+        // --------------------
+        // GIVEN
+        // a socket that is still being listened on
+        // --------------------
+        let tempdir = TempDir::new("sasd").unwrap();
+        let first = bind(tempdir.path(), &PeersSection::default()).unwrap();
+
+        // --------------------
+        // WHEN
+        // bind() is called with the directory
+        // --------------------
+        let result = bind(tempdir.path(), &PeersSection::default());
+
+        // --------------------
+        // THEN
+        // a ListenerError is returned
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::ListenerError(_)),
+            _ => false,
+        };
+        assert!(value);
+
+        drop(first);
+    }
+
+    #[test]
+    fn not_socket_error()
+    {
+        // --------------------
+        // GIVEN
+        // a regular file in place of the socket
+        // --------------------
+        let tempdir = TempDir::new("sasd").unwrap();
+        let path = socket_path(tempdir.path());
+        File::create(&path).unwrap();
+
+        // --------------------
+        // WHEN
+        // bind() is called with the directory
+        // --------------------
+        let result = bind(tempdir.path(), &PeersSection::default());
+
+        // --------------------
+        // THEN
+        // a ListenerError is returned and
+        // the file is left alone
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::ListenerError(_)),
+            _ => false,
+        };
+        assert!(value);
+        assert!(path.is_file());
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,19 @@
+// src/test/os/unix/mod.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+
+// ===========================================================================
+// Modules
+// ===========================================================================
+
+
+mod agent;
+mod listener;
+mod peer;
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,194 @@
+// src/test/os/unix/peer.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::os::unix::net::UnixStream;
+use std::process;
+
+// Third-party imports
+
+use libc;
+
+// Local imports
+
+use error::SasdErrorKind;
+use os::unix::peer::{PeerCred, check_peer, peer_cred};
+use settings::PeersSection;
+
+
+// ===========================================================================
+// Test peer_cred()
+// ===========================================================================
+
+
+mod peer_cred {
+    use super::*;
+
+    #[test]
+    fn same_process()
+    {
+        // --------------------
+        // GIVEN
+        // a connected pair of unix sockets
+        // --------------------
+        let (left, _right) = UnixStream::pair().unwrap();
+
+        // --------------------
+        // WHEN
+        // peer_cred() is called with one end of the pair
+        // --------------------
+        let cred = peer_cred(&left).unwrap();
+
+        // --------------------
+        // THEN
+        // the current process' credentials are returned
+        // --------------------
+        assert_eq!(cred.uid, unsafe { libc::getuid() });
+        assert_eq!(cred.gid, unsafe { libc::getgid() });
+        if let Some(pid) = cred.pid {
+            assert_eq!(pid as u32, process::id());
+        }
+    }
+}
+
+
+// ===========================================================================
+// Test check_peer()
+// ===========================================================================
+
+
+mod check_peer {
+    use super::*;
+
+    fn other_uid() -> u32
+    {
+        unsafe { libc::getuid() }.wrapping_add(1)
+    }
+
+    #[test]
+    fn owner_allowed()
+    {
+        // --------------------
+        // GIVEN
+        // a peer running as the same user as the daemon and
+        // no extra allowed peers
+        // --------------------
+        let uid = unsafe { libc::getuid() };
+        let peer = PeerCred::new(uid, 4242, Some(1));
+        let peers = PeersSection::default();
+
+        // --------------------
+        // WHEN
+        // check_peer() is called
+        // --------------------
+        let result = check_peer(&peer, &peers);
+
+        // --------------------
+        // THEN
+        // the peer is allowed
+        // --------------------
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn other_user_rejected()
+    {
+        // --------------------
+        // GIVEN
+        // a peer running as a different user and
+        // no extra allowed peers
+        // --------------------
+        let peer = PeerCred::new(other_uid(), 4242, Some(1));
+        let peers = PeersSection::default();
+
+        // --------------------
+        // WHEN
+        // check_peer() is called
+        // --------------------
+        let result = check_peer(&peer, &peers);
+
+        // --------------------
+        // THEN
+        // a PeerNotAllowed error is returned
+        // --------------------
+        let value = match result {
+            Err(e) => {
+                match *e.kind() {
+                    SasdErrorKind::PeerNotAllowed(uid, gid) => {
+                        uid == other_uid() && gid == 4242
+                    }
+                    _ => false,
+                }
+            }
+            _ => false,
+        };
+        assert!(value);
+    }
+
+    #[test]
+    fn allowed_uid()
+    {
+        // --------------------
+        // GIVEN
+        // a peer running as a different user and
+        // the peer's uid is in the allowed list
+        // --------------------
+        let peer = PeerCred::new(other_uid(), 4242, None);
+        let peers = PeersSection {
+            allow_uids: vec![other_uid()],
+            allow_gids: vec![],
+        };
+
+        // --------------------
+        // WHEN
+        // check_peer() is called
+        // --------------------
+        let result = check_peer(&peer, &peers);
+
+        // --------------------
+        // THEN
+        // the peer is allowed
+        // --------------------
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn allowed_gid()
+    {
+        // --------------------
+        // GIVEN
+        // a peer running as a different user and
+        // the peer's gid is in the allowed list
+        // --------------------
+        let peer = PeerCred::new(other_uid(), 4242, None);
+        let peers = PeersSection {
+            allow_uids: vec![],
+            allow_gids: vec![4242],
+        };
+
+        // --------------------
+        // WHEN
+        // check_peer() is called
+        // --------------------
+        let result = check_peer(&peer, &peers);
+
+        // --------------------
+        // THEN
+        // the peer is allowed
+        // --------------------
+        assert!(result.is_ok());
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
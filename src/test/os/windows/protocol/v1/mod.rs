@@ -317,6 +317,11 @@ mod initsession {
 
         // Helpers
 
+        use keyring::{Keyring, new_keyring_handle};
+        use protocol::v1::auth::{ProtocolRegistry,
+                                 new_protocol_registry_handle};
+        use protocol::v1::prompt::new_prompts_handle;
+        use secret::SecretBuf;
         use settings::{WindowsSection, new_settings_handle};
         use settings::test::helper::new_settings;
 
@@ -360,11 +365,20 @@ mod initsession {
             );
             let settings_handle = new_settings_handle(settings);
             let session_store = SessionStore {
-                auth_token: auth_token,
+                auth_token: SecretBuf::from(auth_token),
                 auth_file: None,
             };
-            let mut session_state =
-                SessionState::new(session_store, settings_handle, dummy);
+            let keyring = new_keyring_handle(Keyring::new());
+            let protocols =
+                new_protocol_registry_handle(ProtocolRegistry::builtin());
+            let mut session_state = SessionState::new(
+                session_store,
+                settings_handle,
+                keyring,
+                protocols,
+                new_prompts_handle(),
+                dummy,
+            );
             let mut handle = session_state.handle();
 
             // ------------------------------------------------------------
@@ -616,6 +630,7 @@ mod authsession {
                            StateValue as V1StateValue};
         use rmpv::{Utf8String, Value};
         use rpc::v1::{SessionError, SessionMethod};
+        use secret::SecretBuf;
         use siminau_rpc::message::response::RpcResponse;
 
         // Helpers
@@ -650,7 +665,8 @@ mod authsession {
             let mut session_state = dummy_session_state_nofs(dummy);
 
             // Assign tokens to session_state
-            session_state.session_store().auth_token = "NOTCORRECT".to_owned();
+            session_state.session_store().auth_token =
+                SecretBuf::from("NOTCORRECT");
 
             // ------------------------------------------------------------
             // WHEN
@@ -703,7 +719,8 @@ mod authsession {
             let mut session_state = dummy_session_state_nofs(dummy);
 
             // Assign tokens to session_state
-            session_state.session_store().auth_token = auth_token;
+            session_state.session_store().auth_token =
+                SecretBuf::from(auth_token);
 
             // ------------------------------------------------------------
             // WHEN
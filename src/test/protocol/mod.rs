@@ -34,12 +34,23 @@ use tempdir::TempDir;
 // Local imports
 
 use error::{SasdErrorKind, SasdResult};
+use keyfile::KdfParams;
+use keyring::{Attrs, Key, Keyring, KeyringHandle, new_keyring_handle};
 use protocol::{Info, Protocol, Request, Response, State, StateValue};
+use protocol::v1::{ProtocolRequest, ProtocolResponse, Session,
+                   SessionResponse, attrs_to_value};
+use protocol::v1::auth::{ProtocolRegistry, new_protocol_registry_handle};
+use protocol::v1::prompt::{PromptsHandle, new_prompts_handle};
 
 #[cfg(windows)]
 use protocol::SessionStore;
 
 use rpc;
+use rpc::v1::ProtocolMethod;
+
+#[cfg(windows)]
+use secret::SecretBuf;
+
 use settings::{SettingsBuilder, SettingsHandle, new_settings_handle};
 
 #[cfg(windows)]
@@ -48,7 +59,7 @@ use settings::WindowsSection;
 #[cfg(windows)]
 use settings::test::helper::new_settings;
 
-use state::{SessionState, SessionStateHandle};
+use state::{SessionState, SessionStateHandle, SessionStatus};
 
 
 // ===========================================================================
@@ -68,44 +79,59 @@ impl State for Test {
 }
 
 
+// Dummy settings w/ anything else the given fn adds to the builder
 #[cfg(unix)]
-fn dummy_settings() -> SasdResult<SettingsHandle>
+fn dummy_settings_with<F>(settings: F) -> SasdResult<SettingsHandle>
+where
+    F: FnOnce(SettingsBuilder) -> SasdResult<SettingsBuilder>,
 {
     let tempdir = TempDir::new("sasd").unwrap();
     let dirpath = tempdir.into_path().into_os_string().into_string().unwrap();
 
     // Build settings
-    let config = SettingsBuilder::new()
+    let builder = SettingsBuilder::new()
         .port(1234)?
         .unix()
         .socket_dir(dirpath)?
-        .unix_done()?
-        .build()?;
+        .unix_done()?;
+    let config = settings(builder)?.build()?;
     Ok(new_settings_handle(config))
 }
 
 
 #[cfg(windows)]
-fn dummy_settings() -> SasdResult<SettingsHandle>
+fn dummy_settings_with<F>(settings: F) -> SasdResult<SettingsHandle>
+where
+    F: FnOnce(SettingsBuilder) -> SasdResult<SettingsBuilder>,
 {
     let tempdir = TempDir::new("sasd").unwrap();
     let dirpath = tempdir.into_path().into_os_string().into_string().unwrap();
 
-    let config = SettingsBuilder::new()
+    let builder = SettingsBuilder::new()
         .port(1234)?
         .windows()
         .token_data_dir(dirpath)?
-        .windows_done()?
-        .build()?;
+        .windows_done()?;
+    let config = settings(builder)?.build()?;
     Ok(new_settings_handle(config))
 }
 
 
+fn dummy_settings() -> SasdResult<SettingsHandle>
+{
+    dummy_settings_with(Ok)
+}
+
+
 #[cfg(unix)]
-pub fn dummy_session_state(state: StateValue) -> SessionState
+pub fn dummy_session_state_prompts(
+    state: StateValue, keyring: KeyringHandle, protocols: ProtocolRegistry,
+    prompts: PromptsHandle
+) -> SessionState
 {
     let settings = dummy_settings().unwrap();
-    SessionState::new(settings, state)
+    let protocols = new_protocol_registry_handle(protocols);
+    SessionState::new(settings, keyring, protocols, prompts, state)
 }
 
 
@@ -121,18 +147,208 @@ pub fn dummy_session_state_nofs(state: StateValue) -> SessionState
         );
     let settings_handle = new_settings_handle(settings);
     let session_store = SessionStore {
-        auth_token: auth_token,
+        auth_token: SecretBuf::from(auth_token),
         auth_file: None,
     };
-    SessionState::new(session_store, settings_handle, state)
+    let keyring = new_keyring_handle(Keyring::new());
+    let protocols = new_protocol_registry_handle(ProtocolRegistry::builtin());
+    let prompts = new_prompts_handle();
+    SessionState::new(
+        session_store,
+        settings_handle,
+        keyring,
+        protocols,
+        prompts,
+        state,
+    )
 }
 
 #[cfg(windows)]
-pub fn dummy_session_state(state: StateValue) -> SessionState
+pub fn dummy_session_state_prompts(
+    state: StateValue, keyring: KeyringHandle, protocols: ProtocolRegistry,
+    prompts: PromptsHandle
+) -> SessionState
 {
     let settings = dummy_settings().unwrap();
     let store = SessionStore::default();
-    SessionState::new(store, settings, state)
+    let protocols = new_protocol_registry_handle(protocols);
+    SessionState::new(store, settings, keyring, protocols, prompts, state)
+}
+
+
+// A session w/ the builtin protocols and settings changed by the given fn
+#[cfg(unix)]
+pub fn dummy_session_state_settings<F>(
+    state: StateValue, keyring: KeyringHandle, settings: F
+) -> SessionState
+where
+    F: FnOnce(SettingsBuilder) -> SasdResult<SettingsBuilder>,
+{
+    let settings = dummy_settings_with(settings).unwrap();
+    let protocols = new_protocol_registry_handle(ProtocolRegistry::builtin());
+    let prompts = new_prompts_handle();
+    SessionState::new(settings, keyring, protocols, prompts, state)
+}
+
+
+#[cfg(windows)]
+pub fn dummy_session_state_settings<F>(
+    state: StateValue, keyring: KeyringHandle, settings: F
+) -> SessionState
+where
+    F: FnOnce(SettingsBuilder) -> SasdResult<SettingsBuilder>,
+{
+    let settings = dummy_settings_with(settings).unwrap();
+    let store = SessionStore::default();
+    let protocols = new_protocol_registry_handle(ProtocolRegistry::builtin());
+    let prompts = new_prompts_handle();
+    SessionState::new(store, settings, keyring, protocols, prompts, state)
+}
+
+
+pub fn dummy_session_state_protocols(
+    state: StateValue, keyring: KeyringHandle, protocols: ProtocolRegistry
+) -> SessionState
+{
+    let prompts = new_prompts_handle();
+    dummy_session_state_prompts(state, keyring, protocols, prompts)
+}
+
+
+pub fn dummy_session_state_keyring(state: StateValue, keyring: KeyringHandle)
+    -> SessionState
+{
+    dummy_session_state_protocols(state, keyring, ProtocolRegistry::builtin())
+}
+
+
+pub fn dummy_session_state(state: StateValue) -> SessionState
+{
+    let keyring = new_keyring_handle(Keyring::new());
+    dummy_session_state_keyring(state, keyring)
+}
+
+
+pub fn attrs(pairs: &[(&str, &str)]) -> Attrs
+{
+    pairs
+        .iter()
+        .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+        .collect()
+}
+
+
+pub fn alice() -> Key
+{
+    let pairs =
+        [("proto", "pass"), ("user", "alice"), ("!password", "hunter2")];
+    Key::new(attrs(&pairs))
+}
+
+
+// A keyring w/ a single password key for the given protocol and user
+pub fn password_keyring(proto: &str, user: &str, password: &str) -> Keyring
+{
+    let mut keyring = Keyring::new();
+    keyring.add(Key::new(attrs(
+        &[("proto", proto), ("user", user), ("!password", password)],
+    )));
+    keyring
+}
+
+
+// Cheap parameters so tests don't spend their time in scrypt
+pub fn kdf_params() -> KdfParams
+{
+    KdfParams {
+        log_n: 4,
+        r: 8,
+        p: 1,
+    }
+}
+
+
+// An empty unlocked keyring w/ a file in the given directory
+pub fn file_keyring(dir: &TempDir) -> Keyring
+{
+    let path = dir.path().join("keyring");
+    Keyring::open_with_params(&path, b"passphrase", kdf_params()).unwrap()
+}
+
+
+// A Session w/ the given keyring and the builtin protocols
+pub fn new_session_state(keyring: KeyringHandle) -> SessionState
+{
+    dummy_session_state_keyring(Session::new().into(), keyring)
+}
+
+
+// A Session w/ the given keyring and prompts
+pub fn new_session_state_prompts(
+    keyring: KeyringHandle, prompts: &PromptsHandle
+) -> SessionState
+{
+    dummy_session_state_prompts(
+        Session::new().into(),
+        keyring,
+        ProtocolRegistry::builtin(),
+        prompts.clone(),
+    )
+}
+
+
+// A Session w/ the given keyring and settings changed by the given fn
+pub fn new_session_state_settings<F>(keyring: KeyringHandle, settings: F)
+    -> SessionState
+where
+    F: FnOnce(SettingsBuilder) -> SasdResult<SettingsBuilder>,
+{
+    dummy_session_state_settings(Session::new().into(), keyring, settings)
+}
+
+
+// Dispatch a message to a Session, expecting the state to be kept and a
+// response to be sent back
+pub fn dispatch(session_state: &mut SessionState, msg: Message)
+    -> SessionResponse
+{
+    let mut session = Session::new();
+    let result = session.dispatch(&mut session_state.handle(), msg);
+    match result {
+        Ok((None, Some(m))) => SessionResponse::from(m).unwrap(),
+        _ => panic!("unexpected dispatch result"),
+    }
+}
+
+
+pub fn start(pairs: &[(&str, &str)]) -> Message
+{
+    let args = vec![attrs_to_value(&attrs(pairs))];
+    ProtocolRequest::new(42, ProtocolMethod::ProtocolStart, args).into()
+}
+
+
+pub fn read() -> Message
+{
+    ProtocolRequest::new(42, ProtocolMethod::ProtocolRead, vec![]).into()
+}
+
+
+pub fn write(data: &[u8]) -> Message
+{
+    let args = vec![Value::Binary(data.to_vec())];
+    ProtocolRequest::new(42, ProtocolMethod::ProtocolWrite, args).into()
+}
+
+
+// Pass a message through the session, expecting a protocol response
+pub fn send(session_state: &mut SessionState, msg: Message)
+    -> ProtocolResponse
+{
+    match session_state.change(msg).unwrap() {
+        SessionStatus::Reply(m) => ProtocolResponse::from(m).unwrap(),
+        _ => panic!("no reply"),
+    }
 }
 
 
@@ -0,0 +1,213 @@
+// src/test/protocol/v1/apop.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+use error::SasdErrorKind;
+use keyring::Keyring;
+use protocol::v1::auth::{AuthProtocol, Phase, ProtocolRegistry};
+
+use test::protocol::{attrs, password_keyring};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+fn start(keyring: &mut Keyring, pairs: &[(&str, &str)])
+    -> Box<AuthProtocol>
+{
+    let attrs = attrs(pairs);
+    let mut module = ProtocolRegistry::builtin().find(&attrs["proto"]).unwrap();
+    module.start(keyring, &attrs).unwrap();
+    module
+}
+
+
+// Run the client role against a challenge, returning the response
+fn respond(keyring: &mut Keyring, proto: &str, challenge: &[u8]) -> String
+{
+    let mut client = start(keyring, &[("proto", proto), ("role", "client")]);
+    assert_eq!(client.write(keyring, challenge).unwrap(), Phase::Continue);
+    let (response, phase) = client.read(keyring).unwrap();
+    assert_eq!(phase, Phase::Done);
+    response.as_str().unwrap().to_owned()
+}
+
+
+// ===========================================================================
+// Test client role
+// ===========================================================================
+
+
+mod client {
+    use super::*;
+
+    #[test]
+    fn apop_rfc1939()
+    {
+        // --------------------
+        // GIVEN
+        // the APOP example from RFC 1939
+        // --------------------
+        let mut keyring = password_keyring("apop", "mrose", "tanstaaf");
+        let challenge = b"<1896.697170952@dbc.mtview.ca.us>";
+
+        // --------------------
+        // WHEN
+        // the challenge is written to the apop client
+        // --------------------
+        let response = respond(&mut keyring, "apop", challenge);
+
+        // --------------------
+        // THEN
+        // the response from the RFC is read back
+        // --------------------
+        assert_eq!(response, "mrose c4c9334bac560ecc979e58001b3e22fb");
+    }
+
+    #[test]
+    fn cram_rfc2195()
+    {
+        // --------------------
+        // GIVEN
+        // the CRAM-MD5 example from RFC 2195
+        // --------------------
+        let mut keyring = password_keyring("cram", "tim", "tanstaaftanstaaf");
+        let challenge = b"<1896.697170952@postoffice.reston.mci.net>";
+
+        // --------------------
+        // WHEN
+        // the challenge is written to the cram client
+        // --------------------
+        let response = respond(&mut keyring, "cram", challenge);
+
+        // --------------------
+        // THEN
+        // the response from the RFC is read back
+        // --------------------
+        assert_eq!(response, "tim b913a602c7eda7a495b4e6e7334d3890");
+    }
+
+    #[test]
+    fn read_before_challenge()
+    {
+        // --------------------
+        // GIVEN
+        // a started apop client
+        // --------------------
+        let mut keyring = password_keyring("apop", "mrose", "tanstaaf");
+        let mut client = start(&mut keyring, &[("proto", "apop")]);
+
+        // --------------------
+        // WHEN
+        // the response is read before a challenge is written
+        // --------------------
+        let result = client.read(&mut keyring);
+
+        // --------------------
+        // THEN
+        // a ProtocolPhase error is returned
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolPhase(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+    }
+}
+
+
+// ===========================================================================
+// Test server role
+// ===========================================================================
+
+
+mod server {
+    use super::*;
+
+    // Read a challenge from the server, answer it w/ a client using the
+    // given password, and return the server's verdict
+    fn authenticate(proto: &str, password: &str) -> bool
+    {
+        let mut keyring = password_keyring(proto, "alice", "hunter2");
+        let mut server =
+            start(&mut keyring, &[("proto", proto), ("role", "server")]);
+        let (challenge, phase) = server.read(&mut keyring).unwrap();
+        assert_eq!(phase, Phase::Continue);
+
+        let mut client_keyring = password_keyring(proto, "alice", password);
+        let response =
+            respond(&mut client_keyring, proto, challenge.as_bytes());
+
+        match server.write(&mut keyring, response.as_bytes()) {
+            Ok(phase) => {
+                assert_eq!(phase, Phase::Done);
+                let info = server.authinfo().unwrap();
+                assert_eq!(info.cuid, Some("alice".to_owned()));
+                true
+            }
+            Err(e) => {
+                assert!(matches!(*e.kind(), SasdErrorKind::ProtocolFailed(_)));
+                false
+            }
+        }
+    }
+
+    #[test]
+    fn accepts_right_password()
+    {
+        assert!(authenticate("apop", "hunter2"));
+        assert!(authenticate("cram", "hunter2"));
+    }
+
+    #[test]
+    fn rejects_wrong_password()
+    {
+        assert!(!authenticate("apop", "wrong"));
+        assert!(!authenticate("cram", "wrong"));
+    }
+
+    #[test]
+    fn challenges_differ()
+    {
+        // --------------------
+        // GIVEN
+        // 2 cram servers
+        // --------------------
+        let mut keyring = password_keyring("cram", "alice", "hunter2");
+        let pairs = [("proto", "cram"), ("role", "server")];
+        let mut first = start(&mut keyring, &pairs);
+        let mut second = start(&mut keyring, &pairs);
+
+        // --------------------
+        // WHEN
+        // a challenge is read from each
+        // --------------------
+        let (a, _) = first.read(&mut keyring).unwrap();
+        let (b, _) = second.read(&mut keyring).unwrap();
+
+        // --------------------
+        // THEN
+        // the challenges are different
+        // --------------------
+        assert!(a != b);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
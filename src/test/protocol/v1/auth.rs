@@ -0,0 +1,124 @@
+// src/test/protocol/v1/auth.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+use error::SasdResult;
+use keyring::{Attrs, Keyring};
+use protocol::v1::auth::{AuthProtocol, Phase, ProtocolRegistry};
+use secret::SecretBuf;
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+// Finishes as soon as it is read from
+struct Done;
+
+
+impl AuthProtocol for Done {
+    fn start(&mut self, _keyring: &mut Keyring, _attrs: &Attrs)
+        -> SasdResult<()>
+    {
+        Ok(())
+    }
+
+    fn write(&mut self, _keyring: &mut Keyring, _data: &[u8])
+        -> SasdResult<Phase>
+    {
+        Ok(Phase::Continue)
+    }
+
+    fn read(&mut self, _keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>
+    {
+        Ok((SecretBuf::from("done"), Phase::Done))
+    }
+}
+
+
+fn new_done() -> Box<AuthProtocol>
+{
+    Box::new(Done)
+}
+
+
+// ===========================================================================
+// Test ProtocolRegistry
+// ===========================================================================
+
+
+mod registry {
+    use super::*;
+
+    #[test]
+    fn find_registered()
+    {
+        // --------------------
+        // GIVEN
+        // a registry w/ a registered module
+        // --------------------
+        let mut registry = ProtocolRegistry::new();
+        assert!(registry.register("done", new_done));
+
+        // --------------------
+        // WHEN
+        // modules are looked up by name
+        // --------------------
+        let found = registry.find("done");
+        let missing = registry.find("nope");
+
+        // --------------------
+        // THEN
+        // a new module is created for the registered name only
+        // --------------------
+        let mut module = found.unwrap();
+        let (data, phase) = module.read(&mut Keyring::new()).unwrap();
+        assert!(data.ct_eq(b"done"));
+        assert_eq!(phase, Phase::Done);
+        assert!(missing.is_none());
+        assert_eq!(registry.names(), vec!["done"]);
+    }
+
+    #[test]
+    fn register_same_name()
+    {
+        // --------------------
+        // GIVEN
+        // a registry w/ a registered module
+        // --------------------
+        let mut registry = ProtocolRegistry::new();
+        registry.register("done", new_done);
+
+        // --------------------
+        // WHEN
+        // another module is registered under the same name
+        // --------------------
+        let added = registry.register("done", new_done);
+
+        // --------------------
+        // THEN
+        // the module is not registered
+        // --------------------
+        assert!(!added);
+        assert_eq!(registry.names().len(), 1);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
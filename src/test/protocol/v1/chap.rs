@@ -0,0 +1,311 @@
+// src/test/protocol/v1/chap.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+use error::SasdErrorKind;
+use keyring::Keyring;
+use protocol::v1::auth::{AuthProtocol, Phase, ProtocolRegistry, to_hex};
+use protocol::v1::chap::{authenticator_response, generate_nt_response,
+                         master_key, nt_password_hash};
+
+use test::protocol::{attrs, password_keyring};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+// RFC 2759 section 9.2
+const USER: &'static str = "User";
+const PASSWORD: &'static str = "clientPass";
+const AUTH_CHALLENGE: &'static str = "5b5d7c7d7b3f2f3e3c2c602132262628";
+const PEER_CHALLENGE: &'static str = "21402324255e262a28295f2b3a337c7e";
+const PASSWORD_HASH: &'static str = "44ebba8d5312b8d611474411f56989ae";
+const NT_RESPONSE: &'static str =
+    "82309ecd8d708b5ea08faa3981cd83544233114a3d85d6df";
+const AUTH_RESPONSE: &'static str =
+    "S=407A5589115FD0D6209F510FE9C04566932CDA56";
+
+
+fn from_hex(hex: &str) -> Vec<u8>
+{
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+
+fn start(keyring: &mut Keyring, proto: &str) -> Box<AuthProtocol>
+{
+    let attrs = attrs(&[("proto", proto)]);
+    let mut module = ProtocolRegistry::builtin().find(proto).unwrap();
+    module.start(keyring, &attrs).unwrap();
+    module
+}
+
+
+// ===========================================================================
+// Test MS-CHAPv2 primitives
+// ===========================================================================
+
+
+mod primitives {
+    use super::*;
+
+    #[test]
+    fn nt_password_hash_rfc2759()
+    {
+        let hash = nt_password_hash(PASSWORD);
+        assert_eq!(to_hex(hash.as_bytes()), PASSWORD_HASH);
+    }
+
+    #[test]
+    fn nt_response_rfc2759()
+    {
+        let response = generate_nt_response(
+            &from_hex(AUTH_CHALLENGE),
+            &from_hex(PEER_CHALLENGE),
+            USER,
+            &from_hex(PASSWORD_HASH),
+        );
+        assert_eq!(to_hex(&response), NT_RESPONSE);
+    }
+
+    #[test]
+    fn authenticator_response_rfc2759()
+    {
+        let response = authenticator_response(
+            &from_hex(PASSWORD_HASH),
+            &from_hex(NT_RESPONSE),
+            &from_hex(PEER_CHALLENGE),
+            &from_hex(AUTH_CHALLENGE),
+            USER,
+        );
+        assert_eq!(response, AUTH_RESPONSE);
+    }
+
+    #[test]
+    fn master_key_rfc3079()
+    {
+        let key = master_key(&from_hex(PASSWORD_HASH), &from_hex(NT_RESPONSE));
+        assert_eq!(to_hex(key.as_bytes()), "fdece3717a8c838cb388e527ae3cdd31");
+    }
+}
+
+
+// ===========================================================================
+// Test chap
+// ===========================================================================
+
+
+mod chap {
+    use super::*;
+
+    #[test]
+    fn response_is_md5()
+    {
+        // --------------------
+        // GIVEN
+        // a started chap client and a challenge packet w/ id 7
+        // --------------------
+        let mut keyring = password_keyring("chap", "alice", "secret");
+        let mut client = start(&mut keyring, "chap");
+        let mut challenge = vec![7u8];
+        challenge.extend(0u8..16);
+
+        // --------------------
+        // WHEN
+        // the challenge is written and the response read
+        // --------------------
+        let phase = client.write(&mut keyring, &challenge).unwrap();
+        let (response, done) = client.read(&mut keyring).unwrap();
+
+        // --------------------
+        // THEN
+        // the response is md5(id + password + challenge) and
+        // the protocol is done w/ the key's user as cuid
+        // --------------------
+        assert_eq!(phase, Phase::Continue);
+        assert_eq!(done, Phase::Done);
+        assert_eq!(
+            to_hex(response.as_bytes()),
+            "821643665b430359e52ac524d29c8f95"
+        );
+        let info = client.authinfo().unwrap();
+        assert_eq!(info.cuid, Some("alice".to_owned()));
+    }
+
+    #[test]
+    fn short_challenge()
+    {
+        // --------------------
+        // GIVEN
+        // a started chap client
+        // --------------------
+        let mut keyring = password_keyring("chap", "alice", "secret");
+        let mut client = start(&mut keyring, "chap");
+
+        // --------------------
+        // WHEN
+        // only an id is written
+        // --------------------
+        let result = client.write(&mut keyring, &[1]);
+
+        // --------------------
+        // THEN
+        // a ProtocolPhase error is returned
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolPhase(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+    }
+}
+
+
+// ===========================================================================
+// Test mschapv2
+// ===========================================================================
+
+
+mod mschapv2 {
+    use super::*;
+
+    // Write a challenge and read the response packet value, returning the
+    // S= string a server w/ the given password would send back
+    fn exchange(keyring: &mut Keyring, client: &mut Box<AuthProtocol>,
+                password: &str)
+        -> String
+    {
+        let auth_challenge = from_hex(AUTH_CHALLENGE);
+        let phase = client.write(keyring, &auth_challenge).unwrap();
+        assert_eq!(phase, Phase::Continue);
+
+        let (response, phase) = client.read(keyring).unwrap();
+        assert_eq!(phase, Phase::Continue);
+        let response = response.as_bytes();
+        assert_eq!(response.len(), 49);
+        assert_eq!(&response[16..24], &[0u8; 8]);
+
+        let peer_challenge = &response[..16];
+        let nt_response = generate_nt_response(
+            &auth_challenge,
+            peer_challenge,
+            USER,
+            nt_password_hash(PASSWORD).as_bytes(),
+        );
+        assert_eq!(&response[24..48], &nt_response[..]);
+        authenticator_response(
+            nt_password_hash(password).as_bytes(),
+            &nt_response,
+            peer_challenge,
+            &auth_challenge,
+            USER,
+        )
+    }
+
+    #[test]
+    fn server_verified()
+    {
+        // --------------------
+        // GIVEN
+        // a mschapv2 client that has sent its response
+        // --------------------
+        let mut keyring = password_keyring("mschapv2", USER, PASSWORD);
+        let mut client = start(&mut keyring, "mschapv2");
+        let success = exchange(&mut keyring, &mut client, PASSWORD);
+
+        // --------------------
+        // WHEN
+        // the server's success message is written
+        // --------------------
+        let success = format!("{} M=welcome", success);
+        let result = client.write(&mut keyring, success.as_bytes());
+
+        // --------------------
+        // THEN
+        // the protocol is done and authinfo has the user and master key
+        // --------------------
+        assert_eq!(result.unwrap(), Phase::Done);
+        let info = client.authinfo().unwrap();
+        assert_eq!(info.cuid, Some(USER.to_owned()));
+        assert_eq!(info.secret.unwrap().len(), 16);
+    }
+
+    #[test]
+    fn server_not_verified()
+    {
+        // --------------------
+        // GIVEN
+        // a mschapv2 client that has sent its response
+        // --------------------
+        let mut keyring = password_keyring("mschapv2", USER, PASSWORD);
+        let mut client = start(&mut keyring, "mschapv2");
+
+        // --------------------
+        // WHEN
+        // the success message is from a server w/ a different password
+        // --------------------
+        let success = exchange(&mut keyring, &mut client, "wrong");
+        let result = client.write(&mut keyring, success.as_bytes());
+
+        // --------------------
+        // THEN
+        // a ProtocolFailed error is returned
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolFailed(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+        assert_eq!(client.authinfo().unwrap().secret, None);
+    }
+
+    #[test]
+    fn server_role()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring w/ a mschapv2 key
+        // --------------------
+        let mut keyring = password_keyring("mschapv2", USER, PASSWORD);
+        let attrs = attrs(&[("proto", "mschapv2"), ("role", "server")]);
+        let mut module = ProtocolRegistry::builtin().find("mschapv2").unwrap();
+
+        // --------------------
+        // WHEN
+        // the module is started in the server role
+        // --------------------
+        let result = module.start(&mut keyring, &attrs);
+
+        // --------------------
+        // THEN
+        // a ProtocolFailed error is returned
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolFailed(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,651 @@
+// src/test/protocol/v1/conversation.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+use rmpv::Value;
+use siminau_rpc::message::Message;
+use siminau_rpc::message::response::RpcResponse;
+use tempdir::TempDir;
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Attrs, Keyring, KeyringHandle, new_keyring_handle};
+use protocol::{State, StateValue};
+use protocol::v1::{Conversation, ProtocolRequest, ProtocolResponse, Session,
+                   SessionRequest, SessionResponse, attrs_to_value};
+use protocol::v1::auth::{AuthInfo, AuthProtocol, Phase, ProtocolRegistry};
+use rpc::v1::{ProtocolError, ProtocolMethod, SessionError, SessionMethod};
+use secret::SecretBuf;
+use state::SessionState;
+
+use test::protocol::{attrs, cleanup_settings, dummy_session_state_protocols,
+                     new_session_state, read, write};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+// Reads back whatever was last written, then finishes. Writing "fail" makes
+// the protocol fail. A user attr must be given to start it. Given a
+// keyring, an ! is added to what was written if the keyring is free once
+// the write is done.
+#[derive(Default)]
+struct Echo {
+    user: Option<String>,
+    data: Option<SecretBuf>,
+    keyring: Option<KeyringHandle>,
+}
+
+
+impl AuthProtocol for Echo {
+    fn start(&mut self, _keyring: &mut Keyring, attrs: &Attrs)
+        -> SasdResult<()>
+    {
+        match attrs.get("user") {
+            Some(user) => self.user = Some(user.clone()),
+            None => {
+                let errmsg = "no user".to_owned();
+                bail!(SasdErrorKind::ProtocolNeedKey(errmsg))
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, _keyring: &mut Keyring, data: &[u8])
+        -> SasdResult<Phase>
+    {
+        if data == b"fail" {
+            bail!(SasdErrorKind::ProtocolFailed("echo failed".to_owned()))
+        }
+        self.data = Some(SecretBuf::from(data));
+        Ok(Phase::Continue)
+    }
+
+    fn read(&mut self, _keyring: &mut Keyring)
+        -> SasdResult<(SecretBuf, Phase)>
+    {
+        match self.data.take() {
+            Some(data) => Ok((data, Phase::Done)),
+            None => {
+                let errmsg = "nothing to read".to_owned();
+                bail!(SasdErrorKind::ProtocolPhase(errmsg))
+            }
+        }
+    }
+
+    fn run_unlocked(&mut self) -> SasdResult<()>
+    {
+        let free = match self.keyring {
+            Some(ref k) => k.try_write().is_ok(),
+            None => return Ok(()),
+        };
+        if let (true, Some(data)) = (free, self.data.take()) {
+            let mut data = data.as_bytes().to_vec();
+            data.push(b'!');
+            self.data = Some(SecretBuf::from(data));
+        }
+        Ok(())
+    }
+
+    fn needkey(&self) -> Attrs
+    {
+        attrs(&[("proto", "echo"), ("user", "?")])
+    }
+
+    fn authinfo(&self) -> Option<AuthInfo>
+    {
+        let info = AuthInfo {
+            cuid: self.user.clone(),
+            secret: Some(SecretBuf::from("shared")),
+            ..AuthInfo::default()
+        };
+        Some(info)
+    }
+}
+
+
+fn new_echo() -> Box<AuthProtocol>
+{
+    Box::new(Echo::default())
+}
+
+
+// Session state whose registry only knows the echo protocol
+fn echo_session_state() -> SessionState
+{
+    let mut protocols = ProtocolRegistry::new();
+    protocols.register("echo", new_echo);
+    let keyring = new_keyring_handle(Keyring::new());
+    dummy_session_state_protocols(Session::new().into(), keyring, protocols)
+}
+
+
+fn echo() -> Conversation
+{
+    let mut module = new_echo();
+    let mut keyring = Keyring::new();
+    module.start(&mut keyring, &attrs(&[("user", "alice")])).unwrap();
+    Conversation::new("echo".to_owned(), module)
+}
+
+
+fn request(method: ProtocolMethod, args: Vec<Value>) -> Message
+{
+    ProtocolRequest::new(42, method, args).into()
+}
+
+
+// Dispatch a message to a Session, returning the next state and the
+// protocol response
+fn dispatch_session(session_state: &mut SessionState, msg: Message)
+    -> (Option<StateValue>, ProtocolResponse)
+{
+    let mut session = Session::new();
+    let result = session.dispatch(&mut session_state.handle(), msg);
+    match result {
+        Ok((next, Some(m))) => (next, ProtocolResponse::from(m).unwrap()),
+        _ => panic!("unexpected dispatch result"),
+    }
+}
+
+
+// Dispatch a message to a Conversation, returning the next state and the
+// protocol response
+fn dispatch(
+    conv: &mut Conversation, session_state: &mut SessionState, msg: Message
+) -> (Option<StateValue>, ProtocolResponse)
+{
+    let result = conv.dispatch(&mut session_state.handle(), msg);
+    match result {
+        Ok((next, Some(m))) => (next, ProtocolResponse::from(m).unwrap()),
+        _ => panic!("unexpected dispatch result"),
+    }
+}
+
+
+fn is_session(next: &Option<StateValue>) -> bool
+{
+    match next {
+        &Some(StateValue::V1(ref v)) => v.is_session(),
+        _ => false,
+    }
+}
+
+
+// ===========================================================================
+// Test ProtocolStart
+// ===========================================================================
+
+
+mod start {
+    use super::*;
+
+    #[test]
+    fn unknown_protocol()
+    {
+        // --------------------
+        // GIVEN
+        // a session and
+        // a ProtocolStart request naming a protocol w/o a module
+        // --------------------
+        let mut session_state = echo_session_state();
+        let args = vec![attrs_to_value(&attrs(&[("proto", "nope")]))];
+        let msg = request(ProtocolMethod::ProtocolStart, args);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let (next, resp) = dispatch_session(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an UnknownProtocol error response is returned and
+        // the session state is kept
+        // --------------------
+        assert_eq!(resp.message_id(), 42);
+        assert_eq!(resp.error_code(), ProtocolError::UnknownProtocol);
+        assert!(next.is_none());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn registered_protocol()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ the echo protocol registered and
+        // a ProtocolStart request for the echo protocol
+        // --------------------
+        let mut session_state = echo_session_state();
+        let start = attrs(&[("proto", "echo"), ("user", "alice")]);
+        let args = vec![attrs_to_value(&start)];
+        let msg = request(ProtocolMethod::ProtocolStart, args);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let (next, resp) = dispatch_session(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a Nil error response is returned and
+        // the session moves on to a conversation w/ the echo module
+        // --------------------
+        assert_eq!(resp.error_code(), ProtocolError::Nil);
+        let conv = match next {
+            Some(StateValue::V1(v)) => v.to_conversation().unwrap(),
+            _ => panic!("not a conversation"),
+        };
+        assert_eq!(conv.proto(), "echo");
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn needkey()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ the echo protocol registered and
+        // a ProtocolStart request missing attrs the module needs
+        // --------------------
+        let mut session_state = echo_session_state();
+        let args = vec![attrs_to_value(&attrs(&[("proto", "echo")]))];
+        let msg = request(ProtocolMethod::ProtocolStart, args);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let (next, resp) = dispatch_session(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a ProtocolNeedKey error response w/ the module's key template is
+        // returned and
+        // the session state is kept
+        // --------------------
+        let template = attrs(&[("proto", "echo"), ("user", "?")]);
+        assert_eq!(resp.error_code(), ProtocolError::ProtocolNeedKey);
+        assert_eq!(resp.result(), &attrs_to_value(&template));
+        assert!(next.is_none());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn read_outside_conversation()
+    {
+        // --------------------
+        // GIVEN
+        // a session that has not started a protocol
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with a ProtocolRead request
+        // --------------------
+        let mut session = Session::new();
+        let result = session.dispatch(&mut session_state.handle(), read());
+
+        // --------------------
+        // THEN
+        // an UnexpectedMessage error is returned
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::UnexpectedMessage),
+            Ok(_) => false,
+        };
+        assert!(value);
+
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+// Test Conversation
+// ===========================================================================
+
+
+mod conversation {
+    use super::*;
+
+    #[test]
+    fn write_then_read()
+    {
+        // --------------------
+        // GIVEN
+        // a conversation w/ a started protocol module
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let mut conv = echo();
+
+        // --------------------
+        // WHEN
+        // bytes are written to the module
+        // --------------------
+        let (next, resp) =
+            dispatch(&mut conv, &mut session_state, write(b"hi"));
+
+        // --------------------
+        // THEN
+        // the conversation continues
+        // --------------------
+        assert!(next.is_none());
+        assert_eq!(resp.error_code(), ProtocolError::Nil);
+        assert_eq!(resp.result(), &Value::from(false));
+
+        // --------------------
+        // WHEN
+        // bytes are read from the module
+        // --------------------
+        let (next, resp) = dispatch(&mut conv, &mut session_state, read());
+
+        // --------------------
+        // THEN
+        // the module's bytes are returned w/ the done flag and
+        // the session goes back to the Session state
+        // --------------------
+        let expected = Value::Array(
+            vec![Value::Binary(b"hi".to_vec()), Value::from(true)],
+        );
+        assert_eq!(resp.error_code(), ProtocolError::Nil);
+        assert_eq!(resp.result(), &expected);
+        assert!(is_session(&next));
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn write_runs_unlocked()
+    {
+        // --------------------
+        // GIVEN
+        // a conversation w/ an echo module that checks the session's
+        // keyring is free after a write
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring.clone());
+        let mut module = Echo::default();
+        module.keyring = Some(keyring);
+        let mut keyring = Keyring::new();
+        module.start(&mut keyring, &attrs(&[("user", "alice")])).unwrap();
+        let mut conv = Conversation::new("echo".to_owned(), Box::new(module));
+
+        // --------------------
+        // WHEN
+        // bytes are written to the module and read back
+        // --------------------
+        dispatch(&mut conv, &mut session_state, write(b"hi"));
+        let (_, resp) = dispatch(&mut conv, &mut session_state, read());
+
+        // --------------------
+        // THEN
+        // the module's work after the write was run w/o the keyring
+        // --------------------
+        let expected = Value::Array(
+            vec![Value::Binary(b"hi!".to_vec()), Value::from(true)],
+        );
+        assert_eq!(resp.result(), &expected);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn phase_error_continues()
+    {
+        // --------------------
+        // GIVEN
+        // a conversation w/ a started protocol module
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let mut conv = echo();
+
+        // --------------------
+        // WHEN
+        // bytes are read before the module has anything to send
+        // --------------------
+        let (next, resp) = dispatch(&mut conv, &mut session_state, read());
+
+        // --------------------
+        // THEN
+        // an InvalidProtocolMessage error response is returned and
+        // the conversation continues
+        // --------------------
+        assert_eq!(resp.error_code(), ProtocolError::InvalidProtocolMessage);
+        assert!(next.is_none());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn failure_ends_conversation()
+    {
+        // --------------------
+        // GIVEN
+        // a conversation w/ a started protocol module
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let mut conv = echo();
+
+        // --------------------
+        // WHEN
+        // the module fails
+        // --------------------
+        let (next, resp) =
+            dispatch(&mut conv, &mut session_state, write(b"fail"));
+
+        // --------------------
+        // THEN
+        // a ProtocolError error response is returned and
+        // the session goes back to the Session state
+        // --------------------
+        assert_eq!(resp.error_code(), ProtocolError::ProtocolError);
+        assert!(is_session(&next));
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn locked_ends_conversation()
+    {
+        // --------------------
+        // GIVEN
+        // a conversation w/ a started protocol module and
+        // a keyring that has since been locked
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let path = dir.path().join("keyring");
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring.clone());
+        let mut conv = echo();
+        *keyring.write().unwrap() = Keyring::locked(&path);
+
+        // --------------------
+        // WHEN
+        // bytes are written to the module
+        // --------------------
+        let (next, resp) =
+            dispatch(&mut conv, &mut session_state, write(b"hi"));
+
+        // --------------------
+        // THEN
+        // a Locked error response is returned and
+        // the session goes back to the Session state
+        // --------------------
+        assert_eq!(resp.error_code(), ProtocolError::Locked);
+        assert!(is_session(&next));
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn session_request_ends_conversation()
+    {
+        // --------------------
+        // GIVEN
+        // a conversation w/ a started protocol module
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let mut conv = echo();
+
+        // --------------------
+        // WHEN
+        // a KeyList request is sent
+        // --------------------
+        let msg: Message =
+            SessionRequest::new(42, SessionMethod::KeyList, vec![]).into();
+        let result = conv.dispatch(&mut session_state.handle(), msg);
+
+        // --------------------
+        // THEN
+        // the request is handled by Session and
+        // the session goes back to the Session state
+        // --------------------
+        let (next, resp) = match result {
+            Ok((next, Some(m))) => (next, SessionResponse::from(m).unwrap()),
+            _ => panic!("unexpected dispatch result"),
+        };
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        assert!(is_session(&next));
+
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+// Test ProtocolAuthInfo
+// ===========================================================================
+
+
+mod auth_info {
+    use super::*;
+
+    #[test]
+    fn after_protocol_done()
+    {
+        // --------------------
+        // GIVEN
+        // a conversation that has run to the end
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let mut conv = echo();
+        dispatch(&mut conv, &mut session_state, write(b"hi"));
+        let (next, _) = dispatch(&mut conv, &mut session_state, read());
+        let mut session = match next {
+            Some(StateValue::V1(v)) => v.to_session().unwrap(),
+            _ => panic!("not a session"),
+        };
+
+        // --------------------
+        // WHEN
+        // a ProtocolAuthInfo request is sent
+        // --------------------
+        let msg = request(ProtocolMethod::ProtocolAuthInfo, vec![]);
+        let result = session.dispatch(&mut session_state.handle(), msg);
+
+        // --------------------
+        // THEN
+        // the module's auth info is returned
+        // --------------------
+        let resp = match result {
+            Ok((None, Some(m))) => ProtocolResponse::from(m).unwrap(),
+            _ => panic!("unexpected dispatch result"),
+        };
+        let expected = Value::Map(vec![
+            (Value::from("cuid"), Value::from("alice")),
+            (Value::from("secret"), Value::Binary(b"shared".to_vec())),
+        ]);
+        assert_eq!(resp.error_code(), ProtocolError::Nil);
+        assert_eq!(resp.result(), &expected);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn no_protocol_done()
+    {
+        // --------------------
+        // GIVEN
+        // a session that has not run a protocol
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+
+        // --------------------
+        // WHEN
+        // a ProtocolAuthInfo request is sent
+        // --------------------
+        let msg = request(ProtocolMethod::ProtocolAuthInfo, vec![]);
+        let (_, resp) = dispatch_session(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidProtocolAuth error response is returned
+        // --------------------
+        assert_eq!(resp.error_code(), ProtocolError::InvalidProtocolAuth);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn during_conversation()
+    {
+        // --------------------
+        // GIVEN
+        // a conversation that has not finished
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let mut conv = echo();
+        dispatch(&mut conv, &mut session_state, write(b"hi"));
+
+        // --------------------
+        // WHEN
+        // a ProtocolAuthInfo request is sent
+        // --------------------
+        let msg = request(ProtocolMethod::ProtocolAuthInfo, vec![]);
+        let (next, resp) = dispatch(&mut conv, &mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidProtocolAuth error response is returned and
+        // the conversation continues
+        // --------------------
+        assert_eq!(resp.error_code(), ProtocolError::InvalidProtocolAuth);
+        assert!(next.is_none());
+        let (_, resp) = dispatch(&mut conv, &mut session_state, read());
+        assert_eq!(resp.error_code(), ProtocolError::Nil);
+
+        cleanup_settings(session_state);
+    }
+}
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,303 @@
+// src/test/protocol/v1/keygen.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+use data_encoding::{BASE32_NOPAD, BASE64};
+use siminau_rpc::message::Message;
+use siminau_rpc::message::response::RpcResponse;
+
+// Local imports
+
+use error::SasdErrorKind;
+use keyring::{Key, Keyring, KeyringHandle, new_keyring_handle};
+use protocol::State;
+use protocol::v1::{Session, SessionRequest, SessionResponse,
+                   attrs_from_value, attrs_to_value};
+use protocol::v1::sign::ed25519_key;
+use query::Query;
+use rpc::v1::{SessionError, SessionMethod};
+use secret::SecretBuf;
+
+use test::protocol::{attrs, cleanup_settings, dispatch, new_session_state};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+fn generate(key: &[(&str, &str)], spec: &[(&str, &str)]) -> Message
+{
+    let args = vec![attrs_to_value(&attrs(key)), attrs_to_value(&attrs(spec))];
+    SessionRequest::new(42, SessionMethod::GenerateKey, args).into()
+}
+
+
+// Run a GenerateKey request against an empty keyring, returning the
+// response and the keyring
+fn generate_key(key: &[(&str, &str)], spec: &[(&str, &str)])
+    -> (SessionResponse, KeyringHandle)
+{
+    let keyring = new_keyring_handle(Keyring::new());
+    let mut session_state = new_session_state(keyring.clone());
+    let resp = dispatch(&mut session_state, generate(key, spec));
+    cleanup_settings(session_state);
+    (resp, keyring)
+}
+
+
+// The secret attr of the only key in the keyring
+fn secret(keyring: &KeyringHandle, attr: &str) -> SecretBuf
+{
+    let keyring = keyring.read().unwrap();
+    assert_eq!(keyring.len(), 1);
+    keyring.keys()[0].secret()[attr].clone()
+}
+
+
+fn is_invalid_key(resp: &SessionResponse) -> bool
+{
+    resp.error_code() == SessionError::InvalidKey && resp.result().is_str()
+}
+
+
+// ===========================================================================
+// Test GenerateKey
+// ===========================================================================
+
+
+mod generate_key {
+    use super::*;
+
+    #[test]
+    fn password_default()
+    {
+        // --------------------
+        // WHEN
+        // a password key is generated w/ the default spec
+        // --------------------
+        let key = [("proto", "pass"), ("user", "alice")];
+        let (resp, keyring) = generate_key(&key, &[("type", "password")]);
+
+        // --------------------
+        // THEN
+        // only the key's public attrs are returned and
+        // the key has a 24 character password w/ lower and upper case
+        // letters and digits
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        assert_eq!(resp.result(), &attrs_to_value(&attrs(&key)));
+
+        let password = secret(&keyring, "!password");
+        let password = password.as_str().unwrap();
+        assert_eq!(password.len(), 24);
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert!(password.chars().any(|c| c.is_ascii_lowercase()));
+        assert!(password.chars().any(|c| c.is_ascii_uppercase()));
+        assert!(password.chars().any(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn password_spec()
+    {
+        // --------------------
+        // WHEN
+        // a password key is generated w/ a length and charset
+        // --------------------
+        let key = [("proto", "pass"), ("user", "alice")];
+        let spec =
+            [("type", "password"), ("length", "8"), ("charset", "digits")];
+        let (resp, keyring) = generate_key(&key, &spec);
+
+        // --------------------
+        // THEN
+        // the key has an 8 digit password
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        let password = secret(&keyring, "!password");
+        let password = password.as_str().unwrap();
+        assert_eq!(password.len(), 8);
+        assert!(password.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn ed25519()
+    {
+        // --------------------
+        // WHEN
+        // an ed25519 key is generated for the sign protocol
+        // --------------------
+        let key = [("proto", "sign"), ("name", "release")];
+        let (resp, keyring) = generate_key(&key, &[("type", "ed25519")]);
+
+        // --------------------
+        // THEN
+        // the key's alg and pubkey are returned and
+        // the pubkey is the public key of the key's secret
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        let public = attrs_from_value(resp.result()).unwrap();
+        assert_eq!(public["alg"], "ed25519");
+        assert!(public.keys().all(|a| !a.starts_with('!')));
+
+        let private = ed25519_key(&secret(&keyring, "!secret")).unwrap();
+        let pubkey = BASE64.encode(private.verifying_key().as_bytes());
+        assert_eq!(public["pubkey"], pubkey);
+    }
+
+    #[test]
+    fn totp()
+    {
+        // --------------------
+        // WHEN
+        // a totp key is generated
+        // --------------------
+        let key = [("proto", "totp"), ("user", "alice")];
+        let (resp, keyring) = generate_key(&key, &[("type", "totp")]);
+
+        // --------------------
+        // THEN
+        // the key has a base32 20 byte seed
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        let seed = secret(&keyring, "!secret");
+        let seed = BASE32_NOPAD.decode(seed.as_bytes()).unwrap();
+        assert_eq!(seed.len(), 20);
+    }
+
+    #[test]
+    fn key_exists()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding a key
+        // --------------------
+        let key = [("proto", "pass"), ("user", "alice")];
+        let mut keyring = Keyring::new();
+        keyring.add(Key::new(attrs(&key)));
+        let keyring = new_keyring_handle(keyring);
+        let mut session_state = new_session_state(keyring.clone());
+
+        // --------------------
+        // WHEN
+        // a key w/ the same public attrs is generated
+        // --------------------
+        let msg = generate(&key, &[("type", "password")]);
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a KeyExists error response is returned and
+        // the old key is kept
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::KeyExists);
+        let query = Query::parse("!password?").unwrap();
+        assert!(keyring.read().unwrap().find(&query).is_empty());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn invalid_spec()
+    {
+        // --------------------
+        // GIVEN
+        // specs w/ an unknown type, an unknown attr, a bad length and a
+        // charset w/ more classes than the length
+        // --------------------
+        let key = [("proto", "pass"), ("user", "alice")];
+        let specs: &[&[(&str, &str)]] = &[
+            &[("type", "rsa")],
+            &[("type", "ed25519"), ("length", "32")],
+            &[("type", "password"), ("length", "0")],
+            &[("type", "totp"), ("length", "8")],
+            &[("type", "password"), ("charset", "emoji")],
+            &[
+                ("type", "password"),
+                ("length", "2"),
+                ("charset", "lower,upper,digits"),
+            ],
+        ];
+
+        for spec in specs {
+            // --------------------
+            // WHEN
+            // a key is generated w/ the spec
+            // --------------------
+            let (resp, keyring) = generate_key(&key, spec);
+
+            // --------------------
+            // THEN
+            // an InvalidKey error response is returned and
+            // no key is added
+            // --------------------
+            assert!(is_invalid_key(&resp));
+            assert!(keyring.read().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn secret_attr_given()
+    {
+        // --------------------
+        // WHEN
+        // a key is generated w/ a secret attr in its attrs
+        // --------------------
+        let key = [("proto", "pass"), ("!password", "hunter2")];
+        let (resp, keyring) = generate_key(&key, &[("type", "password")]);
+
+        // --------------------
+        // THEN
+        // an InvalidKey error response is returned
+        // --------------------
+        assert!(is_invalid_key(&resp));
+        assert!(keyring.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_spec_invalid()
+    {
+        // --------------------
+        // GIVEN
+        // a GenerateKey request w/o a spec
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let args = vec![attrs_to_value(&attrs(&[("proto", "pass")]))];
+        let msg = SessionRequest::new(42, SessionMethod::GenerateKey, args);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let mut session = Session::new();
+        let result = session.dispatch(&mut session_state.handle(), msg.into());
+
+        // --------------------
+        // THEN
+        // an InvalidMessage error is returned
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::InvalidMessage),
+            Ok(_) => false,
+        };
+        assert!(value);
+
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
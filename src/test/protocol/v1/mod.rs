@@ -8,6 +8,20 @@
 // ===========================================================================
 
 
+mod apop;
+mod auth;
+mod chap;
+mod conversation;
+mod keygen;
+mod otp;
+mod p9any;
+mod p9sk1;
+mod plaintext;
+mod policy;
+mod prompt;
+mod scram;
+mod session;
+mod sign;
 mod statevalue;
 
 
@@ -0,0 +1,422 @@
+// src/test/protocol/v1/otp.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Third-party imports
+
+use tempdir::TempDir;
+
+// Local imports
+
+use error::SasdErrorKind;
+use keyring::{Key, Keyring};
+use protocol::v1::auth::{Phase, ProtocolRegistry};
+use protocol::v1::otp::{Algorithm, hotp, totp_counter};
+
+use test::protocol::{attrs, file_keyring};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+// The RFC 4226 seed, and the same in base32 for a !secret attr
+const SEED: &'static [u8] = b"12345678901234567890";
+const SEED_BASE32: &'static str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+
+fn otp_key(proto: &str, secret: &str, params: &[(&str, &str)]) -> Key
+{
+    let mut key =
+        attrs(&[("proto", proto), ("user", "alice"), ("!secret", secret)]);
+    key.extend(attrs(params));
+    Key::new(key)
+}
+
+
+// Start a conversation and read a code from it
+fn read_code(keyring: &mut Keyring, proto: &str) -> String
+{
+    let attrs = attrs(&[("proto", proto)]);
+    let mut module = ProtocolRegistry::builtin().find(proto).unwrap();
+    module.start(keyring, &attrs).unwrap();
+    let (code, phase) = module.read(keyring).unwrap();
+    assert_eq!(phase, Phase::Done);
+    code.as_str().unwrap().to_owned()
+}
+
+
+// ===========================================================================
+// Test code generation
+// ===========================================================================
+
+
+mod codes {
+    use super::*;
+
+    #[test]
+    fn hotp_rfc4226()
+    {
+        let expected = [
+            "755224",
+            "287082",
+            "359152",
+            "969429",
+            "338314",
+            "254676",
+            "287922",
+            "162583",
+            "399871",
+            "520489",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            let value = hotp(SEED, counter as u64, 6, Algorithm::Sha1);
+            assert_eq!(value.as_str(), Some(*code));
+        }
+    }
+
+    #[test]
+    fn totp_rfc6238()
+    {
+        let times = [59, 1111111109, 1111111111, 1234567890, 2000000000,
+                     20000000000];
+        let seed256 = b"12345678901234567890123456789012";
+        let seed512 = b"1234567890123456789012345678901234567890\
+                        123456789012345678901234";
+        let vectors: [(Algorithm, &[u8], [&str; 6]); 3] = [
+            (
+                Algorithm::Sha1,
+                SEED,
+                ["94287082", "07081804", "14050471", "89005924", "69279037",
+                 "65353130"],
+            ),
+            (
+                Algorithm::Sha256,
+                seed256,
+                ["46119246", "68084774", "67062674", "91819424", "90698825",
+                 "77737706"],
+            ),
+            (
+                Algorithm::Sha512,
+                seed512,
+                ["90693936", "25091201", "99943326", "93441116", "38618901",
+                 "47863826"],
+            ),
+        ];
+        for &(algorithm, seed, ref codes) in vectors.iter() {
+            for (time, code) in times.iter().zip(codes.iter()) {
+                let counter = totp_counter(*time, 30);
+                let value = hotp(seed, counter, 8, algorithm);
+                assert_eq!(value.as_str(), Some(*code));
+            }
+        }
+    }
+
+    #[test]
+    fn algorithm_attr()
+    {
+        assert_eq!(Algorithm::from_attr(None), Some(Algorithm::Sha1));
+        assert_eq!(
+            Algorithm::from_attr(Some("sha256")),
+            Some(Algorithm::Sha256)
+        );
+        assert_eq!(Algorithm::from_attr(Some("MD5")), None);
+    }
+}
+
+
+// ===========================================================================
+// Test totp
+// ===========================================================================
+
+
+mod totp {
+    use super::*;
+
+    fn now() -> u64
+    {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn current_code()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring w/ a totp key using 8 digits and a 60 second period
+        // --------------------
+        let mut keyring = Keyring::new();
+        let params = [("digits", "8"), ("period", "60")];
+        keyring.add(otp_key("totp", SEED_BASE32, &params));
+
+        // --------------------
+        // WHEN
+        // a code is read
+        // --------------------
+        let before = totp_counter(now(), 60);
+        let code = read_code(&mut keyring, "totp");
+        let after = totp_counter(now(), 60);
+
+        // --------------------
+        // THEN
+        // the code is the one for the current period
+        // --------------------
+        let expected: Vec<_> = (before..after + 1)
+            .map(|c| hotp(SEED, c, 8, Algorithm::Sha1))
+            .collect();
+        assert!(expected.iter().any(|c| c.as_str() == Some(&code)));
+    }
+
+    #[test]
+    fn secret_not_base32()
+    {
+        // --------------------
+        // GIVEN
+        // a started totp conversation for a key w/ a bad secret
+        // --------------------
+        let mut keyring = Keyring::new();
+        keyring.add(otp_key("totp", "not base32!", &[]));
+        let attrs = attrs(&[("proto", "totp")]);
+        let mut module = ProtocolRegistry::builtin().find("totp").unwrap();
+        module.start(&mut keyring, &attrs).unwrap();
+
+        // --------------------
+        // WHEN
+        // a code is read
+        // --------------------
+        let result = module.read(&mut keyring);
+
+        // --------------------
+        // THEN
+        // a ProtocolFailed error is returned
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolFailed(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+    }
+
+    #[test]
+    fn no_key()
+    {
+        // --------------------
+        // GIVEN
+        // an empty keyring
+        // --------------------
+        let mut keyring = Keyring::new();
+        let attrs = attrs(&[("proto", "totp"), ("user", "alice")]);
+        let mut module = ProtocolRegistry::builtin().find("totp").unwrap();
+
+        // --------------------
+        // WHEN
+        // a totp conversation is started
+        // --------------------
+        let result = module.start(&mut keyring, &attrs);
+
+        // --------------------
+        // THEN
+        // a ProtocolNeedKey error is returned w/ a template for the key
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolNeedKey(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+        let mut expected = attrs.clone();
+        expected.insert("!secret".to_owned(), "?".to_owned());
+        assert_eq!(module.needkey(), expected);
+    }
+}
+
+
+// ===========================================================================
+// Test hotp
+// ===========================================================================
+
+
+mod hotp {
+    use super::*;
+
+    #[test]
+    fn counter_persisted()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring file w/ a hotp key that has no counter yet
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let mut keyring = file_keyring(&dir);
+        keyring.add(otp_key("hotp", SEED_BASE32, &[]));
+
+        // --------------------
+        // WHEN
+        // a code is read in 2 conversations
+        // --------------------
+        let first = read_code(&mut keyring, "hotp");
+        let second = read_code(&mut keyring, "hotp");
+
+        // --------------------
+        // THEN
+        // the codes are the ones for counters 0 and 1 and
+        // the keyring file has the next counter
+        // --------------------
+        assert_eq!(first, "755224");
+        assert_eq!(second, "287082");
+        keyring.lock();
+        keyring.unlock(b"passphrase", false).unwrap();
+        assert_eq!(keyring.keys()[0].get("counter"), Some("2"));
+    }
+
+    #[test]
+    fn counter_attr()
+    {
+        // --------------------
+        // GIVEN
+        // a hotp key w/ a counter
+        // --------------------
+        let mut keyring = Keyring::new();
+        keyring.add(otp_key("hotp", SEED_BASE32, &[("counter", "9")]));
+
+        // --------------------
+        // WHEN
+        // a code is read
+        // --------------------
+        let code = read_code(&mut keyring, "hotp");
+
+        // --------------------
+        // THEN
+        // the code is for the key's counter
+        // --------------------
+        assert_eq!(code, "520489");
+        assert_eq!(keyring.keys()[0].get("counter"), Some("10"));
+    }
+
+    #[test]
+    fn counter_used_up()
+    {
+        // --------------------
+        // GIVEN
+        // a started hotp conversation for a key whose counter is the
+        // largest there is
+        // --------------------
+        let max = u64::max_value().to_string();
+        let mut keyring = Keyring::new();
+        keyring.add(otp_key("hotp", SEED_BASE32, &[("counter", &max)]));
+        let attrs = attrs(&[("proto", "hotp")]);
+        let mut module = ProtocolRegistry::builtin().find("hotp").unwrap();
+        module.start(&mut keyring, &attrs).unwrap();
+
+        // --------------------
+        // WHEN
+        // a code is read
+        // --------------------
+        let result = module.read(&mut keyring);
+
+        // --------------------
+        // THEN
+        // a ProtocolFailed error is returned and
+        // the counter is left alone
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolFailed(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+        assert_eq!(keyring.keys()[0].get("counter"), Some(&max[..]));
+    }
+
+    #[test]
+    fn done_after_read()
+    {
+        // --------------------
+        // GIVEN
+        // a hotp conversation that has handed out a code
+        // --------------------
+        let mut keyring = Keyring::new();
+        keyring.add(otp_key("hotp", SEED_BASE32, &[]));
+        let attrs = attrs(&[("proto", "hotp")]);
+        let mut module = ProtocolRegistry::builtin().find("hotp").unwrap();
+        module.start(&mut keyring, &attrs).unwrap();
+        module.read(&mut keyring).unwrap();
+
+        // --------------------
+        // WHEN
+        // the auth info is asked for and
+        // another code is read
+        // --------------------
+        let info = module.authinfo().unwrap();
+        let result = module.read(&mut keyring);
+
+        // --------------------
+        // THEN
+        // the key's user is the client user and
+        // a ProtocolPhase error is returned w/o using up a counter
+        // --------------------
+        assert_eq!(info.cuid, Some("alice".to_owned()));
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolPhase(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+        assert_eq!(keyring.keys()[0].get("counter"), Some("1"));
+    }
+
+    #[test]
+    fn key_removed()
+    {
+        // --------------------
+        // GIVEN
+        // a hotp conversation for alice's key and
+        // another hotp key for bob
+        // --------------------
+        let mut keyring = Keyring::new();
+        keyring.add(otp_key("hotp", SEED_BASE32, &[]));
+        let mut bob = otp_key("hotp", SEED_BASE32, &[]).public().clone();
+        bob.insert("user".to_owned(), "bob".to_owned());
+        bob.insert("!secret".to_owned(), SEED_BASE32.to_owned());
+        keyring.add(Key::new(bob));
+        let attrs = attrs(&[("proto", "hotp"), ("user", "alice")]);
+        let mut module = ProtocolRegistry::builtin().find("hotp").unwrap();
+        module.start(&mut keyring, &attrs).unwrap();
+
+        // --------------------
+        // WHEN
+        // alice's key is removed and a code is read
+        // --------------------
+        let alice = keyring.keys()[0].public().clone();
+        keyring.remove(&Key::new(alice));
+        let result = module.read(&mut keyring);
+
+        // --------------------
+        // THEN
+        // a ProtocolFailed error is returned and
+        // bob's counter is not used up
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolFailed(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+        assert_eq!(keyring.keys()[0].get("counter"), None);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
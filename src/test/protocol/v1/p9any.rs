@@ -0,0 +1,145 @@
+// src/test/protocol/v1/p9any.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+use error::SasdErrorKind;
+use keyring::Keyring;
+use protocol::v1::auth::{Phase, ProtocolRegistry};
+
+use super::p9sk1::{AUTHDOM, auth_server, converse, keyring, start};
+use test::protocol::attrs;
+
+
+// ===========================================================================
+// Test p9any
+// ===========================================================================
+
+
+#[test]
+fn negotiate_p9sk1()
+{
+    // --------------------
+    // GIVEN
+    // an auth server that knows glenda and bootes, and
+    // a p9any client for glenda and server for bootes
+    // --------------------
+    let auth = auth_server(&[("glenda", "glendapw"), ("bootes", "bootespw")]);
+    let mut client_keyring = keyring("glenda", "glendapw", &auth);
+    let mut server_keyring = keyring("bootes", "bootespw", "");
+    let mut client = start(&mut client_keyring, "p9any", "client");
+    let mut server = start(&mut server_keyring, "p9any", "server");
+
+    // --------------------
+    // WHEN
+    // the server's offer is passed to the client and
+    // the rest of the protocol is run
+    // --------------------
+    let (offer, _) = server.read(&mut server_keyring).unwrap();
+    client.write(&mut client_keyring, offer.as_bytes()).unwrap();
+    let (choice, _) = client.read(&mut client_keyring).unwrap();
+    server.write(&mut server_keyring, choice.as_bytes()).unwrap();
+    let result = converse(
+        &mut *server,
+        &mut server_keyring,
+        &mut *client,
+        &mut client_keyring,
+    );
+
+    // --------------------
+    // THEN
+    // p9sk1 in the server's domain is picked and
+    // both are done w/ the same users and secret
+    // --------------------
+    assert_eq!(offer.as_bytes(), &b"v.2 p9sk1@example.org\0"[..]);
+    assert_eq!(choice.as_bytes(), &b"p9sk1 example.org\0"[..]);
+    result.unwrap();
+    let info = client.authinfo().unwrap();
+    let server_info = server.authinfo().unwrap();
+    assert_eq!(info.cuid, Some("glenda".to_owned()));
+    assert_eq!(server_info.cuid, info.cuid);
+    assert_eq!(server_info.secret, info.secret);
+}
+
+
+#[test]
+fn client_needs_key()
+{
+    // --------------------
+    // GIVEN
+    // a p9any client w/o any keys
+    // --------------------
+    let mut keyring = Keyring::new();
+    let mut client = ProtocolRegistry::builtin().find("p9any").unwrap();
+    client.start(&mut keyring, &attrs(&[("proto", "p9any")])).unwrap();
+
+    // --------------------
+    // WHEN
+    // an offer is written
+    // --------------------
+    let offer = format!("v.2 p9sk1@{}\0", AUTHDOM);
+    let result = client.write(&mut keyring, offer.as_bytes());
+
+    // --------------------
+    // THEN
+    // a ProtocolNeedKey error is returned w/ a template for a p9sk1 key in
+    // the offered domain
+    // --------------------
+    let value = match result {
+        Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolNeedKey(_)),
+        Ok(_) => false,
+    };
+    assert!(value);
+    let expected = attrs(&[
+        ("proto", "p9sk1"),
+        ("authdom", AUTHDOM),
+        ("!password", "?"),
+    ]);
+    assert_eq!(client.needkey(), expected);
+}
+
+
+#[test]
+fn server_rejects_unoffered()
+{
+    // --------------------
+    // GIVEN
+    // a p9any server that has sent its offer
+    // --------------------
+    let mut keyring = keyring("bootes", "bootespw", "");
+    let mut server = start(&mut keyring, "p9any", "server");
+    let (_, phase) = server.read(&mut keyring).unwrap();
+    assert_eq!(phase, Phase::Continue);
+
+    // --------------------
+    // WHEN
+    // the client picks a domain that wasn't offered
+    // --------------------
+    let result = server.write(&mut keyring, b"p9sk1 other.org\0");
+
+    // --------------------
+    // THEN
+    // a ProtocolFailed error is returned
+    // --------------------
+    let value = match result {
+        Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolFailed(_)),
+        Ok(_) => false,
+    };
+    assert!(value);
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,396 @@
+// src/test/protocol/v1/p9sk1.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::io::{Read, Write};
+use std::mem;
+use std::net::TcpListener;
+use std::thread;
+
+// Third-party imports
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Key, Keyring};
+use protocol::v1::auth::{AuthProtocol, ProtocolRegistry, to_hex};
+use protocol::v1::p9sk1::{AERRLEN, AUTHENTLEN, AUTH_ERR, AUTH_OK, AUTH_TC,
+                          AUTH_TS, TICKETLEN, TICKREQLEN, Ticket, Ticketreq,
+                          decrypt, encrypt, passtokey};
+use secret::SecretBuf;
+
+use test::protocol::attrs;
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+pub const AUTHDOM: &'static str = "example.org";
+
+
+// A keyring w/ a p9sk1 key for the user in AUTHDOM
+pub fn keyring(user: &str, password: &str, auth: &str) -> Keyring
+{
+    let mut keyring = Keyring::new();
+    keyring.add(Key::new(attrs(&[
+        ("proto", "p9sk1"),
+        ("user", user),
+        ("authdom", AUTHDOM),
+        ("auth", auth),
+        ("!password", password),
+    ])));
+    keyring
+}
+
+
+pub fn start(keyring: &mut Keyring, proto: &str, role: &str)
+    -> Box<AuthProtocol>
+{
+    let attrs = attrs(&[("proto", proto), ("role", role)]);
+    let mut module = ProtocolRegistry::builtin().find(proto).unwrap();
+    module.start(keyring, &attrs).unwrap();
+    module
+}
+
+
+// A stand-in for a Plan 9 auth server that knows the given users'
+// passwords, answering a single ticket request. The ticket is for the uid
+// in the request.
+pub fn auth_server(users: &[(&str, &str)]) -> String
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let keys: Vec<(String, SecretBuf)> = users
+        .iter()
+        .map(|&(u, p)| (u.to_owned(), passtokey(p.as_bytes())))
+        .collect();
+
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = vec![0u8; TICKREQLEN];
+        stream.read_exact(&mut buf).unwrap();
+        let tr = Ticketreq::from_bytes(&buf).unwrap();
+        let key = |id: &str| {
+            keys.iter().find(|k| k.0 == id).map(|k| k.1.clone())
+        };
+
+        let mut reply = Vec::new();
+        match (key(&tr.authid), key(&tr.hostid)) {
+            (Some(akey), Some(hkey)) => {
+                let mut ticket = Ticket {
+                    num: AUTH_TC,
+                    chal: tr.chal,
+                    cuid: tr.uid.clone(),
+                    suid: tr.uid.clone(),
+                    key: SecretBuf::from(&b"sesskey"[..]),
+                };
+                let client = ticket.to_bytes(hkey.as_bytes());
+                ticket.num = AUTH_TS;
+                let server = ticket.to_bytes(akey.as_bytes());
+                reply.push(AUTH_OK);
+                reply.extend_from_slice(client.as_bytes());
+                reply.extend_from_slice(server.as_bytes());
+            }
+            _ => {
+                reply.push(AUTH_ERR);
+                let mut errmsg = b"no such user".to_vec();
+                errmsg.resize(AERRLEN, 0);
+                reply.extend(errmsg);
+            }
+        }
+        stream.write_all(&reply).unwrap();
+    });
+    addr
+}
+
+
+// Pass messages between 2 modules, starting w/ a read from the first, until
+// both are done. Each module's work w/o the keyring is run after every step,
+// as a conversation does.
+pub fn converse<'a>(
+    first: &'a mut AuthProtocol, first_keyring: &'a mut Keyring,
+    second: &'a mut AuthProtocol, second_keyring: &'a mut Keyring
+) -> SasdResult<()>
+{
+    let (mut from, mut to) = (first, second);
+    let (mut from_keyring, mut to_keyring) = (first_keyring, second_keyring);
+    loop {
+        let (msg, sent) = from.read(from_keyring)?;
+        from.run_unlocked()?;
+        let received = to.write(to_keyring, msg.as_bytes())?;
+        to.run_unlocked()?;
+        assert_eq!(sent, received);
+        if sent.is_done() {
+            return Ok(());
+        }
+        mem::swap(&mut from, &mut to);
+        mem::swap(&mut from_keyring, &mut to_keyring);
+    }
+}
+
+
+pub fn is_failed<T>(result: SasdResult<T>) -> bool
+{
+    match result {
+        Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolFailed(_)),
+        Ok(_) => false,
+    }
+}
+
+
+// ===========================================================================
+// Test Plan 9 DES
+// ===========================================================================
+
+
+mod des {
+    use super::*;
+
+    #[test]
+    fn encrypt_decrypt()
+    {
+        let key = passtokey(b"password");
+        for len in &[8, 13, 72, 141] {
+            let plain: Vec<u8> = (0..*len).map(|i| i as u8).collect();
+            let mut buf = plain.clone();
+            encrypt(key.as_bytes(), &mut buf);
+            assert!(buf != plain);
+            decrypt(key.as_bytes(), &mut buf);
+            assert_eq!(buf, plain);
+        }
+    }
+
+    #[test]
+    fn known_values()
+    {
+        // Worked out separately from the Plan 9 C code, w/ openssl's DES
+        let key = passtokey(b"glendapw");
+        assert_eq!(to_hex(key.as_bytes()), "6776d94d0ec3ef");
+        let long = passtokey(b"a much longer password");
+        assert_eq!(to_hex(long.as_bytes()), "bf4c032055411a");
+
+        let mut buf: Vec<u8> = (0..13).collect();
+        encrypt(key.as_bytes(), &mut buf);
+        assert_eq!(to_hex(&buf), "f553cace25f1006c4085f9b1ac");
+    }
+
+    #[test]
+    fn passtokey_long_password()
+    {
+        // Passwords longer than 8 bytes are folded in w/ encrypt()
+        let short = passtokey(b"password");
+        let long = passtokey(b"password1");
+        assert_eq!(short.len(), 7);
+        assert!(short != long);
+    }
+}
+
+
+// ===========================================================================
+// Test p9sk1
+// ===========================================================================
+
+
+mod p9sk1 {
+    use super::*;
+
+    #[test]
+    fn client_and_server()
+    {
+        // --------------------
+        // GIVEN
+        // an auth server that knows glenda and bootes, and
+        // a p9sk1 client for glenda and server for bootes
+        // --------------------
+        let auth =
+            auth_server(&[("glenda", "glendapw"), ("bootes", "bootespw")]);
+        let mut client_keyring = keyring("glenda", "glendapw", &auth);
+        let mut server_keyring = keyring("bootes", "bootespw", "");
+        let mut client = start(&mut client_keyring, "p9sk1", "client");
+        let mut server = start(&mut server_keyring, "p9sk1", "server");
+
+        // --------------------
+        // WHEN
+        // the client and server run the protocol
+        // --------------------
+        let result = converse(
+            &mut *client,
+            &mut client_keyring,
+            &mut *server,
+            &mut server_keyring,
+        );
+
+        // --------------------
+        // THEN
+        // both are done w/ the same users and secret
+        // --------------------
+        result.unwrap();
+        let client_info = client.authinfo().unwrap();
+        let server_info = server.authinfo().unwrap();
+        assert_eq!(client_info.cuid, server_info.cuid);
+        assert_eq!(client_info.suid, server_info.suid);
+        assert_eq!(client_info.secret, server_info.secret);
+        assert_eq!(client_info.cuid, Some("glenda".to_owned()));
+        assert_eq!(client_info.suid, Some("glenda".to_owned()));
+        assert_eq!(client_info.secret.unwrap().len(), 8);
+    }
+
+    #[test]
+    fn client_cap()
+    {
+        // --------------------
+        // GIVEN
+        // an auth server that knows glenda and bootes, and
+        // a p9sk1 client for glenda and server for bootes
+        // --------------------
+        let auth =
+            auth_server(&[("glenda", "glendapw"), ("bootes", "bootespw")]);
+        let mut client_keyring = keyring("glenda", "glendapw", &auth);
+        let mut server_keyring = keyring("bootes", "bootespw", "");
+        let mut client = start(&mut client_keyring, "p9sk1", "client");
+        let mut server = start(&mut server_keyring, "p9sk1", "server");
+
+        // --------------------
+        // WHEN
+        // the client and server run the protocol
+        // --------------------
+        converse(
+            &mut *client,
+            &mut client_keyring,
+            &mut *server,
+            &mut server_keyring,
+        ).unwrap();
+
+        // --------------------
+        // THEN
+        // the client's cap is the server's ticket, sealed w/ bootes'
+        // password, followed by the client's authenticator and
+        // the server has no cap
+        // --------------------
+        let cap = client.authinfo().unwrap().cap.unwrap();
+        assert_eq!(cap.len(), TICKETLEN + AUTHENTLEN);
+        let key = passtokey(b"bootespw");
+        let ticket =
+            Ticket::from_bytes(&cap.as_bytes()[..TICKETLEN], key.as_bytes())
+                .unwrap();
+        assert_eq!(ticket.num, AUTH_TS);
+        assert_eq!(ticket.cuid, "glenda");
+        assert!(server.authinfo().unwrap().cap.is_none());
+    }
+
+    #[test]
+    fn wrong_password()
+    {
+        // --------------------
+        // GIVEN
+        // a p9sk1 client w/ the wrong password for glenda
+        // --------------------
+        let auth =
+            auth_server(&[("glenda", "glendapw"), ("bootes", "bootespw")]);
+        let mut client_keyring = keyring("glenda", "wrong", &auth);
+        let mut server_keyring = keyring("bootes", "bootespw", "");
+        let mut client = start(&mut client_keyring, "p9sk1", "client");
+        let mut server = start(&mut server_keyring, "p9sk1", "server");
+
+        // --------------------
+        // WHEN
+        // the client and server run the protocol
+        // --------------------
+        let result = converse(
+            &mut *client,
+            &mut client_keyring,
+            &mut *server,
+            &mut server_keyring,
+        );
+
+        // --------------------
+        // THEN
+        // a ProtocolFailed error is returned
+        // --------------------
+        assert!(is_failed(result));
+    }
+
+    #[test]
+    fn auth_server_error()
+    {
+        // --------------------
+        // GIVEN
+        // an auth server that doesn't know glenda
+        // --------------------
+        let auth = auth_server(&[("bootes", "bootespw")]);
+        let mut client_keyring = keyring("glenda", "glendapw", &auth);
+        let mut server_keyring = keyring("bootes", "bootespw", "");
+        let mut client = start(&mut client_keyring, "p9sk1", "client");
+        let mut server = start(&mut server_keyring, "p9sk1", "server");
+
+        // --------------------
+        // WHEN
+        // the client and server run the protocol
+        // --------------------
+        let result = converse(
+            &mut *client,
+            &mut client_keyring,
+            &mut *server,
+            &mut server_keyring,
+        );
+
+        // --------------------
+        // THEN
+        // the auth server's error is returned as a ProtocolFailed error
+        // --------------------
+        let value = match result {
+            Err(e) => match *e.kind() {
+                SasdErrorKind::ProtocolFailed(ref msg) => {
+                    msg.contains("no such user")
+                }
+                _ => false,
+            },
+            Ok(_) => false,
+        };
+        assert!(value);
+    }
+
+    #[test]
+    fn no_authdom()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring w/ a p9sk1 key that has no authdom
+        // --------------------
+        let mut keyring = Keyring::new();
+        keyring.add(Key::new(attrs(&[
+            ("proto", "p9sk1"),
+            ("user", "glenda"),
+            ("!password", "glendapw"),
+        ])));
+        let attrs = attrs(&[("proto", "p9sk1")]);
+        let mut module = ProtocolRegistry::builtin().find("p9sk1").unwrap();
+
+        // --------------------
+        // WHEN
+        // a p9sk1 conversation is started
+        // --------------------
+        let result = module.start(&mut keyring, &attrs);
+
+        // --------------------
+        // THEN
+        // a ProtocolFailed error is returned
+        // --------------------
+        assert!(is_failed(result));
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,198 @@
+// src/test/protocol/v1/plaintext.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+use rmpv::Value;
+use siminau_rpc::message::response::RpcResponse;
+
+// Local imports
+
+use keyring::{Key, Keyring, KeyringHandle, new_keyring_handle};
+use protocol::v1::attrs_to_value;
+use rpc::v1::ProtocolError;
+use state::SessionState;
+
+use test::protocol::{attrs, cleanup_settings, new_session_state, read, send,
+                     start, write};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+// A keyring holding a password for alice on example.com
+fn keyring() -> KeyringHandle
+{
+    let mut keyring = Keyring::new();
+    keyring.add(Key::new(attrs(&[
+        ("proto", "pass"),
+        ("user", "alice"),
+        ("server", "example.com"),
+        ("!password", "hunter2"),
+    ])));
+    new_keyring_handle(keyring)
+}
+
+
+fn in_conversation(session_state: &SessionState) -> bool
+{
+    session_state.state().as_v1().unwrap().is_conversation()
+}
+
+
+// ===========================================================================
+// Test pass protocol
+// ===========================================================================
+
+
+#[test]
+fn read_user_then_password()
+{
+    // --------------------
+    // GIVEN
+    // a session whose keyring holds a password key and
+    // the pass protocol has been started for that key
+    // --------------------
+    let mut session_state = new_session_state(keyring());
+    let msg = start(&[
+        ("proto", "pass"),
+        ("role", "client"),
+        ("user", "alice"),
+        ("server", "example.com"),
+    ]);
+    let resp = send(&mut session_state, msg);
+    assert_eq!(resp.error_code(), ProtocolError::Nil);
+    assert!(in_conversation(&session_state));
+
+    // --------------------
+    // WHEN
+    // ProtocolRead is sent twice
+    // --------------------
+    let user = send(&mut session_state, read());
+    let password = send(&mut session_state, read());
+
+    // --------------------
+    // THEN
+    // the user name and then the password are returned and
+    // the conversation is over
+    // --------------------
+    let expected = Value::Array(
+        vec![Value::Binary(b"alice".to_vec()), Value::from(false)],
+    );
+    assert_eq!(user.error_code(), ProtocolError::Nil);
+    assert_eq!(user.result(), &expected);
+
+    let expected = Value::Array(
+        vec![Value::Binary(b"hunter2".to_vec()), Value::from(true)],
+    );
+    assert_eq!(password.error_code(), ProtocolError::Nil);
+    assert_eq!(password.result(), &expected);
+    assert!(!in_conversation(&session_state));
+
+    cleanup_settings(session_state);
+}
+
+
+#[test]
+fn no_matching_key()
+{
+    // --------------------
+    // GIVEN
+    // a session whose keyring holds a password key for alice
+    // --------------------
+    let mut session_state = new_session_state(keyring());
+
+    // --------------------
+    // WHEN
+    // the pass protocol is started for bob
+    // --------------------
+    let msg = start(&[("proto", "pass"), ("role", "client"), ("user", "bob")]);
+    let resp = send(&mut session_state, msg);
+
+    // --------------------
+    // THEN
+    // a ProtocolNeedKey error response is returned w/ the attrs of the
+    // missing key and
+    // no conversation is started
+    // --------------------
+    let template =
+        attrs(&[("proto", "pass"), ("user", "bob"), ("!password", "?")]);
+    assert_eq!(resp.error_code(), ProtocolError::ProtocolNeedKey);
+    assert_eq!(resp.result(), &attrs_to_value(&template));
+    assert!(!in_conversation(&session_state));
+
+    cleanup_settings(session_state);
+}
+
+
+#[test]
+fn server_role_unsupported()
+{
+    // --------------------
+    // GIVEN
+    // a session whose keyring holds a password key
+    // --------------------
+    let mut session_state = new_session_state(keyring());
+
+    // --------------------
+    // WHEN
+    // the pass protocol is started in the server role
+    // --------------------
+    let msg =
+        start(&[("proto", "pass"), ("role", "server"), ("user", "alice")]);
+    let resp = send(&mut session_state, msg);
+
+    // --------------------
+    // THEN
+    // a ProtocolError error response is returned
+    // --------------------
+    assert_eq!(resp.error_code(), ProtocolError::ProtocolError);
+    assert!(!in_conversation(&session_state));
+
+    cleanup_settings(session_state);
+}
+
+
+#[test]
+fn write_rejected()
+{
+    // --------------------
+    // GIVEN
+    // a session running the pass protocol
+    // --------------------
+    let mut session_state = new_session_state(keyring());
+    let msg = start(&[("proto", "pass"), ("user", "alice")]);
+    send(&mut session_state, msg);
+
+    // --------------------
+    // WHEN
+    // ProtocolWrite is sent
+    // --------------------
+    let resp = send(&mut session_state, write(b"hello"));
+
+    // --------------------
+    // THEN
+    // an InvalidProtocolMessage error response is returned and
+    // the conversation continues
+    // --------------------
+    assert_eq!(resp.error_code(), ProtocolError::InvalidProtocolMessage);
+    assert!(in_conversation(&session_state));
+
+    cleanup_settings(session_state);
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
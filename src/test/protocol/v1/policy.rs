@@ -0,0 +1,672 @@
+// src/test/protocol/v1/policy.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+use data_encoding::BASE64;
+use siminau_rpc::message::Message;
+use siminau_rpc::message::response::RpcResponse;
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Key, Keyring, KeyringHandle, new_keyring_handle};
+use protocol::State;
+use protocol::v1::{Session, SessionRequest, SessionResponse, attrs_to_value};
+use protocol::v1::sign::ed25519_key;
+use rpc::v1::{SessionError, SessionMethod};
+use secret::SecretBuf;
+use settings::SettingsBuilder;
+use state::SessionState;
+
+use test::protocol::{attrs, cleanup_settings, dispatch,
+                     new_session_state_settings};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+// Passwords of keys in example.com must be 8 characters long, have a digit
+// and an uppercase letter, and differ from the key's last 2 passwords.
+// Those in example.net must be 32 characters long and have a symbol.
+fn policy(builder: SettingsBuilder) -> SasdResult<SettingsBuilder>
+{
+    builder
+        .policy("example.com".to_owned())
+        .min_length(8)
+        .class("digits".to_owned())?
+        .class("upper".to_owned())?
+        .reuse(2)
+        .policy_done()?
+        .policy("example.net".to_owned())
+        .min_length(32)
+        .class("symbols".to_owned())?
+        .policy_done()
+}
+
+
+fn create(key: &[(&str, &str)]) -> Message
+{
+    let args = vec![attrs_to_value(&attrs(key))];
+    SessionRequest::new(42, SessionMethod::CreateKey, args).into()
+}
+
+
+fn update(key: &[(&str, &str)], update: &[(&str, &str)]) -> Message
+{
+    let args =
+        vec![attrs_to_value(&attrs(key)), attrs_to_value(&attrs(update))];
+    SessionRequest::new(42, SessionMethod::UpdateKey, args).into()
+}
+
+
+// The secret attr of the only key in the keyring
+fn secret(keyring: &KeyringHandle, attr: &str) -> Option<SecretBuf>
+{
+    let keyring = keyring.read().unwrap();
+    assert_eq!(keyring.len(), 1);
+    keyring.keys()[0].secret().get(attr).cloned()
+}
+
+
+fn is_violation(resp: &SessionResponse, reason: &str) -> bool
+{
+    resp.error_code() == SessionError::PolicyViolation &&
+        resp.result().as_str().map(|r| r.contains(reason)) == Some(true)
+}
+
+
+const KEY: &'static [(&'static str, &'static str)] =
+    &[("proto", "pass"), ("dom", "example.com"), ("user", "alice")];
+
+
+// A keyring holding KEY w/ the given password
+fn keyring_with(password: &str) -> KeyringHandle
+{
+    let mut key = attrs(KEY);
+    key.insert("!password".to_owned(), password.to_owned());
+    let mut keyring = Keyring::new();
+    keyring.add(Key::new(key));
+    new_keyring_handle(keyring)
+}
+
+
+// ===========================================================================
+// Test CreateKey
+// ===========================================================================
+
+
+mod create_key {
+    use super::*;
+
+    #[test]
+    fn empty_password()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ an empty keyring and
+        // a key in a domain w/o a policy whose password is empty
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state =
+            new_session_state_settings(keyring.clone(), policy);
+        let key = [("proto", "pass"), ("user", "alice"), ("!password", "")];
+
+        // --------------------
+        // WHEN
+        // the key is created
+        // --------------------
+        let resp = dispatch(&mut session_state, create(&key));
+
+        // --------------------
+        // THEN
+        // a PolicyViolation error response is returned and
+        // no key is added
+        // --------------------
+        assert!(is_violation(&resp, "password is empty"));
+        assert!(keyring.read().unwrap().is_empty());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn password_breaks_policy()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ an empty keyring and
+        // passwords that are too short or lack a class
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state =
+            new_session_state_settings(keyring.clone(), policy);
+        let cases = [
+            ("Short1", "at least 8 characters"),
+            ("longer-password1", "an uppercase letter"),
+            ("LONGER-PASSWORD", "a digit"),
+        ];
+
+        for &(password, reason) in &cases {
+            // --------------------
+            // WHEN
+            // a key in example.com is created w/ the password
+            // --------------------
+            let mut key = KEY.to_vec();
+            key.push(("!password", password));
+            let resp = dispatch(&mut session_state, create(&key));
+
+            // --------------------
+            // THEN
+            // a PolicyViolation error response saying why is returned
+            // --------------------
+            assert!(is_violation(&resp, reason), "{:?}", password);
+            assert!(keyring.read().unwrap().is_empty());
+        }
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn password_passes_policy()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ an empty keyring
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state =
+            new_session_state_settings(keyring.clone(), policy);
+
+        // --------------------
+        // WHEN
+        // a key in example.com is created w/ a password that passes the
+        // policy
+        // --------------------
+        let mut key = KEY.to_vec();
+        key.push(("!password", "Passw0rd"));
+        let resp = dispatch(&mut session_state, create(&key));
+
+        // --------------------
+        // THEN
+        // the key is added
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        let password = secret(&keyring, "!password").unwrap();
+        assert_eq!(password.as_str(), Some("Passw0rd"));
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn other_domain()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ an empty keyring
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state =
+            new_session_state_settings(keyring.clone(), policy);
+
+        // --------------------
+        // WHEN
+        // a key in a domain w/o a policy is created w/ a short password
+        // --------------------
+        let key = [("dom", "example.org"), ("!password", "short")];
+        let resp = dispatch(&mut session_state, create(&key));
+
+        // --------------------
+        // THEN
+        // the key is added
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        assert_eq!(keyring.read().unwrap().len(), 1);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn invalid_secrets()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ an empty keyring and
+        // keys w/ an empty secret or a history
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state =
+            new_session_state_settings(keyring.clone(), policy);
+        let keys: &[&[(&str, &str)]] = &[
+            &[("proto", "totp"), ("!secret", "")],
+            &[("proto", "pass"), ("!password", "Passw0rd"), ("!history", "")],
+        ];
+
+        for key in keys {
+            // --------------------
+            // WHEN
+            // the key is created
+            // --------------------
+            let resp = dispatch(&mut session_state, create(key));
+
+            // --------------------
+            // THEN
+            // an InvalidKey error response is returned
+            // --------------------
+            assert_eq!(resp.error_code(), SessionError::InvalidKey);
+            assert!(keyring.read().unwrap().is_empty());
+        }
+
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+// Test GenerateKey
+// ===========================================================================
+
+
+mod generate_key {
+    use super::*;
+
+    #[test]
+    fn password_breaks_policy()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ an empty keyring
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state =
+            new_session_state_settings(keyring.clone(), policy);
+
+        // --------------------
+        // WHEN
+        // a key in example.com is generated w/ a lowercase only password
+        // --------------------
+        let spec = [("type", "password"), ("charset", "lower")];
+        let args =
+            vec![attrs_to_value(&attrs(KEY)), attrs_to_value(&attrs(&spec))];
+        let msg = SessionRequest::new(42, SessionMethod::GenerateKey, args);
+        let resp = dispatch(&mut session_state, msg.into());
+
+        // --------------------
+        // THEN
+        // a PolicyViolation error response is returned
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::PolicyViolation);
+        assert!(keyring.read().unwrap().is_empty());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn password_follows_policy()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ an empty keyring
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state =
+            new_session_state_settings(keyring.clone(), policy);
+
+        // --------------------
+        // WHEN
+        // a key in example.net is generated w/o a length or charset
+        // --------------------
+        let key = [("proto", "pass"), ("dom", "example.net")];
+        let spec = [("type", "password")];
+        let args =
+            vec![attrs_to_value(&attrs(&key)), attrs_to_value(&attrs(&spec))];
+        let msg = SessionRequest::new(42, SessionMethod::GenerateKey, args);
+        let resp = dispatch(&mut session_state, msg.into());
+
+        // --------------------
+        // THEN
+        // the password is as long as the policy asks and has a symbol
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        let password = secret(&keyring, "!password").unwrap();
+        let password = password.as_str().unwrap();
+        assert_eq!(password.len(), 32);
+        assert!(password.chars().any(|c| !c.is_alphanumeric()));
+
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+// Test UpdateKey
+// ===========================================================================
+
+
+mod update_key {
+    use super::*;
+
+    #[test]
+    fn new_password()
+    {
+        // --------------------
+        // GIVEN
+        // a session whose keyring holds a key in example.com
+        // --------------------
+        let keyring = keyring_with("Passw0rd");
+        let mut session_state =
+            new_session_state_settings(keyring.clone(), policy);
+
+        // --------------------
+        // WHEN
+        // the key's password is updated to one that passes the policy
+        // --------------------
+        let msg = update(KEY, &[("!password", "Secr3tPass")]);
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // the key has the new password and
+        // the old one is kept in its history but not in the clear
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        let password = secret(&keyring, "!password").unwrap();
+        assert_eq!(password.as_str(), Some("Secr3tPass"));
+        let history = secret(&keyring, "!history").unwrap();
+        assert!(!history.as_str().unwrap().contains("Passw0rd"));
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn password_breaks_policy()
+    {
+        // --------------------
+        // GIVEN
+        // a session whose keyring holds a key in example.com
+        // --------------------
+        let keyring = keyring_with("Passw0rd");
+        let mut session_state =
+            new_session_state_settings(keyring.clone(), policy);
+
+        // --------------------
+        // WHEN
+        // the key's password is updated to one that is too short
+        // --------------------
+        let msg = update(KEY, &[("!password", "Sh0rt")]);
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a PolicyViolation error response is returned and
+        // the key keeps its password
+        // --------------------
+        assert!(is_violation(&resp, "at least 8 characters"));
+        let password = secret(&keyring, "!password").unwrap();
+        assert_eq!(password.as_str(), Some("Passw0rd"));
+
+        cleanup_settings(session_state);
+    }
+
+    // A session whose keyring holds KEY, whose password was changed from
+    // Passw0rd to each of the given passwords in turn
+    fn changed_session_state(keyring: KeyringHandle, passwords: &[&str])
+        -> SessionState
+    {
+        let mut session_state = new_session_state_settings(keyring, policy);
+        for password in passwords {
+            let msg = update(KEY, &[("!password", password)]);
+            let resp = dispatch(&mut session_state, msg);
+            assert_eq!(resp.error_code(), SessionError::Nil);
+        }
+        session_state
+    }
+
+    #[test]
+    fn reused_password()
+    {
+        // --------------------
+        // GIVEN
+        // a session whose keyring holds a key in example.com whose password
+        // has been changed once
+        // --------------------
+        let keyring = keyring_with("Passw0rd");
+        let mut session_state =
+            changed_session_state(keyring.clone(), &["Secr3tPass"]);
+
+        for password in &["Secr3tPass", "Passw0rd"] {
+            // --------------------
+            // WHEN
+            // the password is updated to the current or the last password
+            // --------------------
+            let msg = update(KEY, &[("!password", password)]);
+            let resp = dispatch(&mut session_state, msg);
+
+            // --------------------
+            // THEN
+            // a PolicyViolation error response is returned and
+            // the key keeps its password
+            // --------------------
+            assert!(is_violation(&resp, "last 2 passwords"), "{}", password);
+            let current = secret(&keyring, "!password").unwrap();
+            assert_eq!(current.as_str(), Some("Secr3tPass"));
+        }
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn old_password()
+    {
+        // --------------------
+        // GIVEN
+        // a session whose keyring holds a key in example.com whose password
+        // has been changed twice
+        // --------------------
+        let keyring = keyring_with("Passw0rd");
+        let changes = ["Secr3tPass", "An0therPass"];
+        let mut session_state =
+            changed_session_state(keyring.clone(), &changes);
+
+        // --------------------
+        // WHEN
+        // the password is updated to the first password
+        // --------------------
+        let msg = update(KEY, &[("!password", "Passw0rd")]);
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // the password is changed since the first one is no longer one of
+        // the last 2
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        let password = secret(&keyring, "!password").unwrap();
+        assert_eq!(password.as_str(), Some("Passw0rd"));
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn key_not_found()
+    {
+        // --------------------
+        // GIVEN
+        // a session whose keyring holds a key in example.com
+        // --------------------
+        let keyring = keyring_with("Passw0rd");
+        let mut session_state = new_session_state_settings(keyring, policy);
+
+        // --------------------
+        // WHEN
+        // a key w/ other public attrs is updated
+        // --------------------
+        let key = [("proto", "pass"), ("dom", "example.com")];
+        let msg = update(&key, &[("!password", "Secr3tPass")]);
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a KeyNotFound error response is returned
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::KeyNotFound);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn fixed_attrs()
+    {
+        // --------------------
+        // GIVEN
+        // a session whose keyring holds a key in example.com
+        // --------------------
+        let keyring = keyring_with("Passw0rd");
+        let mut session_state =
+            new_session_state_settings(keyring.clone(), policy);
+
+        for attr in &["user", "!history"] {
+            // --------------------
+            // WHEN
+            // a public attr or the history is updated
+            // --------------------
+            let msg = update(KEY, &[(attr, "bob")]);
+            let resp = dispatch(&mut session_state, msg);
+
+            // --------------------
+            // THEN
+            // an InvalidKey error response is returned
+            // --------------------
+            assert_eq!(resp.error_code(), SessionError::InvalidKey);
+            assert!(secret(&keyring, "!history").is_none());
+        }
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn sign_key_secret()
+    {
+        // --------------------
+        // GIVEN
+        // a session whose keyring is empty
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state =
+            new_session_state_settings(keyring.clone(), policy);
+        let old = BASE64.encode(&[1; 32]);
+        let key = [("proto", "sign"), ("alg", "ed25519"), ("!secret", &old)];
+        let resp = dispatch(&mut session_state, create(&key));
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        let public = keyring.read().unwrap().keys()[0].public().clone();
+
+        // --------------------
+        // WHEN
+        // a sign key's !secret is updated
+        // --------------------
+        let new = BASE64.encode(&[2; 32]);
+        let public_pairs: Vec<(&str, &str)> =
+            public.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        let msg = update(&public_pairs, &[("!secret", &new)]);
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // the key's pubkey is the public key of the new secret
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        let private = ed25519_key(&SecretBuf::from(new.as_str())).unwrap();
+        let pubkey = BASE64.encode(private.verifying_key().as_bytes());
+        let keyring = keyring.read().unwrap();
+        assert_eq!(keyring.keys()[0].get("pubkey"), Some(pubkey.as_str()));
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn ssh_key_secret()
+    {
+        // --------------------
+        // GIVEN
+        // a session whose keyring is empty and
+        // an ssh key created in it
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state =
+            new_session_state_settings(keyring.clone(), policy);
+        let old = BASE64.encode(&[1; 32]);
+        let key = [("proto", "ssh"), ("alg", "ed25519"), ("!secret", &old)];
+        let resp = dispatch(&mut session_state, create(&key));
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        let public = keyring.read().unwrap().keys()[0].public().clone();
+
+        // --------------------
+        // WHEN
+        // the ssh key's !secret is updated
+        // --------------------
+        let new = BASE64.encode(&[2; 32]);
+        let public_pairs: Vec<(&str, &str)> =
+            public.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        let msg = update(&public_pairs, &[("!secret", &new)]);
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // the key's pubkey is the public key of the new secret
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        let private = ed25519_key(&SecretBuf::from(new.as_str())).unwrap();
+        let pubkey = BASE64.encode(private.verifying_key().as_bytes());
+        let keyring = keyring.read().unwrap();
+        assert_eq!(keyring.keys()[0].get("pubkey"), Some(pubkey.as_str()));
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn missing_update_invalid()
+    {
+        // --------------------
+        // GIVEN
+        // an UpdateKey request w/o the attrs to set
+        // --------------------
+        let keyring = keyring_with("Passw0rd");
+        let mut session_state = new_session_state_settings(keyring, policy);
+        let args = vec![attrs_to_value(&attrs(KEY))];
+        let msg = SessionRequest::new(42, SessionMethod::UpdateKey, args);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let mut session = Session::new();
+        let result = session.dispatch(&mut session_state.handle(), msg.into());
+
+        // --------------------
+        // THEN
+        // an InvalidMessage error is returned
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::InvalidMessage),
+            Ok(_) => false,
+        };
+        assert!(value);
+
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,1006 @@
+// src/test/protocol/v1/prompt.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::io::{self, Cursor, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Third-party imports
+
+use rmpv::Value;
+use siminau_rpc::message::Message;
+use siminau_rpc::message::notify::RpcNotice;
+use siminau_rpc::message::response::RpcResponse;
+use tempdir::TempDir;
+
+// Local imports
+
+use autolock;
+use error::SasdErrorKind;
+use keyring::{Attrs, Key, Keyring, new_keyring_handle};
+use protocol::v1::{ProtocolInfo, ProtocolRequest, ProtocolResponse,
+                   SessionRequest, SessionResponse, attrs_from_value,
+                   attrs_to_value};
+use protocol::v1::prompt::{PromptKind, Prompter, Prompts, PromptsHandle,
+                           new_prompts_handle};
+use rpc::v1::{ProtocolError, ProtocolMethod, ProtocolNotice, SessionError,
+              SessionMethod};
+use server::{Notifier, read_message};
+use settings::LockSection;
+use state::{SessionState, SessionStatus};
+
+use test::protocol::{attrs, cleanup_settings, file_keyring, new_session_state,
+                     new_session_state_prompts, send};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+// Stands in for a prompter's connection
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+
+impl SharedBuf {
+    // Wait for a notification to be written, returning its tag and the
+    // rest of its args
+    fn notice(&self, code: ProtocolNotice) -> (u64, Vec<Value>)
+    {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            {
+                let buf = self.0.lock().unwrap();
+                if !buf.is_empty() {
+                    let mut rd = Cursor::new(buf.clone());
+                    let msg = read_message(&mut rd).unwrap().unwrap();
+                    let info = ProtocolInfo::from(msg).unwrap();
+                    assert_eq!(info.message_code(), code);
+                    let args = info.message_args();
+                    let tag = args[0].as_u64().unwrap();
+                    return (tag, args[1..].to_vec());
+                }
+            }
+            assert!(Instant::now() < deadline, "no notification");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // Wait for a needkey notification, returning its tag and template
+    fn needkey(&self) -> (u64, Attrs)
+    {
+        let (tag, args) = self.notice(ProtocolNotice::NeedKey);
+        (tag, attrs_from_value(&args[0]).unwrap())
+    }
+
+    // Wait for a confirm notification, returning its tag and key attrs
+    fn confirm(&self) -> (u64, Attrs)
+    {
+        let (tag, args) = self.notice(ProtocolNotice::Confirm);
+        (tag, attrs_from_value(&args[0]).unwrap())
+    }
+
+    fn is_empty(&self) -> bool
+    {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
+
+// Stands in for a prompter's connection that stalls on the first write,
+// saying so on started, until released is dropped. The write then fails.
+struct Stalled {
+    started: Sender<()>,
+    released: Receiver<()>,
+}
+
+
+impl Write for Stalled {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize>
+    {
+        self.started.send(()).unwrap();
+        let _ = self.released.recv();
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, "stalled"))
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+
+fn template() -> Attrs
+{
+    attrs(&[("proto", "pass"), ("user", "alice"), ("!password", "?")])
+}
+
+
+fn key() -> Attrs
+{
+    attrs(&[("proto", "pass"), ("user", "alice"), ("confirm", "yes")])
+}
+
+
+fn peer() -> Attrs
+{
+    attrs(&[("uid", "1000"), ("gid", "1000")])
+}
+
+
+fn register(prompts: &PromptsHandle, kind: PromptKind)
+    -> (Prompter, SharedBuf)
+{
+    let buf = SharedBuf::default();
+    let notifier = Notifier::new(buf.clone());
+    (Prompter::new(prompts.clone(), kind, notifier), buf)
+}
+
+
+fn prompter(prompts: &PromptsHandle) -> (Prompter, SharedBuf)
+{
+    register(prompts, PromptKind::NeedKey)
+}
+
+
+fn confirmer(prompts: &PromptsHandle) -> (Prompter, SharedBuf)
+{
+    register(prompts, PromptKind::Confirm)
+}
+
+
+fn protocol_request(method: ProtocolMethod, args: Vec<Value>) -> Message
+{
+    ProtocolRequest::new(42, method, args).into()
+}
+
+
+// ===========================================================================
+// Test Prompts
+// ===========================================================================
+
+
+mod prompts {
+    use super::*;
+
+    #[test]
+    fn no_prompter()
+    {
+        // --------------------
+        // GIVEN
+        // no registered prompters
+        // --------------------
+        let prompts = Prompts::default();
+
+        // --------------------
+        // WHEN
+        // a key is asked for w/ a long timeout
+        // --------------------
+        let start = Instant::now();
+        let answered =
+            prompts.needkey(&template(), Duration::from_secs(60), None);
+
+        // --------------------
+        // THEN
+        // false is returned right away
+        // --------------------
+        assert!(!answered);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn answered()
+    {
+        // --------------------
+        // GIVEN
+        // a registered prompter that answers the first needkey
+        // --------------------
+        let prompts = new_prompts_handle();
+        let (_prompter, buf) = prompter(&prompts);
+        let answer = {
+            let prompts = prompts.clone();
+            let buf = buf.clone();
+            thread::spawn(move || {
+                let (tag, template) = buf.needkey();
+                assert!(prompts.needkey_done(tag));
+                template
+            })
+        };
+
+        // --------------------
+        // WHEN
+        // a key is asked for
+        // --------------------
+        let answered =
+            prompts.needkey(&template(), Duration::from_secs(10), None);
+
+        // --------------------
+        // THEN
+        // the prompter was sent the template and
+        // true is returned
+        // --------------------
+        assert_eq!(answer.join().unwrap(), template());
+        assert!(answered);
+    }
+
+    #[test]
+    fn timeout()
+    {
+        // --------------------
+        // GIVEN
+        // a registered prompter that never answers
+        // --------------------
+        let prompts = new_prompts_handle();
+        let (_prompter, buf) = prompter(&prompts);
+
+        // --------------------
+        // WHEN
+        // a key is asked for w/ a short timeout
+        // --------------------
+        let answered =
+            prompts.needkey(&template(), Duration::from_millis(50), None);
+
+        // --------------------
+        // THEN
+        // the prompter was notified and
+        // false is returned
+        // --------------------
+        assert!(!buf.is_empty());
+        assert!(!answered);
+    }
+
+    #[test]
+    fn asker_not_notified()
+    {
+        // --------------------
+        // GIVEN
+        // a single registered prompter
+        // --------------------
+        let prompts = new_prompts_handle();
+        let (prompter, buf) = prompter(&prompts);
+
+        // --------------------
+        // WHEN
+        // the prompter's own session asks for a key
+        // --------------------
+        let answered = prompts.needkey(
+            &template(),
+            Duration::from_secs(60),
+            Some(&prompter),
+        );
+
+        // --------------------
+        // THEN
+        // false is returned right away and
+        // nothing is sent to the prompter
+        // --------------------
+        assert!(!answered);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn prompter_dropped()
+    {
+        // --------------------
+        // GIVEN
+        // a registered prompter whose session ends once it is notified
+        // --------------------
+        let prompts = new_prompts_handle();
+        let (prompter, buf) = prompter(&prompts);
+        let ended = thread::spawn(move || {
+            buf.needkey();
+            drop(prompter);
+        });
+
+        // --------------------
+        // WHEN
+        // a key is asked for w/ a long timeout
+        // --------------------
+        let start = Instant::now();
+        let answered =
+            prompts.needkey(&template(), Duration::from_secs(60), None);
+
+        // --------------------
+        // THEN
+        // false is returned once the prompter is gone
+        // --------------------
+        ended.join().unwrap();
+        assert!(!answered);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(!prompts.has_prompters());
+    }
+
+    #[test]
+    fn stalled_prompter()
+    {
+        // --------------------
+        // GIVEN
+        // a registered prompter whose connection stalls and
+        // a conversation asking it for a key
+        // --------------------
+        let prompts = new_prompts_handle();
+        let (started, stalled) = mpsc::channel();
+        let (release, released) = mpsc::channel();
+        let writer = Stalled {
+            started: started,
+            released: released,
+        };
+        let kind = PromptKind::NeedKey;
+        let _prompter =
+            Prompter::new(prompts.clone(), kind, Notifier::new(writer));
+        let asking = {
+            let prompts = prompts.clone();
+            thread::spawn(move || {
+                prompts.needkey(&template(), Duration::from_secs(60), None)
+            })
+        };
+        let timeout = Duration::from_secs(10);
+        stalled.recv_timeout(timeout).unwrap();
+
+        // --------------------
+        // WHEN
+        // another session uses the prompts while the write is stalled
+        // --------------------
+        let (checked, has_prompters) = mpsc::channel();
+        {
+            let prompts = prompts.clone();
+            thread::spawn(move || {
+                checked.send(prompts.has_prompters()).unwrap();
+            });
+        }
+        let result = has_prompters.recv_timeout(timeout);
+
+        // --------------------
+        // THEN
+        // it isn't held up and
+        // the prompter is dropped once the write fails
+        // --------------------
+        assert_eq!(result, Ok(true));
+        drop(release);
+        assert!(!asking.join().unwrap());
+        assert!(!prompts.has_prompters());
+    }
+
+    #[test]
+    fn done_unknown_tag()
+    {
+        let prompts = Prompts::default();
+        assert!(!prompts.needkey_done(42));
+    }
+
+    #[test]
+    fn confirm_no_confirmer()
+    {
+        // --------------------
+        // GIVEN
+        // a registered prompter but no confirmer
+        // --------------------
+        let prompts = new_prompts_handle();
+        let (_prompter, buf) = prompter(&prompts);
+
+        // --------------------
+        // WHEN
+        // the use of a key is to be confirmed w/ a long timeout
+        // --------------------
+        let start = Instant::now();
+        let confirmed = prompts.confirm(
+            &key(),
+            &peer(),
+            Duration::from_secs(60),
+            None,
+            None,
+        );
+
+        // --------------------
+        // THEN
+        // false is returned right away and
+        // the prompter is not asked
+        // --------------------
+        assert!(!confirmed);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn confirm_answered()
+    {
+        for &(answer, expected) in &[(true, true), (false, false)] {
+            // --------------------
+            // GIVEN
+            // a registered confirmer that answers the first confirm request
+            // --------------------
+            let prompts = new_prompts_handle();
+            let (_confirmer, buf) = confirmer(&prompts);
+            let confirm = {
+                let prompts = prompts.clone();
+                thread::spawn(move || {
+                    let (tag, key) = buf.confirm();
+                    assert!(prompts.confirm_done(tag, answer));
+                    key
+                })
+            };
+
+            // --------------------
+            // WHEN
+            // the use of a key is to be confirmed
+            // --------------------
+            let confirmed = prompts.confirm(
+                &key(),
+                &peer(),
+                Duration::from_secs(10),
+                None,
+                None,
+            );
+
+            // --------------------
+            // THEN
+            // the confirmer was sent the key's attrs and
+            // its answer is returned
+            // --------------------
+            assert_eq!(confirm.join().unwrap(), key());
+            assert_eq!(confirmed, expected);
+        }
+    }
+
+    #[test]
+    fn confirm_remembered()
+    {
+        // --------------------
+        // GIVEN
+        // a registered confirmer that says yes once and
+        // answers that are remembered for a minute
+        // --------------------
+        let prompts = new_prompts_handle();
+        let (_confirmer, buf) = confirmer(&prompts);
+        let remember = Some(Duration::from_secs(60));
+        let confirm = {
+            let prompts = prompts.clone();
+            let buf = buf.clone();
+            thread::spawn(move || {
+                let (tag, _) = buf.confirm();
+                assert!(prompts.confirm_done(tag, true));
+            })
+        };
+        let timeout = Duration::from_secs(10);
+        assert!(prompts.confirm(&key(), &peer(), timeout, remember, None));
+        confirm.join().unwrap();
+        buf.0.lock().unwrap().clear();
+
+        // --------------------
+        // WHEN
+        // the use of the key by the same peer and another peer is to be
+        // confirmed w/ a short timeout
+        // --------------------
+        let timeout = Duration::from_millis(50);
+        let same = prompts.confirm(&key(), &peer(), timeout, remember, None);
+        assert!(buf.is_empty());
+        let other = attrs(&[("uid", "1001"), ("gid", "1001")]);
+        let another = prompts.confirm(&key(), &other, timeout, remember, None);
+
+        // --------------------
+        // THEN
+        // the same peer is confirmed w/o asking again and
+        // the other peer is asked about and not confirmed
+        // --------------------
+        assert!(same);
+        assert!(!buf.is_empty());
+        assert!(!another);
+    }
+
+    #[test]
+    fn confirm_done_needkey_tag()
+    {
+        // --------------------
+        // GIVEN
+        // a prompter that has been sent a needkey request
+        // --------------------
+        let prompts = new_prompts_handle();
+        let (_prompter, buf) = prompter(&prompts);
+        let answer = {
+            let prompts = prompts.clone();
+            thread::spawn(move || {
+                let (tag, _) = buf.needkey();
+                prompts.confirm_done(tag, true)
+            })
+        };
+
+        // --------------------
+        // WHEN
+        // the needkey request is answered as a confirm request
+        // --------------------
+        let timeout = Duration::from_millis(500);
+        let answered = prompts.needkey(&template(), timeout, None);
+
+        // --------------------
+        // THEN
+        // the answer is refused and
+        // the needkey request is not answered
+        // --------------------
+        assert!(!answer.join().unwrap());
+        assert!(!answered);
+    }
+}
+
+
+// ===========================================================================
+// Test ProtocolNeedKey and ProtocolNeedKeyDone
+// ===========================================================================
+
+
+mod session {
+    use super::*;
+
+    #[test]
+    fn conversation_waits_for_key()
+    {
+        // --------------------
+        // GIVEN
+        // a session registered as a prompter and
+        // a client session w/ no pass key that starts the pass protocol
+        // --------------------
+        let prompts = new_prompts_handle();
+        let keyring = new_keyring_handle(Keyring::new());
+        let buf = SharedBuf::default();
+        let mut prompter_state =
+            new_session_state_prompts(keyring.clone(), &prompts);
+        prompter_state.set_notifier(Notifier::new(buf.clone()));
+        let msg = protocol_request(ProtocolMethod::ProtocolNeedKey, vec![]);
+        let resp = send(&mut prompter_state, msg);
+        assert_eq!(resp.error_code(), ProtocolError::Nil);
+
+        let mut client_state =
+            new_session_state_prompts(keyring.clone(), &prompts);
+        let client = thread::spawn(move || {
+            let start = attrs(&[("proto", "pass"), ("user", "alice")]);
+            let args = vec![attrs_to_value(&start)];
+            let msg = protocol_request(ProtocolMethod::ProtocolStart, args);
+            let resp = send(&mut client_state, msg);
+            (resp, client_state)
+        });
+
+        // --------------------
+        // WHEN
+        // the prompter is notified and adds the key and
+        // sends ProtocolNeedKeyDone w/ the notification's tag
+        // --------------------
+        let (tag, template) = buf.needkey();
+        let mut key = template.clone();
+        key.insert("!password".to_owned(), "hunter2".to_owned());
+        let args = vec![attrs_to_value(&key)];
+        let msg = SessionRequest::new(42, SessionMethod::CreateKey, args);
+        let created = match prompter_state.change(msg.into()).unwrap() {
+            SessionStatus::Reply(m) => SessionResponse::from(m).unwrap(),
+            _ => panic!("no reply"),
+        };
+        let args = vec![Value::from(tag)];
+        let msg = protocol_request(ProtocolMethod::ProtocolNeedKeyDone, args);
+        let done = send(&mut prompter_state, msg);
+
+        // --------------------
+        // THEN
+        // the template asks for a pass key for alice and
+        // the client's conversation starts w/ the new key
+        // --------------------
+        assert_eq!(template, self::template());
+        assert_eq!(created.error_code(), SessionError::Nil);
+        assert_eq!(done.error_code(), ProtocolError::Nil);
+
+        let (resp, client_state) = client.join().unwrap();
+        assert_eq!(resp.error_code(), ProtocolError::Nil);
+        assert!(client_state.state().as_v1().unwrap().is_conversation());
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(prompter_state);
+        cleanup_settings(client_state);
+    }
+
+    #[test]
+    fn autolock_ends_wait()
+    {
+        // --------------------
+        // GIVEN
+        // a session registered as a prompter and
+        // a client session w/ no pass key waiting on the prompter
+        // --------------------
+        let prompts = new_prompts_handle();
+        let dir = TempDir::new("sasd").unwrap();
+        let keyring = new_keyring_handle(file_keyring(&dir));
+        let (_prompter, buf) = prompter(&prompts);
+
+        let mut client_state =
+            new_session_state_prompts(keyring.clone(), &prompts);
+        let start = Instant::now();
+        let client = thread::spawn(move || {
+            let start = attrs(&[("proto", "pass"), ("user", "alice")]);
+            let args = vec![attrs_to_value(&start)];
+            let msg = protocol_request(ProtocolMethod::ProtocolStart, args);
+            let resp = send(&mut client_state, msg);
+            (resp, client_state)
+        });
+        buf.needkey();
+
+        // --------------------
+        // WHEN
+        // the keyring is locked for being idle
+        // --------------------
+        let lock = LockSection {
+            idle_timeout: Some(Duration::from_secs(1)),
+            max_unlocked: None,
+        };
+        let later = Instant::now() + Duration::from_secs(2);
+        let locked = autolock::check(&keyring, &prompts, &lock, later);
+
+        // --------------------
+        // THEN
+        // the client is told right away that the keyring is locked and
+        // its conversation is not started
+        // --------------------
+        assert!(locked);
+        let (resp, client_state) = client.join().unwrap();
+        assert_eq!(resp.error_code(), ProtocolError::Locked);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(client_state.state().as_v1().unwrap().is_session());
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(client_state);
+    }
+
+    #[test]
+    fn need_key_without_notifier()
+    {
+        // --------------------
+        // GIVEN
+        // a session that can't be sent notifications
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+
+        // --------------------
+        // WHEN
+        // ProtocolNeedKey is sent
+        // --------------------
+        let msg = protocol_request(ProtocolMethod::ProtocolNeedKey, vec![]);
+        let resp = send(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidProtocolMessage error is returned and
+        // the session is not a prompter
+        // --------------------
+        assert_eq!(
+            resp.error_code(),
+            ProtocolError::InvalidProtocolMessage
+        );
+        assert!(session_state.prompter().is_none());
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn need_key_done_not_prompter()
+    {
+        // --------------------
+        // GIVEN
+        // a session that is not a prompter
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+
+        // --------------------
+        // WHEN
+        // ProtocolNeedKeyDone is sent
+        // --------------------
+        let args = vec![Value::from(1)];
+        let msg = protocol_request(ProtocolMethod::ProtocolNeedKeyDone, args);
+        let resp = send(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidProtocolMessage error is returned
+        // --------------------
+        assert_eq!(
+            resp.error_code(),
+            ProtocolError::InvalidProtocolMessage
+        );
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn need_key_done_bad_tag()
+    {
+        // --------------------
+        // GIVEN
+        // a session
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+
+        // --------------------
+        // WHEN
+        // ProtocolNeedKeyDone is sent w/ a string tag
+        // --------------------
+        let args = vec![Value::from("1")];
+        let msg = protocol_request(ProtocolMethod::ProtocolNeedKeyDone, args);
+        let result = session_state.change(msg);
+
+        // --------------------
+        // THEN
+        // an InvalidMessage error is returned
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::InvalidMessage),
+            Ok(_) => false,
+        };
+        assert!(value);
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+// Test ProtocolConfirm
+// ===========================================================================
+
+
+mod confirm_session {
+    use super::*;
+
+    // A confirmer session and a client session sharing a keyring w/ a pass
+    // key marked confirm=yes
+    fn sessions() -> (SessionState, SharedBuf, SessionState)
+    {
+        let prompts = new_prompts_handle();
+        let mut keyring = Keyring::new();
+        let mut attrs = key();
+        attrs.insert("!password".to_owned(), "hunter2".to_owned());
+        keyring.add(Key::new(attrs));
+        let keyring = new_keyring_handle(keyring);
+
+        let buf = SharedBuf::default();
+        let mut confirmer_state =
+            new_session_state_prompts(keyring.clone(), &prompts);
+        confirmer_state.set_notifier(Notifier::new(buf.clone()));
+        let msg = protocol_request(ProtocolMethod::ProtocolConfirm, vec![]);
+        let resp = send(&mut confirmer_state, msg);
+        assert_eq!(resp.error_code(), ProtocolError::Nil);
+
+        let client_state = new_session_state_prompts(keyring, &prompts);
+        (confirmer_state, buf, client_state)
+    }
+
+    // Start the pass protocol for alice in another thread
+    fn start(mut client_state: SessionState)
+        -> thread::JoinHandle<(ProtocolResponse, SessionState)>
+    {
+        thread::spawn(move || {
+            let start = attrs(&[("proto", "pass"), ("user", "alice")]);
+            let args = vec![attrs_to_value(&start)];
+            let msg = protocol_request(ProtocolMethod::ProtocolStart, args);
+            let resp = send(&mut client_state, msg);
+            (resp, client_state)
+        })
+    }
+
+    fn answer(tag: u64, answer: &str) -> Message
+    {
+        let tag = tag.to_string();
+        let answer = attrs(&[("tag", &tag), ("answer", answer)]);
+        let args = vec![attrs_to_value(&answer)];
+        protocol_request(ProtocolMethod::ProtocolConfirm, args)
+    }
+
+    #[test]
+    fn confirmed()
+    {
+        // --------------------
+        // GIVEN
+        // a confirmer and
+        // a client that starts the pass protocol w/ a confirm=yes key
+        // --------------------
+        let (mut confirmer_state, buf, client_state) = sessions();
+        let client = start(client_state);
+
+        // --------------------
+        // WHEN
+        // the confirmer is notified and answers yes
+        // --------------------
+        let (tag, key) = buf.confirm();
+        let resp = send(&mut confirmer_state, answer(tag, "yes"));
+
+        // --------------------
+        // THEN
+        // the confirmer was sent the key's public attrs and
+        // the client's conversation starts
+        // --------------------
+        assert_eq!(key, self::key());
+        assert_eq!(resp.error_code(), ProtocolError::Nil);
+        let (resp, client_state) = client.join().unwrap();
+        assert_eq!(resp.error_code(), ProtocolError::Nil);
+        assert!(client_state.state().as_v1().unwrap().is_conversation());
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(confirmer_state);
+        cleanup_settings(client_state);
+    }
+
+    #[test]
+    fn denied()
+    {
+        // --------------------
+        // GIVEN
+        // a confirmer and
+        // a client that starts the pass protocol w/ a confirm=yes key
+        // --------------------
+        let (mut confirmer_state, buf, client_state) = sessions();
+        let client = start(client_state);
+
+        // --------------------
+        // WHEN
+        // the confirmer is notified and answers no
+        // --------------------
+        let (tag, _) = buf.confirm();
+        let resp = send(&mut confirmer_state, answer(tag, "no"));
+
+        // --------------------
+        // THEN
+        // a ProtocolNeedConfirmation error is returned to the client and
+        // no conversation is started
+        // --------------------
+        assert_eq!(resp.error_code(), ProtocolError::Nil);
+        let (resp, client_state) = client.join().unwrap();
+        assert_eq!(
+            resp.error_code(),
+            ProtocolError::ProtocolNeedConfirmation
+        );
+        assert!(client_state.state().as_v1().unwrap().is_session());
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(confirmer_state);
+        cleanup_settings(client_state);
+    }
+
+    #[test]
+    fn no_confirmer()
+    {
+        // --------------------
+        // GIVEN
+        // a client w/ a confirm=yes key whose confirmer has gone away
+        // --------------------
+        let (confirmer_state, buf, client_state) = sessions();
+        cleanup_settings(confirmer_state);
+
+        // --------------------
+        // WHEN
+        // the client starts the pass protocol
+        // --------------------
+        let (resp, client_state) = start(client_state).join().unwrap();
+
+        // --------------------
+        // THEN
+        // a ProtocolNeedConfirmation error is returned and
+        // nothing is sent to the old confirmer
+        // --------------------
+        assert_eq!(
+            resp.error_code(),
+            ProtocolError::ProtocolNeedConfirmation
+        );
+        assert!(buf.is_empty());
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(client_state);
+    }
+
+    #[test]
+    fn answer_not_confirmer()
+    {
+        // --------------------
+        // GIVEN
+        // a session that is not a confirmer
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+
+        // --------------------
+        // WHEN
+        // a ProtocolConfirm answer is sent
+        // --------------------
+        let resp = send(&mut session_state, answer(1, "yes"));
+
+        // --------------------
+        // THEN
+        // an InvalidProtocolMessage error is returned
+        // --------------------
+        assert_eq!(
+            resp.error_code(),
+            ProtocolError::InvalidProtocolMessage
+        );
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn bad_answer()
+    {
+        // --------------------
+        // GIVEN
+        // a session
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+
+        // --------------------
+        // WHEN
+        // a ProtocolConfirm answer other than yes or no is sent
+        // --------------------
+        let result = session_state.change(answer(1, "maybe"));
+
+        // --------------------
+        // THEN
+        // an InvalidMessage error is returned
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::InvalidMessage),
+            Ok(_) => false,
+        };
+        assert!(value);
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
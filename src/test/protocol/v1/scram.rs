@@ -0,0 +1,325 @@
+// src/test/protocol/v1/scram.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+use error::{SasdErrorKind, SasdResult};
+use keyring::{Key, Keyring};
+use protocol::v1::auth::{AuthProtocol, Phase};
+use protocol::v1::scram::Scram;
+
+use test::protocol::attrs;
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+// The example exchange from RFC 7677
+const CLIENT_NONCE: &'static str = "rOprNGfwEbeRWgbNEkqO";
+const SERVER_NONCE: &'static str = "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0";
+const SALT: &'static str = "W22ZaJ0SNY7soEsUEjb6gQ==";
+const SALTED: &'static str = "xKSVEDI6tPlSysH6mUQZOeeOp01r6B3fcJbodRPcYV0=";
+const CLIENT_FIRST: &'static str = "n,,n=user,r=rOprNGfwEbeRWgbNEkqO";
+const SERVER_FIRST: &'static str = "r=rOprNGfwEbeRWgbNEkqO\
+                                    %hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                                    s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+const CLIENT_FINAL: &'static str = "c=biws,r=rOprNGfwEbeRWgbNEkqO\
+                                    %hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                                    p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7A\
+                                    ndVQ=";
+const SERVER_FINAL: &'static str = "v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl\
+                                    95G4=";
+
+
+fn keyring(pairs: &[(&str, &str)]) -> Keyring
+{
+    let mut key = attrs(&[("proto", "scram-sha-256"), ("user", "user")]);
+    key.extend(attrs(pairs));
+    let mut keyring = Keyring::new();
+    keyring.add(Key::new(key));
+    keyring
+}
+
+
+fn start(keyring: &mut Keyring, role: &str, nonce: &str) -> Scram
+{
+    let mut module = Scram::with_nonce(nonce);
+    let attrs = attrs(&[("proto", "scram-sha-256"), ("role", role)]);
+    module.start(keyring, &attrs).unwrap();
+    module
+}
+
+
+fn read(module: &mut Scram, keyring: &mut Keyring, phase: Phase) -> String
+{
+    let (msg, p) = module.read(keyring).unwrap();
+    assert_eq!(p, phase);
+    msg.as_str().unwrap().to_owned()
+}
+
+
+// Write a message, then do the module's work w/o the keyring as a
+// conversation would
+fn write(module: &mut Scram, keyring: &mut Keyring, msg: &str)
+    -> SasdResult<Phase>
+{
+    let phase = module.write(keyring, msg.as_bytes())?;
+    module.run_unlocked()?;
+    Ok(phase)
+}
+
+
+fn is_failed<T>(result: SasdResult<T>) -> bool
+{
+    match result {
+        Err(e) => matches!(*e.kind(), SasdErrorKind::ProtocolFailed(_)),
+        Ok(_) => false,
+    }
+}
+
+
+// ===========================================================================
+// Test client role
+// ===========================================================================
+
+
+mod client {
+    use super::*;
+
+    fn exchange(keyring: &mut Keyring)
+    {
+        let mut client = start(keyring, "client", CLIENT_NONCE);
+        assert_eq!(read(&mut client, keyring, Phase::Continue), CLIENT_FIRST);
+
+        let phase = write(&mut client, keyring, SERVER_FIRST).unwrap();
+        assert_eq!(phase, Phase::Continue);
+        assert_eq!(read(&mut client, keyring, Phase::Continue), CLIENT_FINAL);
+
+        let phase = write(&mut client, keyring, SERVER_FINAL).unwrap();
+        assert_eq!(phase, Phase::Done);
+        let info = client.authinfo().unwrap();
+        assert_eq!(info.cuid, Some("user".to_owned()));
+    }
+
+    #[test]
+    fn password_rfc7677()
+    {
+        let mut keyring = keyring(&[("!password", "pencil")]);
+        exchange(&mut keyring);
+    }
+
+    #[test]
+    fn salted_rfc7677()
+    {
+        let pairs =
+            [("salt", SALT), ("iterations", "4096"), ("!salted", SALTED)];
+        let mut keyring = keyring(&pairs);
+        exchange(&mut keyring);
+    }
+
+    #[test]
+    fn salted_other_salt()
+    {
+        // --------------------
+        // GIVEN
+        // a client w/ a salted key made w/ another iteration count
+        // --------------------
+        let pairs =
+            [("salt", SALT), ("iterations", "8192"), ("!salted", SALTED)];
+        let mut keyring = keyring(&pairs);
+        let mut client = start(&mut keyring, "client", CLIENT_NONCE);
+        read(&mut client, &mut keyring, Phase::Continue);
+
+        // --------------------
+        // WHEN
+        // server-first is written
+        // --------------------
+        let result = write(&mut client, &mut keyring, SERVER_FIRST);
+
+        // --------------------
+        // THEN
+        // a ProtocolFailed error is returned
+        // --------------------
+        assert!(is_failed(result));
+    }
+
+    #[test]
+    fn bad_server_signature()
+    {
+        // --------------------
+        // GIVEN
+        // a client that has sent client-final
+        // --------------------
+        let mut keyring = keyring(&[("!password", "pencil")]);
+        let mut client = start(&mut keyring, "client", CLIENT_NONCE);
+        read(&mut client, &mut keyring, Phase::Continue);
+        write(&mut client, &mut keyring, SERVER_FIRST).unwrap();
+        read(&mut client, &mut keyring, Phase::Continue);
+
+        // --------------------
+        // WHEN
+        // a server-final w/ another signature is written
+        // --------------------
+        let msg = "v=AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+        let result = write(&mut client, &mut keyring, msg);
+
+        // --------------------
+        // THEN
+        // a ProtocolFailed error is returned
+        // --------------------
+        assert!(is_failed(result));
+    }
+
+    #[test]
+    fn nonce_not_extended()
+    {
+        // --------------------
+        // GIVEN
+        // a client that has sent client-first
+        // --------------------
+        let mut keyring = keyring(&[("!password", "pencil")]);
+        let mut client = start(&mut keyring, "client", CLIENT_NONCE);
+        read(&mut client, &mut keyring, Phase::Continue);
+
+        // --------------------
+        // WHEN
+        // server-first has a nonce that doesn't start w/ the client's
+        // --------------------
+        let msg = "r=other,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+        let result = write(&mut client, &mut keyring, msg);
+
+        // --------------------
+        // THEN
+        // a ProtocolFailed error is returned
+        // --------------------
+        assert!(is_failed(result));
+    }
+}
+
+
+// ===========================================================================
+// Test server role
+// ===========================================================================
+
+
+mod server {
+    use super::*;
+
+    #[test]
+    fn accepts_rfc7677()
+    {
+        // --------------------
+        // GIVEN
+        // a server w/ the RFC's salt and nonce
+        // --------------------
+        let pairs =
+            [("salt", SALT), ("iterations", "4096"), ("!password", "pencil")];
+        let mut keyring = keyring(&pairs);
+        let mut server = start(&mut keyring, "server", SERVER_NONCE);
+
+        // --------------------
+        // WHEN
+        // the RFC's client messages are written
+        // --------------------
+        let phase = write(&mut server, &mut keyring, CLIENT_FIRST);
+        let first = read(&mut server, &mut keyring, Phase::Continue);
+        let final_phase = write(&mut server, &mut keyring, CLIENT_FINAL);
+
+        // --------------------
+        // THEN
+        // the RFC's server messages are read back and
+        // the client's user is in authinfo
+        // --------------------
+        assert_eq!(phase.unwrap(), Phase::Continue);
+        assert_eq!(first, SERVER_FIRST);
+        assert_eq!(final_phase.unwrap(), Phase::Continue);
+        assert_eq!(read(&mut server, &mut keyring, Phase::Done), SERVER_FINAL);
+        let info = server.authinfo().unwrap();
+        assert_eq!(info.cuid, Some("user".to_owned()));
+    }
+
+    // Run a client w/ the given password against a server holding "pencil"
+    fn authenticate(password: &str) -> bool
+    {
+        let mut server_keyring = keyring(&[("!password", "pencil")]);
+        let mut client_keyring = keyring(&[("!password", password)]);
+        let mut server = Scram::new();
+        let server_attrs =
+            attrs(&[("proto", "scram-sha-256"), ("role", "server")]);
+        server.start(&mut server_keyring, &server_attrs).unwrap();
+        let mut client = Scram::new();
+        let client_attrs = attrs(&[("proto", "scram-sha-256")]);
+        client.start(&mut client_keyring, &client_attrs).unwrap();
+
+        let msg = read(&mut client, &mut client_keyring, Phase::Continue);
+        write(&mut server, &mut server_keyring, &msg).unwrap();
+        let msg = read(&mut server, &mut server_keyring, Phase::Continue);
+        write(&mut client, &mut client_keyring, &msg).unwrap();
+        let msg = read(&mut client, &mut client_keyring, Phase::Continue);
+        match write(&mut server, &mut server_keyring, &msg) {
+            Ok(_) => {}
+            Err(e) => {
+                assert!(matches!(*e.kind(), SasdErrorKind::ProtocolFailed(_)));
+                return false;
+            }
+        }
+        let msg = read(&mut server, &mut server_keyring, Phase::Done);
+        let phase = write(&mut client, &mut client_keyring, &msg);
+        assert_eq!(phase.unwrap(), Phase::Done);
+        true
+    }
+
+    #[test]
+    fn accepts_right_password()
+    {
+        assert!(authenticate("pencil"));
+    }
+
+    #[test]
+    fn rejects_wrong_password()
+    {
+        assert!(!authenticate("wrong"));
+    }
+
+    #[test]
+    fn channel_binding()
+    {
+        // --------------------
+        // GIVEN
+        // a server
+        // --------------------
+        let mut keyring = keyring(&[("!password", "pencil")]);
+        let mut server = start(&mut keyring, "server", SERVER_NONCE);
+
+        // --------------------
+        // WHEN
+        // client-first asks for channel binding
+        // --------------------
+        let msg = "p=tls-unique,,n=user,r=rOprNGfwEbeRWgbNEkqO";
+        let result = write(&mut server, &mut keyring, msg);
+
+        // --------------------
+        // THEN
+        // a ProtocolFailed error is returned
+        // --------------------
+        assert!(is_failed(result));
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,1048 @@
+// src/test/protocol/v1/session.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+use rmpv::Value;
+use rmpv::decode::read_value;
+use siminau_rpc::message::Message;
+use siminau_rpc::message::response::RpcResponse;
+use tempdir::TempDir;
+
+// Local imports
+
+use error::SasdErrorKind;
+use keyring::{Attrs, Key, Keyring, KeyringHandle, SECRET_PLACEHOLDER,
+              new_keyring_handle};
+use protocol::State;
+use protocol::v1::{ProtocolRequest, ProtocolResponse, Session, SessionRequest,
+                   attrs_to_value};
+use rpc::v1::{ProtocolError, ProtocolMethod, SessionError, SessionMethod};
+use state::SessionState;
+
+use test::protocol::{attrs, cleanup_settings, dispatch, file_keyring,
+                     new_session_state};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+fn request(method: SessionMethod, args: Vec<Value>) -> Message
+{
+    SessionRequest::new(42, method, args).into()
+}
+
+
+// A keyring holding the given key whose file can't be saved, since the
+// directory it was in is gone
+fn unsaveable_keyring(key: Option<Attrs>) -> KeyringHandle
+{
+    let dir = TempDir::new("sasd").unwrap();
+    let mut keyring = file_keyring(&dir);
+    if let Some(attrs) = key {
+        keyring.add(Key::new(attrs));
+    }
+    keyring.save().unwrap();
+    dir.close().unwrap();
+    new_keyring_handle(keyring)
+}
+
+
+fn is_save_error(session_state: &mut SessionState, msg: Message) -> bool
+{
+    let mut session = Session::new();
+    let result = session.dispatch(&mut session_state.handle(), msg);
+    match result {
+        Err(e) => matches!(*e.kind(), SasdErrorKind::KeyringSaveError(_)),
+        _ => false,
+    }
+}
+
+
+// A msgpack string that is not valid UTF-8
+fn invalid_utf8() -> Value
+{
+    let mut buf: &[u8] = &[0xa1, 0xff];
+    read_value(&mut buf).unwrap()
+}
+
+
+fn is_invalid_message(session_state: &mut SessionState, msg: Message)
+    -> bool
+{
+    let mut session = Session::new();
+    let result = session.dispatch(&mut session_state.handle(), msg);
+    match result {
+        Err(e) => matches!(*e.kind(), SasdErrorKind::InvalidMessage),
+        _ => false,
+    }
+}
+
+
+// ===========================================================================
+// Test KeyList
+// ===========================================================================
+
+
+mod key_list {
+    use super::*;
+
+    #[test]
+    fn empty_keyring()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ an empty keyring and
+        // a KeyList request
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let msg = request(SessionMethod::KeyList, vec![]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an empty list is returned
+        // --------------------
+        assert_eq!(resp.message_id(), 42);
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        assert_eq!(resp.result(), &Value::Array(vec![]));
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn list_all_keys()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding 2 keys and
+        // a KeyList request
+        // --------------------
+        let first = attrs(&[("proto", "pass"), ("user", "alice")]);
+        let second = attrs(&[("proto", "pass"), ("user", "bob")]);
+        let mut keyring = Keyring::new();
+        keyring.add(Key::new(first.clone()));
+        keyring.add(Key::new(second.clone()));
+
+        let mut session_state =
+            new_session_state(new_keyring_handle(keyring));
+        let msg = request(SessionMethod::KeyList, vec![]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // both keys are returned as maps
+        // --------------------
+        let expected = Value::Array(
+            vec![attrs_to_value(&first), attrs_to_value(&second)],
+        );
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        assert_eq!(resp.result(), &expected);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn secret_attrs_masked()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding a key w/ a secret attribute and
+        // a KeyList request
+        // --------------------
+        let key = attrs(&[("user", "alice"), ("!password", "hunter2")]);
+        let mut keyring = Keyring::new();
+        keyring.add(Key::new(key));
+
+        let mut session_state =
+            new_session_state(new_keyring_handle(keyring));
+        let msg = request(SessionMethod::KeyList, vec![]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // the public attribute is returned and
+        // the secret attribute's value is replaced by a placeholder
+        // --------------------
+        let expected = attrs(
+            &[("user", "alice"), ("!password", SECRET_PLACEHOLDER)],
+        );
+        let expected = Value::Array(vec![attrs_to_value(&expected)]);
+        assert_eq!(resp.result(), &expected);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn list_matching_keys()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding 3 keys and
+        // a KeyList request w/ a query matching 1 of them
+        // --------------------
+        let mut keyring = Keyring::new();
+        keyring.add(Key::new(attrs(&[("proto", "pass"), ("user", "alice")])));
+        keyring.add(Key::new(attrs(&[("proto", "pass"), ("user", "bob")])));
+        keyring.add(Key::new(attrs(&[("proto", "totp"), ("user", "alice")])));
+
+        let mut session_state =
+            new_session_state(new_keyring_handle(keyring));
+        let query = Value::from("proto=pass user=a*");
+        let msg = request(SessionMethod::KeyList, vec![query]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // only the matching key is returned
+        // --------------------
+        let expected = attrs(&[("proto", "pass"), ("user", "alice")]);
+        let expected = Value::Array(vec![attrs_to_value(&expected)]);
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        assert_eq!(resp.result(), &expected);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn bad_query()
+    {
+        // --------------------
+        // GIVEN
+        // a KeyList request w/ an unparseable query
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let msg = request(SessionMethod::KeyList, vec![Value::from("user")]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidQuery error response is returned
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::InvalidQuery);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn args_invalid()
+    {
+        // --------------------
+        // GIVEN
+        // a KeyList request w/ a non-string argument
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let msg = request(SessionMethod::KeyList, vec![Value::from(1)]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let value = is_invalid_message(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidMessage error is returned
+        // --------------------
+        assert!(value);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn query_invalid_utf8()
+    {
+        // --------------------
+        // GIVEN
+        // KeyList and DeleteKey requests whose query is not valid UTF-8
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let requests = vec![
+            request(SessionMethod::KeyList, vec![invalid_utf8()]),
+            request(SessionMethod::DeleteKey, vec![invalid_utf8()]),
+        ];
+
+        for msg in requests {
+            // --------------------
+            // WHEN
+            // Session::dispatch() is called with the request
+            // --------------------
+            let value = is_invalid_message(&mut session_state, msg);
+
+            // --------------------
+            // THEN
+            // an InvalidMessage error is returned
+            // --------------------
+            assert!(value);
+        }
+
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+// Test CreateKey
+// ===========================================================================
+
+
+mod create_key {
+    use super::*;
+
+    #[test]
+    fn save_failure()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring that can't be saved and
+        // a CreateKey request
+        // --------------------
+        let keyring = unsaveable_keyring(None);
+        let mut session_state = new_session_state(keyring.clone());
+        let key = attrs(&[("proto", "pass"), ("user", "alice")]);
+        let msg = request(SessionMethod::CreateKey, vec![attrs_to_value(&key)]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let value = is_save_error(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a KeyringSaveError is returned and
+        // the key is not kept
+        // --------------------
+        assert!(value);
+        assert!(keyring.read().unwrap().is_empty());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn add_key()
+    {
+        // --------------------
+        // GIVEN
+        // 2 sessions sharing an empty keyring and
+        // a CreateKey request
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut first_state = new_session_state(keyring.clone());
+        let mut second_state = new_session_state(keyring.clone());
+        let key = attrs(&[("proto", "pass"), ("user", "alice")]);
+        let msg = request(SessionMethod::CreateKey, vec![attrs_to_value(&key)]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request in one session
+        // --------------------
+        let resp = dispatch(&mut first_state, msg);
+
+        // --------------------
+        // THEN
+        // a Nil response is returned and
+        // the key can be listed from the other session
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        assert_eq!(resp.result(), &Value::Nil);
+
+        let msg = request(SessionMethod::KeyList, vec![]);
+        let resp = dispatch(&mut second_state, msg);
+        let expected = Value::Array(vec![attrs_to_value(&key)]);
+        assert_eq!(resp.result(), &expected);
+
+        cleanup_settings(first_state);
+        cleanup_settings(second_state);
+    }
+
+    #[test]
+    fn key_exists()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding a key and
+        // a CreateKey request for the same key
+        // --------------------
+        let key = attrs(&[("proto", "pass"), ("user", "alice")]);
+        let mut keyring = Keyring::new();
+        keyring.add(Key::new(key.clone()));
+
+        let mut session_state =
+            new_session_state(new_keyring_handle(keyring));
+        let msg = request(SessionMethod::CreateKey, vec![attrs_to_value(&key)]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a KeyExists error response is returned
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::KeyExists);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn non_string_value_invalid()
+    {
+        // --------------------
+        // GIVEN
+        // a CreateKey request w/ a non-string attribute value
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let arg = Value::Map(vec![(Value::from("proto"), Value::from(1))]);
+        let msg = request(SessionMethod::CreateKey, vec![arg]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let value = is_invalid_message(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidMessage error is returned
+        // --------------------
+        assert!(value);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn secret_only_invalid()
+    {
+        // --------------------
+        // GIVEN
+        // a CreateKey request w/ only secret attributes
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let key = attrs(&[("!password", "hunter2")]);
+        let msg = request(SessionMethod::CreateKey, vec![attrs_to_value(&key)]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let value = is_invalid_message(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidMessage error is returned
+        // --------------------
+        assert!(value);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn empty_map_invalid()
+    {
+        // --------------------
+        // GIVEN
+        // a CreateKey request w/ an empty map
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let msg = request(SessionMethod::CreateKey, vec![Value::Map(vec![])]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let value = is_invalid_message(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidMessage error is returned
+        // --------------------
+        assert!(value);
+
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+// Test DeleteKey
+// ===========================================================================
+
+
+mod delete_key {
+    use super::*;
+
+    #[test]
+    fn save_failure()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding a key that can't be saved and
+        // a DeleteKey request for the key
+        // --------------------
+        let key = attrs(&[("proto", "pass"), ("user", "alice")]);
+        let keyring = unsaveable_keyring(Some(key.clone()));
+        let mut session_state = new_session_state(keyring.clone());
+        let msg = request(SessionMethod::DeleteKey, vec![attrs_to_value(&key)]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let value = is_save_error(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a KeyringSaveError is returned and
+        // the key is kept
+        // --------------------
+        assert!(value);
+        assert_eq!(keyring.read().unwrap().len(), 1);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn remove_key()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding a key and
+        // a DeleteKey request for the key
+        // --------------------
+        let key = attrs(&[("proto", "pass"), ("user", "alice")]);
+        let mut keyring = Keyring::new();
+        keyring.add(Key::new(key.clone()));
+        let keyring = new_keyring_handle(keyring);
+
+        let mut session_state = new_session_state(keyring.clone());
+        let msg = request(SessionMethod::DeleteKey, vec![attrs_to_value(&key)]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // the number of deleted keys is returned and
+        // the keyring is empty
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        assert_eq!(resp.result(), &Value::from(1));
+        assert!(keyring.read().unwrap().is_empty());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn key_not_found()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding a key and
+        // a DeleteKey request for a different key
+        // --------------------
+        let key = attrs(&[("proto", "pass"), ("user", "alice")]);
+        let other = attrs(&[("proto", "pass"), ("user", "bob")]);
+        let mut keyring = Keyring::new();
+        keyring.add(Key::new(key.clone()));
+        let keyring = new_keyring_handle(keyring);
+
+        let mut session_state = new_session_state(keyring.clone());
+        let msg =
+            request(SessionMethod::DeleteKey, vec![attrs_to_value(&other)]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a KeyNotFound error response is returned and
+        // the keyring is unchanged
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::KeyNotFound);
+        assert_eq!(keyring.read().unwrap().len(), 1);
+
+        cleanup_settings(session_state);
+    }
+
+    fn pass_keyring() -> KeyringHandle
+    {
+        let mut keyring = Keyring::new();
+        keyring.add(Key::new(attrs(&[("proto", "pass"), ("user", "alice")])));
+        keyring.add(Key::new(attrs(&[("proto", "pass"), ("user", "bob")])));
+        keyring.add(Key::new(attrs(&[("proto", "totp"), ("user", "alice")])));
+        new_keyring_handle(keyring)
+    }
+
+    #[test]
+    fn remove_matching_keys()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding 3 keys and
+        // a DeleteKey request w/ a query matching 2 of them
+        // --------------------
+        let keyring = pass_keyring();
+        let mut session_state = new_session_state(keyring.clone());
+        let msg =
+            request(SessionMethod::DeleteKey, vec![Value::from("proto=pass")]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // 2 keys are reported deleted and
+        // only the unmatched key is left
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        assert_eq!(resp.result(), &Value::from(2));
+        let keyring = keyring.read().unwrap();
+        assert_eq!(keyring.len(), 1);
+        assert_eq!(keyring.keys()[0].get("proto"), Some("totp"));
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn single_refuses_multiple_keys()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding 3 keys and
+        // a DeleteKey request limited to a single key w/ a query matching 2
+        // --------------------
+        let keyring = pass_keyring();
+        let mut session_state = new_session_state(keyring.clone());
+        let args = vec![Value::from("user=alice"), Value::from(true)];
+        let msg = request(SessionMethod::DeleteKey, args);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a MultipleKeys error response is returned and
+        // no key is deleted
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::MultipleKeys);
+        assert_eq!(keyring.read().unwrap().len(), 3);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn empty_query_refused()
+    {
+        // --------------------
+        // GIVEN
+        // a keyring holding 3 keys and
+        // a DeleteKey request w/ an empty query
+        // --------------------
+        let keyring = pass_keyring();
+        let mut session_state = new_session_state(keyring.clone());
+        let msg = request(SessionMethod::DeleteKey, vec![Value::from("")]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidQuery error response is returned and
+        // no key is deleted
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::InvalidQuery);
+        assert_eq!(keyring.read().unwrap().len(), 3);
+
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+// Test Lock and Unlock
+// ===========================================================================
+
+
+mod lock {
+    use super::*;
+
+    // A locked keyring whose file holds a single key
+    fn locked_keyring(dir: &TempDir) -> KeyringHandle
+    {
+        let mut keyring = file_keyring(&dir);
+        keyring.add(Key::new(attrs(&[("proto", "pass"), ("user", "alice")])));
+        keyring.save().unwrap();
+        keyring.lock();
+        new_keyring_handle(keyring)
+    }
+
+    #[test]
+    fn key_methods_locked()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ a locked keyring
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let mut session_state = new_session_state(locked_keyring(&dir));
+        let key = attrs_to_value(&attrs(&[("user", "bob")]));
+
+        let requests = vec![
+            request(SessionMethod::KeyList, vec![]),
+            request(SessionMethod::CreateKey, vec![key.clone()]),
+            request(SessionMethod::DeleteKey, vec![key]),
+        ];
+
+        for msg in requests {
+            // --------------------
+            // WHEN
+            // Session::dispatch() is called with a key request
+            // --------------------
+            let resp = dispatch(&mut session_state, msg);
+
+            // --------------------
+            // THEN
+            // a Locked error response is returned
+            // --------------------
+            assert_eq!(resp.error_code(), SessionError::Locked);
+        }
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn protocol_methods_locked()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ a locked keyring and
+        // a ProtocolStart request
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let mut session_state = new_session_state(locked_keyring(&dir));
+        let arg = attrs_to_value(&attrs(&[("proto", "pass")]));
+        let msg: Message =
+            ProtocolRequest::new(42, ProtocolMethod::ProtocolStart, vec![arg])
+                .into();
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let mut session = Session::new();
+        let result = session.dispatch(&mut session_state.handle(), msg);
+
+        // --------------------
+        // THEN
+        // a Locked protocol error response is returned
+        // --------------------
+        let resp = match result {
+            Ok((None, Some(m))) => ProtocolResponse::from(m).unwrap(),
+            _ => panic!("unexpected dispatch result"),
+        };
+        assert_eq!(resp.message_id(), 42);
+        assert_eq!(resp.error_code(), ProtocolError::Locked);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn unlock_wrong_passphrase()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ a locked keyring and
+        // an Unlock request w/ the wrong passphrase
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let keyring = locked_keyring(&dir);
+        let mut session_state = new_session_state(keyring.clone());
+        let msg = request(SessionMethod::Unlock, vec![Value::from("wrong")]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidPassphrase error response is returned and
+        // the keyring stays locked
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::InvalidPassphrase);
+        assert!(keyring.read().unwrap().is_locked());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn unlock_invalid_utf8()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ a locked keyring and
+        // an Unlock request whose passphrase is not valid UTF-8
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let keyring = locked_keyring(&dir);
+        let mut session_state = new_session_state(keyring.clone());
+        let msg = request(SessionMethod::Unlock, vec![invalid_utf8()]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let value = is_invalid_message(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidMessage error is returned and
+        // the keyring stays locked
+        // --------------------
+        assert!(value);
+        assert!(keyring.read().unwrap().is_locked());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn shared_by_all_sessions()
+    {
+        // --------------------
+        // GIVEN
+        // 2 sessions sharing a locked keyring
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let keyring = locked_keyring(&dir);
+        let mut first_state = new_session_state(keyring.clone());
+        let mut second_state = new_session_state(keyring.clone());
+
+        // --------------------
+        // WHEN
+        // the keyring is unlocked from the first session
+        // --------------------
+        let msg =
+            request(SessionMethod::Unlock, vec![Value::from("passphrase")]);
+        let resp = dispatch(&mut first_state, msg);
+        assert_eq!(resp.error_code(), SessionError::Nil);
+
+        // --------------------
+        // THEN
+        // the second session can list the key
+        // --------------------
+        let msg = request(SessionMethod::KeyList, vec![]);
+        let resp = dispatch(&mut second_state, msg);
+        assert_eq!(resp.error_code(), SessionError::Nil);
+        assert_eq!(resp.result().as_array().unwrap().len(), 1);
+
+        // --------------------
+        // WHEN
+        // the keyring is locked from the second session
+        // --------------------
+        let msg = request(SessionMethod::Lock, vec![]);
+        let resp = dispatch(&mut second_state, msg);
+        assert_eq!(resp.error_code(), SessionError::Nil);
+
+        // --------------------
+        // THEN
+        // the first session can no longer list keys
+        // --------------------
+        let msg = request(SessionMethod::KeyList, vec![]);
+        let resp = dispatch(&mut first_state, msg);
+        assert_eq!(resp.error_code(), SessionError::Locked);
+
+        cleanup_settings(first_state);
+        cleanup_settings(second_state);
+    }
+
+    #[test]
+    fn lock_no_file()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ a keyring w/o a file and
+        // a Lock request
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring.clone());
+        let msg = request(SessionMethod::Lock, vec![]);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a NotLockable error response is returned and
+        // the keyring stays unlocked
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::NotLockable);
+        assert!(!keyring.read().unwrap().is_locked());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn unlock_create()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ a locked keyring whose file does not exist
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let path = dir.path().join("keyring");
+        let keyring = new_keyring_handle(Keyring::locked(&path));
+        let mut session_state = new_session_state(keyring.clone());
+        let passphrase = Value::from("passphrase");
+
+        // --------------------
+        // WHEN
+        // Unlock is sent w/o and then w/ the create flag
+        // --------------------
+        let msg = request(SessionMethod::Unlock, vec![passphrase.clone()]);
+        let first = dispatch(&mut session_state, msg);
+        let args = vec![passphrase, Value::from(true)];
+        let msg = request(SessionMethod::Unlock, args);
+        let second = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // only the second unlocks the keyring, creating its file
+        // --------------------
+        assert_eq!(first.error_code(), SessionError::InvalidPassphrase);
+        assert_eq!(second.error_code(), SessionError::Nil);
+        assert!(!keyring.read().unwrap().is_locked());
+        assert!(path.exists());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn unlock_create_fails()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ a locked keyring whose file can't be created
+        // since its directory does not exist
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let path = dir.path().join("missing").join("keyring");
+        let keyring = new_keyring_handle(Keyring::locked(&path));
+        let mut session_state = new_session_state(keyring.clone());
+        let args = vec![Value::from("passphrase"), Value::from(true)];
+        let msg = request(SessionMethod::Unlock, args);
+
+        // --------------------
+        // WHEN
+        // Unlock is sent w/ the create flag
+        // --------------------
+        let resp = dispatch(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a KeyringError response is returned and
+        // the keyring is still locked
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::KeyringError);
+        assert!(resp.result().as_str().is_some());
+        assert!(keyring.read().unwrap().is_locked());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn unlock_create_not_bool()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ a locked keyring and
+        // an Unlock request whose create flag is not a bool
+        // --------------------
+        let dir = TempDir::new("sasd").unwrap();
+        let mut session_state = new_session_state(locked_keyring(&dir));
+        let args = vec![Value::from("passphrase"), Value::from(1)];
+        let msg = request(SessionMethod::Unlock, args);
+
+        // --------------------
+        // WHEN
+        // Session::dispatch() is called with the request
+        // --------------------
+        let value = is_invalid_message(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // an InvalidMessage error is returned
+        // --------------------
+        assert!(value);
+
+        cleanup_settings(session_state);
+    }
+}
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,407 @@
+// src/test/protocol/v1/sign.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+use data_encoding::BASE64;
+use ed25519_dalek;
+use ed25519_dalek::Verifier;
+use p256::ecdsa;
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use rmpv::Value;
+use sha2::{Digest, Sha256};
+use siminau_rpc::message::Message;
+use siminau_rpc::message::response::RpcResponse;
+
+// Local imports
+
+use keyring::{Attrs, Key, Keyring, KeyringHandle, new_keyring_handle};
+use protocol::v1::{SessionRequest, SessionResponse, attrs_from_value,
+                   attrs_to_value};
+use rpc::v1::{ProtocolError, SessionError, SessionMethod};
+use state::{SessionState, SessionStatus};
+
+use test::protocol::{attrs, cleanup_settings, new_session_state, read, send,
+                     start, write};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+const SECRET: [u8; 32] = [1; 32];
+
+
+fn sign_key(alg: &str) -> Attrs
+{
+    let secret = BASE64.encode(&SECRET);
+    attrs(&[
+        ("proto", "sign"),
+        ("alg", alg),
+        ("name", "release"),
+        ("!secret", &secret),
+    ])
+}
+
+
+// A keyring holding an Ed25519 and a P-256 signing key
+fn keyring() -> KeyringHandle
+{
+    let mut keyring = Keyring::new();
+    keyring.add(Key::new(sign_key("ed25519")));
+    keyring.add(Key::new(sign_key("p256")));
+    new_keyring_handle(keyring)
+}
+
+
+fn session_send(session_state: &mut SessionState, msg: Message)
+    -> SessionResponse
+{
+    match session_state.change(msg).unwrap() {
+        SessionStatus::Reply(m) => SessionResponse::from(m).unwrap(),
+        _ => panic!("no reply"),
+    }
+}
+
+
+// Run the sign protocol over data, returning the signature
+fn sign(session_state: &mut SessionState, start_attrs: &[(&str, &str)],
+        data: &[u8])
+    -> Vec<u8>
+{
+    let resp = send(session_state, start(start_attrs));
+    assert_eq!(resp.error_code(), ProtocolError::Nil);
+    let resp = send(session_state, write(data));
+    assert_eq!(resp.error_code(), ProtocolError::Nil);
+    assert_eq!(resp.result(), &Value::from(false));
+
+    let resp = send(session_state, read());
+    assert_eq!(resp.error_code(), ProtocolError::Nil);
+    let result = resp.result().as_array().unwrap();
+    assert_eq!(result[1], Value::from(true));
+    result[0].as_slice().unwrap().to_vec()
+}
+
+
+fn ed25519_public() -> ed25519_dalek::VerifyingKey
+{
+    ed25519_dalek::SigningKey::from_bytes(&SECRET).verifying_key()
+}
+
+
+fn p256_public() -> ecdsa::VerifyingKey
+{
+    *ecdsa::SigningKey::from_slice(&SECRET).unwrap().verifying_key()
+}
+
+
+// ===========================================================================
+// Test sign protocol
+// ===========================================================================
+
+
+mod sign {
+    use super::*;
+
+    #[test]
+    fn ed25519_message()
+    {
+        // --------------------
+        // GIVEN
+        // a session whose keyring holds an Ed25519 signing key
+        // --------------------
+        let mut session_state = new_session_state(keyring());
+        let start_attrs = [("proto", "sign"), ("alg", "ed25519")];
+
+        // --------------------
+        // WHEN
+        // a message is signed w/ the key
+        // --------------------
+        let signature = sign(&mut session_state, &start_attrs, b"release");
+
+        // --------------------
+        // THEN
+        // the signature is valid for the key's public key
+        // --------------------
+        let signature =
+            ed25519_dalek::Signature::from_slice(&signature).unwrap();
+        assert!(ed25519_public().verify(b"release", &signature).is_ok());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn p256_message()
+    {
+        // --------------------
+        // GIVEN
+        // a session whose keyring holds a P-256 signing key
+        // --------------------
+        let mut session_state = new_session_state(keyring());
+        let start_attrs = [("proto", "sign"), ("alg", "p256")];
+
+        // --------------------
+        // WHEN
+        // a message is signed w/ the key
+        // --------------------
+        let signature = sign(&mut session_state, &start_attrs, b"release");
+
+        // --------------------
+        // THEN
+        // the DER signature is valid for the key's public key
+        // --------------------
+        let signature = ecdsa::Signature::from_der(&signature).unwrap();
+        assert!(p256_public().verify(b"release", &signature).is_ok());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn p256_digest()
+    {
+        // --------------------
+        // GIVEN
+        // a session whose keyring holds a P-256 signing key and
+        // the SHA-256 digest of a message
+        // --------------------
+        let mut session_state = new_session_state(keyring());
+        let start_attrs =
+            [("proto", "sign"), ("alg", "p256"), ("input", "digest")];
+        let digest = Sha256::digest(b"release");
+
+        // --------------------
+        // WHEN
+        // the digest is signed w/ the key
+        // --------------------
+        let signature = sign(&mut session_state, &start_attrs, &digest);
+
+        // --------------------
+        // THEN
+        // the signature is valid for the digest and the message
+        // --------------------
+        let signature = ecdsa::Signature::from_der(&signature).unwrap();
+        let public = p256_public();
+        assert!(public.verify_prehash(&digest, &signature).is_ok());
+        assert!(public.verify(b"release", &signature).is_ok());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn p256_bad_digest()
+    {
+        // --------------------
+        // GIVEN
+        // a P-256 sign conversation for a digest
+        // --------------------
+        let mut session_state = new_session_state(keyring());
+        let msg =
+            start(&[("proto", "sign"), ("alg", "p256"), ("input", "digest")]);
+        send(&mut session_state, msg);
+
+        // --------------------
+        // WHEN
+        // something other than a SHA-256 digest is written
+        // --------------------
+        let resp = send(&mut session_state, write(b"release"));
+
+        // --------------------
+        // THEN
+        // an InvalidProtocolMessage error response is returned
+        // --------------------
+        let code = resp.error_code();
+        assert_eq!(code, ProtocolError::InvalidProtocolMessage);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn read_before_write()
+    {
+        // --------------------
+        // GIVEN
+        // an Ed25519 sign conversation
+        // --------------------
+        let mut session_state = new_session_state(keyring());
+        let msg = start(&[("proto", "sign"), ("alg", "ed25519")]);
+        send(&mut session_state, msg);
+
+        // --------------------
+        // WHEN
+        // ProtocolRead is sent before anything is written
+        // --------------------
+        let resp = send(&mut session_state, read());
+
+        // --------------------
+        // THEN
+        // an InvalidProtocolMessage error response is returned
+        // --------------------
+        let code = resp.error_code();
+        assert_eq!(code, ProtocolError::InvalidProtocolMessage);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn no_matching_key()
+    {
+        // --------------------
+        // GIVEN
+        // a session whose keyring holds no key named nightly
+        // --------------------
+        let mut session_state = new_session_state(keyring());
+
+        // --------------------
+        // WHEN
+        // the sign protocol is started for a key named nightly
+        // --------------------
+        let msg = start(&[("proto", "sign"), ("name", "nightly")]);
+        let resp = send(&mut session_state, msg);
+
+        // --------------------
+        // THEN
+        // a ProtocolNeedKey error response is returned
+        // --------------------
+        let template = attrs(&[
+            ("proto", "sign"),
+            ("name", "nightly"),
+            ("!secret", "?"),
+        ]);
+        assert_eq!(resp.error_code(), ProtocolError::ProtocolNeedKey);
+        assert_eq!(resp.result(), &attrs_to_value(&template));
+
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+// Test CreateKey w/ sign keys
+// ===========================================================================
+
+
+mod create_key {
+    use super::*;
+
+    fn create_key(session_state: &mut SessionState, key: &Attrs)
+        -> SessionResponse
+    {
+        let args = vec![attrs_to_value(key)];
+        let msg = SessionRequest::new(42, SessionMethod::CreateKey, args);
+        session_send(session_state, msg.into())
+    }
+
+    #[test]
+    fn pubkey_listed()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ an empty keyring
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+
+        // --------------------
+        // WHEN
+        // an Ed25519 and a P-256 signing key are created
+        // --------------------
+        let first = create_key(&mut session_state, &sign_key("ed25519"));
+        let second = create_key(&mut session_state, &sign_key("p256"));
+
+        // --------------------
+        // THEN
+        // KeyList shows each key's public key in its pubkey attr
+        // --------------------
+        assert_eq!(first.error_code(), SessionError::Nil);
+        assert_eq!(second.error_code(), SessionError::Nil);
+
+        let msg = SessionRequest::new(42, SessionMethod::KeyList, vec![]);
+        let resp = session_send(&mut session_state, msg.into());
+        let keys: Vec<Attrs> = resp.result()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|k| attrs_from_value(k).unwrap())
+            .collect();
+        let pubkey = |alg: &str| {
+            let key = keys.iter().find(|k| k["alg"] == alg).unwrap();
+            BASE64.decode(key["pubkey"].as_bytes()).unwrap()
+        };
+        assert_eq!(pubkey("ed25519"), ed25519_public().to_bytes().to_vec());
+        let point = p256_public().to_encoded_point(false);
+        assert_eq!(pubkey("p256"), point.as_bytes().to_vec());
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn invalid_secret()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ an empty keyring and
+        // a signing key whose secret is too short
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let mut key = sign_key("ed25519");
+        key.insert("!secret".to_owned(), BASE64.encode(b"short"));
+
+        // --------------------
+        // WHEN
+        // the key is created
+        // --------------------
+        let resp = create_key(&mut session_state, &key);
+
+        // --------------------
+        // THEN
+        // an InvalidKey error response is returned
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::InvalidKey);
+
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn mismatched_pubkey()
+    {
+        // --------------------
+        // GIVEN
+        // a session w/ an empty keyring and
+        // a signing key w/ someone else's pubkey
+        // --------------------
+        let keyring = new_keyring_handle(Keyring::new());
+        let mut session_state = new_session_state(keyring);
+        let mut key = sign_key("ed25519");
+        key.insert("pubkey".to_owned(), BASE64.encode(&[2; 32]));
+
+        // --------------------
+        // WHEN
+        // the key is created
+        // --------------------
+        let resp = create_key(&mut session_state, &key);
+
+        // --------------------
+        // THEN
+        // an InvalidKey error response is returned
+        // --------------------
+        assert_eq!(resp.error_code(), SessionError::InvalidKey);
+
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,189 @@
+// src/test/query.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+use error::SasdErrorKind;
+use keyring::Key;
+use query::Query;
+use secret::SecretBuf;
+
+use test::protocol::{alice, attrs};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+fn key(pairs: &[(&str, &str)]) -> Key
+{
+    Key::new(attrs(pairs))
+}
+
+
+fn matches(query: &str, key: &Key) -> bool
+{
+    Query::parse(query).unwrap().matches(key)
+}
+
+
+fn is_invalid_query(query: &str) -> bool
+{
+    match Query::parse(query) {
+        Err(e) => {
+            match e.kind() {
+                &SasdErrorKind::InvalidQuery(_) => true,
+                _ => false,
+            }
+        }
+        Ok(_) => false,
+    }
+}
+
+
+// ===========================================================================
+// Test parse
+// ===========================================================================
+
+
+mod parse {
+    use super::*;
+
+    #[test]
+    fn empty_query()
+    {
+        let query = Query::parse("  ").unwrap();
+        assert!(query.is_empty());
+        assert!(query.matches(&alice()));
+    }
+
+    #[test]
+    fn invalid_terms()
+    {
+        for q in &["user", "=alice", "-?", "us*er=alice", "user='alice"] {
+            assert!(is_invalid_query(q), "{} should be invalid", q);
+        }
+    }
+
+    #[test]
+    fn secret_value_invalid()
+    {
+        // --------------------
+        // WHEN
+        // a query tests the value of a secret attribute
+        // --------------------
+        let value = is_invalid_query("!password=hunter2");
+
+        // --------------------
+        // THEN
+        // the query is invalid
+        // --------------------
+        assert!(value);
+    }
+}
+
+
+// ===========================================================================
+// Test matches
+// ===========================================================================
+
+
+mod query_matches {
+    use super::*;
+
+    #[test]
+    fn exact_value()
+    {
+        let key = alice();
+        assert!(matches("proto=pass user=alice", &key));
+        assert!(!matches("proto=pass user=bob", &key));
+        assert!(!matches("user=ali", &key));
+    }
+
+    #[test]
+    fn present()
+    {
+        let key = alice();
+        assert!(matches("user?", &key));
+        assert!(matches("!password?", &key));
+        assert!(!matches("dom?", &key));
+    }
+
+    #[test]
+    fn present_binary_secret()
+    {
+        // --------------------
+        // GIVEN
+        // a key whose secret is not valid utf-8
+        // --------------------
+        let secret = SecretBuf::from(vec![0xff, 0x00, 0xfe]);
+        let key = Key::with_secret(attrs(&[("proto", "ssh")]), "!seed", secret);
+
+        // --------------------
+        // THEN
+        // the secret is still found to be present
+        // --------------------
+        assert!(matches("!seed?", &key));
+        assert!(!matches("-!seed?", &key));
+        assert!(!matches("!password?", &key));
+    }
+
+    #[test]
+    fn wildcard()
+    {
+        let key = alice();
+        assert!(matches("user=*", &key));
+        assert!(matches("user=a*e", &key));
+        assert!(matches("user=*l*c*", &key));
+        assert!(!matches("user=b*", &key));
+        assert!(!matches("dom=*", &key));
+    }
+
+    #[test]
+    fn negate()
+    {
+        let key = alice();
+        assert!(matches("-user=bob", &key));
+        assert!(matches("-dom?", &key));
+        assert!(!matches("-user=a*", &key));
+        assert!(!matches("proto=pass -!password?", &key));
+    }
+
+    #[test]
+    fn quoted_value()
+    {
+        // --------------------
+        // GIVEN
+        // keys w/ values containing a space and a literal *
+        // --------------------
+        let star = key(&[("user", "a*"), ("note", "it's")]);
+        let mail = key(&[("service", "mail server")]);
+
+        // --------------------
+        // THEN
+        // quoting keeps spaces and disables wildcards
+        // --------------------
+        assert!(matches("service='mail server'", &mail));
+        assert!(matches("'service'='mail '*", &mail));
+        assert!(matches("user='a*'", &star));
+        assert!(!matches("user='a*'", &alice()));
+        assert!(matches("note='it''s'", &star));
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
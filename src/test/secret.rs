@@ -0,0 +1,102 @@
+// src/test/secret.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+use secret::SecretBuf;
+
+
+// ===========================================================================
+// Tests
+// ===========================================================================
+
+
+#[test]
+fn contents()
+{
+    let secret = SecretBuf::from("hunter2");
+    assert_eq!(secret.as_bytes(), b"hunter2");
+    assert_eq!(secret.as_str(), Some("hunter2"));
+    assert_eq!(secret.len(), 7);
+    assert!(!secret.is_empty());
+    assert!(SecretBuf::new().is_empty());
+}
+
+
+#[test]
+fn not_utf8()
+{
+    let secret = SecretBuf::from(vec![0xff, 0xfe]);
+    assert_eq!(secret.as_str(), None);
+}
+
+
+#[test]
+fn compare()
+{
+    // --------------------
+    // GIVEN
+    // a secret
+    // --------------------
+    let secret = SecretBuf::from("hunter2");
+
+    // --------------------
+    // THEN
+    // it only equals the exact same bytes
+    // --------------------
+    assert!(secret.ct_eq(b"hunter2"));
+    assert!(!secret.ct_eq(b"hunter3"));
+    assert!(!secret.ct_eq(b"hunter"));
+    assert!(!secret.ct_eq(b""));
+    assert_eq!(secret, SecretBuf::from("hunter2"));
+    assert_eq!(secret.clone(), secret);
+    assert!(secret != SecretBuf::from("Hunter2"));
+}
+
+
+#[test]
+fn debug_hides_contents()
+{
+    // --------------------
+    // GIVEN
+    // a secret
+    // --------------------
+    let secret = SecretBuf::from("hunter2");
+
+    // --------------------
+    // WHEN
+    // the secret is formatted for debugging
+    // --------------------
+    let output = format!("{:?}", secret);
+
+    // --------------------
+    // THEN
+    // the contents do not appear
+    // --------------------
+    assert!(!output.contains("hunter2"));
+}
+
+
+#[test]
+fn modify_in_place()
+{
+    let mut secret = SecretBuf::from(vec![0u8; 4]);
+    secret.as_mut_bytes().copy_from_slice(b"abcd");
+    assert!(secret.ct_eq(b"abcd"));
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
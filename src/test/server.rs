@@ -0,0 +1,281 @@
+// src/test/server.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+use std::io::{self, Cursor, Read, Write};
+
+// Third-party imports
+
+use rmpv::Value;
+use rmpv::encode::write_value;
+use siminau_rpc::message::{CodeConvert, Message, MessageType, RpcMessage};
+
+// Local imports
+
+use protocol::{Protocol, Request, Start, StateValue};
+use rpc;
+
+use super::protocol::{cleanup_settings, dummy_session_state};
+
+
+// ===========================================================================
+// Helpers
+// ===========================================================================
+
+
+// In-memory stand-in for a client connection
+struct TestStream {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+
+impl TestStream {
+    fn new(messages: Vec<Value>) -> Self
+    {
+        let mut input = Vec::new();
+        for m in messages {
+            write_value(&mut input, &m).unwrap();
+        }
+        TestStream {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        }
+    }
+}
+
+
+impl Read for TestStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        self.input.read(buf)
+    }
+}
+
+
+impl Write for TestStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+
+fn version_request(ver: u64) -> Value
+{
+    let args = vec![Value::from(ver)];
+    let request = Request::new(42, rpc::RequestMethod::Version, args);
+    let msg: Message = request.into();
+    Value::Array(msg.as_vec().clone())
+}
+
+
+fn done_notice() -> Value
+{
+    let msgtype = Value::from(MessageType::Notification.to_number());
+    let msgcode = Value::from(rpc::Notice::Done.to_number());
+    let msgargs = Value::Array(vec![]);
+    Value::Array(vec![msgtype, msgcode, msgargs])
+}
+
+
+// ===========================================================================
+// Test read_message()
+// ===========================================================================
+
+
+mod read_message {
+    use super::*;
+    use server::read_message;
+
+    #[test]
+    fn empty_stream_returns_none()
+    {
+        // --------------------
+        // GIVEN
+        // a stream with no data
+        // --------------------
+        let mut stream = TestStream::new(vec![]);
+
+        // --------------------
+        // WHEN
+        // read_message() is called with the stream
+        // --------------------
+        let result = read_message(&mut stream);
+
+        // --------------------
+        // THEN
+        // None is returned
+        // --------------------
+        assert!(matches!(result, Ok(None)));
+    }
+
+    #[test]
+    fn truncated_message_is_error()
+    {
+        // --------------------------------------------
+        // GIVEN
+        // a stream containing a partial message
+        // --------------------------------------------
+        let mut stream = TestStream::new(vec![done_notice()]);
+        let len = stream.input.get_ref().len();
+        stream.input.get_mut().truncate(len - 1);
+
+        // --------------------
+        // WHEN
+        // read_message() is called with the stream
+        // --------------------
+        let result = read_message(&mut stream);
+
+        // --------------------
+        // THEN
+        // an error is returned
+        // --------------------
+        assert!(result.is_err());
+    }
+}
+
+
+// ===========================================================================
+// Test serve_session()
+// ===========================================================================
+
+
+mod serve_session {
+    use super::*;
+    use server::{read_message, serve_session};
+    use siminau_rpc::message::response::RpcResponse;
+
+    #[test]
+    fn done_ends_session()
+    {
+        // ----------------------------------------------------
+        // GIVEN
+        // a stream containing a version request followed by a
+        // done notice and another version request and
+        // a session state in the Start state
+        // ----------------------------------------------------
+        let ver = Protocol::V1.to_u64();
+        let mut stream = TestStream::new(
+            vec![version_request(ver), done_notice(), version_request(ver)],
+        );
+        let mut session_state =
+            dummy_session_state(StateValue::Start(Start::new()));
+
+        // ----------------------------------------------------
+        // WHEN
+        // serve_session() is called with the stream and state
+        // ----------------------------------------------------
+        let result = serve_session(&mut stream, &mut session_state);
+
+        // ---------------------------------------------------
+        // THEN
+        // the session ends without reading the last request and
+        // the session state has moved to a v1 state and
+        // nothing is written to the stream
+        // ---------------------------------------------------
+        assert!(result.is_ok());
+        assert!(read_message(&mut stream.input).unwrap().is_some());
+        assert!(session_state.state().is_v1());
+        assert!(stream.output.is_empty());
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn unsupported_version_reply()
+    {
+        // ----------------------------------------------------
+        // GIVEN
+        // a stream containing a request for an unknown version and
+        // a session state in the Start state
+        // ----------------------------------------------------
+        let ver = Protocol::max_number() + 1;
+        let mut stream = TestStream::new(vec![version_request(ver)]);
+        let mut session_state =
+            dummy_session_state(StateValue::Start(Start::new()));
+
+        // ----------------------------------------------------
+        // WHEN
+        // serve_session() is called with the stream and state
+        // ----------------------------------------------------
+        let result = serve_session(&mut stream, &mut session_state);
+
+        // ---------------------------------------------------
+        // THEN
+        // the session ends once the stream is exhausted and
+        // an UnsupportedVersion response was written and
+        // the session state is still Start
+        // ---------------------------------------------------
+        assert!(result.is_ok());
+        assert!(session_state.state().is_start());
+
+        let mut output = Cursor::new(stream.output);
+        let msg = read_message(&mut output).unwrap().unwrap();
+        let resp = ::protocol::Response::from(msg).unwrap();
+        assert_eq!(resp.message_id(), 42);
+        assert_eq!(resp.error_code(), rpc::ResponseError::UnsupportedVersion);
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(session_state);
+    }
+
+    #[test]
+    fn unexpected_message_is_error()
+    {
+        // ----------------------------------------------------
+        // GIVEN
+        // a stream containing a notification w/ an unknown code and
+        // a session state in the Start state
+        // ----------------------------------------------------
+        let msgtype = Value::from(MessageType::Notification.to_number());
+        let msgcode = Value::from(9999);
+        let msgargs = Value::Array(vec![]);
+        let notice = Value::Array(vec![msgtype, msgcode, msgargs]);
+        let mut stream = TestStream::new(vec![notice]);
+        let mut session_state =
+            dummy_session_state(StateValue::Start(Start::new()));
+
+        // ----------------------------------------------------
+        // WHEN
+        // serve_session() is called with the stream and state
+        // ----------------------------------------------------
+        let result = serve_session(&mut stream, &mut session_state);
+
+        // ---------------------------------------------------
+        // THEN
+        // an error is returned and
+        // the session state is kept
+        // ---------------------------------------------------
+        assert!(result.is_err());
+        assert!(session_state.state().is_start());
+
+        // --------------------
+        // Cleanup
+        // --------------------
+        cleanup_settings(session_state);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -0,0 +1,143 @@
+// src/test/settings/keyringbuilder.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+
+// ===========================================================================
+// Modules
+// ===========================================================================
+
+
+mod path {
+    use error::SasdErrorKind;
+    use settings::SettingsBuilder;
+    use std::env;
+
+    #[cfg(unix)]
+    fn builder() -> SettingsBuilder
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        SettingsBuilder::new()
+            .port(1234).unwrap()
+            .unix()
+                .socket_dir(curdir).unwrap()
+            .unix_done().unwrap()
+    }
+
+    #[cfg(windows)]
+    fn builder() -> SettingsBuilder
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        SettingsBuilder::new()
+            .port(1234).unwrap()
+            .windows()
+                .token_data_dir(curdir).unwrap()
+            .windows_done().unwrap()
+    }
+
+    #[test]
+    fn default_none()
+    {
+        let settings = builder().build().unwrap();
+        assert!(settings.keyring().path.is_none());
+        assert!(!settings.keyring().start_locked);
+    }
+
+    #[test]
+    fn start_locked()
+    {
+        let settings = builder()
+            .keyring()
+                .start_locked(true)
+            .keyring_done().unwrap()
+            .build().unwrap();
+        assert!(settings.keyring().path.is_none());
+        assert!(settings.keyring().start_locked);
+    }
+
+    #[test]
+    fn new_file_in_existing_dir()
+    {
+        // --------------------
+        // GIVEN
+        // a path to a file that does not exist in an existing directory
+        // --------------------
+        let path = env::current_dir().unwrap().join("no-such-keyring");
+        let pathstr = path.to_str().unwrap().to_owned();
+
+        // --------------------
+        // WHEN
+        // the keyring path is set and the settings are built
+        // --------------------
+        let settings = builder()
+            .keyring()
+                .path(pathstr).unwrap()
+            .keyring_done().unwrap()
+            .build().unwrap();
+
+        // --------------------
+        // THEN
+        // the path is kept
+        // --------------------
+        assert_eq!(settings.keyring().path, Some(path));
+    }
+
+    #[test]
+    fn missing_dir()
+    {
+        // --------------------
+        // GIVEN
+        // a path inside a directory that does not exist
+        // --------------------
+        let path = env::current_dir()
+            .unwrap()
+            .join("no-such-dir")
+            .join("keyring");
+        let pathstr = path.to_str().unwrap().to_owned();
+
+        // --------------------
+        // WHEN
+        // the keyring path is set
+        // --------------------
+        let result = builder().keyring().path(pathstr);
+
+        // --------------------
+        // THEN
+        // a SettingsError is returned
+        // --------------------
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::SettingsError(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+    }
+
+    #[test]
+    fn path_is_dir()
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        let result = builder().keyring().path(curdir);
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::SettingsError(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
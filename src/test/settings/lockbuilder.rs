@@ -0,0 +1,159 @@
+// src/test/settings/lockbuilder.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+
+// ===========================================================================
+// Modules
+// ===========================================================================
+
+
+mod parse_duration {
+    use settings::parse_duration;
+    use std::time::Duration;
+
+    #[test]
+    fn units()
+    {
+        let cases = [
+            ("90", 90),
+            ("90s", 90),
+            ("15m", 15 * 60),
+            ("8h", 8 * 60 * 60),
+            (" 1d ", 24 * 60 * 60),
+        ];
+        for &(value, secs) in &cases {
+            let expected = Duration::from_secs(secs);
+            assert_eq!(parse_duration(value).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn invalid()
+    {
+        for value in &["", "m", "15 m", "15w", "-5", "1.5h"] {
+            assert!(parse_duration(value).is_err(), "{:?} should fail", value);
+        }
+    }
+}
+
+
+mod lock_done {
+    use error::SasdErrorKind;
+    use settings::SettingsBuilder;
+    use std::env;
+    use std::time::Duration;
+
+    #[cfg(unix)]
+    fn builder() -> SettingsBuilder
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        SettingsBuilder::new()
+            .port(1234).unwrap()
+            .unix()
+                .socket_dir(curdir).unwrap()
+            .unix_done().unwrap()
+    }
+
+    #[cfg(windows)]
+    fn builder() -> SettingsBuilder
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        SettingsBuilder::new()
+            .port(1234).unwrap()
+            .windows()
+                .token_data_dir(curdir).unwrap()
+            .windows_done().unwrap()
+    }
+
+    fn is_settings_error<T>(result: ::error::SasdResult<T>) -> bool
+    {
+        match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::SettingsError(_)),
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn default_no_timeouts()
+    {
+        let settings = builder().build().unwrap();
+        assert!(settings.lock().idle_timeout.is_none());
+        assert!(settings.lock().max_unlocked.is_none());
+    }
+
+    #[test]
+    fn set_timeouts()
+    {
+        // --------------------
+        // WHEN
+        // both timeouts are set and the settings are built
+        // --------------------
+        let settings = builder()
+            .lock()
+                .idle_timeout(Duration::from_secs(60)).unwrap()
+                .max_unlocked(Duration::from_secs(3600)).unwrap()
+            .lock_done().unwrap()
+            .build().unwrap();
+
+        // --------------------
+        // THEN
+        // both timeouts are kept
+        // --------------------
+        let lock = settings.lock();
+        assert_eq!(lock.idle_timeout, Some(Duration::from_secs(60)));
+        assert_eq!(lock.max_unlocked, Some(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn zero_timeout()
+    {
+        let result = builder().lock().idle_timeout(Duration::from_secs(0));
+        assert!(is_settings_error(result));
+
+        let result = builder().lock().max_unlocked(Duration::from_secs(0));
+        assert!(is_settings_error(result));
+    }
+
+    #[test]
+    fn idle_longer_than_max()
+    {
+        // --------------------
+        // GIVEN
+        // an idle timeout longer than the max unlocked time
+        // --------------------
+        let lock = builder()
+            .lock()
+                .idle_timeout(Duration::from_secs(3600)).unwrap()
+                .max_unlocked(Duration::from_secs(60)).unwrap();
+
+        // --------------------
+        // WHEN
+        // LockBuilder::lock_done() is called
+        // --------------------
+        let result = lock.lock_done();
+
+        // --------------------
+        // THEN
+        // a SettingsError is returned
+        // --------------------
+        assert!(is_settings_error(result));
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
@@ -8,6 +8,11 @@
 // ===========================================================================
 
 
+mod keyringbuilder;
+mod lockbuilder;
+mod peersbuilder;
+mod policybuilder;
+mod protocolbuilder;
 mod unixbuilder;
 mod windowsbuilder;
 
@@ -0,0 +1,102 @@
+// src/test/settings/peersbuilder.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+
+// ===========================================================================
+// Modules
+// ===========================================================================
+
+
+mod peers_done {
+    use settings::SettingsBuilder;
+    use std::env;
+
+    #[cfg(unix)]
+    fn builder() -> SettingsBuilder
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        SettingsBuilder::new()
+            .port(1234).unwrap()
+            .unix()
+                .socket_dir(curdir).unwrap()
+            .unix_done().unwrap()
+    }
+
+    #[cfg(windows)]
+    fn builder() -> SettingsBuilder
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        SettingsBuilder::new()
+            .port(1234).unwrap()
+            .windows()
+                .token_data_dir(curdir).unwrap()
+            .windows_done().unwrap()
+    }
+
+    #[test]
+    fn default_empty()
+    {
+        // --------------------
+        // WHEN
+        // settings are built without a peers section
+        // --------------------
+        let settings = builder().build().unwrap();
+
+        // --------------------
+        // THEN
+        // no extra users or groups are allowed
+        // --------------------
+        assert!(settings.peers().allow_uids.is_empty());
+        assert!(settings.peers().allow_gids.is_empty());
+    }
+
+    #[test]
+    fn allowed_ids_deduplicated()
+    {
+        // --------------------
+        // GIVEN
+        // a SettingsBuilder instance
+        // --------------------
+        let builder = builder();
+
+        // --------------------
+        // WHEN
+        // the same uids and gids are allowed more than once and
+        // the settings are built
+        // --------------------
+        let settings = builder
+            .peers()
+                .allow_uid(1000)
+                .allow_uid(1001)
+                .allow_uid(1000)
+                .allow_gid(100)
+                .allow_gid(100)
+            .peers_done().unwrap()
+            .build().unwrap();
+
+        // --------------------
+        // THEN
+        // each id is only listed once
+        // --------------------
+        assert_eq!(settings.peers().allow_uids, vec![1000, 1001]);
+        assert_eq!(settings.peers().allow_gids, vec![100]);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
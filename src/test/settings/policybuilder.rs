@@ -0,0 +1,114 @@
+// src/test/settings/policybuilder.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+
+// ===========================================================================
+// Modules
+// ===========================================================================
+
+
+mod policy_done {
+    use error::SasdErrorKind;
+    use settings::SettingsBuilder;
+    use std::env;
+
+    #[cfg(unix)]
+    fn builder() -> SettingsBuilder
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        SettingsBuilder::new()
+            .port(1234).unwrap()
+            .unix()
+                .socket_dir(curdir).unwrap()
+            .unix_done().unwrap()
+    }
+
+    #[cfg(windows)]
+    fn builder() -> SettingsBuilder
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        SettingsBuilder::new()
+            .port(1234).unwrap()
+            .windows()
+                .token_data_dir(curdir).unwrap()
+            .windows_done().unwrap()
+    }
+
+    fn is_settings_error<T>(result: ::error::SasdResult<T>) -> bool
+    {
+        match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::SettingsError(_)),
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn default_no_policy()
+    {
+        let settings = builder().build().unwrap();
+        assert!(settings.policy("example.com").is_none());
+    }
+
+    #[test]
+    fn set_policy()
+    {
+        // --------------------
+        // WHEN
+        // a policy is set for a domain and the settings are built
+        // --------------------
+        let settings = builder()
+            .policy("example.com".to_owned())
+                .min_length(12)
+                .class("digits".to_owned()).unwrap()
+                .class("upper".to_owned()).unwrap()
+                .class("digits".to_owned()).unwrap()
+                .reuse(3)
+            .policy_done().unwrap()
+            .build().unwrap();
+
+        // --------------------
+        // THEN
+        // the policy is kept for that domain only and
+        // each class is listed once
+        // --------------------
+        let policy = settings.policy("example.com").unwrap();
+        assert_eq!(policy.min_length, 12);
+        assert_eq!(policy.classes, vec!["digits", "upper"]);
+        assert_eq!(policy.reuse, 3);
+        assert!(settings.policy("example.org").is_none());
+    }
+
+    #[test]
+    fn unknown_class()
+    {
+        let result = builder()
+            .policy("example.com".to_owned())
+            .class("emoji".to_owned());
+        assert!(is_settings_error(result));
+    }
+
+    #[test]
+    fn empty_domain()
+    {
+        let result = builder().policy(String::new()).policy_done();
+        assert!(is_settings_error(result));
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
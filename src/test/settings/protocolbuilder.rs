@@ -0,0 +1,141 @@
+// src/test/settings/protocolbuilder.rs
+// Copyright (C) 2017 authors and contributors (see AUTHORS file)
+//
+// This file is released under the MIT License.
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+
+// Stdlib imports
+
+// Third-party imports
+
+// Local imports
+
+
+// ===========================================================================
+// Modules
+// ===========================================================================
+
+
+mod protocol_done {
+    use error::SasdErrorKind;
+    use settings::SettingsBuilder;
+    use std::env;
+    use std::time::Duration;
+
+    #[cfg(unix)]
+    fn builder() -> SettingsBuilder
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        SettingsBuilder::new()
+            .port(1234).unwrap()
+            .unix()
+                .socket_dir(curdir).unwrap()
+            .unix_done().unwrap()
+    }
+
+    #[cfg(windows)]
+    fn builder() -> SettingsBuilder
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        SettingsBuilder::new()
+            .port(1234).unwrap()
+            .windows()
+                .token_data_dir(curdir).unwrap()
+            .windows_done().unwrap()
+    }
+
+    #[test]
+    fn default_needkey_timeout()
+    {
+        let settings = builder().build().unwrap();
+        let timeout = settings.protocol().needkey_timeout;
+        assert_eq!(timeout, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn set_needkey_timeout()
+    {
+        // --------------------
+        // WHEN
+        // the needkey timeout is set and the settings are built
+        // --------------------
+        let settings = builder()
+            .protocol()
+                .needkey_timeout(Duration::from_secs(5)).unwrap()
+            .protocol_done().unwrap()
+            .build().unwrap();
+
+        // --------------------
+        // THEN
+        // the timeout is kept
+        // --------------------
+        let timeout = settings.protocol().needkey_timeout;
+        assert_eq!(timeout, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn zero_needkey_timeout()
+    {
+        let result =
+            builder().protocol().needkey_timeout(Duration::from_secs(0));
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::SettingsError(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+    }
+
+    #[test]
+    fn default_confirm()
+    {
+        let settings = builder().build().unwrap();
+        let protocol = settings.protocol();
+        assert_eq!(protocol.confirm_timeout, Duration::from_secs(60));
+        assert_eq!(protocol.confirm_remember, None);
+    }
+
+    #[test]
+    fn set_confirm()
+    {
+        // --------------------
+        // WHEN
+        // the confirm timeout and remember period are set and the settings
+        // are built
+        // --------------------
+        let settings = builder()
+            .protocol()
+                .confirm_timeout(Duration::from_secs(30)).unwrap()
+                .confirm_remember(Duration::from_secs(300)).unwrap()
+            .protocol_done().unwrap()
+            .build().unwrap();
+
+        // --------------------
+        // THEN
+        // both are kept
+        // --------------------
+        let protocol = settings.protocol();
+        assert_eq!(protocol.confirm_timeout, Duration::from_secs(30));
+        assert_eq!(protocol.confirm_remember, Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn zero_confirm_remember()
+    {
+        let result =
+            builder().protocol().confirm_remember(Duration::from_secs(0));
+        let value = match result {
+            Err(e) => matches!(*e.kind(), SasdErrorKind::SettingsError(_)),
+            Ok(_) => false,
+        };
+        assert!(value);
+    }
+}
+
+
+// ===========================================================================
+//
+// ===========================================================================
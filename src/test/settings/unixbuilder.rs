@@ -274,6 +274,56 @@ mod socket_dir {
 }
 
 
+#[cfg(unix)]
+mod ssh_agent {
+    use settings::SettingsBuilder;
+    use std::env;
+
+    #[test]
+    fn default_off()
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        let settings = SettingsBuilder::new()
+            .port(1234).unwrap()
+            .unix()
+                .socket_dir(curdir).unwrap()
+            .unix_done().unwrap()
+            .build().unwrap();
+        assert!(!settings.unix().ssh_agent);
+    }
+
+    #[test]
+    fn enabled()
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        let settings = SettingsBuilder::new()
+            .port(1234).unwrap()
+            .unix()
+                .socket_dir(curdir).unwrap()
+                .ssh_agent(true)
+            .unix_done().unwrap()
+            .build().unwrap();
+        assert!(settings.unix().ssh_agent);
+        assert!(!settings.unix().ssh_agent_remove_all);
+    }
+
+    #[test]
+    fn remove_all_enabled()
+    {
+        let curdir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+        let settings = SettingsBuilder::new()
+            .port(1234).unwrap()
+            .unix()
+                .socket_dir(curdir).unwrap()
+                .ssh_agent(true)
+                .ssh_agent_remove_all(true)
+            .unix_done().unwrap()
+            .build().unwrap();
+        assert!(settings.unix().ssh_agent_remove_all);
+    }
+}
+
+
 mod unix_done {
     use settings::SettingsBuilder;
 